@@ -2,14 +2,28 @@
 //!
 //! This module handles making requests to OpenAI, Gemini, and Anthropic APIs.
 
-use super::convert::{convert_anthropic_to_openai, convert_openai_to_anthropic, map_model, generate_message_id};
+use super::convert::{
+    convert_anthropic_to_openai, convert_gemini_to_openai, convert_openai_to_anthropic, convert_openai_to_gemini,
+    gemini_finish_reason, generate_message_id, generate_tool_id, map_model, matched_stop_sequence,
+};
+use super::tokens::count_input_tokens;
 use super::types::*;
-use futures_util::StreamExt;
+use futures_util::{Stream, StreamExt};
 use reqwest::{Client, header};
 use serde::Deserialize;
 use serde_json::json;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
+/// Time limits applied to a single proxied request
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestLimits {
+    /// Absolute point after which the request (including streaming) is abandoned
+    pub deadline: Option<tokio::time::Instant>,
+    /// Maximum gap between upstream stream chunks
+    pub idle_timeout: Option<Duration>,
+}
+
 /// API client for making requests to upstream providers
 #[derive(Clone)]
 pub struct ApiClient {
@@ -18,14 +32,48 @@ pub struct ApiClient {
 }
 
 impl ApiClient {
-    /// Create a new API client with the given configuration
+    /// Create a new API client with the given configuration.
+    /// An unusable proxy or CA setting is logged and the client connects directly.
     pub fn new(config: ProxyConfig) -> Self {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(300))
-            .build()
-            .expect("Failed to create HTTP client");
+        match Self::try_new(config.clone()) {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("{}; ignoring upstream proxy and CA settings", e);
+                let client = Client::builder()
+                    .connect_timeout(Duration::from_secs(config.request_timeout_secs))
+                    .build()
+                    .expect("Failed to create HTTP client");
+                Self { client, config }
+            }
+        }
+    }
 
-        Self { client, config }
+    /// Create a new API client, failing if the upstream proxy or CA certificate can't be used
+    pub fn try_new(config: ProxyConfig) -> Result<Self, ApiError> {
+        // No client-wide timeout: it would also cut off long streams mid-flight
+        let mut builder = Client::builder().connect_timeout(Duration::from_secs(config.request_timeout_secs));
+
+        if let Some(ref url) = config.upstream_proxy {
+            let proxy = reqwest::Proxy::all(url)
+                .map_err(|e| ApiError::RequestFailed(format!("Invalid upstream proxy {}: {}", url, e)))?
+                .no_proxy(reqwest::NoProxy::from_env());
+            builder = builder.proxy(proxy);
+        }
+
+        if let Some(ref path) = config.ca_cert_path {
+            let pem = std::fs::read(path)
+                .map_err(|e| ApiError::RequestFailed(format!("Failed to read CA certificate {}: {}", path, e)))?;
+            let certs = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| ApiError::RequestFailed(format!("Invalid CA certificate {}: {}", path, e)))?;
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        let client = builder
+            .build()
+            .map_err(|e| ApiError::RequestFailed(format!("Failed to create HTTP client: {}", e)))?;
+        Ok(Self { client, config })
     }
 
     /// Create a new API client from environment variables
@@ -33,6 +81,31 @@ impl ApiClient {
         Self::new(ProxyConfig::from_env())
     }
 
+    /// Get the client's configuration
+    pub fn config(&self) -> &ProxyConfig {
+        &self.config
+    }
+
+    /// A client sharing this one's connection pool that sends `api_key` to whichever
+    /// provider the request is routed to (for `passthrough_auth`)
+    pub fn with_upstream_key(&self, api_key: &str) -> Self {
+        let key = Some(api_key.to_string());
+        Self {
+            client: self.client.clone(),
+            config: ProxyConfig {
+                openai_api_key: key.clone(),
+                gemini_api_key: key.clone(),
+                anthropic_api_key: key.clone(),
+                azure_api_key: key,
+                ..self.config.clone()
+            },
+        }
+    }
+
+    fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.request_timeout_secs)
+    }
+
     /// Get the base URL for a provider
     fn get_base_url(&self, provider: &str) -> String {
         match provider {
@@ -53,6 +126,7 @@ impl ApiClient {
             "openai" => self.config.openai_api_key.as_deref(),
             "gemini" | "google" => self.config.gemini_api_key.as_deref(),
             "anthropic" => self.config.anthropic_api_key.as_deref(),
+            "azure" => self.config.azure_api_key.as_deref(),
             _ => None,
         }
     }
@@ -61,6 +135,28 @@ impl ApiClient {
     pub async fn send_message(
         &self,
         request: &MessagesRequest,
+    ) -> Result<MessagesResponse, ApiError> {
+        self.send_message_with_limits(request, self.request_limits(None))
+            .await
+    }
+
+    /// Send a non-streaming request, dropping the upstream call once the deadline passes
+    pub async fn send_message_with_limits(
+        &self,
+        request: &MessagesRequest,
+        limits: RequestLimits,
+    ) -> Result<MessagesResponse, ApiError> {
+        match limits.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, self.send_message_inner(request))
+                .await
+                .map_err(|_| ApiError::Timeout("request deadline exceeded".to_string()))?,
+            None => self.send_message_inner(request).await,
+        }
+    }
+
+    async fn send_message_inner(
+        &self,
+        request: &MessagesRequest,
     ) -> Result<MessagesResponse, ApiError> {
         let original_model = request.model.clone();
         let mapped = map_model(&request.model, &self.config);
@@ -78,19 +174,43 @@ impl ApiClient {
         }
 
         // Convert to OpenAI format
-        let openai_request = convert_anthropic_to_openai(request, &mapped);
+        let openai_request = convert_anthropic_to_openai(request, &mapped, &self.config);
 
         // Send request
         let response = self.send_openai_request(&openai_request, &mapped).await?;
 
         // Convert response back to Anthropic format
-        Ok(convert_openai_to_anthropic(&response, &original_model))
+        Ok(convert_openai_to_anthropic(
+            &response,
+            &original_model,
+            request.stop_sequences.as_deref(),
+        ))
+    }
+
+    /// Deadline/idle limits for a request, falling back to the configured defaults
+    pub fn request_limits(&self, deadline_ms: Option<u64>) -> RequestLimits {
+        RequestLimits {
+            deadline: deadline_ms
+                .or(self.config.request_deadline_ms)
+                .map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms)),
+            idle_timeout: self.config.stream_idle_timeout_ms.map(Duration::from_millis),
+        }
     }
 
     /// Send a streaming request
     pub async fn send_message_streaming(
         &self,
         request: &MessagesRequest,
+    ) -> Result<mpsc::Receiver<Result<StreamEvent, ApiError>>, ApiError> {
+        self.send_message_streaming_with_limits(request, self.request_limits(None))
+            .await
+    }
+
+    /// Send a streaming request that is cancelled when `limits` are exceeded
+    pub async fn send_message_streaming_with_limits(
+        &self,
+        request: &MessagesRequest,
+        limits: RequestLimits,
     ) -> Result<mpsc::Receiver<Result<StreamEvent, ApiError>>, ApiError> {
         let original_model = request.model.clone();
         let mapped = map_model(&request.model, &self.config);
@@ -108,17 +228,85 @@ impl ApiClient {
 
         // If targeting Anthropic directly, use native streaming
         if mapped.provider == "anthropic" {
-            return self.stream_anthropic_native(&streaming_request).await;
+            return self.stream_anthropic_native(&streaming_request, limits).await;
         }
 
         // Convert to OpenAI format
-        let openai_request = convert_anthropic_to_openai(&streaming_request, &mapped);
+        let openai_request = convert_anthropic_to_openai(&streaming_request, &mapped, &self.config);
+
+        // Upstream only reports prompt tokens at the end (if at all), so message_start carries an estimate
+        let input_tokens = count_input_tokens(request.system.as_ref(), &request.messages, request.tools.as_deref());
 
         // Send streaming request
-        self.stream_openai_request(&openai_request, &mapped, &original_model)
+        self.stream_openai_request(&openai_request, &mapped, &original_model, input_tokens, limits)
             .await
     }
 
+    /// Send a request, retrying rate limits and transient server errors with exponential backoff.
+    /// A non-2xx response that is not retried (or runs out of attempts) becomes `UpstreamError`.
+    async fn send_with_retry(&self, mut req: reqwest::RequestBuilder) -> Result<reqwest::Response, ApiError> {
+        let max_attempts = self.config.retry_max_attempts.max(1);
+        let mut attempt = 1;
+
+        loop {
+            // Bodies here are in-memory JSON, so the builder can always be cloned for another attempt
+            let next = if attempt < max_attempts { req.try_clone() } else { None };
+
+            // Bounds time to first byte; streams are then governed by the idle timeout
+            let timed_out =
+                || ApiError::Timeout(format!("No response from upstream within {}s", self.config.request_timeout_secs));
+            let response = tokio::time::timeout(self.request_timeout(), req.send())
+                .await
+                .map_err(|_| timed_out())?
+                .map_err(|e| if e.is_timeout() { timed_out() } else { ApiError::RequestFailed(e.to_string()) })?;
+
+            let status = response.status();
+            if status.is_success() {
+                return Ok(response);
+            }
+
+            match next {
+                Some(next) if matches!(status.as_u16(), 429 | 500 | 502 | 503 | 504) => {
+                    let delay = retry_after(response.headers()).unwrap_or_else(|| {
+                        Duration::from_millis(self.config.retry_base_delay_ms.saturating_mul(2u64.saturating_pow(attempt - 1)))
+                    });
+                    log::warn!(
+                        "Upstream returned {}, retrying in {:?} (attempt {}/{})",
+                        status,
+                        delay,
+                        attempt + 1,
+                        max_attempts
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    req = next;
+                }
+                _ => {
+                    let body = response.text().await.unwrap_or_default();
+                    return Err(ApiError::UpstreamError {
+                        status: status.as_u16(),
+                        message: body,
+                    });
+                }
+            }
+        }
+    }
+
+    /// `send_with_retry` bounded by the request deadline, so waiting for the response headers
+    /// of a stream (including retry backoff) can't outlive it
+    async fn send_before_deadline(
+        &self,
+        req: reqwest::RequestBuilder,
+        limits: &RequestLimits,
+    ) -> Result<reqwest::Response, ApiError> {
+        match limits.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, self.send_with_retry(req))
+                .await
+                .map_err(|_| ApiError::Timeout("request deadline exceeded".to_string()))?,
+            None => self.send_with_retry(req).await,
+        }
+    }
+
     /// Send native Anthropic request
     async fn send_anthropic_native(
         &self,
@@ -131,25 +319,15 @@ impl ApiClient {
         let base_url = self.get_base_url("anthropic");
         let url = format!("{}/messages", base_url);
 
-        let response = self
+        let req = self
             .client
             .post(&url)
             .header("x-api-key", api_key)
             .header("anthropic-version", "2023-06-01")
             .header(header::CONTENT_TYPE, "application/json")
-            .json(request)
-            .send()
-            .await
-            .map_err(|e| ApiError::RequestFailed(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(ApiError::UpstreamError {
-                status: status.as_u16(),
-                message: body,
-            });
-        }
+            .timeout(self.request_timeout())
+            .json(request);
+        let response = self.send_with_retry(req).await?;
 
         response
             .json()
@@ -162,60 +340,128 @@ impl ApiClient {
         &self,
         request: &OpenAIRequest,
         mapped: &super::convert::MappedModel,
+    ) -> Result<OpenAIResponse, ApiError> {
+        if mapped.provider == "gemini" {
+            return self.send_gemini_request(request, mapped).await;
+        }
+
+        match self.post_openai_request(request, mapped).await {
+            Err(ApiError::UpstreamError { status: 400, message }) => {
+                // Some gateways reject an oversized max_tokens instead of clamping it; retry once
+                let requested = request.max_completion_tokens.unwrap_or(u32::MAX);
+                match max_tokens_limit_from_error(&message, &mapped.model).filter(|limit| *limit < requested) {
+                    Some(limit) => {
+                        log::warn!(
+                            "{} rejected max_tokens={}, retrying with {}",
+                            mapped.model,
+                            requested,
+                            limit
+                        );
+                        let mut retry = request.clone();
+                        retry.max_completion_tokens = Some(limit);
+                        self.post_openai_request(&retry, mapped).await
+                    }
+                    None => Err(ApiError::UpstreamError { status: 400, message }),
+                }
+            }
+            result => result,
+        }
+    }
+
+    /// POST a chat completion to the OpenAI-compatible endpoint
+    async fn post_openai_request(
+        &self,
+        request: &OpenAIRequest,
+        mapped: &super::convert::MappedModel,
     ) -> Result<OpenAIResponse, ApiError> {
         let api_key = self
             .get_api_key(&mapped.provider)
             .ok_or_else(|| ApiError::MissingApiKey(mapped.provider.clone()))?;
 
-        let (url, auth_header) = if mapped.provider == "gemini" {
-            // Gemini uses URL-based API key
-            let base_url = self.get_base_url("gemini");
-            let url = format!(
-                "{}/models/{}:generateContent?key={}",
-                base_url, mapped.model, api_key
-            );
-            (url, None)
-        } else {
-            // OpenAI uses Authorization header
-            let base_url = self.get_base_url("openai");
-            let url = format!("{}/chat/completions", base_url);
-            (url, Some(format!("Bearer {}", api_key)))
-        };
+        let req = self
+            .chat_completions_request(mapped, api_key)?
+            .header(header::CONTENT_TYPE, "application/json")
+            .timeout(self.request_timeout())
+            .json(request);
+        let response = self.send_with_retry(req).await?;
 
-        let mut req = self
+        response
+            .json()
+            .await
+            .map_err(|e| ApiError::ParseError(e.to_string()))
+    }
+
+    /// Chat completions POST with the provider's URL and auth header.
+    /// Azure addresses a deployment rather than a model and authenticates with `api-key`.
+    fn chat_completions_request(
+        &self,
+        mapped: &super::convert::MappedModel,
+        api_key: &str,
+    ) -> Result<reqwest::RequestBuilder, ApiError> {
+        if mapped.provider != "azure" {
+            let url = format!("{}/chat/completions", self.get_base_url("openai"));
+            return Ok(self
+                .client
+                .post(&url)
+                .header(header::AUTHORIZATION, format!("Bearer {}", api_key)));
+        }
+
+        let endpoint = self
+            .config
+            .azure_endpoint
+            .as_deref()
+            .ok_or_else(|| ApiError::RequestFailed("AZURE_OPENAI_ENDPOINT is not set".to_string()))?;
+        let deployment = self
+            .config
+            .azure_deployments
+            .get(&mapped.model)
+            .unwrap_or(&mapped.model);
+        let url = format!(
+            "{}/openai/deployments/{}/chat/completions",
+            endpoint.trim_end_matches('/'),
+            deployment
+        );
+        Ok(self
             .client
             .post(&url)
-            .header(header::CONTENT_TYPE, "application/json");
+            .query(&[("api-version", self.config.azure_api_version.as_str())])
+            .header("api-key", api_key))
+    }
 
-        if let Some(auth) = auth_header {
-            req = req.header(header::AUTHORIZATION, auth);
-        }
+    /// Send a request to Gemini's native generateContent endpoint
+    async fn send_gemini_request(
+        &self,
+        request: &OpenAIRequest,
+        mapped: &super::convert::MappedModel,
+    ) -> Result<OpenAIResponse, ApiError> {
+        let api_key = self
+            .get_api_key(&mapped.provider)
+            .ok_or_else(|| ApiError::MissingApiKey(mapped.provider.clone()))?;
 
-        let response = req
-            .json(request)
-            .send()
-            .await
-            .map_err(|e| ApiError::RequestFailed(e.to_string()))?;
+        let base_url = self.get_base_url("gemini");
+        let url = format!("{}/models/{}:generateContent", base_url, mapped.model);
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(ApiError::UpstreamError {
-                status: status.as_u16(),
-                message: body,
-            });
-        }
+        let req = self
+            .client
+            .post(&url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-goog-api-key", api_key)
+            .timeout(self.request_timeout())
+            .json(&convert_openai_to_gemini(request));
+        let response = self.send_with_retry(req).await?;
 
-        response
+        let gemini: GeminiResponse = response
             .json()
             .await
-            .map_err(|e| ApiError::ParseError(e.to_string()))
+            .map_err(|e| ApiError::ParseError(e.to_string()))?;
+        Ok(convert_gemini_to_openai(&gemini, &mapped.model))
     }
 
     /// Stream native Anthropic response
     async fn stream_anthropic_native(
         &self,
         request: &MessagesRequest,
+        limits: RequestLimits,
     ) -> Result<mpsc::Receiver<Result<StreamEvent, ApiError>>, ApiError> {
         let api_key = self
             .get_api_key("anthropic")
@@ -225,57 +471,18 @@ impl ApiClient {
         let base_url = self.get_base_url("anthropic");
         let url = format!("{}/messages", base_url);
 
-        let response = self
+        let req = self
             .client
             .post(&url)
             .header("x-api-key", &api_key)
             .header("anthropic-version", "2023-06-01")
             .header(header::CONTENT_TYPE, "application/json")
             .header(header::ACCEPT, "text/event-stream")
-            .json(request)
-            .send()
-            .await
-            .map_err(|e| ApiError::RequestFailed(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(ApiError::UpstreamError {
-                status: status.as_u16(),
-                message: body,
-            });
-        }
+            .json(request);
+        let response = self.send_before_deadline(req, &limits).await?;
 
         let (tx, rx) = mpsc::channel(100);
-        let mut stream = response.bytes_stream();
-
-        tokio::spawn(async move {
-            let mut buffer = String::new();
-
-            while let Some(chunk) = stream.next().await {
-                match chunk {
-                    Ok(bytes) => {
-                        buffer.push_str(&String::from_utf8_lossy(&bytes));
-
-                        // Process complete SSE events
-                        while let Some(pos) = buffer.find("\n\n") {
-                            let event_str = buffer[..pos].to_string();
-                            buffer = buffer[pos + 2..].to_string();
-
-                            if let Some(event) = parse_anthropic_sse(&event_str) {
-                                if tx.send(Ok(event)).await.is_err() {
-                                    return;
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        let _ = tx.send(Err(ApiError::StreamError(e.to_string()))).await;
-                        return;
-                    }
-                }
-            }
-        });
+        tokio::spawn(pump_anthropic_stream(response.bytes_stream(), tx, limits));
 
         Ok(rx)
     }
@@ -286,246 +493,190 @@ impl ApiClient {
         request: &OpenAIRequest,
         mapped: &super::convert::MappedModel,
         original_model: &str,
+        input_tokens: u32,
+        limits: RequestLimits,
     ) -> Result<mpsc::Receiver<Result<StreamEvent, ApiError>>, ApiError> {
+        if mapped.provider == "gemini" {
+            return self.stream_gemini_request(request, mapped, original_model, input_tokens, limits).await;
+        }
+
         let api_key = self
             .get_api_key(&mapped.provider)
-            .ok_or_else(|| ApiError::MissingApiKey(mapped.provider.clone()))?
-            .to_string();
-
-        let (url, auth_header) = if mapped.provider == "gemini" {
-            let base_url = self.get_base_url("gemini");
-            let url = format!(
-                "{}/models/{}:streamGenerateContent?key={}&alt=sse",
-                base_url, mapped.model, api_key
-            );
-            (url, None)
-        } else {
-            let base_url = self.get_base_url("openai");
-            let url = format!("{}/chat/completions", base_url);
-            (url, Some(format!("Bearer {}", api_key)))
-        };
+            .ok_or_else(|| ApiError::MissingApiKey(mapped.provider.clone()))?;
 
-        let mut req = self
-            .client
-            .post(&url)
+        let req = self
+            .chat_completions_request(mapped, api_key)?
+            .json(request)
             .header(header::CONTENT_TYPE, "application/json")
             .header(header::ACCEPT, "text/event-stream");
-
-        if let Some(auth) = auth_header {
-            req = req.header(header::AUTHORIZATION, auth);
-        }
-
-        let response = req
-            .json(request)
-            .send()
-            .await
-            .map_err(|e| ApiError::RequestFailed(e.to_string()))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(ApiError::UpstreamError {
-                status: status.as_u16(),
-                message: body,
-            });
-        }
+        // Retries only happen on the status line, before any stream data is consumed
+        let response = self.send_before_deadline(req, &limits).await?;
 
         let (tx, rx) = mpsc::channel(100);
-        let mut stream = response.bytes_stream();
-        let model = original_model.to_string();
-
-        tokio::spawn(async move {
-            let message_id = generate_message_id();
-            let mut buffer = String::new();
-            let mut sent_message_start = false;
-            let mut sent_content_block_start = false;
-            let mut current_tool_index: Option<u32> = None;
-            let mut content_index = 0u32;
-
-            while let Some(chunk) = stream.next().await {
-                match chunk {
-                    Ok(bytes) => {
-                        buffer.push_str(&String::from_utf8_lossy(&bytes));
-
-                        // Process complete SSE events
-                        while let Some(pos) = buffer.find("\n\n") {
-                            let event_str = buffer[..pos].to_string();
-                            buffer = buffer[pos + 2..].to_string();
-
-                            // Parse SSE event
-                            let data_line = event_str
-                                .lines()
-                                .find(|l| l.starts_with("data: "))
-                                .map(|l| &l[6..]);
-
-                            if let Some(data) = data_line {
-                                if data == "[DONE]" {
-                                    // Send message_stop
-                                    let _ = tx.send(Ok(StreamEvent::MessageStop)).await;
-                                    return;
-                                }
+        tokio::spawn(pump_openai_stream(
+            response.bytes_stream(),
+            tx,
+            original_model.to_string(),
+            request.stop.clone().unwrap_or_default(),
+            input_tokens,
+            limits,
+        ));
 
-                                // Parse OpenAI chunk
-                                if let Ok(chunk) = serde_json::from_str::<OpenAIStreamChunk>(data) {
-                                    // Send message_start if not sent
-                                    if !sent_message_start {
-                                        sent_message_start = true;
-                                        let _ = tx
-                                            .send(Ok(StreamEvent::MessageStart {
-                                                message: StreamMessage {
-                                                    id: message_id.clone(),
-                                                    message_type: "message".to_string(),
-                                                    role: "assistant".to_string(),
-                                                    model: model.clone(),
-                                                    content: vec![],
-                                                    stop_reason: None,
-                                                    stop_sequence: None,
-                                                    usage: StreamUsage::default(),
-                                                },
-                                            }))
-                                            .await;
-                                    }
+        Ok(rx)
+    }
 
-                                    if let Some(choice) = chunk.choices.first() {
-                                        // Handle text content
-                                        if let Some(ref content) = choice.delta.content {
-                                            if !content.is_empty() {
-                                                // Send content_block_start if not sent
-                                                if !sent_content_block_start {
-                                                    sent_content_block_start = true;
-                                                    let _ = tx
-                                                        .send(Ok(StreamEvent::ContentBlockStart {
-                                                            index: content_index,
-                                                            content_block: StreamContentBlock::Text {
-                                                                text: String::new(),
-                                                            },
-                                                        }))
-                                                        .await;
-                                                }
+    /// Stream from Gemini's native streamGenerateContent endpoint and convert to Anthropic format
+    async fn stream_gemini_request(
+        &self,
+        request: &OpenAIRequest,
+        mapped: &super::convert::MappedModel,
+        original_model: &str,
+        input_tokens: u32,
+        limits: RequestLimits,
+    ) -> Result<mpsc::Receiver<Result<StreamEvent, ApiError>>, ApiError> {
+        let api_key = self
+            .get_api_key(&mapped.provider)
+            .ok_or_else(|| ApiError::MissingApiKey(mapped.provider.clone()))?;
 
-                                                // Send text delta
-                                                let _ = tx
-                                                    .send(Ok(StreamEvent::ContentBlockDelta {
-                                                        index: content_index,
-                                                        delta: StreamDelta::TextDelta {
-                                                            text: content.clone(),
-                                                        },
-                                                    }))
-                                                    .await;
-                                            }
-                                        }
+        // Key in a header rather than the URL
+        let base_url = self.get_base_url("gemini");
+        let url = format!("{}/models/{}:streamGenerateContent?alt=sse", base_url, mapped.model);
+        let req = self
+            .client
+            .post(&url)
+            .header("x-goog-api-key", api_key)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "text/event-stream")
+            .json(&convert_openai_to_gemini(request));
+        let response = self.send_before_deadline(req, &limits).await?;
 
-                                        // Handle tool calls
-                                        if let Some(ref tool_calls) = choice.delta.tool_calls {
-                                            for tool_call in tool_calls {
-                                                let tool_idx = tool_call.index.unwrap_or(0);
-
-                                                // New tool call
-                                                if current_tool_index != Some(tool_idx) {
-                                                    // Close previous text block if needed
-                                                    if sent_content_block_start && current_tool_index.is_none() {
-                                                        let _ = tx
-                                                            .send(Ok(StreamEvent::ContentBlockStop {
-                                                                index: content_index,
-                                                            }))
-                                                            .await;
-                                                        content_index += 1;
-                                                    }
-
-                                                    current_tool_index = Some(tool_idx);
-
-                                                    // Send tool_use content_block_start
-                                                    if let Some(ref function) = tool_call.function {
-                                                        let _ = tx
-                                                            .send(Ok(StreamEvent::ContentBlockStart {
-                                                                index: content_index,
-                                                                content_block: StreamContentBlock::ToolUse {
-                                                                    id: tool_call.id.clone().unwrap_or_else(|| {
-                                                                        format!("toolu_{}", uuid::Uuid::new_v4().simple())
-                                                                    }),
-                                                                    name: function.name.clone().unwrap_or_default(),
-                                                                    input: json!({}),
-                                                                },
-                                                            }))
-                                                            .await;
-                                                    }
-                                                }
+        let (tx, rx) = mpsc::channel(100);
+        tokio::spawn(pump_gemini_stream(
+            response.bytes_stream(),
+            tx,
+            original_model.to_string(),
+            request.stop.clone().unwrap_or_default(),
+            input_tokens,
+            limits,
+        ));
 
-                                                // Send tool call arguments as delta
-                                                if let Some(ref function) = tool_call.function {
-                                                    if let Some(ref args) = function.arguments {
-                                                        if !args.is_empty() {
-                                                            let _ = tx
-                                                                .send(Ok(StreamEvent::ContentBlockDelta {
-                                                                    index: content_index,
-                                                                    delta: StreamDelta::InputJsonDelta {
-                                                                        partial_json: args.clone(),
-                                                                    },
-                                                                }))
-                                                                .await;
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
+        Ok(rx)
+    }
+}
 
-                                        // Handle finish_reason
-                                        if let Some(ref finish_reason) = choice.finish_reason {
-                                            // Close any open content blocks
-                                            let _ = tx
-                                                .send(Ok(StreamEvent::ContentBlockStop {
-                                                    index: content_index,
-                                                }))
-                                                .await;
+/// Delay requested by a `Retry-After` header (seconds form), capped at one minute
+fn retry_after(headers: &header::HeaderMap) -> Option<Duration> {
+    let seconds: u64 = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds.min(60)))
+}
 
-                                            // Map finish reason
-                                            let stop_reason = match finish_reason.as_str() {
-                                                "stop" => Some(StopReason::EndTurn),
-                                                "length" => Some(StopReason::MaxTokens),
-                                                "tool_calls" => Some(StopReason::ToolUse),
-                                                _ => Some(StopReason::EndTurn),
-                                            };
+/// Result of checking an API key against a provider
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct KeyStatus {
+    pub valid: bool,
+    /// Permissions attached to the key, when the provider reports them
+    pub scopes: Option<Vec<String>>,
+    pub error: Option<String>,
+}
 
-                                            // Send message_delta with stop reason
-                                            let _ = tx
-                                                .send(Ok(StreamEvent::MessageDelta {
-                                                    delta: MessageDeltaData {
-                                                        stop_reason,
-                                                        stop_sequence: None,
-                                                    },
-                                                    usage: StreamUsage {
-                                                        output_tokens: chunk.usage.as_ref().map(|u| u.completion_tokens).unwrap_or(0),
-                                                        ..Default::default()
-                                                    },
-                                                }))
-                                                .await;
+/// Check an API key by listing models, which is free on every supported provider
+pub async fn verify_api_key(provider: &str, api_key: &str, base_url: Option<&str>) -> KeyStatus {
+    let redact = |text: &str| {
+        if api_key.is_empty() {
+            text.to_string()
+        } else {
+            text.replace(api_key, "[REDACTED]")
+        }
+    };
 
-                                            // Send message_stop
-                                            let _ = tx.send(Ok(StreamEvent::MessageStop)).await;
-                                            return;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        let _ = tx.send(Err(ApiError::StreamError(e.to_string()))).await;
-                        return;
-                    }
-                }
-            }
+    let invalid = |error: String| KeyStatus {
+        valid: false,
+        scopes: None,
+        error: Some(error),
+    };
 
-            // Send final message_stop if we haven't yet
-            let _ = tx.send(Ok(StreamEvent::MessageStop)).await;
-        });
+    if api_key.trim().is_empty() {
+        return invalid("API key is empty".to_string());
+    }
 
-        Ok(rx)
+    let client = match Client::builder()
+        .timeout(Duration::from_secs(15))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => return invalid(format!("Failed to create HTTP client: {}", e)),
+    };
+
+    let request = match provider.parse::<Provider>() {
+        Ok(Provider::Anthropic) => {
+            let base = base_url.unwrap_or("https://api.anthropic.com/v1");
+            client
+                .get(format!("{}/models", base.trim_end_matches('/')))
+                .header("x-api-key", api_key)
+                .header("anthropic-version", "2023-06-01")
+        }
+        Ok(Provider::OpenAI) => {
+            let base = base_url.unwrap_or("https://api.openai.com/v1");
+            client
+                .get(format!("{}/models", base.trim_end_matches('/')))
+                .header(header::AUTHORIZATION, format!("Bearer {}", api_key))
+        }
+        Ok(Provider::Google) => {
+            let base = base_url.unwrap_or("https://generativelanguage.googleapis.com/v1beta");
+            // Header instead of ?key= so the key never ends up in a URL
+            client
+                .get(format!("{}/models", base.trim_end_matches('/')))
+                .header("x-goog-api-key", api_key)
+        }
+        Ok(Provider::Azure) => {
+            // The resource endpoint is required; there is no global Azure URL
+            let Some(base) = base_url else {
+                return invalid("Azure OpenAI requires an endpoint URL".to_string());
+            };
+            client
+                .get(format!("{}/openai/models", base.trim_end_matches('/')))
+                .query(&[("api-version", DEFAULT_AZURE_API_VERSION)])
+                .header("api-key", api_key)
+        }
+        Err(e) => return invalid(e),
+    };
+
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => return invalid(redact(&format!("Request failed: {}", e))),
+    };
+
+    let status = response.status();
+    if status.is_success() {
+        return KeyStatus {
+            valid: true,
+            scopes: None,
+            error: None,
+        };
     }
+
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|v| {
+            v.pointer("/error/message")
+                .and_then(|m| m.as_str())
+                .map(|m| m.to_string())
+        })
+        .unwrap_or(body);
+
+    let error = match status.as_u16() {
+        401 => format!("API key was rejected: {}", message),
+        403 => format!("API key lacks permission: {}", message),
+        code => format!("Unexpected response ({}): {}", code, message),
+    };
+
+    invalid(redact(&error))
 }
 
 /// OpenAI streaming chunk
 #[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
 struct OpenAIStreamChunk {
     id: Option<String>,
     choices: Vec<OpenAIStreamChoice>,
@@ -533,6 +684,7 @@ struct OpenAIStreamChunk {
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
 struct OpenAIStreamChoice {
     index: u32,
     delta: OpenAIStreamDelta,
@@ -540,6 +692,7 @@ struct OpenAIStreamChoice {
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
 struct OpenAIStreamDelta {
     role: Option<String>,
     content: Option<String>,
@@ -547,6 +700,7 @@ struct OpenAIStreamDelta {
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
 struct OpenAIStreamToolCall {
     index: Option<u32>,
     id: Option<String>,
@@ -562,22 +716,604 @@ struct OpenAIStreamFunction {
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
 struct OpenAIStreamUsage {
     prompt_tokens: u32,
     completion_tokens: u32,
     total_tokens: u32,
 }
 
+/// Forward a native Anthropic SSE byte stream as parsed events
+async fn pump_anthropic_stream<S, B, E>(
+    mut stream: S,
+    tx: mpsc::Sender<Result<StreamEvent, ApiError>>,
+    limits: RequestLimits,
+) where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let mut buffer = String::new();
+
+    loop {
+        let chunk = match next_chunk(&mut stream, &limits).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        match chunk {
+            Ok(bytes) => {
+                buffer.push_str(&String::from_utf8_lossy(bytes.as_ref()));
+
+                // Process complete SSE events
+                while let Some(pos) = buffer.find("\n\n") {
+                    let event_str = buffer[..pos].to_string();
+                    buffer = buffer[pos + 2..].to_string();
+
+                    if let Some(event) = parse_anthropic_sse(&event_str) {
+                        if tx.send(Ok(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                let _ = tx.send(Err(ApiError::StreamError(e.to_string()))).await;
+                return;
+            }
+        }
+    }
+}
+
+/// Wait for the next upstream chunk, honouring the idle window and the overall deadline
+async fn next_chunk<S>(stream: &mut S, limits: &RequestLimits) -> Result<Option<S::Item>, ApiError>
+where
+    S: Stream + Unpin,
+{
+    let idle = async {
+        match limits.idle_timeout {
+            Some(idle) => tokio::time::timeout(idle, stream.next()).await.map_err(|_| {
+                ApiError::Timeout(format!("no data from upstream for {}ms", idle.as_millis()))
+            }),
+            None => Ok(stream.next().await),
+        }
+    };
+
+    match limits.deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, idle)
+            .await
+            .map_err(|_| ApiError::Timeout("request deadline exceeded".to_string()))?,
+        None => idle.await,
+    }
+}
+
+/// Convert an OpenAI-format SSE byte stream into Anthropic stream events
+async fn pump_openai_stream<S, B, E>(
+    mut stream: S,
+    tx: mpsc::Sender<Result<StreamEvent, ApiError>>,
+    model: String,
+    stop_sequences: Vec<String>,
+    input_tokens: u32,
+    limits: RequestLimits,
+) where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let message_id = generate_message_id();
+    let mut buffer = String::new();
+    let mut sent_message_start = false;
+    // Only text blocks stay open across chunks
+    let mut text_open = false;
+    // Tool calls in first-seen order; parallel calls interleave their argument deltas, so
+    // each one is buffered and emitted as a whole block once no more deltas can arrive
+    let mut pending_tools: Vec<(u32, PendingToolCall)> = Vec::new();
+    let mut content_index = 0u32;
+    // Set once finish_reason arrives; the stream ends when usage or [DONE] follows
+    let mut pending_stop: Option<(Option<StopReason>, Option<String>)> = None;
+    // Replaced by the upstream's prompt_tokens when its usage chunk arrives
+    let mut usage = StreamUsage { input_tokens, ..Default::default() };
+    // End of the streamed text, long enough to check for a stop sequence
+    let tail_len = stop_sequences.iter().map(|s| s.len()).max().unwrap_or(0);
+    let mut text_tail = String::new();
+
+    loop {
+        let chunk = match next_chunk(&mut stream, &limits).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        match chunk {
+            Ok(bytes) => {
+                buffer.push_str(&String::from_utf8_lossy(bytes.as_ref()));
+
+                // Process complete SSE events
+                while let Some(pos) = buffer.find("\n\n") {
+                    let event_str = buffer[..pos].to_string();
+                    buffer = buffer[pos + 2..].to_string();
+
+                    // Parse SSE event
+                    let data_line = event_str
+                        .lines()
+                        .find(|l| l.starts_with("data: "))
+                        .map(|l| &l[6..]);
+
+                    if let Some(data) = data_line {
+                        if data == "[DONE]" {
+                            send_message_start(&tx, &mut sent_message_start, &message_id, &model, &usage).await;
+                            flush_tool_calls(&tx, &mut pending_tools, &mut content_index).await;
+                            match pending_stop.take() {
+                                Some((stop_reason, stop_sequence)) => {
+                                    finish_openai_stream(&tx, stop_reason, stop_sequence, usage).await
+                                }
+                                None => {
+                                    let _ = tx.send(Ok(StreamEvent::MessageStop)).await;
+                                }
+                            }
+                            return;
+                        }
+
+                        // Parse OpenAI chunk
+                        if let Ok(chunk) = serde_json::from_str::<OpenAIStreamChunk>(data) {
+                            send_message_start(&tx, &mut sent_message_start, &message_id, &model, &usage).await;
+
+                            if let Some(choice) = chunk.choices.first() {
+                                // Handle text content
+                                if let Some(ref content) = choice.delta.content {
+                                    if !content.is_empty() {
+                                        // Open a text block, emitting any buffered tool calls first
+                                        if !text_open {
+                                            flush_tool_calls(&tx, &mut pending_tools, &mut content_index)
+                                                .await;
+                                            text_open = true;
+                                            let _ = tx
+                                                .send(Ok(StreamEvent::ContentBlockStart {
+                                                    index: content_index,
+                                                    content_block: StreamContentBlock::Text {
+                                                        text: String::new(),
+                                                    },
+                                                }))
+                                                .await;
+                                        }
+
+                                        push_text_tail(&mut text_tail, content, tail_len);
+
+                                        // Send text delta
+                                        let _ = tx
+                                            .send(Ok(StreamEvent::ContentBlockDelta {
+                                                index: content_index,
+                                                delta: StreamDelta::TextDelta {
+                                                    text: content.clone(),
+                                                },
+                                            }))
+                                            .await;
+                                    }
+                                }
+
+                                // Handle tool calls
+                                if let Some(ref tool_calls) = choice.delta.tool_calls {
+                                    for tool_call in tool_calls {
+                                        let tool_idx = tool_call.index.unwrap_or(0);
+
+                                        // Text can't continue once a tool call starts
+                                        if text_open {
+                                            text_open = false;
+                                            let _ = tx
+                                                .send(Ok(StreamEvent::ContentBlockStop {
+                                                    index: content_index,
+                                                }))
+                                                .await;
+                                            content_index += 1;
+                                        }
+
+                                        let position = match pending_tools.iter().position(|(i, _)| *i == tool_idx) {
+                                            Some(position) => position,
+                                            None => {
+                                                pending_tools.push((tool_idx, PendingToolCall::default()));
+                                                pending_tools.len() - 1
+                                            }
+                                        };
+                                        let pending = &mut pending_tools[position].1;
+                                        if let Some(ref id) = tool_call.id {
+                                            pending.id.get_or_insert_with(|| id.clone());
+                                        }
+                                        if let Some(ref function) = tool_call.function {
+                                            if let Some(ref name) = function.name {
+                                                if pending.name.is_empty() {
+                                                    pending.name = name.clone();
+                                                }
+                                            }
+                                            if let Some(ref args) = function.arguments {
+                                                pending.arguments.push_str(args);
+                                            }
+                                        }
+                                    }
+                                }
+
+                                // Handle finish_reason
+                                if let Some(ref finish_reason) = choice.finish_reason {
+                                    // Close any open content block
+                                    if text_open {
+                                        text_open = false;
+                                        let _ = tx
+                                            .send(Ok(StreamEvent::ContentBlockStop {
+                                                index: content_index,
+                                            }))
+                                            .await;
+                                        content_index += 1;
+                                    }
+                                    flush_tool_calls(&tx, &mut pending_tools, &mut content_index).await;
+
+                                    // Map finish reason
+                                    let stop_reason = match finish_reason.as_str() {
+                                        "stop" => Some(StopReason::EndTurn),
+                                        "length" => Some(StopReason::MaxTokens),
+                                        "tool_calls" => Some(StopReason::ToolUse),
+                                        _ => Some(StopReason::EndTurn),
+                                    };
+
+                                    // A plain "stop" may really be a stop-sequence hit
+                                    let stop_sequence = matches!(stop_reason, Some(StopReason::EndTurn))
+                                        .then(|| matched_stop_sequence(&text_tail, &stop_sequences).cloned())
+                                        .flatten();
+                                    let stop_reason = if stop_sequence.is_some() {
+                                        Some(StopReason::StopSequence)
+                                    } else {
+                                        stop_reason
+                                    };
+
+                                    // Don't stop yet: with include_usage the usage chunk comes after this one
+                                    pending_stop = Some((stop_reason, stop_sequence));
+                                }
+                            }
+
+                            if let Some(ref chunk_usage) = chunk.usage {
+                                usage.input_tokens = chunk_usage.prompt_tokens;
+                                usage.output_tokens = chunk_usage.completion_tokens;
+                                if let Some((stop_reason, stop_sequence)) = pending_stop.take() {
+                                    finish_openai_stream(&tx, stop_reason, stop_sequence, usage).await;
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                let _ = tx.send(Err(ApiError::StreamError(e.to_string()))).await;
+                return;
+            }
+        }
+    }
+
+    // Send the final events if we haven't yet
+    send_message_start(&tx, &mut sent_message_start, &message_id, &model, &usage).await;
+    if text_open {
+        let _ = tx.send(Ok(StreamEvent::ContentBlockStop { index: content_index })).await;
+        content_index += 1;
+    }
+    flush_tool_calls(&tx, &mut pending_tools, &mut content_index).await;
+    match pending_stop {
+        Some((stop_reason, stop_sequence)) => finish_openai_stream(&tx, stop_reason, stop_sequence, usage).await,
+        None => {
+            let _ = tx.send(Ok(StreamEvent::MessageStop)).await;
+        }
+    }
+}
+
+/// Convert a Gemini streamGenerateContent SSE byte stream into Anthropic stream events.
+/// Each chunk is a partial `GeminiResponse`; function calls arrive whole, and the stream
+/// ends when the connection closes rather than with a `[DONE]` marker.
+async fn pump_gemini_stream<S, B, E>(
+    mut stream: S,
+    tx: mpsc::Sender<Result<StreamEvent, ApiError>>,
+    model: String,
+    stop_sequences: Vec<String>,
+    input_tokens: u32,
+    limits: RequestLimits,
+) where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let message_id = generate_message_id();
+    let mut buffer = String::new();
+    let mut sent_message_start = false;
+    // Only text blocks stay open across chunks
+    let mut text_open = false;
+    let mut content_index = 0u32;
+    let mut saw_tool_call = false;
+    let mut finish_reason: Option<String> = None;
+    let mut usage = StreamUsage { input_tokens, ..Default::default() };
+    let tail_len = stop_sequences.iter().map(|s| s.len()).max().unwrap_or(0);
+    let mut text_tail = String::new();
+
+    loop {
+        let chunk = match next_chunk(&mut stream, &limits).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        let bytes = match chunk {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                let _ = tx.send(Err(ApiError::StreamError(e.to_string()))).await;
+                return;
+            }
+        };
+        // Gemini separates events with CRLF pairs
+        buffer.push_str(&String::from_utf8_lossy(bytes.as_ref()).replace("\r\n", "\n"));
+
+        while let Some(pos) = buffer.find("\n\n") {
+            let event_str = buffer[..pos].to_string();
+            buffer = buffer[pos + 2..].to_string();
+
+            let Some(data) = event_str.lines().find_map(|l| l.strip_prefix("data: ")) else {
+                continue;
+            };
+            let chunk = match serde_json::from_str::<GeminiResponse>(data) {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    log::warn!("Skipping unparseable Gemini chunk: {}", e);
+                    continue;
+                }
+            };
+
+            send_message_start(&tx, &mut sent_message_start, &message_id, &model, &usage).await;
+
+            if let Some(ref chunk_usage) = chunk.usage_metadata {
+                if chunk_usage.prompt_token_count > 0 {
+                    usage.input_tokens = chunk_usage.prompt_token_count;
+                }
+                usage.output_tokens = chunk_usage.candidates_token_count;
+            }
+
+            let Some(candidate) = chunk.candidates.first() else {
+                continue;
+            };
+            let parts = candidate.content.as_ref().map(|c| c.parts.as_slice()).unwrap_or_default();
+
+            for part in parts {
+                if let Some(text) = part.text.as_deref().filter(|t| !t.is_empty()) {
+                    if !text_open {
+                        text_open = true;
+                        let _ = tx
+                            .send(Ok(StreamEvent::ContentBlockStart {
+                                index: content_index,
+                                content_block: StreamContentBlock::Text { text: String::new() },
+                            }))
+                            .await;
+                    }
+                    push_text_tail(&mut text_tail, text, tail_len);
+                    let _ = tx
+                        .send(Ok(StreamEvent::ContentBlockDelta {
+                            index: content_index,
+                            delta: StreamDelta::TextDelta { text: text.to_string() },
+                        }))
+                        .await;
+                }
+
+                if let Some(ref call) = part.function_call {
+                    if text_open {
+                        text_open = false;
+                        let _ = tx.send(Ok(StreamEvent::ContentBlockStop { index: content_index })).await;
+                        content_index += 1;
+                    }
+                    saw_tool_call = true;
+
+                    // The whole call arrives at once: start, one input delta, stop
+                    let _ = tx
+                        .send(Ok(StreamEvent::ContentBlockStart {
+                            index: content_index,
+                            content_block: StreamContentBlock::ToolUse {
+                                id: generate_tool_id(),
+                                name: call.name.clone(),
+                                input: json!({}),
+                            },
+                        }))
+                        .await;
+                    let _ = tx
+                        .send(Ok(StreamEvent::ContentBlockDelta {
+                            index: content_index,
+                            delta: StreamDelta::InputJsonDelta { partial_json: call.args.to_string() },
+                        }))
+                        .await;
+                    let _ = tx.send(Ok(StreamEvent::ContentBlockStop { index: content_index })).await;
+                    content_index += 1;
+                }
+            }
+
+            if let Some(ref reason) = candidate.finish_reason {
+                finish_reason = Some(reason.clone());
+            }
+        }
+    }
+
+    // An empty stream still needs message_start before message_stop
+    send_message_start(&tx, &mut sent_message_start, &message_id, &model, &usage).await;
+    if text_open {
+        let _ = tx.send(Ok(StreamEvent::ContentBlockStop { index: content_index })).await;
+    }
+
+    let Some(reason) = finish_reason else {
+        let _ = tx.send(Ok(StreamEvent::MessageStop)).await;
+        return;
+    };
+    let stop_reason = match gemini_finish_reason(&reason, saw_tool_call).as_str() {
+        "length" => StopReason::MaxTokens,
+        "tool_calls" => StopReason::ToolUse,
+        _ => StopReason::EndTurn,
+    };
+    let stop_sequence = matches!(stop_reason, StopReason::EndTurn)
+        .then(|| matched_stop_sequence(&text_tail, &stop_sequences).cloned())
+        .flatten();
+    let stop_reason = if stop_sequence.is_some() { StopReason::StopSequence } else { stop_reason };
+    finish_openai_stream(&tx, Some(stop_reason), stop_sequence, usage).await;
+}
+
+/// Keep the last `tail_len`-ish bytes of streamed text for stop-sequence matching
+fn push_text_tail(tail: &mut String, text: &str, tail_len: usize) {
+    if tail_len == 0 {
+        return;
+    }
+    tail.push_str(text);
+    if tail.len() > tail_len * 2 {
+        let mut cut = tail.len() - tail_len;
+        while !tail.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        tail.drain(..cut);
+    }
+}
+
+/// An OpenAI tool call whose arguments are still streaming in
+#[derive(Debug, Default)]
+struct PendingToolCall {
+    id: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+/// Emit each buffered tool call as a complete tool_use block: start, arguments, stop
+async fn flush_tool_calls(
+    tx: &mpsc::Sender<Result<StreamEvent, ApiError>>,
+    pending_tools: &mut Vec<(u32, PendingToolCall)>,
+    content_index: &mut u32,
+) {
+    for (_, tool) in pending_tools.drain(..) {
+        let _ = tx
+            .send(Ok(StreamEvent::ContentBlockStart {
+                index: *content_index,
+                content_block: StreamContentBlock::ToolUse {
+                    id: tool.id.unwrap_or_else(generate_tool_id),
+                    name: tool.name,
+                    input: json!({}),
+                },
+            }))
+            .await;
+        if !tool.arguments.is_empty() {
+            let _ = tx
+                .send(Ok(StreamEvent::ContentBlockDelta {
+                    index: *content_index,
+                    delta: StreamDelta::InputJsonDelta {
+                        partial_json: tool.arguments,
+                    },
+                }))
+                .await;
+        }
+        let _ = tx.send(Ok(StreamEvent::ContentBlockStop { index: *content_index })).await;
+        *content_index += 1;
+    }
+}
+
+/// Emit message_start unless it has been sent already
+async fn send_message_start(
+    tx: &mpsc::Sender<Result<StreamEvent, ApiError>>,
+    sent: &mut bool,
+    message_id: &str,
+    model: &str,
+    usage: &StreamUsage,
+) {
+    if std::mem::replace(sent, true) {
+        return;
+    }
+    let _ = tx
+        .send(Ok(StreamEvent::MessageStart {
+            message: StreamMessage {
+                id: message_id.to_string(),
+                message_type: "message".to_string(),
+                role: "assistant".to_string(),
+                model: model.to_string(),
+                content: vec![],
+                stop_reason: None,
+                stop_sequence: None,
+                usage: usage.clone(),
+            },
+        }))
+        .await;
+}
+
+/// Emit the closing message_delta (stop reason + usage) and message_stop
+async fn finish_openai_stream(
+    tx: &mpsc::Sender<Result<StreamEvent, ApiError>>,
+    stop_reason: Option<StopReason>,
+    stop_sequence: Option<String>,
+    usage: StreamUsage,
+) {
+    let _ = tx
+        .send(Ok(StreamEvent::MessageDelta {
+            delta: MessageDeltaData {
+                stop_reason,
+                stop_sequence,
+            },
+            usage,
+        }))
+        .await;
+    let _ = tx.send(Ok(StreamEvent::MessageStop)).await;
+}
+
+/// Known output-token caps for models whose limit is below the default clamp
+fn known_max_output_tokens(model: &str) -> Option<u32> {
+    const CAPS: &[(&str, u32)] = &[
+        ("gpt-3.5-turbo", 4096),
+        ("gpt-4-turbo", 4096),
+        ("gpt-4-32k", 4096),
+        ("gpt-4", 8192),
+    ];
+    CAPS.iter()
+        .find(|(prefix, _)| model.starts_with(prefix) && !model.starts_with("gpt-4o") && !model.starts_with("gpt-4.1"))
+        .map(|(_, cap)| *cap)
+}
+
+/// If a 400 body is a "max_tokens too large" error, return the largest allowed value
+fn max_tokens_limit_from_error(body: &str, model: &str) -> Option<u32> {
+    static LIMIT_RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+
+    let parsed: Option<serde_json::Value> = serde_json::from_str(body).ok();
+    let message = parsed
+        .as_ref()
+        .and_then(|v| v.pointer("/error/message").and_then(|m| m.as_str()))
+        .unwrap_or(body);
+    let param = parsed
+        .as_ref()
+        .and_then(|v| v.pointer("/error/param").and_then(|p| p.as_str()))
+        .unwrap_or("");
+
+    let lower = message.to_lowercase();
+    let about_max_tokens = param.contains("max_tokens")
+        || param.contains("max_completion_tokens")
+        || lower.contains("max_tokens")
+        || lower.contains("max_completion_tokens")
+        || lower.contains("max output tokens");
+    if !about_max_tokens {
+        return None;
+    }
+
+    let re = LIMIT_RE.get_or_init(|| {
+        regex::Regex::new(r"(?i)(?:at most|maximum (?:value )?(?:is|of)|less than or equal to|<=)\s*`?(\d+)")
+            .expect("valid regex")
+    });
+    re.captures(message)
+        .and_then(|c| c[1].parse().ok())
+        .or_else(|| known_max_output_tokens(model))
+}
+
 /// Parse Anthropic SSE event
 fn parse_anthropic_sse(event_str: &str) -> Option<StreamEvent> {
-    let mut event_type = None;
     let mut data = None;
 
     for line in event_str.lines() {
-        if line.starts_with("event: ") {
-            event_type = Some(&line[7..]);
-        } else if line.starts_with("data: ") {
-            data = Some(&line[6..]);
+        if let Some(d) = line.strip_prefix("data: ") {
+            data = Some(d);
         }
     }
 
@@ -597,6 +1333,7 @@ pub enum ApiError {
     RequestFailed(String),
     ParseError(String),
     StreamError(String),
+    Timeout(String),
     UpstreamError { status: u16, message: String },
 }
 
@@ -609,6 +1346,7 @@ impl std::fmt::Display for ApiError {
             ApiError::RequestFailed(msg) => write!(f, "Request failed: {}", msg),
             ApiError::ParseError(msg) => write!(f, "Parse error: {}", msg),
             ApiError::StreamError(msg) => write!(f, "Stream error: {}", msg),
+            ApiError::Timeout(msg) => write!(f, "Timeout: {}", msg),
             ApiError::UpstreamError { status, message } => {
                 write!(f, "Upstream error ({}): {}", status, message)
             }
@@ -626,3 +1364,657 @@ impl serde::Serialize for ApiError {
         serializer.serialize_str(&self.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    #[tokio::test]
+    async fn test_stalled_stream_hits_idle_timeout() {
+        let first = "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":null}]}\n\n";
+        // One chunk, then the upstream goes silent without closing the connection
+        let upstream = stream::iter(vec![Ok::<_, std::io::Error>(first.as_bytes().to_vec())])
+            .chain(stream::pending());
+
+        let limits = RequestLimits {
+            deadline: None,
+            idle_timeout: Some(Duration::from_millis(50)),
+        };
+        let (tx, mut rx) = mpsc::channel(16);
+        let pump = tokio::spawn(pump_openai_stream(Box::pin(upstream), tx, "claude-3-sonnet".to_string(), Vec::new(), 0, limits));
+
+        let mut events = Vec::new();
+        while let Some(event) = tokio::time::timeout(Duration::from_secs(2), rx.recv())
+            .await
+            .expect("stream should end instead of hanging")
+        {
+            events.push(event);
+        }
+        pump.await.unwrap();
+
+        assert!(matches!(events.first(), Some(Ok(StreamEvent::MessageStart { .. }))));
+        assert!(matches!(events.last(), Some(Err(ApiError::Timeout(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_usage_after_finish_reason_is_reported() {
+        let chunks = [
+            r#"data: {"id":"c1","choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":null}]}"#,
+            r#"data: {"id":"c1","choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+            r#"data: {"id":"c1","choices":[],"usage":{"prompt_tokens":12,"completion_tokens":5,"total_tokens":17}}"#,
+            "data: [DONE]",
+        ];
+        let upstream = stream::iter(
+            chunks
+                .iter()
+                .map(|c| Ok::<_, std::io::Error>(format!("{}\n\n", c).into_bytes()))
+                .collect::<Vec<_>>(),
+        );
+
+        let (tx, mut rx) = mpsc::channel(16);
+        pump_openai_stream(upstream, tx, "claude-3-sonnet".to_string(), Vec::new(), 0, RequestLimits::default()).await;
+
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event.unwrap());
+        }
+
+        let deltas: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                StreamEvent::MessageDelta { delta, usage } => Some((delta.stop_reason, usage.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(deltas.len(), 1);
+        assert_eq!(deltas[0].0, Some(StopReason::EndTurn));
+        assert_eq!(deltas[0].1.input_tokens, 12);
+        assert_eq!(deltas[0].1.output_tokens, 5);
+        assert!(matches!(events.last(), Some(StreamEvent::MessageStop)));
+        assert_eq!(events.iter().filter(|e| matches!(e, StreamEvent::MessageStop)).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_thinking_stream_passes_through() {
+        // Captured from a native Anthropic stream with extended thinking enabled
+        let capture = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_01\",\"type\":\"message\",\"role\":\"assistant\",\"model\":\"claude-3-7-sonnet-20250219\",\"content\":[],\"stop_reason\":null,\"stop_sequence\":null,\"usage\":{\"input_tokens\":36,\"output_tokens\":4}}}\n\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"thinking\",\"thinking\":\"\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"Let me work this out. 27 * 453\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\" = 12231\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"signature_delta\",\"signature\":\"EqQBCgIYAhIM1gbcDa9GJwZA2b3hGgxBdjrkzLoky3dl1pkiMOYds\"}}\n\n",
+            "event: content_block_stop\n",
+            "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"27 * 453 = 12,231\"}}\n\n",
+            "event: content_block_stop\n",
+            "data: {\"type\":\"content_block_stop\",\"index\":1}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":52}}\n\n",
+            "event: message_stop\n",
+            "data: {\"type\":\"message_stop\"}\n\n",
+        );
+        let upstream = stream::iter(vec![Ok::<_, std::io::Error>(capture.as_bytes().to_vec())]);
+
+        let (tx, mut rx) = mpsc::channel(32);
+        pump_anthropic_stream(upstream, tx, RequestLimits::default()).await;
+
+        let mut acc = super::super::transcript::StreamAccumulator::default();
+        let mut thinking_deltas = 0;
+        while let Some(event) = rx.recv().await {
+            let event = event.unwrap();
+            if let StreamEvent::ContentBlockDelta { delta: StreamDelta::ThinkingDelta { .. }, .. } = &event {
+                thinking_deltas += 1;
+            }
+            // Events must serialize back to the same wire types
+            let wire = serde_json::to_value(&event).unwrap();
+            if wire["type"] == "content_block_delta" && wire["index"] == 0 {
+                assert!(matches!(wire["delta"]["type"].as_str(), Some("thinking_delta" | "signature_delta")));
+            }
+            acc.push(&event);
+        }
+        assert_eq!(thinking_deltas, 2);
+
+        let response = acc.finish();
+        assert_eq!(response.content.len(), 2);
+        match &response.content[0] {
+            ResponseContentBlock::Thinking { thinking, signature } => {
+                assert_eq!(thinking, "Let me work this out. 27 * 453 = 12231");
+                assert!(signature.as_deref().is_some_and(|s| s.starts_with("EqQB")));
+            }
+            other => panic!("unexpected block: {:?}", other),
+        }
+        assert!(matches!(&response.content[1], ResponseContentBlock::Text { text } if text == "27 * 453 = 12,231"));
+    }
+
+    #[tokio::test]
+    async fn test_message_start_reports_input_tokens() {
+        use axum::{routing::post, Router};
+
+        let body = concat!(
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let app = Router::new().route(
+            "/chat/completions",
+            post(move || async move { ([(header::CONTENT_TYPE, "text/event-stream")], body) }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = ApiClient::new(ProxyConfig {
+            openai_api_key: Some("sk-test".to_string()),
+            openai_base_url: Some(format!("http://{}", addr)),
+            ..Default::default()
+        });
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "system": "You are a careful assistant.",
+            "messages": [{ "role": "user", "content": "Explain what a monad is in one sentence." }]
+        }))
+        .unwrap();
+
+        let mut rx = client.send_message_streaming(&request).await.unwrap();
+        match rx.recv().await {
+            Some(Ok(StreamEvent::MessageStart { message })) => assert!(message.usage.input_tokens > 5),
+            other => panic!("unexpected first event: {:?}", other),
+        }
+
+        // Without a usage chunk from upstream the estimate is carried into message_delta
+        let mut delta_usage = None;
+        while let Some(event) = rx.recv().await {
+            if let Ok(StreamEvent::MessageDelta { usage, .. }) = event {
+                delta_usage = Some(usage);
+            }
+        }
+        assert!(delta_usage.is_some_and(|u| u.input_tokens > 5));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_stop_sequence_is_reported() {
+        let chunks = [
+            r#"data: {"id":"c1","choices":[{"index":0,"delta":{"content":"All done. ST"},"finish_reason":null}]}"#,
+            r#"data: {"id":"c1","choices":[{"index":0,"delta":{"content":"OP"},"finish_reason":null}]}"#,
+            r#"data: {"id":"c1","choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+            "data: [DONE]",
+        ];
+        let upstream = stream::iter(
+            chunks
+                .iter()
+                .map(|c| Ok::<_, std::io::Error>(format!("{}\n\n", c).into_bytes()))
+                .collect::<Vec<_>>(),
+        );
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let stops = vec!["STOP".to_string()];
+        pump_openai_stream(upstream, tx, "claude-3-sonnet".to_string(), stops, 0, RequestLimits::default()).await;
+
+        let mut delta = None;
+        while let Some(event) = rx.recv().await {
+            if let StreamEvent::MessageDelta { delta: d, .. } = event.unwrap() {
+                delta = Some(d);
+            }
+        }
+        let delta = delta.expect("message_delta should be sent");
+        assert_eq!(delta.stop_reason, Some(StopReason::StopSequence));
+        assert_eq!(delta.stop_sequence.as_deref(), Some("STOP"));
+    }
+
+    #[tokio::test]
+    async fn test_gemini_stream_converts_text_and_function_calls() {
+        // Captured from streamGenerateContent?alt=sse (ids and usage trimmed)
+        let chunks = [
+            r#"data: {"candidates": [{"content": {"parts": [{"text": "Let me check"}],"role": "model"},"index": 0}],"usageMetadata": {"promptTokenCount": 42,"totalTokenCount": 42}}"#,
+            r#"data: {"candidates": [{"content": {"parts": [{"text": " that file."}],"role": "model"},"index": 0}]}"#,
+            r#"data: {"candidates": [{"content": {"parts": [{"functionCall": {"name": "read_file","args": {"path": "src/main.rs"}}}],"role": "model"},"finishReason": "STOP","index": 0}],"usageMetadata": {"promptTokenCount": 42,"candidatesTokenCount": 17,"totalTokenCount": 59}}"#,
+        ];
+        let upstream = stream::iter(
+            chunks
+                .iter()
+                .map(|c| Ok::<_, std::io::Error>(format!("{}\r\n\r\n", c).into_bytes()))
+                .collect::<Vec<_>>(),
+        );
+
+        let (tx, mut rx) = mpsc::channel(32);
+        pump_gemini_stream(upstream, tx, "claude-3-sonnet".to_string(), vec![], 5, RequestLimits::default()).await;
+
+        let mut acc = super::super::transcript::StreamAccumulator::default();
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            let event = event.unwrap();
+            acc.push(&event);
+            events.push(event);
+        }
+        assert!(matches!(events.last(), Some(StreamEvent::MessageStop)));
+
+        let response = acc.finish();
+        assert_eq!(response.stop_reason, Some(StopReason::ToolUse));
+        assert_eq!(response.usage.input_tokens, 42);
+        assert_eq!(response.usage.output_tokens, 17);
+        assert_eq!(response.content.len(), 2);
+        match &response.content[0] {
+            ResponseContentBlock::Text { text } => assert_eq!(text, "Let me check that file."),
+            other => panic!("unexpected block: {:?}", other),
+        }
+        match &response.content[1] {
+            ResponseContentBlock::ToolUse { name, input, .. } => {
+                assert_eq!(name, "read_file");
+                assert_eq!(input, &json!({ "path": "src/main.rs" }));
+            }
+            other => panic!("unexpected block: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_streams_start_before_stopping() {
+        let empty = || stream::iter(Vec::<Result<Vec<u8>, std::io::Error>>::new());
+        let collect = |mut rx: mpsc::Receiver<Result<StreamEvent, ApiError>>| async move {
+            let mut events = Vec::new();
+            while let Some(event) = rx.recv().await {
+                events.push(event.unwrap());
+            }
+            events
+        };
+
+        let (tx, rx) = mpsc::channel(8);
+        pump_gemini_stream(empty(), tx, "claude-3-sonnet".to_string(), vec![], 5, RequestLimits::default()).await;
+        let events = collect(rx).await;
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], StreamEvent::MessageStart { .. }));
+        assert!(matches!(events[1], StreamEvent::MessageStop));
+
+        let (tx, rx) = mpsc::channel(8);
+        let done = stream::iter(vec![Ok::<_, std::io::Error>(b"data: [DONE]\n\n".to_vec())]);
+        pump_openai_stream(done, tx, "claude-3-sonnet".to_string(), vec![], 5, RequestLimits::default()).await;
+        let events = collect(rx).await;
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], StreamEvent::MessageStart { .. }));
+        assert!(matches!(events[1], StreamEvent::MessageStop));
+    }
+
+    #[tokio::test]
+    async fn test_max_tokens_error_retries_with_allowed_value() {
+        use axum::{routing::post, Json, Router};
+        use std::sync::{Arc, Mutex};
+
+        let seen: Arc<Mutex<Vec<u64>>> = Arc::default();
+        let recorded = Arc::clone(&seen);
+        let app = Router::new().route(
+            "/chat/completions",
+            post(move |Json(body): Json<serde_json::Value>| {
+                let recorded = Arc::clone(&recorded);
+                async move {
+                    let max_tokens = body["max_completion_tokens"].as_u64().unwrap_or(0);
+                    recorded.lock().unwrap().push(max_tokens);
+                    if max_tokens > 4096 {
+                        return (
+                            axum::http::StatusCode::BAD_REQUEST,
+                            Json(json!({
+                                "error": {
+                                    "message": format!("max_tokens is too large: {}. This model supports at most 4096 completion tokens, whereas you provided {}.", max_tokens, max_tokens),
+                                    "type": "invalid_request_error",
+                                    "param": "max_tokens"
+                                }
+                            })),
+                        );
+                    }
+                    (
+                        axum::http::StatusCode::OK,
+                        Json(json!({
+                            "id": "chatcmpl-1",
+                            "object": "chat.completion",
+                            "created": 0,
+                            "model": "gpt-4.1",
+                            "choices": [{
+                                "index": 0,
+                                "message": { "role": "assistant", "content": "ok" },
+                                "finish_reason": "stop"
+                            }],
+                            "usage": { "prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4 }
+                        })),
+                    )
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = ApiClient::new(ProxyConfig {
+            openai_api_key: Some("sk-test".to_string()),
+            openai_base_url: Some(format!("http://{}", addr)),
+            ..Default::default()
+        });
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 8000,
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+
+        let response = client.send_message(&request).await.expect("retry should succeed");
+        assert_eq!(response.usage.output_tokens, 1);
+        assert_eq!(*seen.lock().unwrap(), vec![8000, 4096]);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_request_is_retried() {
+        use axum::{routing::post, Json, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let app = Router::new().route(
+            "/chat/completions",
+            post(move || {
+                let counter = Arc::clone(&counter);
+                async move {
+                    if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                        return (
+                            axum::http::StatusCode::TOO_MANY_REQUESTS,
+                            [(header::RETRY_AFTER, "0")],
+                            Json(json!({ "error": { "message": "Rate limit reached" } })),
+                        );
+                    }
+                    (
+                        axum::http::StatusCode::OK,
+                        [(header::RETRY_AFTER, "0")],
+                        Json(json!({
+                            "id": "chatcmpl-1",
+                            "object": "chat.completion",
+                            "created": 0,
+                            "model": "gpt-4.1",
+                            "choices": [{
+                                "index": 0,
+                                "message": { "role": "assistant", "content": "ok" },
+                                "finish_reason": "stop"
+                            }],
+                            "usage": { "prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4 }
+                        })),
+                    )
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = ApiClient::new(ProxyConfig {
+            openai_api_key: Some("sk-test".to_string()),
+            openai_base_url: Some(format!("http://{}", addr)),
+            retry_max_attempts: 3,
+            retry_base_delay_ms: 1,
+            ..Default::default()
+        });
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+
+        let response = client.send_message(&request).await.expect("third attempt should succeed");
+        assert_eq!(response.usage.output_tokens, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // With a single attempt the 429 is surfaced
+        calls.store(0, Ordering::SeqCst);
+        let client = ApiClient::new(ProxyConfig { retry_max_attempts: 1, ..client.config().clone() });
+        match client.send_message(&request).await {
+            Err(ApiError::UpstreamError { status, .. }) => assert_eq!(status, 429),
+            other => panic!("unexpected result: {:?}", other.map(|r| r.id)),
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_is_applied() {
+        use axum::{routing::post, Router};
+
+        let app = Router::new().route(
+            "/chat/completions",
+            post(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "too late"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = ApiClient::new(ProxyConfig {
+            openai_api_key: Some("sk-test".to_string()),
+            openai_base_url: Some(format!("http://{}", addr)),
+            request_timeout_secs: 1,
+            retry_max_attempts: 1,
+            ..Default::default()
+        });
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+
+        let started = std::time::Instant::now();
+        match client.send_message(&request).await {
+            Err(ApiError::Timeout(msg)) => assert!(msg.contains("1s"), "{}", msg),
+            other => panic!("expected a timeout, got ok={}", other.is_ok()),
+        }
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_client_with_upstream_proxy() {
+        let config = ProxyConfig {
+            upstream_proxy: Some("http://proxy.internal:3128".to_string()),
+            ..Default::default()
+        };
+        assert!(ApiClient::try_new(config.clone()).is_ok());
+
+        let bad_cert = ProxyConfig { ca_cert_path: Some("/nonexistent/ca.pem".to_string()), ..config };
+        match ApiClient::try_new(bad_cert) {
+            Err(ApiError::RequestFailed(msg)) => assert!(msg.contains("CA certificate"), "{}", msg),
+            other => panic!("expected a CA error, got ok={}", other.is_ok()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_azure_request_targets_deployment() {
+        use axum::extract::{Path, RawQuery};
+        use axum::http::HeaderMap;
+        use axum::{routing::post, Json, Router};
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(None));
+        let captured = Arc::clone(&seen);
+        let app = Router::new().route(
+            "/openai/deployments/:deployment/chat/completions",
+            post(move |Path(deployment): Path<String>, RawQuery(query): RawQuery, headers: HeaderMap| {
+                let captured = Arc::clone(&captured);
+                async move {
+                    *captured.lock().unwrap() = Some((
+                        deployment,
+                        query,
+                        headers.get("api-key").map(|v| v.to_str().unwrap().to_string()),
+                        headers.contains_key(header::AUTHORIZATION),
+                    ));
+                    Json(json!({
+                        "id": "chatcmpl-1",
+                        "object": "chat.completion",
+                        "created": 0,
+                        "model": "gpt-4o",
+                        "choices": [{
+                            "index": 0,
+                            "message": { "role": "assistant", "content": "ok" },
+                            "finish_reason": "stop"
+                        }],
+                        "usage": { "prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4 }
+                    }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = ApiClient::new(ProxyConfig {
+            preferred_provider: Provider::Azure,
+            big_model: "gpt-4o".to_string(),
+            azure_api_key: Some("azure-key".to_string()),
+            azure_endpoint: Some(format!("http://{}/", addr)),
+            azure_deployments: std::collections::HashMap::from([("gpt-4o".to_string(), "prod-gpt4o".to_string())]),
+            ..Default::default()
+        });
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+
+        let response = client.send_message(&request).await.expect("azure request should succeed");
+        assert_eq!(response.usage.output_tokens, 1);
+
+        let (deployment, query, api_key, has_authorization) = seen.lock().unwrap().take().unwrap();
+        assert_eq!(deployment, "prod-gpt4o");
+        assert_eq!(query.as_deref(), Some("api-version=2024-10-21"));
+        assert_eq!(api_key.as_deref(), Some("azure-key"));
+        assert!(!has_authorization);
+    }
+
+    #[test]
+    fn test_max_tokens_limit_ignores_other_errors() {
+        let context = r#"{"error":{"message":"This model's maximum context length is 8192 tokens."}}"#;
+        assert_eq!(max_tokens_limit_from_error(context, "gpt-4.1"), None);
+        let bare = r#"{"error":{"message":"max_tokens too large","param":"max_tokens"}}"#;
+        assert_eq!(max_tokens_limit_from_error(bare, "gpt-4-turbo"), Some(4096));
+    }
+
+    #[tokio::test]
+    async fn test_parallel_tool_calls_get_separate_blocks() {
+        let chunks = [
+            r#"data: {"choices":[{"index":0,"delta":{"content":"Checking"},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_a","type":"function","function":{"name":"read","arguments":""}}]},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":1,"id":"call_b","type":"function","function":{"name":"grep","arguments":""}}]},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"path\":\"a\"}"}}]},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":1,"function":{"arguments":"{\"q\":\"b\"}"}}]},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}"#,
+            "data: [DONE]",
+        ];
+        let upstream = stream::iter(
+            chunks
+                .iter()
+                .map(|c| Ok::<_, std::io::Error>(format!("{}\n\n", c).into_bytes()))
+                .collect::<Vec<_>>(),
+        );
+
+        let (tx, mut rx) = mpsc::channel(64);
+        pump_openai_stream(upstream, tx, "claude-3-sonnet".to_string(), Vec::new(), 0, RequestLimits::default()).await;
+
+        let mut starts = Vec::new();
+        let mut stops = Vec::new();
+        let mut json_deltas = Vec::new();
+        while let Some(event) = rx.recv().await {
+            match event.unwrap() {
+                StreamEvent::ContentBlockStart { index, content_block } => {
+                    // Every block must be closed before the next one opens
+                    assert_eq!(starts.len(), stops.len(), "block {} opened while another is open", index);
+                    let name = match content_block {
+                        StreamContentBlock::Text { .. } => "text".to_string(),
+                        StreamContentBlock::ToolUse { name, .. } => name,
+                        other => panic!("unexpected block: {:?}", other),
+                    };
+                    starts.push((index, name));
+                }
+                StreamEvent::ContentBlockStop { index } => stops.push(index),
+                StreamEvent::ContentBlockDelta { index, delta: StreamDelta::InputJsonDelta { partial_json } } => {
+                    assert!(!stops.contains(&index), "input_json_delta for closed block {}", index);
+                    json_deltas.push((index, partial_json));
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(
+            starts,
+            vec![(0, "text".to_string()), (1, "read".to_string()), (2, "grep".to_string())]
+        );
+        assert_eq!(stops, vec![0, 1, 2]);
+        assert_eq!(
+            json_deltas,
+            vec![(1, r#"{"path":"a"}"#.to_string()), (2, r#"{"q":"b"}"#.to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deadline_bounds_stream_headers() {
+        use axum::{routing::post, Router};
+
+        let app = Router::new().route(
+            "/chat/completions",
+            post(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "too late"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = ApiClient::new(ProxyConfig {
+            openai_api_key: Some("sk-test".to_string()),
+            openai_base_url: Some(format!("http://{}", addr)),
+            retry_max_attempts: 1,
+            ..Default::default()
+        });
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 100,
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+
+        let started = std::time::Instant::now();
+        match client.send_message_streaming_with_limits(&request, client.request_limits(Some(200))).await {
+            Err(ApiError::Timeout(msg)) => assert!(msg.contains("deadline"), "{}", msg),
+            other => panic!("expected a deadline timeout, got ok={}", other.is_ok()),
+        }
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_deadline_cancels_active_stream() {
+        // Chunks keep arriving, so only the overall deadline can stop it
+        let upstream = stream::repeat_with(|| Ok::<_, std::io::Error>(b": keep-alive\n\n".to_vec()))
+            .then(|chunk| async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                chunk
+            });
+
+        let limits = RequestLimits {
+            deadline: Some(tokio::time::Instant::now() + Duration::from_millis(80)),
+            idle_timeout: Some(Duration::from_millis(50)),
+        };
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(pump_anthropic_stream(Box::pin(upstream), tx, limits));
+
+        let event = tokio::time::timeout(Duration::from_secs(2), rx.recv()).await.unwrap();
+        match event {
+            Some(Err(ApiError::Timeout(msg))) => assert!(msg.contains("deadline")),
+            other => panic!("expected deadline timeout, got {:?}", other.map(|r| r.is_ok())),
+        }
+    }
+}
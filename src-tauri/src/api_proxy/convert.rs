@@ -1,6 +1,7 @@
 //! Request/Response Conversion
 //!
-//! This module handles conversion between Anthropic API format and OpenAI API format.
+//! This module handles conversion between Anthropic API format and OpenAI API format,
+//! and between OpenAI format and Gemini's native generateContent format.
 
 use super::types::*;
 use serde_json::json;
@@ -33,17 +34,59 @@ pub struct MappedModel {
     pub full_name: String,
 }
 
+/// Canonical provider name used in `MappedModel`
+fn normalize_provider(provider: &str) -> Option<&'static str> {
+    match provider.to_lowercase().as_str() {
+        "google" | "gemini" => Some("gemini"),
+        "anthropic" => Some("anthropic"),
+        "openai" => Some("openai"),
+        "azure" => Some("azure"),
+        _ => None,
+    }
+}
+
+/// Mapping for an explicit override or rule target; unknown providers fall back to openai
+fn routed_model(provider: &str, model: &str) -> MappedModel {
+    let provider = normalize_provider(provider).unwrap_or("openai");
+    MappedModel {
+        provider: provider.to_string(),
+        model: model.to_string(),
+        full_name: format!("{}/{}", provider, model),
+    }
+}
+
 /// Map Claude model names to target provider models
 pub fn map_model(model: &str, config: &ProxyConfig) -> MappedModel {
     // Remove any existing provider prefix
     let clean_model = model
         .strip_prefix("anthropic/")
         .or_else(|| model.strip_prefix("openai/"))
+        .or_else(|| model.strip_prefix("azure/"))
         .or_else(|| model.strip_prefix("gemini/"))
         .unwrap_or(model);
 
     let lower_model = clean_model.to_lowercase();
 
+    // Explicit per-model overrides win over everything else
+    if let Some(target) = config
+        .model_overrides
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(clean_model))
+        .map(|(_, target)| target)
+    {
+        return match target.split_once('/') {
+            Some((provider, model)) if normalize_provider(provider).is_some() => {
+                routed_model(provider, model)
+            }
+            _ => routed_model("openai", target),
+        };
+    }
+
+    // Then the first matching routing rule
+    if let Some(rule) = config.model_rules.iter().find(|rule| rule.pattern.is_match(clean_model)) {
+        return routed_model(&rule.provider, &rule.model);
+    }
+
     // Check for Anthropic provider preference
     if matches!(config.preferred_provider, Provider::Anthropic) {
         return MappedModel {
@@ -59,6 +102,7 @@ pub fn map_model(model: &str, config: &ProxyConfig) -> MappedModel {
             Provider::Google if GEMINI_MODELS.contains(&config.small_model.as_str()) => {
                 ("gemini", &config.small_model)
             }
+            Provider::Azure => ("azure", &config.small_model),
             _ => ("openai", &config.small_model),
         };
         return MappedModel {
@@ -74,6 +118,7 @@ pub fn map_model(model: &str, config: &ProxyConfig) -> MappedModel {
             Provider::Google if GEMINI_MODELS.contains(&config.big_model.as_str()) => {
                 ("gemini", &config.big_model)
             }
+            Provider::Azure => ("azure", &config.big_model),
             _ => ("openai", &config.big_model),
         };
         return MappedModel {
@@ -89,6 +134,7 @@ pub fn map_model(model: &str, config: &ProxyConfig) -> MappedModel {
             Provider::Google if GEMINI_MODELS.contains(&config.big_model.as_str()) => {
                 ("gemini", &config.big_model)
             }
+            Provider::Azure => ("azure", &config.big_model),
             _ => ("openai", &config.big_model),
         };
         return MappedModel {
@@ -107,8 +153,8 @@ pub fn map_model(model: &str, config: &ProxyConfig) -> MappedModel {
         };
     }
 
-    // Check if model is a known OpenAI model
-    if OPENAI_MODELS.contains(&clean_model) {
+    // Check if model is a known OpenAI model (Azure hosts the same models)
+    if OPENAI_MODELS.contains(&clean_model) && !matches!(config.preferred_provider, Provider::Azure) {
         return MappedModel {
             provider: "openai".to_string(),
             model: clean_model.to_string(),
@@ -121,6 +167,7 @@ pub fn map_model(model: &str, config: &ProxyConfig) -> MappedModel {
         Provider::OpenAI => "openai",
         Provider::Google => "gemini",
         Provider::Anthropic => "anthropic",
+        Provider::Azure => "azure",
     };
     MappedModel {
         provider: provider.to_string(),
@@ -194,10 +241,77 @@ fn clean_gemini_schema(schema: &mut serde_json::Value) {
     }
 }
 
+/// OpenAI reasoning models (o1, o3, ...) reject sampling params and the system role
+pub fn is_reasoning_model(model: &str) -> bool {
+    let model = model.rsplit('/').next().unwrap_or(model);
+    let mut chars = model.chars();
+    chars.next() == Some('o') && chars.next().is_some_and(|c| c.is_ascii_digit())
+}
+
+/// Resolve the temperature to send upstream, applying per-model overrides
+fn resolve_temperature(
+    requested: Option<f32>,
+    mapped_model: &MappedModel,
+    config: &ProxyConfig,
+) -> Option<f32> {
+    let override_value = config
+        .temperature_overrides
+        .get(&mapped_model.model)
+        .or_else(|| config.temperature_overrides.get(&mapped_model.full_name))
+        .copied()
+        .or_else(|| is_reasoning_model(&mapped_model.model).then_some(TemperatureOverride::Omit));
+
+    match override_value {
+        Some(TemperatureOverride::Value(value)) => Some(value),
+        Some(TemperatureOverride::Omit) => None,
+        None => requested,
+    }
+}
+
+/// Fold system messages into the first user message for models without a system role
+fn fold_system_into_user(messages: Vec<OpenAIMessage>) -> Vec<OpenAIMessage> {
+    let (system, mut rest): (Vec<_>, Vec<_>) = messages.into_iter().partition(|m| m.role == "system");
+    let system_text = system
+        .iter()
+        .filter_map(|m| match &m.content {
+            OpenAIContent::Text(text) if !text.is_empty() => Some(text.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    if system_text.is_empty() {
+        return rest;
+    }
+
+    match rest.iter_mut().find(|m| m.role == "user") {
+        Some(user) => {
+            user.content = match std::mem::replace(&mut user.content, OpenAIContent::Text(String::new())) {
+                OpenAIContent::Text(text) => OpenAIContent::Text(format!("{}\n\n{}", system_text, text)),
+                OpenAIContent::Parts(mut parts) => {
+                    parts.insert(0, OpenAIContentPart::Text { text: system_text });
+                    OpenAIContent::Parts(parts)
+                }
+            };
+        }
+        None => rest.insert(
+            0,
+            OpenAIMessage {
+                role: "user".to_string(),
+                content: OpenAIContent::Text(system_text),
+                name: None,
+                tool_calls: None,
+                tool_call_id: None,
+            },
+        ),
+    }
+    rest
+}
+
 /// Convert Anthropic request to OpenAI format
 pub fn convert_anthropic_to_openai(
     request: &MessagesRequest,
     mapped_model: &MappedModel,
+    config: &ProxyConfig,
 ) -> OpenAIRequest {
     let mut messages = Vec::new();
 
@@ -229,8 +343,42 @@ pub fn convert_anthropic_to_openai(
                 // Check if message contains tool results (user message with tool_result)
                 let has_tool_results = blocks.iter().any(|b| matches!(b, ContentBlock::ToolResult { .. }));
 
-                if msg.role == "user" && has_tool_results {
-                    // For user messages with tool results, convert to text format
+                if msg.role == "user" && has_tool_results && !config.tool_results_as_text {
+                    // Each tool result becomes a `tool` message tied to its call; OpenAI expects
+                    // these right after the assistant turn, so any accompanying text follows them
+                    let mut text_content = String::new();
+
+                    for block in blocks {
+                        match block {
+                            ContentBlock::Text { text } => {
+                                text_content.push_str(text);
+                                text_content.push('\n');
+                            }
+                            ContentBlock::ToolResult { tool_use_id, content, .. } => {
+                                messages.push(OpenAIMessage {
+                                    role: "tool".to_string(),
+                                    content: OpenAIContent::Text(parse_tool_result_content(content)),
+                                    name: None,
+                                    tool_calls: None,
+                                    tool_call_id: Some(tool_use_id.clone()),
+                                });
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    let text_content = text_content.trim();
+                    if !text_content.is_empty() {
+                        messages.push(OpenAIMessage {
+                            role: "user".to_string(),
+                            content: OpenAIContent::Text(text_content.to_string()),
+                            name: None,
+                            tool_calls: None,
+                            tool_call_id: None,
+                        });
+                    }
+                } else if msg.role == "user" && has_tool_results {
+                    // Providers without a tool role get the results inlined as text
                     let mut text_content = String::new();
 
                     for block in blocks {
@@ -277,6 +425,11 @@ pub fn convert_anthropic_to_openai(
                                     image_url: OpenAIImageUrl { url },
                                 });
                             }
+                            ContentBlock::Document { source, title, .. } => {
+                                parts.push(OpenAIContentPart::Text {
+                                    text: document_to_text(source, title.as_deref()),
+                                });
+                            }
                             ContentBlock::ToolUse { id, name, input } => {
                                 tool_calls.push(OpenAIToolCall {
                                     id: id.clone(),
@@ -373,23 +526,63 @@ pub fn convert_anthropic_to_openai(
         }
     });
 
+    let reasoning = is_reasoning_model(&mapped_model.model);
+    let messages = if reasoning { fold_system_into_user(messages) } else { messages };
+
+    // Reasoning models and Gemini's native API have no logprobs
+    let logprobs = request.logprobs == Some(true) && !reasoning && mapped_model.provider != "gemini";
+
     OpenAIRequest {
         model: mapped_model.full_name.clone(),
         messages,
         max_completion_tokens: max_tokens,
-        temperature: request.temperature,
-        top_p: request.top_p,
-        stop: request.stop_sequences.clone(),
+        temperature: resolve_temperature(request.temperature, mapped_model, config),
+        top_p: if reasoning { None } else { request.top_p },
+        stop: if reasoning { None } else { request.stop_sequences.clone() },
         stream: request.stream,
         tools,
         tool_choice,
+        stream_options: request.stream.then_some(OpenAIStreamOptions { include_usage: true }),
+        logprobs: logprobs.then_some(true),
+        top_logprobs: if logprobs { request.top_logprobs.map(|n| n.min(20)) } else { None },
+    }
+}
+
+/// Plain-text documents are inlined; anything else (PDFs) becomes a note that it was omitted
+fn document_to_text(source: &serde_json::Value, title: Option<&str>) -> String {
+    let label = title.unwrap_or("untitled");
+    let source_type = source.get("type").and_then(|v| v.as_str()).unwrap_or("");
+
+    if source_type == "text" {
+        if let Some(data) = source.get("data").and_then(|v| v.as_str()) {
+            return format!("Document ({}):\n{}", label, data);
+        }
     }
+
+    let media_type = source
+        .get("media_type")
+        .and_then(|v| v.as_str())
+        .unwrap_or(source_type);
+    format!(
+        "[Document \"{}\" ({}) omitted: this model does not accept document inputs]",
+        label, media_type
+    )
+}
+
+/// The stop sequence the text ends with, if any (longest match wins).
+/// OpenAI reports a stop-sequence hit as a plain `stop`, so this is how `StopSequence` is recovered.
+pub fn matched_stop_sequence<'a>(text: &str, stop_sequences: &'a [String]) -> Option<&'a String> {
+    stop_sequences
+        .iter()
+        .filter(|seq| !seq.is_empty() && text.ends_with(seq.as_str()))
+        .max_by_key(|seq| seq.len())
 }
 
 /// Convert OpenAI response to Anthropic format
 pub fn convert_openai_to_anthropic(
     response: &OpenAIResponse,
     original_model: &str,
+    stop_sequences: Option<&[String]>,
 ) -> MessagesResponse {
     let choice = response.choices.first();
 
@@ -426,7 +619,7 @@ pub fn convert_openai_to_anthropic(
     }
 
     // Map finish_reason to stop_reason
-    let stop_reason = choice.and_then(|c| {
+    let mut stop_reason = choice.and_then(|c| {
         c.finish_reason.as_ref().map(|r| match r.as_str() {
             "stop" => StopReason::EndTurn,
             "length" => StopReason::MaxTokens,
@@ -435,6 +628,15 @@ pub fn convert_openai_to_anthropic(
         })
     });
 
+    let mut stop_sequence = None;
+    if matches!(stop_reason, Some(StopReason::EndTurn)) {
+        let text = choice.and_then(|c| c.message.content.as_deref()).unwrap_or_default();
+        if let Some(seq) = stop_sequences.and_then(|seqs| matched_stop_sequence(text, seqs)) {
+            stop_reason = Some(StopReason::StopSequence);
+            stop_sequence = Some(seq.clone());
+        }
+    }
+
     MessagesResponse {
         id: response.id.clone(),
         model: original_model.to_string(),
@@ -442,13 +644,217 @@ pub fn convert_openai_to_anthropic(
         content,
         response_type: "message".to_string(),
         stop_reason,
-        stop_sequence: None,
+        stop_sequence,
         usage: Usage {
             input_tokens: response.usage.prompt_tokens,
             output_tokens: response.usage.completion_tokens,
             cache_creation_input_tokens: 0,
             cache_read_input_tokens: 0,
         },
+        logprobs: choice.and_then(|c| c.logprobs.clone()),
+    }
+}
+
+/// Convert OpenAI message content into Gemini parts
+fn openai_content_to_gemini_parts(content: &OpenAIContent) -> Vec<GeminiPart> {
+    let text_part = |text: &str| GeminiPart {
+        text: Some(text.to_string()),
+        ..Default::default()
+    };
+
+    match content {
+        OpenAIContent::Text(text) if text.is_empty() => Vec::new(),
+        OpenAIContent::Text(text) => vec![text_part(text)],
+        OpenAIContent::Parts(parts) => parts
+            .iter()
+            .map(|part| match part {
+                OpenAIContentPart::Text { text } => text_part(text),
+                OpenAIContentPart::ImageUrl { image_url } => {
+                    // data:<mime>;base64,<data> becomes inline data; remote URLs can't be fetched by Gemini
+                    let inline = image_url
+                        .url
+                        .strip_prefix("data:")
+                        .and_then(|rest| rest.split_once(";base64,"));
+                    match inline {
+                        Some((mime_type, data)) => GeminiPart {
+                            inline_data: Some(GeminiInlineData {
+                                mime_type: mime_type.to_string(),
+                                data: data.to_string(),
+                            }),
+                            ..Default::default()
+                        },
+                        None => text_part(&format!("[image: {}]", image_url.url)),
+                    }
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Convert an OpenAI-format request into Gemini's native generateContent body
+pub fn convert_openai_to_gemini(request: &OpenAIRequest) -> GeminiRequest {
+    let mut system_parts = Vec::new();
+    let mut contents: Vec<GeminiContent> = Vec::new();
+    // Gemini function responses are matched by name, OpenAI tool messages by call id
+    let mut tool_names: std::collections::HashMap<String, String> = std::collections::HashMap::new();
+
+    for msg in &request.messages {
+        let mut parts = openai_content_to_gemini_parts(&msg.content);
+
+        let role = match msg.role.as_str() {
+            "system" => {
+                system_parts.append(&mut parts);
+                continue;
+            }
+            "assistant" => {
+                for call in msg.tool_calls.iter().flatten() {
+                    tool_names.insert(call.id.clone(), call.function.name.clone());
+                    parts.push(GeminiPart {
+                        function_call: Some(GeminiFunctionCall {
+                            name: call.function.name.clone(),
+                            args: serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| json!({})),
+                        }),
+                        ..Default::default()
+                    });
+                }
+                "model"
+            }
+            "tool" => {
+                let output: String = parts.drain(..).filter_map(|p| p.text).collect::<Vec<_>>().join("\n");
+                let name = msg
+                    .tool_call_id
+                    .as_ref()
+                    .and_then(|id| tool_names.get(id))
+                    .cloned()
+                    .unwrap_or_else(|| "tool".to_string());
+                parts.push(GeminiPart {
+                    function_response: Some(GeminiFunctionResponse {
+                        name,
+                        response: json!({ "content": output }),
+                    }),
+                    ..Default::default()
+                });
+                "user"
+            }
+            _ => "user",
+        };
+
+        if parts.is_empty() {
+            continue;
+        }
+
+        // Gemini expects alternating turns, so merge consecutive same-role messages
+        match contents.last_mut() {
+            Some(last) if last.role.as_deref() == Some(role) => last.parts.append(&mut parts),
+            _ => contents.push(GeminiContent {
+                role: Some(role.to_string()),
+                parts,
+            }),
+        }
+    }
+
+    let tools = request.tools.as_ref().map(|tools| {
+        vec![GeminiTool {
+            function_declarations: tools.iter().map(|t| t.function.clone()).collect(),
+        }]
+    });
+
+    let tool_config = request.tool_choice.as_ref().map(|choice| {
+        let (mode, allowed) = match choice {
+            serde_json::Value::String(s) if s == "none" => ("NONE", None),
+            serde_json::Value::String(s) if s == "any" || s == "required" => ("ANY", None),
+            serde_json::Value::Object(_) => (
+                "ANY",
+                choice.pointer("/function/name").and_then(|n| n.as_str()).map(|n| vec![n.to_string()]),
+            ),
+            _ => ("AUTO", None),
+        };
+        let mut config = json!({ "functionCallingConfig": { "mode": mode } });
+        if let Some(names) = allowed {
+            config["functionCallingConfig"]["allowedFunctionNames"] = json!(names);
+        }
+        config
+    });
+
+    GeminiRequest {
+        contents,
+        system_instruction: (!system_parts.is_empty()).then(|| GeminiContent {
+            role: None,
+            parts: system_parts,
+        }),
+        generation_config: Some(GeminiGenerationConfig {
+            max_output_tokens: request.max_completion_tokens,
+            temperature: request.temperature,
+            top_p: request.top_p,
+            stop_sequences: request.stop.clone(),
+        }),
+        tools,
+        tool_config,
+    }
+}
+
+/// Map a Gemini finishReason to the OpenAI equivalent
+pub fn gemini_finish_reason(reason: &str, has_tool_calls: bool) -> String {
+    if has_tool_calls {
+        return "tool_calls".to_string();
+    }
+    match reason {
+        "MAX_TOKENS" => "length",
+        _ => "stop",
+    }
+    .to_string()
+}
+
+/// Convert a Gemini generateContent response into an OpenAI response
+pub fn convert_gemini_to_openai(response: &GeminiResponse, model: &str) -> OpenAIResponse {
+    let candidate = response.candidates.first();
+    let parts = candidate
+        .and_then(|c| c.content.as_ref())
+        .map(|c| c.parts.as_slice())
+        .unwrap_or_default();
+
+    let text: String = parts.iter().filter_map(|p| p.text.as_deref()).collect();
+    let tool_calls: Vec<OpenAIToolCall> = parts
+        .iter()
+        .filter_map(|p| p.function_call.as_ref())
+        .map(|call| OpenAIToolCall {
+            id: generate_tool_id(),
+            call_type: "function".to_string(),
+            function: OpenAIFunction {
+                name: call.name.clone(),
+                arguments: call.args.to_string(),
+            },
+        })
+        .collect();
+
+    let finish_reason = candidate
+        .and_then(|c| c.finish_reason.as_deref())
+        .map(|r| gemini_finish_reason(r, !tool_calls.is_empty()));
+    let usage = response.usage_metadata.clone().unwrap_or_default();
+
+    OpenAIResponse {
+        id: format!("gemini-{}", Uuid::new_v4().simple()),
+        object: "chat.completion".to_string(),
+        created: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        model: model.to_string(),
+        choices: vec![OpenAIChoice {
+            index: 0,
+            message: OpenAIResponseMessage {
+                role: "assistant".to_string(),
+                content: (!text.is_empty()).then_some(text),
+                tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            },
+            finish_reason,
+            logprobs: None,
+        }],
+        usage: OpenAIUsage {
+            prompt_tokens: usage.prompt_token_count,
+            completion_tokens: usage.candidates_token_count,
+            total_tokens: usage.total_token_count,
+        },
     }
 }
 
@@ -466,6 +872,29 @@ pub fn generate_tool_id() -> String {
 mod tests {
     use super::*;
 
+    fn test_request(model: &str) -> MessagesRequest {
+        MessagesRequest {
+            model: model.to_string(),
+            max_tokens: 1024,
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::Text("Hello".to_string()),
+            }],
+            system: None,
+            stop_sequences: None,
+            stream: false,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            metadata: None,
+            tools: None,
+            tool_choice: None,
+            thinking: None,
+            logprobs: None,
+            top_logprobs: None,
+        }
+    }
+
     #[test]
     fn test_map_model_haiku() {
         let config = ProxyConfig::default();
@@ -502,4 +931,364 @@ mod tests {
         assert_eq!(result.provider, "gemini");
         assert_eq!(result.model, "gemini-2.5-pro");
     }
+
+    #[test]
+    fn test_map_model_override_to_gemini() {
+        let config = ProxyConfig {
+            model_overrides: parse_model_overrides(
+                "claude-3-5-sonnet-20241022=gemini/gemini-2.5-pro, claude-3-7-sonnet=openai/o3-mini",
+            ),
+            ..Default::default()
+        };
+        let result = map_model("claude-3-5-sonnet-20241022", &config);
+        assert_eq!(result.provider, "gemini");
+        assert_eq!(result.model, "gemini-2.5-pro");
+        assert_eq!(result.full_name, "gemini/gemini-2.5-pro");
+
+        // Names without an override keep the sonnet heuristic
+        let result = map_model("claude-3-sonnet", &config);
+        assert_eq!(result.provider, "openai");
+        assert_eq!(result.model, "gpt-4.1");
+    }
+
+    #[test]
+    fn test_map_model_override_custom_name() {
+        let config = ProxyConfig {
+            model_overrides: parse_model_overrides("my-team-model=openai/gpt-4o"),
+            ..Default::default()
+        };
+        let result = map_model("my-team-model", &config);
+        assert_eq!(result.provider, "openai");
+        assert_eq!(result.model, "gpt-4o");
+        assert_eq!(result.full_name, "openai/gpt-4o");
+    }
+
+    #[test]
+    fn test_model_rules_first_match_wins() {
+        let config = ProxyConfig {
+            model_rules: vec![
+                ModelRule::new(".*-mini", "openai", "gpt-4o-mini").unwrap(),
+                ModelRule::new(".*-pro", "google", "gemini-2.5-pro").unwrap(),
+                ModelRule::new("o3-.*", "openai", "o3").unwrap(),
+            ],
+            model_overrides: parse_model_overrides("special-mini=openai/gpt-4.1"),
+            ..Default::default()
+        };
+
+        let mapped = map_model("team-pro", &config);
+        assert_eq!(mapped.full_name, "gemini/gemini-2.5-pro");
+
+        // Both the first and third rule match; the earlier one wins
+        let mapped = map_model("o3-mini", &config);
+        assert_eq!(mapped.full_name, "openai/gpt-4o-mini");
+
+        // Explicit overrides are checked before rules
+        let mapped = map_model("special-mini", &config);
+        assert_eq!(mapped.full_name, "openai/gpt-4.1");
+
+        // Patterns are anchored to the whole name
+        let mapped = map_model("mini-model", &config);
+        assert_eq!(mapped.full_name, "openai/mini-model");
+    }
+
+    #[test]
+    fn test_model_rules_fall_through_to_defaults() {
+        let config = ProxyConfig {
+            model_rules: vec![ModelRule::new(".*-pro", "gemini", "gemini-2.5-pro").unwrap()],
+            ..Default::default()
+        };
+
+        assert_eq!(map_model("claude-3-haiku", &config).full_name, "openai/gpt-4.1-mini");
+        assert_eq!(map_model("claude-3-sonnet", &config).full_name, "openai/gpt-4.1");
+        assert_eq!(map_model("gpt-4o", &config).full_name, "openai/gpt-4o");
+    }
+
+    #[test]
+    fn test_temperature_override_value() {
+        let mut config = ProxyConfig::default();
+        config
+            .temperature_overrides
+            .insert("gpt-4.1".to_string(), TemperatureOverride::Value(0.2));
+
+        let mut request = test_request("claude-3-sonnet");
+        request.temperature = Some(0.9);
+        let mapped = map_model(&request.model, &config);
+        let converted = convert_anthropic_to_openai(&request, &mapped, &config);
+        assert_eq!(converted.temperature, Some(0.2));
+
+        // Models without an override keep the client's temperature
+        let request = MessagesRequest { temperature: Some(0.9), ..test_request("claude-3-haiku") };
+        let mapped = map_model(&request.model, &config);
+        let converted = convert_anthropic_to_openai(&request, &mapped, &config);
+        assert_eq!(converted.temperature, Some(0.9));
+    }
+
+    #[test]
+    fn test_temperature_override_omit() {
+        let config = ProxyConfig {
+            big_model: "o3-mini".to_string(),
+            temperature_overrides: parse_temperature_overrides("gpt-4.1-mini=omit"),
+            ..Default::default()
+        };
+
+        // Explicit omit
+        let request = MessagesRequest { temperature: Some(0.5), ..test_request("claude-3-haiku") };
+        let mapped = map_model(&request.model, &config);
+        assert_eq!(convert_anthropic_to_openai(&request, &mapped, &config).temperature, None);
+
+        // o-series models omit temperature by default
+        let request = MessagesRequest { temperature: Some(0.5), ..test_request("claude-3-sonnet") };
+        let mapped = map_model(&request.model, &config);
+        assert_eq!(mapped.model, "o3-mini");
+        assert_eq!(convert_anthropic_to_openai(&request, &mapped, &config).temperature, None);
+    }
+
+    #[test]
+    fn test_is_reasoning_model() {
+        assert!(is_reasoning_model("o1"));
+        assert!(is_reasoning_model("o3-mini"));
+        assert!(is_reasoning_model("openai/o1-preview"));
+        assert!(!is_reasoning_model("gpt-4.1"));
+        assert!(!is_reasoning_model("omni-model"));
+    }
+
+    #[test]
+    fn test_logprobs_forwarded_only_when_requested() {
+        let config = ProxyConfig::default();
+        let plain = test_request("claude-3-sonnet");
+        let mapped = map_model(&plain.model, &config);
+        let body = serde_json::to_value(convert_anthropic_to_openai(&plain, &mapped, &config)).unwrap();
+        assert!(body.get("logprobs").is_none());
+        assert!(body.get("top_logprobs").is_none());
+
+        // top_logprobs alone doesn't turn logprobs on
+        let request = MessagesRequest { top_logprobs: Some(3), ..test_request("claude-3-sonnet") };
+        let body = serde_json::to_value(convert_anthropic_to_openai(&request, &mapped, &config)).unwrap();
+        assert!(body.get("top_logprobs").is_none());
+
+        let request = MessagesRequest {
+            logprobs: Some(true),
+            top_logprobs: Some(3),
+            ..test_request("claude-3-sonnet")
+        };
+        let body = serde_json::to_value(convert_anthropic_to_openai(&request, &mapped, &config)).unwrap();
+        assert_eq!(body["logprobs"], true);
+        assert_eq!(body["top_logprobs"], 3);
+        // Never leaks into the Anthropic-shaped request
+        assert!(serde_json::to_value(&request).unwrap().get("logprobs").is_none());
+
+        let response: OpenAIResponse = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-4.1",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Hi" },
+                "finish_reason": "stop",
+                "logprobs": { "content": [{ "token": "Hi", "logprob": -0.01, "top_logprobs": [] }] }
+            }],
+            "usage": { "prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4 }
+        }))
+        .unwrap();
+        let converted = convert_openai_to_anthropic(&response, "claude-3-sonnet", None);
+        assert_eq!(converted.logprobs.unwrap()["content"][0]["token"], "Hi");
+    }
+
+    #[test]
+    fn test_reasoning_model_drops_sampling_params() {
+        let config = ProxyConfig { big_model: "o1".to_string(), ..Default::default() };
+        let request = MessagesRequest {
+            system: Some(SystemContent::Text("Be brief.".to_string())),
+            temperature: Some(0.5),
+            top_p: Some(0.9),
+            stop_sequences: Some(vec!["END".to_string()]),
+            ..test_request("claude-3-sonnet")
+        };
+        let mapped = map_model(&request.model, &config);
+        let converted = convert_anthropic_to_openai(&request, &mapped, &config);
+
+        let body = serde_json::to_value(&converted).unwrap();
+        assert!(body.get("temperature").is_none());
+        assert!(body.get("top_p").is_none());
+        assert!(body.get("stop").is_none());
+
+        assert!(converted.messages.iter().all(|m| m.role != "system"));
+        assert_eq!(converted.messages[0].role, "user");
+        match &converted.messages[0].content {
+            OpenAIContent::Text(text) => assert_eq!(text, "Be brief.\n\nHello"),
+            other => panic!("unexpected content: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stop_sequence_is_reported() {
+        let response: OpenAIResponse = serde_json::from_value(json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-4.1",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Answer: 42\n###" },
+                "finish_reason": "stop"
+            }],
+            "usage": { "prompt_tokens": 3, "completion_tokens": 4, "total_tokens": 7 }
+        }))
+        .unwrap();
+        let stops = vec!["END".to_string(), "###".to_string(), "\n###".to_string()];
+
+        let anthropic = convert_openai_to_anthropic(&response, "claude-3-sonnet", Some(&stops));
+        assert_eq!(anthropic.stop_reason, Some(StopReason::StopSequence));
+        assert_eq!(anthropic.stop_sequence.as_deref(), Some("\n###"));
+
+        // No matching sequence: a normal end of turn
+        let stops = vec!["END".to_string()];
+        let anthropic = convert_openai_to_anthropic(&response, "claude-3-sonnet", Some(&stops));
+        assert_eq!(anthropic.stop_reason, Some(StopReason::EndTurn));
+        assert_eq!(anthropic.stop_sequence, None);
+    }
+
+    #[test]
+    fn test_tool_results_become_tool_messages() {
+        let config = ProxyConfig::default();
+        let mut request = test_request("claude-3-sonnet");
+        request.messages.push(Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Blocks(vec![ContentBlock::ToolUse {
+                id: "toolu_1".to_string(),
+                name: "read_file".to_string(),
+                input: json!({"path": "a.txt"}),
+            }]),
+        });
+        request.messages.push(serde_json::from_value(json!({
+            "role": "user",
+            "content": [
+                {"type": "tool_result", "tool_use_id": "toolu_1", "content": "file contents"},
+                {"type": "text", "text": "Now summarize it"}
+            ]
+        })).unwrap());
+
+        let mapped = map_model(&request.model, &config);
+        let converted = convert_anthropic_to_openai(&request, &mapped, &config);
+        let roles: Vec<&str> = converted.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["user", "assistant", "tool", "user"]);
+
+        let tool = &converted.messages[2];
+        assert_eq!(tool.tool_call_id.as_deref(), Some("toolu_1"));
+        match &tool.content {
+            OpenAIContent::Text(text) => assert_eq!(text, "file contents"),
+            other => panic!("unexpected content: {:?}", other),
+        }
+        assert_eq!(
+            converted.messages[1].tool_calls.as_ref().map(|calls| calls[0].id.as_str()),
+            Some("toolu_1")
+        );
+
+        // Text fallback for endpoints without the tool role
+        let config = ProxyConfig { tool_results_as_text: true, ..Default::default() };
+        let converted = convert_anthropic_to_openai(&request, &mapped, &config);
+        assert!(converted.messages.iter().all(|m| m.role != "tool"));
+    }
+
+    #[test]
+    fn test_document_block_deserializes_and_converts() {
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 1024,
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {
+                        "type": "document",
+                        "source": { "type": "base64", "media_type": "application/pdf", "data": "JVBERi0x" },
+                        "title": "spec.pdf"
+                    },
+                    { "type": "text", "text": "Summarize this" }
+                ]
+            }]
+        }))
+        .expect("document block should deserialize");
+
+        // Round-trips unchanged for the native Anthropic path
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(value["messages"][0]["content"][0]["type"], "document");
+        assert_eq!(value["messages"][0]["content"][0]["source"]["media_type"], "application/pdf");
+
+        let config = ProxyConfig::default();
+        let mapped = map_model(&request.model, &config);
+        let converted = convert_anthropic_to_openai(&request, &mapped, &config);
+        match &converted.messages[0].content {
+            OpenAIContent::Parts(parts) => match &parts[0] {
+                OpenAIContentPart::Text { text } => {
+                    assert!(text.contains("spec.pdf"));
+                    assert!(text.contains("omitted"));
+                }
+                other => panic!("unexpected part: {:?}", other),
+            },
+            other => panic!("unexpected content: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_convert_openai_to_gemini_body() {
+        let request: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "max_tokens": 512,
+            "system": "Be brief.",
+            "messages": [
+                { "role": "user", "content": "What's the weather?" },
+                { "role": "assistant", "content": [
+                    { "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": { "city": "Paris" } }
+                ]}
+            ],
+            "tools": [{ "name": "get_weather", "input_schema": { "type": "object", "additionalProperties": false } }]
+        }))
+        .unwrap();
+        let config = ProxyConfig {
+            preferred_provider: Provider::Google,
+            big_model: "gemini-2.5-pro".to_string(),
+            ..Default::default()
+        };
+        let mapped = map_model(&request.model, &config);
+        let openai = convert_anthropic_to_openai(&request, &mapped, &config);
+
+        let body = serde_json::to_value(convert_openai_to_gemini(&openai)).unwrap();
+        let contents = body["contents"].as_array().expect("contents array");
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0]["role"], "user");
+        assert_eq!(contents[0]["parts"][0]["text"], "What's the weather?");
+        assert_eq!(contents[1]["role"], "model");
+        let call = contents[1]["parts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find_map(|p| p.get("functionCall"))
+            .expect("functionCall part");
+        assert_eq!(call["args"]["city"], "Paris");
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "Be brief.");
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 512);
+        assert_eq!(body["tools"][0]["functionDeclarations"][0]["name"], "get_weather");
+        assert!(body.get("messages").is_none());
+    }
+
+    #[test]
+    fn test_convert_gemini_response() {
+        let response: GeminiResponse = serde_json::from_value(json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [{ "text": "Sunny" }] },
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": { "promptTokenCount": 7, "candidatesTokenCount": 2, "totalTokenCount": 9 }
+        }))
+        .unwrap();
+
+        let anthropic = convert_openai_to_anthropic(&convert_gemini_to_openai(&response, "gemini-2.5-pro"), "claude-3-sonnet", None);
+        assert_eq!(anthropic.usage.input_tokens, 7);
+        assert_eq!(anthropic.usage.output_tokens, 2);
+        match &anthropic.content[0] {
+            ResponseContentBlock::Text { text } => assert_eq!(text, "Sunny"),
+            other => panic!("unexpected block: {:?}", other),
+        }
+    }
 }
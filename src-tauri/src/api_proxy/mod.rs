@@ -56,6 +56,8 @@
 //!         tools: None,
 //!         tool_choice: None,
 //!         thinking: None,
+//!         logprobs: None,
+//!         top_logprobs: None,
 //!     };
 //!
 //!     let response = client.send_message(&request).await.unwrap();
@@ -67,24 +69,45 @@
 //!
 //! The proxy can be configured via environment variables:
 //!
-//! - `PREFERRED_PROVIDER`: Default provider to use ("openai", "google", or "anthropic")
+//! - `PREFERRED_PROVIDER`: Default provider to use ("openai", "google", "anthropic", or "azure")
 //! - `BIG_MODEL`: Model to use for sonnet/opus (default: "gpt-4.1")
 //! - `SMALL_MODEL`: Model to use for haiku (default: "gpt-4.1-mini")
 //! - `OPENAI_API_KEY`: API key for OpenAI
 //! - `GEMINI_API_KEY`: API key for Google Gemini
 //! - `ANTHROPIC_API_KEY`: API key for Anthropic (for passthrough)
 //! - `OPENAI_BASE_URL`: Custom base URL for OpenAI-compatible APIs
+//! - `AZURE_OPENAI_API_KEY`: API key for Azure OpenAI, sent as the `api-key` header
+//! - `AZURE_OPENAI_ENDPOINT`: Azure resource endpoint, e.g. `https://my-resource.openai.azure.com`
+//! - `AZURE_OPENAI_API_VERSION`: Azure REST API version (default: "2024-10-21")
+//! - `AZURE_OPENAI_DEPLOYMENTS`: Model to deployment names, e.g. `gpt-4o=prod-gpt4o` (unmapped models use the model name)
+//! - `PROXY_TRANSCRIPT_DIR`: Write a JSON transcript of every request to this directory
+//! - `PROXY_TRANSCRIPT_MAX_FILES`: Transcripts to keep before pruning the oldest (default: 500, 0 keeps all)
+//! - `TEMPERATURE_OVERRIDES`: Per-model temperature, e.g. `gpt-4.1=0.2,o3-mini=omit`
+//! - `PROXY_DEADLINE_MS`: Default total deadline per request, overridable with the `x-proxy-deadline-ms` header
+//! - `PROXY_STREAM_IDLE_TIMEOUT_MS`: Abort a stream after this long without upstream data (default: 120000, 0 disables)
+//! - `MODEL_OVERRIDES`: Exact model routing, e.g. `claude-3-7-sonnet=gemini/gemini-2.5-pro,my-model=openai/gpt-4o`
+//! - `MODEL_MAP_FILE`: JSON file with `overrides` (like `MODEL_OVERRIDES`) and ordered regex `rules`
+//!   (`{"pattern": ".*-mini", "provider": "openai", "model": "gpt-4o-mini"}`), first match wins
+//! - `PROXY_RETRY_MAX_ATTEMPTS`: Total attempts for 429/500/502/503/504 responses (default: 3, 1 disables retries)
+//! - `PROXY_RETRY_BASE_DELAY_MS`: Initial retry backoff, doubled per attempt unless `Retry-After` is sent (default: 500)
+//! - `TOOL_RESULTS_AS_TEXT`: Send tool results as user text instead of `tool` role messages (for endpoints without tool support)
+//! - `PROXY_UPSTREAM_URL`: Egress proxy for upstream requests (falls back to `HTTPS_PROXY`/`HTTP_PROXY`; `NO_PROXY` is honoured)
+//! - `REQUEST_TIMEOUT_SECS`: Time allowed to connect and get response headers; non-streaming bodies too (default: 300, must be > 0)
+//! - `PROXY_PASSTHROUGH_AUTH`: Use each caller's `x-api-key`/`Authorization` header as the upstream key (configured keys are the fallback)
+//! - `PROXY_CA_CERT`: PEM file with extra root certificates to trust, e.g. a corporate TLS-inspection CA
 
 pub mod client;
 pub mod convert;
 pub mod server;
+pub mod tokens;
+pub mod transcript;
 pub mod types;
 
 // Re-export commonly used types
-pub use client::{ApiClient, ApiError};
+pub use client::{verify_api_key, ApiClient, ApiError, KeyStatus, RequestLimits};
 pub use convert::map_model;
-pub use server::{create_router, run_server, run_server_from_env, AppState};
+pub use server::{create_router, run_server, run_server_from_env, run_server_with_shutdown, AppState};
 pub use types::{
-    ContentBlock, Message, MessageContent, MessagesRequest, MessagesResponse, ProxyConfig,
+    ConfigField, ConfigSource, ContentBlock, Message, MessageContent, MessagesRequest, MessagesResponse, ProxyConfig,
     ResponseContentBlock, StopReason, StreamEvent, Tool, Usage,
 };
@@ -0,0 +1,126 @@
+//! Token Counting
+//!
+//! Counts request tokens with the `cl100k_base` BPE tokenizer. Claude's tokenizer
+//! isn't public, but cl100k is far closer than a character heuristic, especially
+//! for code and non-English text. If the tokenizer can't be loaded, counting falls
+//! back to roughly four characters per token.
+
+use super::types::*;
+use std::sync::OnceLock;
+use tiktoken_rs::CoreBPE;
+
+fn tokenizer() -> Option<&'static CoreBPE> {
+    static TOKENIZER: OnceLock<Option<CoreBPE>> = OnceLock::new();
+    TOKENIZER
+        .get_or_init(|| match tiktoken_rs::cl100k_base() {
+            Ok(bpe) => Some(bpe),
+            Err(e) => {
+                log::warn!("Failed to load cl100k_base tokenizer, estimating instead: {}", e);
+                None
+            }
+        })
+        .as_ref()
+}
+
+/// Tokens in a piece of text
+pub fn count_text_tokens(text: &str) -> usize {
+    if text.is_empty() {
+        return 0;
+    }
+    match tokenizer() {
+        Some(bpe) => bpe.encode_ordinary(text).len(),
+        None => text.chars().count().div_ceil(4),
+    }
+}
+
+fn count_block_tokens(block: &ContentBlock) -> usize {
+    match block {
+        ContentBlock::Text { text } => count_text_tokens(text),
+        ContentBlock::ToolUse { name, input, .. } => {
+            count_text_tokens(name) + count_text_tokens(&serde_json::to_string(input).unwrap_or_default())
+        }
+        ContentBlock::ToolResult { content, .. } => match content {
+            ToolResultContent::Text(text) => count_text_tokens(text),
+            ToolResultContent::Blocks(blocks) => blocks.iter().map(count_block_tokens).sum(),
+        },
+        ContentBlock::Document { source, title, context, .. } => {
+            let text = source.get("data").and_then(|d| d.as_str()).unwrap_or_default();
+            count_text_tokens(text)
+                + title.as_deref().map(count_text_tokens).unwrap_or(0)
+                + context.as_deref().map(count_text_tokens).unwrap_or(0)
+        }
+        // Image cost depends on resolution, which we don't decode here
+        ContentBlock::Image { .. } => 0,
+    }
+}
+
+/// Input tokens for a request: system prompt, messages (including tool calls) and tool definitions
+pub fn count_tokens(request: &TokenCountRequest) -> u32 {
+    count_input_tokens(request.system.as_ref(), &request.messages, request.tools.as_deref())
+}
+
+/// Input tokens for the parts of a request that are sent upstream as the prompt
+pub fn count_input_tokens(system: Option<&SystemContent>, messages: &[Message], tools: Option<&[Tool]>) -> u32 {
+    let mut total = 0usize;
+
+    if let Some(system) = system {
+        total += match system {
+            SystemContent::Text(text) => count_text_tokens(text),
+            SystemContent::Blocks(blocks) => blocks.iter().map(|b| count_text_tokens(&b.text)).sum(),
+        };
+    }
+
+    for msg in messages {
+        total += match &msg.content {
+            MessageContent::Text(text) => count_text_tokens(text),
+            MessageContent::Blocks(blocks) => blocks.iter().map(count_block_tokens).sum(),
+        };
+    }
+
+    for tool in tools.unwrap_or_default() {
+        total += count_text_tokens(&tool.name);
+        total += tool.description.as_deref().map(count_text_tokens).unwrap_or(0);
+        total += count_text_tokens(&serde_json::to_string(&tool.input_schema).unwrap_or_default());
+    }
+
+    (total as u32).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_count_text_tokens_matches_tiktoken() {
+        // Reference counts from tiktoken's cl100k_base
+        assert_eq!(count_text_tokens("hello world"), 2);
+        let count = count_text_tokens("tiktoken is great!");
+        assert!((5..=7).contains(&count), "got {}", count);
+        assert_eq!(count_text_tokens(""), 0);
+    }
+
+    #[test]
+    fn test_count_tokens_includes_tool_use_input() {
+        let mut request: TokenCountRequest = serde_json::from_value(json!({
+            "model": "claude-3-sonnet",
+            "system": "You are helpful.",
+            "messages": [{ "role": "user", "content": "Read the file" }]
+        }))
+        .unwrap();
+        let without_tool_use = count_tokens(&request);
+
+        request.messages.push(Message {
+            role: "assistant".to_string(),
+            content: MessageContent::Blocks(vec![ContentBlock::ToolUse {
+                id: "toolu_1".to_string(),
+                name: "read_file".to_string(),
+                input: json!({ "path": "src/main.rs" }),
+            }]),
+        });
+        let with_tool_use = count_tokens(&request);
+
+        assert!(without_tool_use >= 6);
+        assert!(with_tool_use > without_tool_use + 5, "{} vs {}", with_tool_use, without_tool_use);
+    }
+}
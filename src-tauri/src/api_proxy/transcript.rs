@@ -0,0 +1,466 @@
+//! Request/Response Transcripts
+//!
+//! When `PROXY_TRANSCRIPT_DIR` is set, every completed request is written to that
+//! directory as a JSON file (`{timestamp, model, request, response, usage, latency_ms}`).
+//! Streaming responses are reassembled into a regular `MessagesResponse` first; a stream
+//! that fails part-way is written with what arrived so far plus an `error` field.
+//! Writes happen on a background task so they never hold up the request path.
+//!
+//! [`render_markdown`] turns a conversation into a shareable Markdown document.
+
+use super::types::*;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Keys whose values are replaced before a transcript is written
+const REDACTED_KEYS: &[&str] = &[
+    "api_key",
+    "apikey",
+    "x-api-key",
+    "authorization",
+    "access_token",
+    "secret",
+    "password",
+];
+
+/// Replace credential-like fields anywhere in a JSON value
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                let lower = key.to_lowercase();
+                if REDACTED_KEYS.contains(&lower.as_str()) || lower.ends_with("_api_key") {
+                    *v = json!("[REDACTED]");
+                } else {
+                    redact(v);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                redact(item);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Writes transcript files and prunes old ones
+#[derive(Debug, Clone)]
+pub struct TranscriptWriter {
+    dir: PathBuf,
+    max_files: usize,
+}
+
+impl TranscriptWriter {
+    /// Create a writer if transcripts are enabled in the config
+    pub fn from_config(config: &ProxyConfig) -> Option<Self> {
+        config.transcript_dir.as_ref().map(|dir| Self {
+            dir: PathBuf::from(dir),
+            max_files: config.transcript_max_files,
+        })
+    }
+
+    /// Record a completed request in the background
+    pub fn record(&self, request: &MessagesRequest, response: &MessagesResponse, latency_ms: u64) {
+        self.spawn_write(request, response, None, latency_ms);
+    }
+
+    /// Record a stream that failed part-way, with the response received before the error
+    pub fn record_error(&self, request: &MessagesRequest, partial: &MessagesResponse, error: &str, latency_ms: u64) {
+        self.spawn_write(request, partial, Some(error), latency_ms);
+    }
+
+    fn spawn_write(&self, request: &MessagesRequest, response: &MessagesResponse, error: Option<&str>, latency_ms: u64) {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        let mut request_value = serde_json::to_value(request).unwrap_or(Value::Null);
+        redact(&mut request_value);
+        let mut response_value = serde_json::to_value(response).unwrap_or(Value::Null);
+        redact(&mut response_value);
+
+        let mut entry = json!({
+            "timestamp": timestamp,
+            "model": request.model,
+            "request": request_value,
+            "response": response_value,
+            "usage": response.usage,
+            "latency_ms": latency_ms,
+        });
+        if let Some(error) = error {
+            entry["error"] = json!(error);
+        }
+
+        let writer = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = writer.write(timestamp, &entry).await {
+                log::warn!("Failed to write transcript: {}", e);
+            }
+        });
+    }
+
+    async fn write(&self, timestamp: u64, entry: &Value) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        // Zero-padded timestamp keeps file names in chronological order
+        let file_name = format!("{:016}-{}.json", timestamp, &uuid::Uuid::new_v4().simple().to_string()[..8]);
+        let content = serde_json::to_vec_pretty(entry)?;
+        tokio::fs::write(self.dir.join(file_name), content).await?;
+
+        self.prune().await
+    }
+
+    /// Remove the oldest transcripts beyond `max_files` (0 keeps every file)
+    async fn prune(&self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            return Ok(());
+        }
+
+        let mut names = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.ends_with(".json") {
+                names.push(name);
+            }
+        }
+
+        if names.len() <= self.max_files {
+            return Ok(());
+        }
+
+        names.sort();
+        let excess = names.len() - self.max_files;
+        for name in names.into_iter().take(excess) {
+            let _ = tokio::fs::remove_file(self.dir.join(name)).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Content block being rebuilt from stream deltas
+#[derive(Debug)]
+enum AccumulatedBlock {
+    Text(String),
+    ToolUse { id: String, name: String, json: String },
+    Thinking { thinking: String, signature: Option<String> },
+    RedactedThinking(String),
+}
+
+/// Reassembles a streamed response into a `MessagesResponse`
+#[derive(Debug, Default)]
+pub struct StreamAccumulator {
+    id: String,
+    model: String,
+    blocks: BTreeMap<u32, AccumulatedBlock>,
+    stop_reason: Option<StopReason>,
+    stop_sequence: Option<String>,
+    usage: Usage,
+}
+
+impl StreamAccumulator {
+    /// Apply one stream event
+    pub fn push(&mut self, event: &StreamEvent) {
+        match event {
+            StreamEvent::MessageStart { message } => {
+                self.id = message.id.clone();
+                self.model = message.model.clone();
+                self.usage.input_tokens = message.usage.input_tokens;
+            }
+            StreamEvent::ContentBlockStart { index, content_block } => {
+                let block = match content_block {
+                    StreamContentBlock::Text { text } => AccumulatedBlock::Text(text.clone()),
+                    StreamContentBlock::ToolUse { id, name, .. } => AccumulatedBlock::ToolUse {
+                        id: id.clone(),
+                        name: name.clone(),
+                        json: String::new(),
+                    },
+                    StreamContentBlock::Thinking { thinking, signature } => AccumulatedBlock::Thinking {
+                        thinking: thinking.clone(),
+                        signature: signature.clone(),
+                    },
+                    StreamContentBlock::RedactedThinking { data } => AccumulatedBlock::RedactedThinking(data.clone()),
+                };
+                self.blocks.insert(*index, block);
+            }
+            StreamEvent::ContentBlockDelta { index, delta } => {
+                match (self.blocks.get_mut(index), delta) {
+                    (Some(AccumulatedBlock::Text(text)), StreamDelta::TextDelta { text: delta }) => {
+                        text.push_str(delta);
+                    }
+                    (Some(AccumulatedBlock::ToolUse { json, .. }), StreamDelta::InputJsonDelta { partial_json }) => {
+                        json.push_str(partial_json);
+                    }
+                    (Some(AccumulatedBlock::Thinking { thinking, .. }), StreamDelta::ThinkingDelta { thinking: delta }) => {
+                        thinking.push_str(delta);
+                    }
+                    (Some(AccumulatedBlock::Thinking { signature, .. }), StreamDelta::SignatureDelta { signature: delta }) => {
+                        signature.get_or_insert_with(String::new).push_str(delta);
+                    }
+                    _ => {}
+                }
+            }
+            StreamEvent::MessageDelta { delta, usage } => {
+                self.stop_reason = delta.stop_reason;
+                self.stop_sequence = delta.stop_sequence.clone();
+                self.usage.output_tokens = usage.output_tokens;
+                if usage.input_tokens > 0 {
+                    self.usage.input_tokens = usage.input_tokens;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Build the final response
+    pub fn finish(self) -> MessagesResponse {
+        let content = self
+            .blocks
+            .into_values()
+            .map(|block| match block {
+                AccumulatedBlock::Text(text) => ResponseContentBlock::Text { text },
+                AccumulatedBlock::ToolUse { id, name, json } => ResponseContentBlock::ToolUse {
+                    id,
+                    name,
+                    input: serde_json::from_str(&json).unwrap_or_else(|_| json!({})),
+                },
+                AccumulatedBlock::Thinking { thinking, signature } => {
+                    ResponseContentBlock::Thinking { thinking, signature }
+                }
+                AccumulatedBlock::RedactedThinking(data) => ResponseContentBlock::RedactedThinking { data },
+            })
+            .collect();
+
+        MessagesResponse {
+            id: self.id,
+            model: self.model,
+            role: "assistant".to_string(),
+            content,
+            response_type: "message".to_string(),
+            stop_reason: self.stop_reason,
+            stop_sequence: self.stop_sequence,
+            usage: self.usage,
+            logprobs: None,
+        }
+    }
+}
+
+/// Tool results longer than this many lines are collapsed in Markdown exports
+const COLLAPSE_LINES: usize = 20;
+
+/// Code fence one backtick longer than any run inside `body`, so content can't close it early
+fn fenced(body: &str, lang: &str) -> String {
+    let longest = body
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{fence}{lang}\n{}\n{fence}\n", body.trim_end_matches('\n'))
+}
+
+fn tool_result_text(content: &ToolResultContent) -> String {
+    match content {
+        ToolResultContent::Text(text) => text.clone(),
+        ToolResultContent::Blocks(blocks) => blocks
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text.clone()),
+                ContentBlock::Image { .. } => Some("[image]".to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Render a conversation as readable Markdown: a section per message, tool calls and
+/// results in code fences, and long results folded into `<details>` blocks
+pub fn render_markdown(system: Option<&SystemContent>, messages: &[Message]) -> String {
+    let mut out = String::from("# Conversation\n\n");
+
+    if let Some(system) = system {
+        let text = match system {
+            SystemContent::Text(text) => text.clone(),
+            SystemContent::Blocks(blocks) => blocks.iter().map(|b| b.text.as_str()).collect::<Vec<_>>().join("\n\n"),
+        };
+        out.push_str("## System\n\n");
+        out.push_str(text.trim());
+        out.push_str("\n\n");
+    }
+
+    // Tool results only carry the call id; show the tool's name instead
+    let mut tool_names: BTreeMap<&str, &str> = BTreeMap::new();
+
+    for message in messages {
+        let role = match message.role.as_str() {
+            "user" => "User",
+            "assistant" => "Assistant",
+            other => other,
+        };
+        out.push_str(&format!("## {}\n\n", role));
+
+        let blocks = match &message.content {
+            MessageContent::Text(text) => {
+                out.push_str(text.trim());
+                out.push_str("\n\n");
+                continue;
+            }
+            MessageContent::Blocks(blocks) => blocks,
+        };
+
+        for block in blocks {
+            match block {
+                ContentBlock::Text { text } => {
+                    out.push_str(text.trim());
+                    out.push_str("\n\n");
+                }
+                ContentBlock::Image { source } => {
+                    out.push_str(&format!("_[image: {}]_\n\n", source.media_type));
+                }
+                ContentBlock::Document { title, .. } => {
+                    out.push_str(&format!("_[document: {}]_\n\n", title.as_deref().unwrap_or("untitled")));
+                }
+                ContentBlock::ToolUse { id, name, input } => {
+                    tool_names.insert(id, name);
+                    let input = serde_json::to_string_pretty(input).unwrap_or_default();
+                    out.push_str(&format!("**Tool call:** `{}`\n\n", name));
+                    out.push_str(&fenced(&input, "json"));
+                    out.push('\n');
+                }
+                ContentBlock::ToolResult { tool_use_id, content, is_error } => {
+                    let name = tool_names.get(tool_use_id.as_str()).copied().unwrap_or(tool_use_id);
+                    let label = if *is_error == Some(true) { "Tool error" } else { "Tool result" };
+                    let text = tool_result_text(content);
+                    let lines = text.lines().count();
+
+                    if lines > COLLAPSE_LINES {
+                        out.push_str(&format!(
+                            "<details>\n<summary>{}: <code>{}</code> ({} lines)</summary>\n\n",
+                            label, name, lines
+                        ));
+                        out.push_str(&fenced(&text, ""));
+                        out.push_str("\n</details>\n\n");
+                    } else {
+                        out.push_str(&format!("**{}:** `{}`\n\n", label, name));
+                        out.push_str(&fenced(&text, ""));
+                        out.push('\n');
+                    }
+                }
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let long_output: String = (1..=30).map(|i| format!("line {}\n", i)).collect();
+        let messages: Vec<Message> = serde_json::from_value(json!([
+            { "role": "user", "content": "List the files" },
+            { "role": "assistant", "content": [
+                { "type": "text", "text": "Running ls." },
+                { "type": "tool_use", "id": "toolu_1", "name": "bash", "input": { "command": "ls" } }
+            ]},
+            { "role": "user", "content": [
+                { "type": "tool_result", "tool_use_id": "toolu_1", "content": long_output }
+            ]},
+            { "role": "assistant", "content": "Here is a snippet:\n```rust\nfn main() {}\n```" }
+        ]))
+        .unwrap();
+        let system = SystemContent::Text("Be brief.".to_string());
+
+        let markdown = render_markdown(Some(&system), &messages);
+        assert!(markdown.starts_with("# Conversation\n\n## System\n\nBe brief.\n\n## User\n\nList the files"));
+        assert!(markdown.contains("**Tool call:** `bash`\n\n```json\n{\n  \"command\": \"ls\"\n}\n```"));
+        assert!(markdown.contains("<summary>Tool result: <code>bash</code> (30 lines)</summary>"));
+        assert!(markdown.contains("line 30\n```\n\n</details>"));
+        assert!(markdown.ends_with("```rust\nfn main() {}\n```\n\n"));
+    }
+
+    #[tokio::test]
+    async fn test_transcript_pruning() {
+        let dir = std::env::temp_dir().join(format!("transcripts-{}", uuid::Uuid::new_v4().simple()));
+        let count = || std::fs::read_dir(&dir).unwrap().count();
+
+        // 0 means unlimited; it must not prune the file that was just written
+        let unlimited = TranscriptWriter { dir: dir.clone(), max_files: 0 };
+        unlimited.write(1, &json!({})).await.unwrap();
+        unlimited.write(2, &json!({})).await.unwrap();
+        assert_eq!(count(), 2);
+
+        let capped = TranscriptWriter { dir: dir.clone(), max_files: 2 };
+        capped.write(3, &json!({})).await.unwrap();
+        assert_eq!(count(), 2);
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert!(names[0].starts_with("0000000000000002-"), "{:?}", names);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_redact_nested_keys() {
+        let mut value = json!({
+            "max_tokens": 100,
+            "metadata": { "api_key": "sk-123", "user_id": "u1" },
+            "headers": [{ "Authorization": "Bearer sk-456" }]
+        });
+        redact(&mut value);
+        assert_eq!(value["max_tokens"], 100);
+        assert_eq!(value["metadata"]["api_key"], "[REDACTED]");
+        assert_eq!(value["metadata"]["user_id"], "u1");
+        assert_eq!(value["headers"][0]["Authorization"], "[REDACTED]");
+    }
+
+    #[test]
+    fn test_stream_accumulator_rebuilds_response() {
+        let mut acc = StreamAccumulator::default();
+        acc.push(&StreamEvent::ContentBlockStart {
+            index: 0,
+            content_block: StreamContentBlock::Text { text: String::new() },
+        });
+        for part in ["Hel", "lo"] {
+            acc.push(&StreamEvent::ContentBlockDelta {
+                index: 0,
+                delta: StreamDelta::TextDelta { text: part.to_string() },
+            });
+        }
+        acc.push(&StreamEvent::ContentBlockStart {
+            index: 1,
+            content_block: StreamContentBlock::ToolUse {
+                id: "toolu_1".to_string(),
+                name: "read".to_string(),
+                input: json!({}),
+            },
+        });
+        acc.push(&StreamEvent::ContentBlockDelta {
+            index: 1,
+            delta: StreamDelta::InputJsonDelta { partial_json: r#"{"path":"a"}"#.to_string() },
+        });
+
+        let response = acc.finish();
+        assert_eq!(response.content.len(), 2);
+        match &response.content[0] {
+            ResponseContentBlock::Text { text } => assert_eq!(text, "Hello"),
+            other => panic!("unexpected block: {:?}", other),
+        }
+        match &response.content[1] {
+            ResponseContentBlock::ToolUse { input, .. } => assert_eq!(input["path"], "a"),
+            other => panic!("unexpected block: {:?}", other),
+        }
+    }
+}
@@ -15,6 +15,13 @@ pub mod api_proxy;
 // 全局状态：PID 到终端 ID 的映射 (这个用 std::sync::Mutex 因为只在 kill_process 中使用)
 type TerminalMap = Arc<std::sync::Mutex<HashMap<u32, String>>>;
 
+// 后台进程的启动参数（PID -> spec），用于 restart_process
+type ProcessSpecMap = Arc<std::sync::Mutex<HashMap<u32, ProcessSpec>>>;
+
+fn create_process_spec_map() -> ProcessSpecMap {
+    Arc::new(std::sync::Mutex::new(HashMap::new()))
+}
+
 // PTY 会话存储（存储writer用于输入）- 使用 tokio::sync::Mutex 支持异步
 type PtyWriter = Box<dyn std::io::Write + Send>;
 type PtyWriterMap = Arc<Mutex<HashMap<String, Arc<Mutex<PtyWriter>>>>>;
@@ -28,6 +35,79 @@ fn create_pty_writer_map() -> PtyWriterMap {
     Arc::new(Mutex::new(HashMap::new()))
 }
 
+// PTY master（终端 ID -> master），用于调整终端尺寸；resize 是同步的快速调用，用 std::sync::Mutex
+type PtyMaster = Box<dyn portable_pty::MasterPty + Send>;
+type PtyMasterMap = Arc<std::sync::Mutex<HashMap<String, PtyMaster>>>;
+
+fn create_pty_master_map() -> PtyMasterMap {
+    Arc::new(std::sync::Mutex::new(HashMap::new()))
+}
+
+// PTY 中的 shell 进程（终端 ID -> pid + killer），用于 terminal_signal；Child 本身交给等待退出的线程
+struct PtyProcess {
+    pid: Option<u32>,
+    // Unix signals go by pid; Windows can only terminate through the killer
+    #[cfg(target_os = "windows")]
+    killer: Box<dyn portable_pty::ChildKiller + Send + Sync>,
+}
+type PtyChildMap = Arc<std::sync::Mutex<HashMap<String, PtyProcess>>>;
+
+fn create_pty_child_map() -> PtyChildMap {
+    Arc::new(std::sync::Mutex::new(HashMap::new()))
+}
+
+// 终端元数据（终端 ID -> 工作目录、标题、尺寸），供 list_terminals 在前端刷新后重建标签页
+#[derive(Debug, Clone, Default)]
+struct TerminalMeta {
+    cwd: Option<String>,
+    title: Option<String>,
+    rows: u16,
+    cols: u16,
+}
+type PtyMetaMap = Arc<std::sync::Mutex<HashMap<String, TerminalMeta>>>;
+
+fn create_pty_meta_map() -> PtyMetaMap {
+    Arc::new(std::sync::Mutex::new(HashMap::new()))
+}
+
+// git blame 缓存：(项目, 文件) -> HEAD、文件内容哈希和结果；两者都未变化时直接返回
+type BlameCache = Arc<std::sync::Mutex<HashMap<(String, String), BlameCacheEntry>>>;
+
+// 缓存的文件数上限，超出时淘汰最久未使用的条目
+const BLAME_CACHE_MAX_ENTRIES: usize = 64;
+
+struct BlameCacheEntry {
+    head: String,
+    blob: String,
+    hunks: Vec<BlameHunk>,
+    last_used: std::time::Instant,
+}
+
+fn create_blame_cache() -> BlameCache {
+    Arc::new(std::sync::Mutex::new(HashMap::new()))
+}
+
+// 流式任务（文件索引、diff 流、下载、克隆）的取消句柄（id -> AbortHandle）
+type TaskAbortMap = Arc<std::sync::Mutex<HashMap<String, Arc<AbortHandle>>>>;
+
+fn create_task_abort_map() -> TaskAbortMap {
+    Arc::new(std::sync::Mutex::new(HashMap::new()))
+}
+
+// 目录监听（目录 -> watcher），drop watcher 即停止监听
+type FsWatcherMap = Arc<std::sync::Mutex<HashMap<String, notify::RecommendedWatcher>>>;
+
+fn create_fs_watcher_map() -> FsWatcherMap {
+    Arc::new(std::sync::Mutex::new(HashMap::new()))
+}
+
+// 正在运行的 Claude/Kiro CLI 会话（session id -> 取消通知），任务结束时移除
+type CliSessionMap = Arc<std::sync::Mutex<HashMap<String, Arc<tokio::sync::Notify>>>>;
+
+fn create_cli_session_map() -> CliSessionMap {
+    Arc::new(std::sync::Mutex::new(HashMap::new()))
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 struct ExtractResult {
     success: bool,
@@ -141,1641 +221,9160 @@ fn get_cli_path(_app_handle: tauri::AppHandle, cli_name: String) -> Result<Strin
     Ok(specific_cli_dir.to_string_lossy().to_string())
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct FileItem {
-    name: String,
-    path: String,
-    is_directory: bool,
-    size: Option<u64>,
-    modified_time: Option<u64>,
-}
+/// 遍历项目文件（遵循 .gitignore），返回相对路径；每找到一批文件就把这一批回调出去
+fn walk_project_files(
+    project_dir: &std::path::Path,
+    abort: &AbortHandle,
+    mut on_batch: impl FnMut(&[String]),
+) -> Vec<String> {
+    const PROGRESS_EVERY: usize = 500;
 
-/// 读取目录中的文件列表
-#[tauri::command]
-fn read_directory(directory: String) -> Result<Vec<FileItem>, String> {
-    let path = PathBuf::from(&directory);
+    let walker = ignore::WalkBuilder::new(project_dir)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
 
-    if !path.exists() {
-        return Err(format!("Directory does not exist: {}", directory));
+    let mut files = Vec::new();
+    for entry in walker.flatten() {
+        if abort.is_aborted() {
+            break;
+        }
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        if let Ok(relative) = entry.path().strip_prefix(project_dir) {
+            files.push(relative.to_string_lossy().replace('\\', "/"));
+            if files.len() % PROGRESS_EVERY == 0 {
+                on_batch(&files[files.len() - PROGRESS_EVERY..]);
+            }
+        }
     }
 
-    if !path.is_dir() {
-        return Err(format!("Path is not a directory: {}", directory));
-    }
+    files
+}
 
-    let entries = fs::read_dir(&path)
-        .map_err(|e| format!("Failed to read directory: {}", e))?;
+/// 非 git 目录快速计数的上限，超过后报告 "10000+"
+const QUICK_COUNT_CAP: u64 = 10_000;
 
-    let mut files = Vec::new();
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FileCount {
+    count: u64,
+    capped: bool,  // true 表示实际数量至少为 count
+}
 
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let metadata = entry.metadata().map_err(|e| format!("Failed to read metadata: {}", e))?;
-        let file_name = entry.file_name().to_string_lossy().to_string();
+/// 快速估算项目文件数（遵循 .gitignore）：git 仓库用 ls-files，否则有上限地遍历
+#[tauri::command]
+fn quick_file_count(project_dir: String) -> Result<FileCount, String> {
+    let root = PathBuf::from(&project_dir);
+    if !root.is_dir() {
+        return Err(format!("Directory does not exist: {}", project_dir));
+    }
 
-        // Skip hidden files (starting with .)
-        if file_name.starts_with('.') {
-            continue;
+    if root.join(".git").exists() {
+        let output = run_git(&project_dir, &["ls-files", "-z", "--cached", "--others", "--exclude-standard"])?;
+        if output.status.success() {
+            let count = output.stdout.iter().filter(|b| **b == 0).count() as u64;
+            return Ok(FileCount { count, capped: false });
         }
-
-        let modified_time = metadata.modified()
-            .ok()
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs());
-
-        files.push(FileItem {
-            name: file_name,
-            path: entry.path().to_string_lossy().to_string(),
-            is_directory: metadata.is_dir(),
-            size: if metadata.is_file() { Some(metadata.len()) } else { None },
-            modified_time,
-        });
     }
 
-    // Sort: directories first, then files, both alphabetically
-    files.sort_by(|a, b| {
-        match (a.is_directory, b.is_directory) {
-            (true, false) => std::cmp::Ordering::Less,
-            (false, true) => std::cmp::Ordering::Greater,
-            _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+    Ok(count_files_capped(&root, QUICK_COUNT_CAP))
+}
+
+/// 遍历计数（遵循 .gitignore），最多数到 cap；只有确实超过 cap 时才标记 capped
+fn count_files_capped(root: &std::path::Path, cap: u64) -> FileCount {
+    let walker = ignore::WalkBuilder::new(root)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+
+    let mut count = 0u64;
+    for entry in walker.flatten() {
+        if entry.file_type().is_some_and(|t| t.is_file()) {
+            if count == cap {
+                return FileCount { count, capped: true };
+            }
+            count += 1;
         }
-    });
+    }
 
-    Ok(files)
+    FileCount { count, capped: false }
 }
 
-/// 读取文件内容
+/// 流式索引项目文件：周期性发送 index-progress，结束时发送 index-complete
 #[tauri::command]
-fn read_file_content(file_path: String) -> Result<String, String> {
-    let path = PathBuf::from(&file_path);
-
-    if !path.exists() {
-        return Err(format!("File does not exist: {}", file_path));
+async fn index_project_files_stream(
+    app: tauri::AppHandle,
+    abort_map: tauri::State<'_, TaskAbortMap>,
+    project_dir: String,
+    index_id: String,
+) -> Result<(), String> {
+    let root = PathBuf::from(&project_dir);
+    if !root.is_dir() {
+        return Err(format!("Directory does not exist: {}", project_dir));
     }
 
-    if !path.is_file() {
-        return Err(format!("Path is not a file: {}", file_path));
+    let abort = register_abort(&abort_map, &index_id)?;
+
+    #[derive(serde::Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct IndexProgress {
+        index_id: String,
+        files_found: usize,
+        files: Vec<String>,  // 本批新找到的文件，前端可以先用部分结果做匹配
     }
 
-    fs::read_to_string(&path)
-        .map_err(|e| format!("Failed to read file: {}", e))
-}
+    #[derive(serde::Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct IndexComplete {
+        index_id: String,
+        files: Vec<String>,
+        cancelled: bool,
+    }
 
-/// 读取文件二进制内容（用于图片等）
-#[tauri::command]
-fn read_file_bytes(file_path: String) -> Result<Vec<u8>, String> {
-    let path = PathBuf::from(&file_path);
+    let walk_app = app.clone();
+    let walk_id = index_id.clone();
+    let walk_abort = Arc::clone(&abort);
+    let files = tokio::task::spawn_blocking(move || {
+        let mut files_found = 0;
+        walk_project_files(&root, &walk_abort, |batch| {
+            files_found += batch.len();
+            let _ = walk_app.emit("index-progress", IndexProgress {
+                index_id: walk_id.clone(),
+                files_found,
+                files: batch.to_vec(),
+            });
+        })
+    })
+    .await
+    .map_err(|e| format!("Index task failed: {}", e));
 
-    if !path.exists() {
-        return Err(format!("File does not exist: {}", file_path));
+    if let Ok(mut map) = abort_map.lock() {
+        map.remove(&index_id);
     }
+    let files = files?;
 
-    if !path.is_file() {
-        return Err(format!("Path is not a file: {}", file_path));
-    }
+    println!("[index_project_files_stream] {} files in {}", files.len(), project_dir);
+    let _ = app.emit("index-complete", IndexComplete {
+        index_id,
+        files,
+        cancelled: abort.is_aborted(),
+    });
 
-    fs::read(&path)
-        .map_err(|e| format!("Failed to read file: {}", e))
+    Ok(())
 }
 
-/// 创建新文件
+/// 取消正在进行的文件索引
 #[tauri::command]
-fn create_file(file_path: String, content: Option<String>) -> Result<(), String> {
-    let path = PathBuf::from(&file_path);
+fn cancel_index(abort_map: tauri::State<'_, TaskAbortMap>, index_id: String) -> Result<bool, String> {
+    abort_task(&abort_map, &index_id)
+}
 
-    if path.exists() {
-        return Err(format!("File already exists: {}", file_path));
+/// 流式任务的取消句柄：同步代码检查标记，异步代码在 select! 中等待通知
+#[derive(Default)]
+struct AbortHandle {
+    aborted: std::sync::atomic::AtomicBool,
+    notify: tokio::sync::Notify,
+}
+
+impl AbortHandle {
+    fn abort(&self) {
+        self.aborted.store(true, std::sync::atomic::Ordering::SeqCst);
+        self.notify.notify_waiters();
     }
 
-    // Ensure parent directory exists
-    if let Some(parent) = path.parent() {
-        if !parent.exists() {
-            fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create parent directory: {}", e))?;
+    fn is_aborted(&self) -> bool {
+        self.aborted.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// 等待取消；先登记通知再检查标记，这样在两者之间发生的 abort 也不会丢失
+    async fn wait(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if self.is_aborted() {
+            return;
         }
+        notified.await;
     }
+}
 
-    let content = content.unwrap_or_default();
-    fs::write(&path, content)
-        .map_err(|e| format!("Failed to create file: {}", e))
+/// 在取消表中登记一个流式任务，返回它的取消句柄
+fn register_abort(abort_map: &TaskAbortMap, id: &str) -> Result<Arc<AbortHandle>, String> {
+    let abort = Arc::new(AbortHandle::default());
+    abort_map
+        .lock()
+        .map_err(|e| format!("Failed to lock abort map: {}", e))?
+        .insert(id.to_string(), Arc::clone(&abort));
+    Ok(abort)
 }
 
-/// 创建新文件夹
-#[tauri::command]
-fn create_directory(dir_path: String) -> Result<(), String> {
-    let path = PathBuf::from(&dir_path);
+/// 取消流式任务；任务不存在（已结束）时返回 false
+fn abort_task(abort_map: &TaskAbortMap, id: &str) -> Result<bool, String> {
+    let map = abort_map
+        .lock()
+        .map_err(|e| format!("Failed to lock abort map: {}", e))?;
 
-    if path.exists() {
-        return Err(format!("Directory already exists: {}", dir_path));
+    match map.get(id) {
+        Some(abort) => {
+            abort.abort();
+            Ok(true)
+        }
+        None => Ok(false),
     }
-
-    fs::create_dir_all(&path)
-        .map_err(|e| format!("Failed to create directory: {}", e))
 }
 
-/// 删除文件或文件夹
-#[tauri::command]
-fn delete_path(target_path: String) -> Result<(), String> {
-    let path = PathBuf::from(&target_path);
-
-    if !path.exists() {
-        return Err(format!("Path does not exist: {}", target_path));
-    }
+/// search_files 的默认结果上限，防止在超大目录上耗尽内存
+const SEARCH_DEFAULT_MAX_RESULTS: usize = 1000;
+/// 超过此大小的文件不参与内容搜索
+const SEARCH_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+/// 单行返回的最大字符数（压缩后的 js 等超长行会被截断）
+const SEARCH_MAX_LINE_CHARS: usize = 500;
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct SearchOptions {
+    case_sensitive: bool,
+    whole_word: bool,
+    regex: bool,
+    max_results: Option<usize>,
+    include: Vec<String>,         // glob，非空时只搜索匹配的文件
+    exclude: Vec<String>,         // glob，匹配的文件和目录被跳过
+    include_ignored: bool,        // true 时不遵循 .gitignore
+}
 
-    if path.is_dir() {
-        fs::remove_dir_all(&path)
-            .map_err(|e| format!("Failed to delete directory: {}", e))
-    } else {
-        fs::remove_file(&path)
-            .map_err(|e| format!("Failed to delete file: {}", e))
-    }
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SearchMatch {
+    path: String,
+    line_number: usize,  // 从 1 开始
+    line_text: String,
+    column: usize,       // 从 1 开始，按字符计
 }
 
-/// 重命名文件或文件夹
-#[tauri::command]
-fn rename_path(old_path: String, new_path: String) -> Result<(), String> {
-    let old = PathBuf::from(&old_path);
-    let new = PathBuf::from(&new_path);
+/// 根据选项构造搜索用的正则
+fn build_search_regex(query: &str, options: &SearchOptions) -> Result<regex::Regex, String> {
+    let pattern = if options.regex { query.to_string() } else { regex::escape(query) };
+    let pattern = if options.whole_word { format!(r"\b(?:{})\b", pattern) } else { pattern };
+    regex::RegexBuilder::new(&pattern)
+        .case_insensitive(!options.case_sensitive)
+        .build()
+        .map_err(|e| format!("Invalid search pattern: {}", e))
+}
 
-    if !old.exists() {
-        return Err(format!("Path does not exist: {}", old_path));
+/// 遍历目录做内容搜索（遵循 .gitignore，跳过二进制文件），最多返回 max_results 条
+fn search_tree(
+    root: &std::path::Path,
+    query: &regex::Regex,
+    options: &SearchOptions,
+) -> Result<Vec<SearchMatch>, String> {
+    let max_results = options.max_results.unwrap_or(SEARCH_DEFAULT_MAX_RESULTS);
+
+    let mut overrides = ignore::overrides::OverrideBuilder::new(root);
+    for glob in &options.include {
+        overrides.add(glob).map_err(|e| format!("Invalid include glob: {}", e))?;
+    }
+    for glob in &options.exclude {
+        overrides.add(&format!("!{}", glob)).map_err(|e| format!("Invalid exclude glob: {}", e))?;
     }
+    let overrides = overrides.build().map_err(|e| format!("Invalid glob: {}", e))?;
+
+    let walker = ignore::WalkBuilder::new(root)
+        .hidden(false)
+        .require_git(false)
+        .git_ignore(!options.include_ignored)
+        .git_exclude(!options.include_ignored)
+        .ignore(!options.include_ignored)
+        .overrides(overrides)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+
+    let mut matches = Vec::new();
+    for entry in walker.flatten() {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        if entry.metadata().map(|m| m.len() > SEARCH_MAX_FILE_SIZE).unwrap_or(true) {
+            continue;
+        }
+        let Ok(bytes) = fs::read(entry.path()) else {
+            continue;
+        };
+        // 前 8KB 内出现 NUL 字节视为二进制文件
+        if bytes.iter().take(8192).any(|b| *b == 0) {
+            continue;
+        }
 
-    if new.exists() {
-        return Err(format!("Target path already exists: {}", new_path));
+        let text = String::from_utf8_lossy(&bytes);
+        for (index, line) in text.lines().enumerate() {
+            let Some(found) = query.find(line) else {
+                continue;
+            };
+            matches.push(SearchMatch {
+                path: entry.path().to_string_lossy().to_string(),
+                line_number: index + 1,
+                line_text: line.chars().take(SEARCH_MAX_LINE_CHARS).collect(),
+                column: line[..found.start()].chars().count() + 1,
+            });
+            if matches.len() >= max_results {
+                return Ok(matches);
+            }
+        }
     }
 
-    fs::rename(&old, &new)
-        .map_err(|e| format!("Failed to rename: {}", e))
+    Ok(matches)
 }
 
-/// 保存文件内容
+/// 在目录下搜索文件内容，每个匹配行返回一条结果；遍历在阻塞线程池中进行
 #[tauri::command]
-fn save_file(file_path: String, content: String) -> Result<(), String> {
-    let path = PathBuf::from(&file_path);
+async fn search_files(
+    directory: String,
+    query: String,
+    options: Option<SearchOptions>,
+) -> Result<Vec<SearchMatch>, String> {
+    let root = PathBuf::from(&directory);
+    if !root.is_dir() {
+        return Err(format!("Directory does not exist: {}", directory));
+    }
+    if query.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let options = options.unwrap_or_default();
+    let regex = build_search_regex(&query, &options)?;
+    let matches = tokio::task::spawn_blocking(move || search_tree(&root, &regex, &options))
+        .await
+        .map_err(|e| format!("Search task failed: {}", e))??;
 
-    fs::write(&path, content)
-        .map_err(|e| format!("Failed to save file: {}", e))
+    println!("[search_files] {} matches for {:?} in {}", matches.len(), query, directory);
+    Ok(matches)
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
-struct SkillInfo {
+struct ArchiveEntry {
     name: String,
-    path: String,
-    token_count: Option<u64>,
+    size: u64,
+    is_dir: bool,
+    compressed_size: Option<u64>,  // tar 条目没有单独的压缩大小
 }
 
-/// 读取 ~/.claude/skills 目录下的 skills
-#[tauri::command]
-fn read_claude_skills() -> Result<Vec<SkillInfo>, String> {
-    let home = std::env::var("HOME")
-        .or_else(|_| std::env::var("USERPROFILE"))
-        .map_err(|e| format!("Failed to get home directory: {}", e))?;
-
-    let skills_dir = PathBuf::from(home).join(".claude").join("skills");
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
 
-    if !skills_dir.exists() {
-        return Ok(Vec::new()); // 如果目录不存在，返回空列表
+/// 根据扩展名判断压缩包类型
+fn archive_kind(path: &str) -> Result<ArchiveKind, String> {
+    let lower = path.to_lowercase();
+    if lower.ends_with(".zip") {
+        Ok(ArchiveKind::Zip)
+    } else if lower.ends_with(".tar.gz") || lower.ends_with(".tgz") {
+        Ok(ArchiveKind::TarGz)
+    } else if lower.ends_with(".tar") {
+        Ok(ArchiveKind::Tar)
+    } else {
+        Err(format!("Unsupported archive format: {}", path))
     }
+}
 
-    if !skills_dir.is_dir() {
-        return Err(format!("Skills path is not a directory: {:?}", skills_dir));
+fn open_tar(path: &str, gzip: bool) -> Result<tar::Archive<Box<dyn std::io::Read>>, String> {
+    let file = fs::File::open(path).map_err(|e| format!("Failed to open archive: {}", e))?;
+    let reader: Box<dyn std::io::Read> = if gzip {
+        Box::new(flate2::read::GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// 校验条目名只包含普通路径段，防止 zip-slip（../ 或绝对路径）；
+/// 去掉 `./`，让 `./x` 和 `x` 指向同一个条目
+fn safe_entry_path(entry_name: &str) -> Result<PathBuf, String> {
+    use std::path::Component;
+
+    let mut path = PathBuf::new();
+    for component in std::path::Path::new(entry_name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return Err(format!("Unsafe archive entry path: {}", entry_name)),
+        }
+    }
+    if path.as_os_str().is_empty() {
+        return Err(format!("Unsafe archive entry path: {}", entry_name));
     }
+    Ok(path)
+}
 
-    let entries = fs::read_dir(&skills_dir)
-        .map_err(|e| format!("Failed to read skills directory: {}", e))?;
+/// Stream an archive entry to `target` through a temporary file next to it, so a failed
+/// extraction never leaves a partial file behind
+fn write_extracted_entry(reader: &mut dyn std::io::Read, target: &std::path::Path) -> Result<(), String> {
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| format!("Invalid destination path: {}", target.display()))?
+        .to_string_lossy();
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create parent directory: {}", e))?;
+    }
 
-    let mut skills = Vec::new();
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    let part_path = target.with_file_name(format!(".{}.{}.part", file_name, suffix));
+    let mut out = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&part_path)
+        .map_err(|e| format!("Failed to create file: {}", e))?;
+    let copied = std::io::copy(reader, &mut out);
+    drop(out);
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&part_path);
+        return Err(format!("Failed to extract entry: {}", e));
+    }
 
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let metadata = entry.metadata().map_err(|e| format!("Failed to read metadata: {}", e))?;
+    fs::rename(&part_path, target).map_err(|e| {
+        let _ = fs::remove_file(&part_path);
+        format!("Failed to extract entry: {}", e)
+    })
+}
 
-        // 只处理目录
-        if !metadata.is_dir() {
-            continue;
+/// 列出压缩包（zip / tar / tar.gz）中的条目，不解压
+#[tauri::command]
+fn list_archive_entries(path: String) -> Result<Vec<ArchiveEntry>, String> {
+    let mut entries = Vec::new();
+
+    match archive_kind(&path)? {
+        ArchiveKind::Zip => {
+            let file = fs::File::open(&path).map_err(|e| format!("Failed to open archive: {}", e))?;
+            let mut archive = zip::ZipArchive::new(file)
+                .map_err(|e| format!("Failed to read zip archive: {}", e))?;
+            for i in 0..archive.len() {
+                let entry = archive
+                    .by_index(i)
+                    .map_err(|e| format!("Failed to read zip entry: {}", e))?;
+                entries.push(ArchiveEntry {
+                    name: entry.name().to_string(),
+                    size: entry.size(),
+                    is_dir: entry.is_dir(),
+                    compressed_size: Some(entry.compressed_size()),
+                });
+            }
         }
-
-        let skill_name = entry.file_name().to_string_lossy().to_string();
-
-        // 跳过隐藏目录
-        if skill_name.starts_with('.') {
-            continue;
+        kind => {
+            let mut archive = open_tar(&path, matches!(kind, ArchiveKind::TarGz))?;
+            let tar_entries = archive
+                .entries()
+                .map_err(|e| format!("Failed to read tar archive: {}", e))?;
+            for entry in tar_entries {
+                let entry = entry.map_err(|e| format!("Failed to read tar entry: {}", e))?;
+                let name = entry
+                    .path()
+                    .map_err(|e| format!("Invalid tar entry path: {}", e))?
+                    .to_string_lossy()
+                    .to_string();
+                entries.push(ArchiveEntry {
+                    name,
+                    size: entry.size(),
+                    is_dir: entry.header().entry_type().is_dir(),
+                    compressed_size: None,
+                });
+            }
         }
+    }
 
-        // 尝试估算 token 数量（通过读取目录下的文件大小）
-        let skill_path = entry.path();
-        let token_count = estimate_skill_tokens(&skill_path);
+    Ok(entries)
+}
 
-        skills.push(SkillInfo {
-            name: skill_name,
-            path: skill_path.to_string_lossy().to_string(),
-            token_count,
-        });
+/// 从压缩包中解压单个文件到 dest 目录，返回解压后的路径；目标已存在时报错而不覆盖
+#[tauri::command]
+fn extract_archive_entry(path: String, entry_name: String, dest: String) -> Result<String, String> {
+    let relative = safe_entry_path(&entry_name)?;
+    let target = PathBuf::from(&dest).join(&relative);
+    if fs::symlink_metadata(&target).is_ok() {
+        return Err(format!("Target path already exists: {}", target.display()));
     }
 
-    // 按名称排序
-    skills.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-
-    Ok(skills)
-}
-
-/// 估算 skill 的 token 数量（粗略估计：字符数 / 4）
-fn estimate_skill_tokens(skill_path: &PathBuf) -> Option<u64> {
-    let mut total_chars = 0u64;
-
-    if let Ok(entries) = fs::read_dir(skill_path) {
-        for entry in entries.flatten() {
-            if let Ok(metadata) = entry.metadata() {
-                if metadata.is_file() {
-                    if let Ok(content) = fs::read_to_string(entry.path()) {
-                        total_chars += content.len() as u64;
+    // 条目名按规范化后的路径比较（不安全的条目名永远不会匹配）
+    let is_requested = |name: &str| safe_entry_path(name).is_ok_and(|p| p == relative);
+
+    match archive_kind(&path)? {
+        ArchiveKind::Zip => {
+            let file = fs::File::open(&path).map_err(|e| format!("Failed to open archive: {}", e))?;
+            let mut archive = zip::ZipArchive::new(file)
+                .map_err(|e| format!("Failed to read zip archive: {}", e))?;
+            let index = (0..archive.len())
+                .find(|&i| archive.name_for_index(i).is_some_and(is_requested))
+                .ok_or_else(|| format!("Entry not found in archive: {}", entry_name))?;
+            let mut entry = archive
+                .by_index(index)
+                .map_err(|e| format!("Failed to read zip entry: {}", e))?;
+            if entry.is_dir() {
+                return Err(format!("Entry is a directory: {}", entry_name));
+            }
+            write_extracted_entry(&mut entry, &target)?;
+        }
+        kind => {
+            let mut archive = open_tar(&path, matches!(kind, ArchiveKind::TarGz))?;
+            let tar_entries = archive
+                .entries()
+                .map_err(|e| format!("Failed to read tar archive: {}", e))?;
+            let mut found = false;
+            for entry in tar_entries {
+                let mut entry = entry.map_err(|e| format!("Failed to read tar entry: {}", e))?;
+                let matches = entry
+                    .path()
+                    .is_ok_and(|p| is_requested(&p.to_string_lossy()));
+                if matches {
+                    if entry.header().entry_type().is_dir() {
+                        return Err(format!("Entry is a directory: {}", entry_name));
                     }
+                    write_extracted_entry(&mut entry, &target)?;
+                    found = true;
+                    break;
                 }
             }
+            if !found {
+                return Err(format!("Entry not found in archive: {}", entry_name));
+            }
         }
     }
 
-    if total_chars > 0 {
-        // 粗略估计：每个 token 约 4 个字符
-        Some(total_chars / 4)
-    } else {
-        None
-    }
+    Ok(target.to_string_lossy().to_string())
+}
+
+/// 最近项目列表最多保留的条目数
+const MAX_RECENT_PROJECTS: usize = 20;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct RecentProjectEntry {
+    path: String,
+    last_opened: u64,   // ms since epoch
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
-struct McpServerInfo {
+struct RecentProject {
+    path: String,
     name: String,
-    transport: String,  // "stdio" or "http"
-    disabled: Option<bool>,
-    // stdio transport
-    command: Option<String>,
-    args: Option<Vec<String>>,
-    env: Option<HashMap<String, String>>,
-    // http transport
-    url: Option<String>,
-    headers: Option<HashMap<String, String>>,
+    last_opened: u64,
+    exists: bool,
+    is_git: bool,
+    branch: Option<String>,
 }
 
-/// 读取 ~/.claude.json 中的 MCP servers 配置
-#[tauri::command]
-fn read_mcp_servers() -> Result<Vec<McpServerInfo>, String> {
-    let home = std::env::var("HOME")
-        .or_else(|_| std::env::var("USERPROFILE"))
-        .map_err(|e| format!("Failed to get home directory: {}", e))?;
-
-    let claude_config_path = PathBuf::from(home).join(".claude.json");
+/// 最近项目记录文件 ~/.voltcode/recent.json
+fn recent_projects_file() -> Result<PathBuf, String> {
+    Ok(get_voltcode_dir()?.join("recent.json"))
+}
 
-    if !claude_config_path.exists() {
-        return Ok(Vec::new()); // 如果配置文件不存在，返回空列表
+fn load_recent_projects() -> Result<Vec<RecentProjectEntry>, String> {
+    let file = recent_projects_file()?;
+    if !file.exists() {
+        return Ok(Vec::new());
     }
 
-    let content = fs::read_to_string(&claude_config_path)
-        .map_err(|e| format!("Failed to read ~/.claude.json: {}", e))?;
-
-    let config: serde_json::Value = serde_json::from_str(&content)
-        .map_err(|e| format!("Failed to parse ~/.claude.json: {}", e))?;
-
-    let mut servers = Vec::new();
+    let content = fs::read_to_string(&file)
+        .map_err(|e| format!("Failed to read recent projects: {}", e))?;
+    // 文件损坏时当作空列表，下次打开项目会重新写入
+    Ok(serde_json::from_str(&content).unwrap_or_default())
+}
 
-    if let Some(mcp_servers) = config.get("mcpServers").and_then(|v| v.as_object()) {
-        for (name, server_config) in mcp_servers {
-            // Detect transport type: if has "url" field, it's http; otherwise stdio
-            let url = server_config.get("url").and_then(|v| v.as_str()).map(|s| s.to_string());
-            let transport = if url.is_some() {
-                "http".to_string()
+/// 读取最近打开的项目（按时间倒序）
+#[tauri::command]
+fn get_recent_projects() -> Result<Vec<RecentProject>, String> {
+    let projects = load_recent_projects()?
+        .into_iter()
+        .map(|entry| {
+            let path = PathBuf::from(&entry.path);
+            let exists = path.is_dir();
+            let is_git = exists && path.join(".git").exists();
+            let branch = if is_git {
+                run_git(&entry.path, &["branch", "--show-current"])
+                    .ok()
+                    .filter(|o| o.status.success())
+                    .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+                    .filter(|b| !b.is_empty())
             } else {
-                "stdio".to_string()
+                None
             };
 
-            let command = server_config.get("command").and_then(|v| v.as_str()).map(|s| s.to_string());
-            let args = server_config.get("args").and_then(|v| v.as_array()).map(|arr| {
-                arr.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect()
-            });
-            let env = server_config.get("env").and_then(|v| v.as_object()).map(|obj| {
-                obj.iter()
-                    .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
-                    .collect()
-            });
+            RecentProject {
+                name: path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_else(|| entry.path.clone()),
+                path: entry.path,
+                last_opened: entry.last_opened,
+                exists,
+                is_git,
+                branch,
+            }
+        })
+        .collect();
 
-            let disabled = server_config.get("disabled").and_then(|v| v.as_bool());
-            let headers = server_config.get("headers").and_then(|v| v.as_object()).map(|obj| {
-                obj.iter()
-                    .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
-                    .collect()
-            });
+    Ok(projects)
+}
 
-            servers.push(McpServerInfo {
-                name: name.clone(),
-                transport,
-                disabled,
-                command,
-                args,
-                env,
-                url,
-                headers,
-            });
-        }
-    }
+/// 记录项目被打开（移到列表首位）
+#[tauri::command]
+fn touch_recent_project(path: String) -> Result<(), String> {
+    let trimmed = path.trim_end_matches(['/', '\\']);
+    let path = if trimmed.is_empty() { path.as_str() } else { trimmed }.to_string();
 
-    // 按名称排序
-    servers.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    let mut entries = load_recent_projects()?;
+    entries.retain(|entry| entry.path != path);
+    entries.insert(0, RecentProjectEntry { path, last_opened: now });
+    entries.truncate(MAX_RECENT_PROJECTS);
+
+    let file = recent_projects_file()?;
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+    }
 
-    Ok(servers)
+    let content = serde_json::to_string_pretty(&entries)
+        .map_err(|e| format!("Failed to serialize recent projects: {}", e))?;
+    write_file_atomic(&file, content.as_bytes())
 }
 
-/// 添加 MCP server 到 ~/.claude.json
+/// 将对话导出为 Markdown 文件（原子写入）
 #[tauri::command]
-fn add_mcp_server(
-    name: String,
-    transport: String,
-    command: Option<String>,
-    args: Option<Vec<String>>,
-    env: Option<HashMap<String, String>>,
-    url: Option<String>,
-    token: Option<String>,
+fn export_transcript(
+    messages: Vec<api_proxy::Message>,
+    system: Option<api_proxy::types::SystemContent>,
+    dest_path: String,
 ) -> Result<(), String> {
-    let home = std::env::var("HOME")
-        .or_else(|_| std::env::var("USERPROFILE"))
-        .map_err(|e| format!("Failed to get home directory: {}", e))?;
+    let markdown = api_proxy::transcript::render_markdown(system.as_ref(), &messages);
+    write_file_atomic(std::path::Path::new(&dest_path), markdown.as_bytes())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileItem {
+    name: String,
+    path: String,
+    is_directory: bool,
+    size: Option<u64>,
+    modified_time: Option<u64>,
+    is_hidden: bool,
+}
 
-    let claude_config_path = PathBuf::from(home).join(".claude.json");
+/// 是否为隐藏文件：以 . 开头，Windows 上还包括带隐藏属性的文件
+fn is_hidden_entry(file_name: &str, metadata: &fs::Metadata) -> bool {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0 {
+            return true;
+        }
+    }
+    #[cfg(not(target_os = "windows"))]
+    let _ = metadata;
+    file_name.starts_with('.')
+}
 
-    // Read existing config or create new
-    let mut config: serde_json::Value = if claude_config_path.exists() {
-        let content = fs::read_to_string(&claude_config_path)
-            .map_err(|e| format!("Failed to read ~/.claude.json: {}", e))?;
-        serde_json::from_str(&content)
-            .map_err(|e| format!("Failed to parse ~/.claude.json: {}", e))?
-    } else {
-        serde_json::json!({})
-    };
+/// 读取目录中的文件列表（默认跳过以 . 开头的文件，show_hidden 为 true 时包含）。
+/// Windows 隐藏属性只体现在 is_hidden 上，不参与过滤，默认结果和以前一致
+#[tauri::command]
+fn read_directory(directory: String, show_hidden: Option<bool>) -> Result<Vec<FileItem>, String> {
+    let show_hidden = show_hidden.unwrap_or(false);
+    let path = PathBuf::from(&directory);
 
-    // Ensure mcpServers object exists
-    if config.get("mcpServers").is_none() {
-        config["mcpServers"] = serde_json::json!({});
+    if !path.exists() {
+        return Err(format!("Directory does not exist: {}", directory));
     }
 
-    // Create server config based on transport type
-    let server_config = if transport == "http" {
-        // HTTP transport - requires url
-        let url_value = url.ok_or("URL is required for HTTP transport")?;
-        let mut cfg = serde_json::json!({
-            "type": "http",
-            "url": url_value
-        });
+    if !path.is_dir() {
+        return Err(format!("Path is not a directory: {}", directory));
+    }
 
-        // Add Authorization header if token is provided
-        if let Some(token_value) = token {
-            if !token_value.is_empty() {
-                cfg["headers"] = serde_json::json!({
-                    "Authorization": format!("Bearer {}", token_value)
-                });
-            }
+    let entries = fs::read_dir(&path)
+        .map_err(|e| format!("Failed to read directory: {}", e))?;
+
+    let mut files = Vec::new();
+
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+        let metadata = entry.metadata().map_err(|e| format!("Failed to read metadata: {}", e))?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        // Skip dotfiles unless asked for them
+        if file_name.starts_with('.') && !show_hidden {
+            continue;
         }
+        let is_hidden = is_hidden_entry(&file_name, &metadata);
 
-        cfg
-    } else {
-        // stdio transport - requires command
-        let cmd = command.ok_or("Command is required for stdio transport")?;
-        let mut cfg = serde_json::json!({
-            "type": "stdio",
-            "command": cmd
+        let modified_time = metadata.modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+
+        files.push(FileItem {
+            name: file_name,
+            path: entry.path().to_string_lossy().to_string(),
+            is_directory: metadata.is_dir(),
+            size: if metadata.is_file() { Some(metadata.len()) } else { None },
+            modified_time,
+            is_hidden,
         });
+    }
 
-        if let Some(args_vec) = args {
-            cfg["args"] = serde_json::json!(args_vec);
+    // Sort: directories first, then files, both alphabetically
+    files.sort_by(|a, b| {
+        match (a.is_directory, b.is_directory) {
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
         }
+    });
 
-        if let Some(env_map) = env {
-            cfg["env"] = serde_json::json!(env_map);
-        }
+    Ok(files)
+}
 
-        cfg
-    };
+/// 文件系统变化（fs-change 事件中的一项）
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+struct FsChange {
+    kind: &'static str,  // "create", "modify", "remove" or "rename"
+    path: String,
+}
 
-    // Add server to config
-    config["mcpServers"][&name] = server_config;
+/// Map a notify event to fs-change entries; access and other metadata-only events are dropped
+fn fs_changes(event: notify::Event) -> Vec<FsChange> {
+    use notify::event::{EventKind, ModifyKind};
 
-    // Write back to file
-    let content = serde_json::to_string_pretty(&config)
-        .map_err(|e| format!("Failed to serialize config: {}", e))?;
+    let kind = match event.kind {
+        EventKind::Create(_) => "create",
+        EventKind::Modify(ModifyKind::Name(_)) => "rename",
+        EventKind::Modify(_) => "modify",
+        EventKind::Remove(_) => "remove",
+        _ => return Vec::new(),
+    };
+    event
+        .paths
+        .into_iter()
+        .map(|path| FsChange { kind, path: path.to_string_lossy().to_string() })
+        .collect()
+}
 
-    fs::write(&claude_config_path, content)
-        .map_err(|e| format!("Failed to write ~/.claude.json: {}", e))?;
+/// Watch a directory and report changes in batches: a batch is flushed once no event has
+/// arrived for `debounce` (or after 10x `debounce` during a continuous storm), with duplicates
+/// removed. Watching stops when the returned watcher is dropped.
+fn spawn_fs_watcher(
+    directory: &str,
+    recursive: bool,
+    debounce: std::time::Duration,
+    on_changes: impl Fn(Vec<FsChange>) + Send + 'static,
+) -> Result<notify::RecommendedWatcher, String> {
+    use notify::Watcher;
+    use std::sync::mpsc::RecvTimeoutError;
+
+    let (tx, rx) = std::sync::mpsc::channel::<FsChange>();
+    let mut watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+        match result {
+            Ok(event) => {
+                for change in fs_changes(event) {
+                    let _ = tx.send(change);
+                }
+            }
+            Err(e) => println!("[watch_directory] Watch error: {}", e),
+        }
+    })
+    .map_err(|e| format!("Failed to create watcher: {}", e))?;
+
+    let mode = if recursive { notify::RecursiveMode::Recursive } else { notify::RecursiveMode::NonRecursive };
+    watcher
+        .watch(std::path::Path::new(directory), mode)
+        .map_err(|e| format!("Failed to watch {}: {}", directory, e))?;
+
+    // The sender lives in the watcher's callback, so this thread ends once the watcher is dropped
+    std::thread::spawn(move || {
+        while let Ok(first) = rx.recv() {
+            let started = std::time::Instant::now();
+            let max_wait = debounce * 10;
+            let mut batch = vec![first];
+            let mut disconnected = false;
+            while started.elapsed() < max_wait {
+                match rx.recv_timeout(debounce.min(max_wait.saturating_sub(started.elapsed()))) {
+                    Ok(change) => batch.push(change),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        disconnected = true;
+                        break;
+                    }
+                }
+            }
+            let mut seen = std::collections::HashSet::new();
+            batch.retain(|change| seen.insert((change.kind, change.path.clone())));
+            on_changes(batch);
+            if disconnected {
+                break;
+            }
+        }
+    });
 
-    Ok(())
+    Ok(watcher)
 }
 
-/// 删除 MCP server 从 ~/.claude.json
+/// 监听目录变化：每个去抖批次（200ms）向前端发送一个 fs-change 事件 `{ directory, changes: [{ kind, path }] }`；
+/// recursive 默认为 true，包含所有子目录
 #[tauri::command]
-fn remove_mcp_server(name: String) -> Result<(), String> {
-    let home = std::env::var("HOME")
-        .or_else(|_| std::env::var("USERPROFILE"))
-        .map_err(|e| format!("Failed to get home directory: {}", e))?;
-
-    let claude_config_path = PathBuf::from(home).join(".claude.json");
-
-    if !claude_config_path.exists() {
-        return Err("Config file does not exist".to_string());
+fn watch_directory(
+    app: tauri::AppHandle,
+    fs_watchers: tauri::State<'_, FsWatcherMap>,
+    directory: String,
+    recursive: Option<bool>,
+) -> Result<(), String> {
+    if !PathBuf::from(&directory).is_dir() {
+        return Err(format!("Path is not a directory: {}", directory));
     }
 
-    let content = fs::read_to_string(&claude_config_path)
-        .map_err(|e| format!("Failed to read ~/.claude.json: {}", e))?;
+    #[derive(serde::Serialize, Clone)]
+    struct FsChangeBatch {
+        directory: String,
+        changes: Vec<FsChange>,
+    }
 
-    let mut config: serde_json::Value = serde_json::from_str(&content)
-        .map_err(|e| format!("Failed to parse ~/.claude.json: {}", e))?;
+    let watched = directory.clone();
+    let watcher = spawn_fs_watcher(
+        &directory,
+        recursive.unwrap_or(true),
+        std::time::Duration::from_millis(200),
+        move |changes| {
+            let _ = app.emit("fs-change", FsChangeBatch { directory: watched.clone(), changes });
+        },
+    )?;
+
+    // Replacing an existing watcher for the same directory drops (stops) the old one
+    fs_watchers
+        .lock()
+        .map_err(|e| format!("Lock error: {}", e))?
+        .insert(directory.clone(), watcher);
+    println!("[watch_directory] Watching {}", directory);
+    Ok(())
+}
 
-    // Remove server from mcpServers
-    if let Some(mcp_servers) = config.get_mut("mcpServers").and_then(|v| v.as_object_mut()) {
-        if mcp_servers.remove(&name).is_none() {
-            return Err(format!("MCP server '{}' not found", name));
+/// 停止监听目录
+#[tauri::command]
+fn unwatch_directory(fs_watchers: tauri::State<'_, FsWatcherMap>, directory: String) -> Result<(), String> {
+    let removed = fs_watchers
+        .lock()
+        .map_err(|e| format!("Lock error: {}", e))?
+        .remove(&directory);
+    match removed {
+        Some(_) => {
+            println!("[watch_directory] Stopped watching {}", directory);
+            Ok(())
         }
-    } else {
-        return Err("No MCP servers configured".to_string());
+        None => Err(format!("Directory is not being watched: {}", directory)),
     }
+}
 
-    // Write back to file
-    let content = serde_json::to_string_pretty(&config)
-        .map_err(|e| format!("Failed to serialize config: {}", e))?;
+/// 读取文件内容
+#[tauri::command]
+fn read_file_content(file_path: String) -> Result<String, String> {
+    let path = PathBuf::from(&file_path);
 
-    fs::write(&claude_config_path, content)
-        .map_err(|e| format!("Failed to write ~/.claude.json: {}", e))?;
+    if !path.exists() {
+        return Err(format!("File does not exist: {}", file_path));
+    }
 
-    Ok(())
+    if !path.is_file() {
+        return Err(format!("Path is not a file: {}", file_path));
+    }
+
+    fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read file: {}", e))
 }
 
-/// 切换 MCP server 启用/禁用状态
-#[tauri::command]
-fn toggle_mcp_server(name: String, disabled: bool) -> Result<(), String> {
-    let home = std::env::var("HOME")
-        .or_else(|_| std::env::var("USERPROFILE"))
-        .map_err(|e| format!("Failed to get home directory: {}", e))?;
+/// read_file_text 默认最多读取的字节数
+const READ_TEXT_DEFAULT_MAX_BYTES: u64 = 5 * 1024 * 1024;
 
-    let claude_config_path = PathBuf::from(home).join(".claude.json");
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FileText {
+    content: String,
+    encoding: String,  // "utf-8" | "utf-16le" | "utf-16be" | "latin-1"
+    truncated: bool,   // 文件超过 max_bytes，只读取了开头部分
+    lossy: bool,       // 有无法解码的字节被替换为 U+FFFD
+    size: u64,         // 文件实际大小
+}
 
-    if !claude_config_path.exists() {
-        return Err("Config file does not exist".to_string());
+/// 规范化编码名称
+fn normalize_encoding(name: &str) -> Result<&'static str, String> {
+    match name.to_lowercase().replace('_', "-").as_str() {
+        "utf-8" | "utf8" => Ok("utf-8"),
+        "utf-16le" | "utf-16" => Ok("utf-16le"),
+        "utf-16be" => Ok("utf-16be"),
+        "latin-1" | "latin1" | "iso-8859-1" => Ok("latin-1"),
+        _ => Err(format!("Unsupported encoding: {}", name)),
     }
+}
 
-    let content = fs::read_to_string(&claude_config_path)
-        .map_err(|e| format!("Failed to read ~/.claude.json: {}", e))?;
+/// 根据 BOM 和字节分布猜测编码，返回 (编码, BOM 长度)；truncated 表示 bytes 只是文件开头
+fn detect_encoding(bytes: &[u8], truncated: bool) -> (&'static str, usize) {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return ("utf-8", 3);
+    }
+    if bytes.starts_with(&[0xFF, 0xFE]) {
+        return ("utf-16le", 2);
+    }
+    if bytes.starts_with(&[0xFE, 0xFF]) {
+        return ("utf-16be", 2);
+    }
 
-    let mut config: serde_json::Value = serde_json::from_str(&content)
-        .map_err(|e| format!("Failed to parse ~/.claude.json: {}", e))?;
+    // 无 BOM 的 UTF-16：ASCII 文本的高位字节全是 0，集中在奇数或偶数位置
+    let sample = &bytes[..bytes.len().min(4096)];
+    if sample.len() >= 4 {
+        let even_zeros = sample.iter().step_by(2).filter(|b| **b == 0).count();
+        let odd_zeros = sample.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+        let half = sample.len() / 2;
+        if odd_zeros * 10 >= half * 4 && even_zeros * 10 < half {
+            return ("utf-16le", 0);
+        }
+        if even_zeros * 10 >= half * 4 && odd_zeros * 10 < half {
+            return ("utf-16be", 0);
+        }
+    }
 
-    // Toggle server disabled state
-    if let Some(mcp_servers) = config.get_mut("mcpServers").and_then(|v| v.as_object_mut()) {
-        if let Some(server) = mcp_servers.get_mut(&name) {
-            if disabled {
-                server["disabled"] = serde_json::json!(true);
-            } else {
-                // Remove disabled field if enabling (false = not disabled = enabled)
-                if let Some(obj) = server.as_object_mut() {
-                    obj.remove("disabled");
-                }
-            }
-        } else {
-            return Err(format!("MCP server '{}' not found", name));
+    // 截断处可能切开多字节字符，末尾不完整的序列不算非法
+    match std::str::from_utf8(bytes) {
+        Ok(_) => ("utf-8", 0),
+        Err(e) if truncated && e.error_len().is_none() => ("utf-8", 0),
+        Err(_) => ("latin-1", 0),
+    }
+}
+
+/// 按指定编码解码，返回 (文本, 是否有损)
+fn decode_text(bytes: &[u8], encoding: &str, truncated: bool) -> (String, bool) {
+    match encoding {
+        "utf-16le" | "utf-16be" => {
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| if encoding == "utf-16le" {
+                    u16::from_le_bytes([pair[0], pair[1]])
+                } else {
+                    u16::from_be_bytes([pair[0], pair[1]])
+                })
+                .collect();
+            let mut lossy = false;
+            let text = char::decode_utf16(units)
+                .map(|c| c.unwrap_or_else(|_| {
+                    lossy = true;
+                    char::REPLACEMENT_CHARACTER
+                }))
+                .collect();
+            (text, lossy)
+        }
+        "latin-1" => (bytes.iter().map(|b| *b as char).collect(), false),
+        _ => {
+            // 去掉截断留下的不完整 UTF-8 序列
+            let bytes = match std::str::from_utf8(bytes) {
+                Err(e) if truncated && e.error_len().is_none() => &bytes[..e.valid_up_to()],
+                _ => bytes,
+            };
+            let text = String::from_utf8_lossy(bytes);
+            let lossy = matches!(text, std::borrow::Cow::Owned(_));
+            (text.into_owned(), lossy)
         }
-    } else {
-        return Err("No MCP servers configured".to_string());
     }
+}
 
-    // Write back to file
-    let content = serde_json::to_string_pretty(&config)
-        .map_err(|e| format!("Failed to serialize config: {}", e))?;
+/// 以文本方式读取文件：自动识别 UTF-8 / UTF-16 / latin-1（也可通过 encoding 指定），
+/// 超过 max_bytes 时只读取开头部分并标记 truncated
+#[tauri::command]
+fn read_file_text(file_path: String, encoding: Option<String>, max_bytes: Option<u64>) -> Result<FileText, String> {
+    use std::io::Read;
+
+    let path = PathBuf::from(&file_path);
+    if !path.exists() {
+        return Err(format!("File does not exist: {}", file_path));
+    }
+    if !path.is_file() {
+        return Err(format!("Path is not a file: {}", file_path));
+    }
 
-    fs::write(&claude_config_path, content)
-        .map_err(|e| format!("Failed to write ~/.claude.json: {}", e))?;
+    let max_bytes = max_bytes.unwrap_or(READ_TEXT_DEFAULT_MAX_BYTES);
+    let file = fs::File::open(&path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let size = file.metadata().map_err(|e| format!("Failed to read metadata: {}", e))?.len();
 
-    Ok(())
+    let mut bytes = Vec::with_capacity(size.min(max_bytes) as usize);
+    file.take(max_bytes)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+
+    let truncated = size > max_bytes;
+    let (detected, bom_len) = detect_encoding(&bytes, truncated);
+    let encoding = match encoding {
+        Some(name) => normalize_encoding(&name)?,
+        None => detected,
+    };
+    // 只有与 BOM 对应的编码一致时才去掉 BOM
+    let body = if encoding == detected { &bytes[bom_len..] } else { &bytes[..] };
+    let (content, lossy) = decode_text(body, encoding, truncated);
+
+    Ok(FileText {
+        content,
+        encoding: encoding.to_string(),
+        truncated,
+        lossy,
+        size,
+    })
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
-struct GitStatusFile {
-    status: String,      // e.g., "M", "A", "D", "??"
-    path: String,
-    staged: bool,
+struct HighlightStyle {
+    foreground: String,  // "#rrggbb"
+    bold: bool,
+    italic: bool,
+    underline: bool,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
-struct GitStatus {
-    branch: String,
-    ahead: u32,
-    behind: u32,
-    files: Vec<GitStatusFile>,
-    is_repo: bool,
+struct HighlightSpan {
+    start: usize,        // UTF-16 offsets, matching JavaScript string indices
+    end: usize,
+    style: HighlightStyle,
 }
 
-/// 获取 git status
-#[tauri::command]
-fn git_status(project_dir: String) -> Result<GitStatus, String> {
-    use std::process::Command;
-
-    // Check if it's a git repo
-    let git_dir = PathBuf::from(&project_dir).join(".git");
-    if !git_dir.exists() {
-        return Ok(GitStatus {
-            branch: String::new(),
-            ahead: 0,
-            behind: 0,
-            files: Vec::new(),
-            is_repo: false,
-        });
-    }
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HighlightedLine {
+    text: String,
+    spans: Vec<HighlightSpan>,
+}
 
-    // Get branch name
-    let branch_output = Command::new("git")
-        .args(["branch", "--show-current"])
-        .current_dir(&project_dir)
-        .output()
-        .map_err(|e| format!("Failed to run git branch: {}", e))?;
+/// Syntax and theme definitions are expensive to load, so keep them for the app's lifetime
+fn syntect_assets() -> &'static (syntect::parsing::SyntaxSet, syntect::highlighting::ThemeSet) {
+    static ASSETS: std::sync::OnceLock<(syntect::parsing::SyntaxSet, syntect::highlighting::ThemeSet)> =
+        std::sync::OnceLock::new();
+    ASSETS.get_or_init(|| {
+        (
+            syntect::parsing::SyntaxSet::load_defaults_newlines(),
+            syntect::highlighting::ThemeSet::load_defaults(),
+        )
+    })
+}
 
-    let branch = String::from_utf8_lossy(&branch_output.stdout).trim().to_string();
+/// 读取文件并返回语法高亮后的行（按扩展名识别语言，未知类型返回纯文本行）
+#[tauri::command]
+async fn highlight_file(path: String, theme: Option<String>) -> Result<Vec<HighlightedLine>, String> {
+    let file_path = PathBuf::from(&path);
 
-    // Get ahead/behind info
-    let status_branch = Command::new("git")
-        .args(["status", "-sb"])
-        .current_dir(&project_dir)
-        .output()
-        .map_err(|e| format!("Failed to run git status: {}", e))?;
+    if !file_path.is_file() {
+        return Err(format!("Path is not a file: {}", path));
+    }
 
-    let status_line = String::from_utf8_lossy(&status_branch.stdout);
-    let first_line = status_line.lines().next().unwrap_or("");
+    let content = fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
 
-    let mut ahead = 0u32;
-    let mut behind = 0u32;
+    tokio::task::spawn_blocking(move || {
+        use syntect::easy::HighlightLines;
+        use syntect::highlighting::FontStyle;
+        use syntect::util::LinesWithEndings;
+
+        let (syntax_set, theme_set) = syntect_assets();
+
+        let syntax = file_path
+            .extension()
+            .and_then(|ext| syntax_set.find_syntax_by_extension(&ext.to_string_lossy()));
+
+        let Some(syntax) = syntax else {
+            // Unknown language: plain lines without spans
+            return Ok(content
+                .lines()
+                .map(|line| HighlightedLine { text: line.to_string(), spans: Vec::new() })
+                .collect());
+        };
 
-    if let Some(bracket_start) = first_line.find('[') {
-        if let Some(bracket_end) = first_line.find(']') {
-            let info = &first_line[bracket_start + 1..bracket_end];
-            for part in info.split(", ") {
-                if part.starts_with("ahead ") {
-                    ahead = part[6..].parse().unwrap_or(0);
-                } else if part.starts_with("behind ") {
-                    behind = part[7..].parse().unwrap_or(0);
+        let theme_name = theme.unwrap_or_else(|| "base16-ocean.dark".to_string());
+        let theme = theme_set
+            .themes
+            .get(&theme_name)
+            .ok_or_else(|| format!("Unknown theme: {}", theme_name))?;
+
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        let mut lines = Vec::new();
+
+        for line in LinesWithEndings::from(&content) {
+            let ranges = highlighter
+                .highlight_line(line, syntax_set)
+                .map_err(|e| format!("Failed to highlight: {}", e))?;
+
+            let text = line.trim_end_matches(['\n', '\r']);
+            let text_len = text.encode_utf16().count();
+            let mut spans = Vec::new();
+            let mut offset = 0usize;
+
+            for (style, piece) in ranges {
+                let start = offset;
+                offset += piece.encode_utf16().count();
+                let end = offset.min(text_len);
+                if end <= start {
+                    continue;
                 }
+                let fg = style.foreground;
+                spans.push(HighlightSpan {
+                    start,
+                    end,
+                    style: HighlightStyle {
+                        foreground: format!("#{:02x}{:02x}{:02x}", fg.r, fg.g, fg.b),
+                        bold: style.font_style.contains(FontStyle::BOLD),
+                        italic: style.font_style.contains(FontStyle::ITALIC),
+                        underline: style.font_style.contains(FontStyle::UNDERLINE),
+                    },
+                });
             }
-        }
-    }
-
-    // Get file status
-    let status_output = Command::new("git")
-        .args(["status", "--porcelain=v1"])
-        .current_dir(&project_dir)
-        .output()
-        .map_err(|e| format!("Failed to run git status: {}", e))?;
 
-    let status_text = String::from_utf8_lossy(&status_output.stdout);
-    let mut files = Vec::new();
-
-    for line in status_text.lines() {
-        if line.len() < 4 {
-            continue;
+            lines.push(HighlightedLine { text: text.to_string(), spans });
         }
 
-        let index_status = line.chars().nth(0).unwrap_or(' ');
-        let worktree_status = line.chars().nth(1).unwrap_or(' ');
-        let file_path = line[3..].to_string();
+        Ok(lines)
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))?
+}
 
-        // Determine status code (single letter for cleaner display)
-        let status = match (index_status, worktree_status) {
-            ('?', '?') => "N".to_string(),  // New/Untracked
-            ('M', _) | (_, 'M') => "M".to_string(),  // Modified
-            ('A', _) => "A".to_string(),  // Added (staged)
-            ('D', _) | (_, 'D') => "D".to_string(),  // Deleted
-            ('R', _) => "R".to_string(),  // Renamed
-            ('C', _) => "C".to_string(),  // Copied
-            ('U', _) => "U".to_string(),  // Unmerged (conflict)
-            ('!', '!') => "I".to_string(),  // Ignored
-            _ => "?".to_string(),  // Unknown
-        };
+/// 读取文件二进制内容（用于图片等）
+#[tauri::command]
+fn read_file_bytes(file_path: String) -> Result<Vec<u8>, String> {
+    let path = PathBuf::from(&file_path);
 
-        let staged = index_status != ' ' && index_status != '?';
+    if !path.exists() {
+        return Err(format!("File does not exist: {}", file_path));
+    }
 
-        files.push(GitStatusFile {
-            status,
-            path: file_path,
-            staged,
-        });
+    if !path.is_file() {
+        return Err(format!("Path is not a file: {}", file_path));
     }
 
-    Ok(GitStatus {
-        branch,
-        ahead,
-        behind,
-        files,
-        is_repo: true,
-    })
+    fs::read(&path)
+        .map_err(|e| format!("Failed to read file: {}", e))
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct GitCommit {
-    graph: String,
-    short_hash: String,
-    refs: String,
-    message: String,
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum FileKind {
+    Text,
+    Binary,
+    Image,
+    Empty,
+    Missing,     // also directories and unreadable files
 }
 
-/// 获取 git log with graph
-#[tauri::command]
-fn git_log(project_dir: String, limit: Option<u32>) -> Result<Vec<GitCommit>, String> {
-    use std::process::Command;
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FileClass {
+    path: String,
+    kind: FileKind,
+}
 
-    let limit = limit.unwrap_or(50);
+/// Bytes sniffed from the start of each file
+const CLASSIFY_SNIFF_BYTES: usize = 8192;
+
+fn is_image_magic(head: &[u8], file_len: u64) -> bool {
+    head.starts_with(b"\x89PNG\r\n\x1a\n")
+        || head.starts_with(&[0xFF, 0xD8, 0xFF])
+        || head.starts_with(b"GIF87a")
+        || head.starts_with(b"GIF89a")
+        || (head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP")
+        || is_bmp_header(head, file_len)
+        || head.starts_with(&[0x00, 0x00, 0x01, 0x00]) // ICO
+        || head.starts_with(b"II*\0") // TIFF, little and big endian
+        || head.starts_with(b"MM\0*")
+        || (head.len() >= 12 && &head[4..8] == b"ftyp" && matches!(&head[8..12], b"avif" | b"heic" | b"heix"))
+}
 
-    // Check if it's a git repo
-    let git_dir = PathBuf::from(&project_dir).join(".git");
-    if !git_dir.exists() {
-        return Ok(Vec::new());
+/// "BM" alone also starts plenty of text files, so check the rest of the BMP file header:
+/// zero reserved fields and a pixel-data offset inside the file
+fn is_bmp_header(head: &[u8], file_len: u64) -> bool {
+    if head.len() < 14 || !head.starts_with(b"BM") || head[6..10] != [0, 0, 0, 0] {
+        return false;
     }
+    let offset = u32::from_le_bytes([head[10], head[11], head[12], head[13]]) as u64;
+    offset >= 14 && offset < file_len
+}
 
-    // Get commit log with graph
-    // Format: graph + hash + refs + message
-    let log_output = Command::new("git")
-        .args([
-            "log",
-            &format!("-{}", limit),
-            "--graph",
-            "--pretty=format:%h|%D|%s",
-            "--abbrev-commit",
-        ])
-        .current_dir(&project_dir)
-        .output()
-        .map_err(|e| format!("Failed to run git log: {}", e))?;
-
-    let log_text = String::from_utf8_lossy(&log_output.stdout);
-    let mut commits = Vec::new();
-
-    for line in log_text.lines() {
-        // Split at first occurrence of commit hash (7 chars after graph symbols)
-        // Graph chars: * | / \ space
-        let mut graph_end = 0;
-        let chars: Vec<char> = line.chars().collect();
+fn classify_path(path: &std::path::Path) -> FileKind {
+    use std::io::Read;
 
-        for (i, c) in chars.iter().enumerate() {
-            if *c != '*' && *c != '|' && *c != '/' && *c != '\\' && *c != ' ' && *c != '_' {
-                graph_end = i;
-                break;
-            }
-        }
+    let Ok(file) = fs::File::open(path) else {
+        return FileKind::Missing;
+    };
+    let file_len = match file.metadata() {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => return FileKind::Missing,
+    };
 
-        let graph = chars[..graph_end].iter().collect::<String>();
-        let rest = chars[graph_end..].iter().collect::<String>();
+    let mut head = Vec::with_capacity(CLASSIFY_SNIFF_BYTES);
+    if file.take(CLASSIFY_SNIFF_BYTES as u64).read_to_end(&mut head).is_err() {
+        return FileKind::Missing;
+    }
 
-        // Parse rest: hash|refs|message
-        let parts: Vec<&str> = rest.splitn(3, '|').collect();
-        if parts.len() >= 3 {
-            commits.push(GitCommit {
-                graph,
-                short_hash: parts[0].to_string(),
-                refs: parts[1].to_string(),
-                message: parts[2].to_string(),
-            });
-        } else if parts.len() >= 1 && !parts[0].is_empty() {
-            // Fallback for merge lines without full info
-            commits.push(GitCommit {
-                graph,
-                short_hash: parts.get(0).unwrap_or(&"").to_string(),
-                refs: parts.get(1).unwrap_or(&"").to_string(),
-                message: parts.get(2).unwrap_or(&"").to_string(),
-            });
-        } else if !graph.is_empty() {
-            // Pure graph line (continuation)
-            commits.push(GitCommit {
-                graph,
-                short_hash: String::new(),
-                refs: String::new(),
-                message: String::new(),
-            });
-        }
+    if head.is_empty() {
+        FileKind::Empty
+    } else if is_image_magic(&head, file_len) {
+        FileKind::Image
+    } else if head.starts_with(&[0xFF, 0xFE]) || head.starts_with(&[0xFE, 0xFF]) {
+        // UTF-16 text is full of NULs but has a BOM
+        FileKind::Text
+    } else if head.contains(&0) {
+        FileKind::Binary
+    } else {
+        FileKind::Text
     }
+}
 
-    Ok(commits)
+/// 批量判断文件类型（文本/二进制/图片/空文件/不存在），用于资源管理器选择图标和查看器
+#[tauri::command]
+fn classify_files(paths: Vec<String>) -> Vec<FileClass> {
+    paths
+        .into_iter()
+        .map(|path| {
+            let kind = classify_path(std::path::Path::new(&path));
+            FileClass { path, kind }
+        })
+        .collect()
 }
 
-/// Get git diff for files (for AI commit message generation)
+/// 创建新文件；目标已存在时报错，除非 overwrite 为 true（此时原子替换）
 #[tauri::command]
-fn git_diff(project_dir: String, files: Option<Vec<String>>) -> Result<String, String> {
-    use std::process::Command;
+fn create_file(file_path: String, content: Option<String>, overwrite: Option<bool>) -> Result<(), String> {
+    use std::io::Write;
 
-    let git_dir = PathBuf::from(&project_dir).join(".git");
-    if !git_dir.exists() {
-        return Err("Not a git repository".to_string());
+    let path = PathBuf::from(&file_path);
+    let content = content.unwrap_or_default();
+
+    if overwrite.unwrap_or(false) && path.exists() {
+        if !path.is_file() {
+            return Err(format!("Path is not a file: {}", file_path));
+        }
+        return write_file_atomic(&path, content.as_bytes());
     }
 
-    // Get diff for specific files or all
-    let output = if let Some(ref file_list) = files {
-        if file_list.is_empty() {
-            // Get all changes
-            Command::new("git")
-                .args(["diff", "HEAD", "--stat"])
-                .current_dir(&project_dir)
-                .output()
-                .map_err(|e| format!("Failed to get diff: {}", e))?
-        } else {
-            let mut args = vec!["diff", "HEAD", "--stat", "--"];
-            args.extend(file_list.iter().map(|s| s.as_str()));
-            Command::new("git")
-                .args(&args)
-                .current_dir(&project_dir)
-                .output()
-                .map_err(|e| format!("Failed to get diff: {}", e))?
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        if !parent.exists() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create parent directory: {}", e))?;
         }
-    } else {
-        Command::new("git")
-            .args(["diff", "HEAD", "--stat"])
-            .current_dir(&project_dir)
-            .output()
-            .map_err(|e| format!("Failed to get diff: {}", e))?
-    };
+    }
 
-    // Also get status for untracked files
-    let status_output = Command::new("git")
-        .args(["status", "--porcelain"])
-        .current_dir(&project_dir)
-        .output()
-        .map_err(|e| format!("Failed to get status: {}", e))?;
+    // create_new 让检查和创建成为一步，不会覆盖在此期间出现的文件
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => format!("File already exists: {}", file_path),
+            _ => format!("Failed to create file: {}", e),
+        })?;
+    file.write_all(content.as_bytes())
+        .map_err(|e| format!("Failed to create file: {}", e))
+}
 
-    let diff_text = String::from_utf8_lossy(&output.stdout);
-    let status_text = String::from_utf8_lossy(&status_output.stdout);
+/// 创建新文件夹
+#[tauri::command]
+fn create_directory(dir_path: String) -> Result<(), String> {
+    let path = PathBuf::from(&dir_path);
 
-    // Combine diff and status info
-    let mut result = String::new();
-    if !diff_text.is_empty() {
-        result.push_str(&diff_text);
+    if path.exists() {
+        return Err(format!("Directory already exists: {}", dir_path));
     }
 
-    // Add untracked files info
-    let untracked: Vec<&str> = status_text
-        .lines()
-        .filter(|l| l.starts_with("??"))
-        .map(|l| l.trim_start_matches("?? "))
-        .collect();
+    fs::create_dir_all(&path)
+        .map_err(|e| format!("Failed to create directory: {}", e))
+}
 
-    if !untracked.is_empty() {
-        if !result.is_empty() {
-            result.push_str("\n");
-        }
-        result.push_str("New files:\n");
-        for f in untracked {
-            result.push_str(&format!("  {}\n", f));
-        }
+/// 删除文件或文件夹
+#[tauri::command]
+fn delete_path(target_path: String) -> Result<(), String> {
+    let path = PathBuf::from(&target_path);
+
+    if !path.exists() {
+        return Err(format!("Path does not exist: {}", target_path));
     }
 
-    Ok(result)
+    if path.is_dir() {
+        fs::remove_dir_all(&path)
+            .map_err(|e| format!("Failed to delete directory: {}", e))
+    } else {
+        fs::remove_file(&path)
+            .map_err(|e| format!("Failed to delete file: {}", e))
+    }
 }
 
-/// Git commit with message (stages selected files or all if empty)
+/// 重命名文件或文件夹
 #[tauri::command]
-fn git_commit(project_dir: String, message: String, files: Option<Vec<String>>) -> Result<String, String> {
-    use std::process::Command;
+fn rename_path(old_path: String, new_path: String) -> Result<(), String> {
+    let old = PathBuf::from(&old_path);
+    let new = PathBuf::from(&new_path);
 
-    if message.trim().is_empty() {
-        return Err("Commit message cannot be empty".to_string());
+    if !old.exists() {
+        return Err(format!("Path does not exist: {}", old_path));
     }
 
-    // Check if it's a git repo
-    let git_dir = PathBuf::from(&project_dir).join(".git");
-    if !git_dir.exists() {
-        return Err("Not a git repository".to_string());
+    if new.exists() {
+        return Err(format!("Target path already exists: {}", new_path));
     }
 
-    // Stage files
-    let add_output = if let Some(ref file_list) = files {
-        if file_list.is_empty() {
-            return Err("No files selected".to_string());
+    match fs::rename(&old, &new) {
+        // 跨文件系统（EXDEV）无法直接 rename，改为复制后删除源路径
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            println!("[rename_path] Cross-device move, copying {} -> {}", old_path, new_path);
+            move_by_copy(&old, &new)
+        }
+        result => result.map_err(|e| format!("Failed to rename: {}", e)),
+    }
+}
+
+/// 复制后删除源路径来完成移动；复制失败时清理已复制的部分，源文件删除失败时撤销复制
+fn move_by_copy(source: &std::path::Path, destination: &std::path::Path) -> Result<(), String> {
+    let remove = |path: &std::path::Path| {
+        if path.is_dir() && !path.is_symlink() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
         }
-        // Stage specific files
-        let mut args = vec!["add", "--"];
-        args.extend(file_list.iter().map(|s| s.as_str()));
-        Command::new("git")
-            .args(&args)
-            .current_dir(&project_dir)
-            .output()
-            .map_err(|e| format!("Failed to stage changes: {}", e))?
-    } else {
-        // Stage all changes
-        Command::new("git")
-            .args(["add", "-A"])
-            .current_dir(&project_dir)
-            .output()
-            .map_err(|e| format!("Failed to stage changes: {}", e))?
     };
 
-    if !add_output.status.success() {
-        let stderr = String::from_utf8_lossy(&add_output.stderr);
-        return Err(format!("Failed to stage changes: {}", stderr));
+    if let Err(e) = copy_recursive(source, destination) {
+        let _ = remove(destination);
+        return Err(format!("Failed to move: {}", e));
     }
 
-    // Commit
-    let commit_output = Command::new("git")
-        .args(["commit", "-m", &message])
-        .current_dir(&project_dir)
-        .output()
-        .map_err(|e| format!("Failed to commit: {}", e))?;
-
-    if !commit_output.status.success() {
-        let stderr = String::from_utf8_lossy(&commit_output.stderr);
-        let stdout = String::from_utf8_lossy(&commit_output.stdout);
-        if stdout.contains("nothing to commit") || stderr.contains("nothing to commit") {
-            return Err("Nothing to commit".to_string());
+    if let Err(e) = remove(source) {
+        // 目录可能已被删掉一部分，此时保留目标处的完整副本以免丢数据
+        if fs::symlink_metadata(source).is_ok() && !source.is_dir() {
+            let _ = remove(destination);
+            return Err(format!("Failed to move: could not remove source: {}", e));
         }
-        return Err(format!("Failed to commit: {}", stderr));
+        return Err(format!(
+            "Moved to {} but failed to fully remove source: {}",
+            destination.display(),
+            e
+        ));
     }
 
-    let stdout = String::from_utf8_lossy(&commit_output.stdout);
-    Ok(stdout.to_string())
+    Ok(())
 }
 
-/// Associate a PID with a terminal ID
-#[tauri::command]
-fn associate_terminal(
-    terminal_map: tauri::State<TerminalMap>,
-    pid: u32,
-    terminal_id: String
-) -> Result<(), String> {
-    println!("[associate_terminal] Associating PID {} with terminal {}", pid, terminal_id);
+/// 递归复制文件或目录；符号链接按链接本身复制（Windows 上复制其指向的内容）
+fn copy_recursive(source: &std::path::Path, destination: &std::path::Path) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(source)?;
 
-    let mut map = terminal_map.lock().map_err(|e| format!("Failed to lock terminal map: {}", e))?;
-    map.insert(pid, terminal_id);
+    if metadata.file_type().is_symlink() {
+        #[cfg(unix)]
+        {
+            return std::os::unix::fs::symlink(fs::read_link(source)?, destination);
+        }
+        #[cfg(not(unix))]
+        {
+            if source.is_dir() {
+                return copy_dir_contents(source, destination);
+            }
+            return fs::copy(source, destination).map(|_| ());
+        }
+    }
 
-    println!("[associate_terminal] Current mappings: {:?}", *map);
+    if metadata.is_dir() {
+        copy_dir_contents(source, destination)
+    } else {
+        fs::copy(source, destination).map(|_| ())
+    }
+}
+
+fn copy_dir_contents(source: &std::path::Path, destination: &std::path::Path) -> std::io::Result<()> {
+    fs::create_dir_all(destination)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        copy_recursive(&entry.path(), &destination.join(entry.file_name()))?;
+    }
     Ok(())
 }
 
-/// Create a new interactive terminal with PTY
+/// 复制文件或文件夹（目录递归复制）；目标已存在时需要 overwrite 为 true
 #[tauri::command]
-async fn create_interactive_terminal(
-    app: tauri::AppHandle,
-    pty_writer_map: tauri::State<'_, PtyWriterMap>,
-    terminal_id: String,
-    cwd: Option<String>,
-) -> Result<(), String> {
-    println!("[create_interactive_terminal] Creating terminal: {}", terminal_id);
-    println!("[create_interactive_terminal] Working directory: {:?}", cwd);
+fn copy_path(source: String, destination: String, overwrite: Option<bool>) -> Result<(), String> {
+    let src = PathBuf::from(&source);
+    let dest = PathBuf::from(&destination);
 
-    // Get the user's default shell
-    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string());
-    println!("[create_interactive_terminal] Using shell: {}", shell);
+    if fs::symlink_metadata(&src).is_err() {
+        return Err(format!("Path does not exist: {}", source));
+    }
 
-    // Create PTY system
-    let pty_system = native_pty_system();
+    let file_name = dest
+        .file_name()
+        .ok_or_else(|| format!("Invalid destination path: {}", destination))?
+        .to_string_lossy()
+        .to_string();
+
+    // 不能把目录复制到它自身内部（会无限递归），也不能覆盖包含源路径的目录（会先删掉源）
+    if let (Ok(src_abs), Some(dest_parent)) = (src.canonicalize(), dest.parent()) {
+        let dest_abs = dest_parent
+            .canonicalize()
+            .map(|parent| parent.join(&file_name))
+            .unwrap_or_else(|_| dest.clone());
+        if dest_abs.starts_with(&src_abs) {
+            return Err(format!("Cannot copy {} into itself", source));
+        }
+        if src_abs.starts_with(&dest_abs) {
+            return Err(format!("Cannot copy {} over a directory that contains it", source));
+        }
+    }
 
-    // Create a new PTY with size
-    let pair = pty_system
-        .openpty(PtySize {
-            rows: 24,
-            cols: 80,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .map_err(|e| format!("Failed to create PTY: {}", e))?;
+    let dest_exists = fs::symlink_metadata(&dest).is_ok();
+    if dest_exists && !overwrite.unwrap_or(false) {
+        return Err(format!("Target path already exists: {}", destination));
+    }
 
-    // Build shell command
-    let mut cmd = CommandBuilder::new(&shell);
-    cmd.arg("-l"); // Login shell to load user environment
+    if let Some(parent) = dest.parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create parent directory: {}", e))?;
+        }
+    }
 
-    if let Some(dir) = cwd {
-        cmd.cwd(dir);
+    let remove = |path: &std::path::Path| {
+        if path.is_dir() && !path.is_symlink() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    };
+
+    // 先复制到同目录的临时路径，成功后再替换目标，失败时原目标保持不变
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    let tmp = dest.with_file_name(format!(".{}.{}.tmp", file_name, suffix));
+    if let Err(e) = copy_recursive(&src, &tmp) {
+        let _ = remove(&tmp);
+        return Err(format!("Failed to copy: {}", e));
     }
 
-    // Spawn the shell in the PTY
-    let _child = pair.slave.spawn_command(cmd)
-        .map_err(|e| format!("Failed to spawn shell: {}", e))?;
+    if !dest_exists {
+        return fs::rename(&tmp, &dest).map_err(|e| {
+            let _ = remove(&tmp);
+            format!("Failed to copy: {}", e)
+        });
+    }
 
-    println!("[create_interactive_terminal] Shell spawned with PID: {:?}", _child.process_id());
+    // 目录无法直接 rename 覆盖，先把旧目标挪开，替换成功后再删除
+    let old = dest.with_file_name(format!(".{}.{}.old", file_name, suffix));
+    if let Err(e) = fs::rename(&dest, &old) {
+        let _ = remove(&tmp);
+        return Err(format!("Failed to replace existing target: {}", e));
+    }
+    if let Err(e) = fs::rename(&tmp, &dest) {
+        let _ = fs::rename(&old, &dest);
+        let _ = remove(&tmp);
+        return Err(format!("Failed to replace existing target: {}", e));
+    }
+    let _ = remove(&old);
+    Ok(())
+}
 
-    // Get the master PTY reader
-    let mut reader = pair.master.try_clone_reader()
-        .map_err(|e| format!("Failed to clone reader: {}", e))?;
+/// 分块计算文件摘要，返回小写十六进制字符串
+fn hash_reader<D: sha2::Digest>(mut reader: impl std::io::Read) -> std::io::Result<String> {
+    let mut hasher = D::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
 
-    // Get and store the writer
-    let writer = pair.master.take_writer()
-        .map_err(|e| format!("Failed to get writer: {}", e))?;
+/// 计算文件哈希（sha256 或 md5），用于校验下载的 CLI 等文件；读取在阻塞线程池中进行
+#[tauri::command]
+async fn hash_file(file_path: String, algorithm: Option<String>) -> Result<String, String> {
+    let path = PathBuf::from(&file_path);
+    if !path.exists() {
+        return Err(format!("File does not exist: {}", file_path));
+    }
+    if !path.is_file() {
+        return Err(format!("Path is not a file: {}", file_path));
+    }
 
-    // Store writer (using tokio async Mutex with .await)
-    {
-        let mut map = pty_writer_map.lock().await;
-        map.insert(terminal_id.clone(), Arc::new(Mutex::new(writer)));
+    let algorithm = algorithm.unwrap_or_else(|| "sha256".to_string()).to_lowercase();
+    if !matches!(algorithm.as_str(), "sha256" | "md5") {
+        return Err(format!("Unsupported hash algorithm: {}", algorithm));
     }
 
-    // Spawn task to read PTY output and emit to frontend
-    // Use tokio::task::spawn_blocking for blocking PTY read operations
-    let terminal_id_clone = terminal_id.clone();
     tokio::task::spawn_blocking(move || {
-        use std::io::Read;
-        let mut buffer = [0u8; 8192];
-        loop {
-            match reader.read(&mut buffer) {
-                Ok(0) => {
-                    // EOF - shell exited
-                    println!("[Terminal {}] Shell exited", terminal_id_clone);
-                    let _ = app.emit("terminal-output", serde_json::json!({
-                        "terminalId": terminal_id_clone,
-                        "output": "\r\n[Process exited]\r\n"
-                    }));
-                    break;
-                }
-                Ok(n) => {
-                    // Convert bytes to string (PTY output is usually UTF-8)
-                    let output = String::from_utf8_lossy(&buffer[..n]).to_string();
-
-                    // Emit to frontend
-                    let _ = app.emit("terminal-output", serde_json::json!({
-                        "terminalId": terminal_id_clone,
-                        "output": output
-                    }));
-                }
-                Err(e) => {
-                    println!("[Terminal {}] Read error: {}", terminal_id_clone, e);
-                    break;
-                }
-            }
+        let file = fs::File::open(&path).map_err(|e| format!("Failed to open file: {}", e))?;
+        let reader = std::io::BufReader::new(file);
+        if algorithm == "md5" {
+            hash_reader::<md5::Md5>(reader)
+        } else {
+            hash_reader::<sha2::Sha256>(reader)
         }
-    });
+        .map_err(|e| format!("Failed to read file: {}", e))
+    })
+    .await
+    .map_err(|e| format!("Hash task failed: {}", e))?
+}
 
-    println!("[create_interactive_terminal] Terminal {} created successfully", terminal_id);
-    Ok(())
+/// 下载进度事件的最小间隔
+const DOWNLOAD_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// 把 URL 的内容流式写入 part 文件；返回 false 表示被取消（part 文件由调用方清理）
+async fn download_to(
+    url: &str,
+    part: &std::path::Path,
+    abort: &AbortHandle,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<bool, String> {
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let response = tokio::select! {
+        biased;
+        _ = abort.wait() => return Ok(false),
+        response = reqwest::get(url) => response.map_err(|e| format!("Download failed: {}", e))?,
+    };
+    if !response.status().is_success() {
+        return Err(format!("Download failed: HTTP {}", response.status()));
+    }
+
+    let total = response.content_length();
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(part)
+        .await
+        .map_err(|e| format!("Failed to create file: {}", e))?;
+    let mut stream = response.bytes_stream();
+    let mut received = 0u64;
+    let mut last_progress = std::time::Instant::now();
+    loop {
+        let chunk = tokio::select! {
+            biased;
+            _ = abort.wait() => return Ok(false),
+            chunk = stream.next() => chunk,
+        };
+        let Some(chunk) = chunk else { break };
+        let chunk = chunk.map_err(|e| format!("Download failed: {}", e))?;
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| format!("Failed to write file: {}", e))?;
+        received += chunk.len() as u64;
+        if last_progress.elapsed() >= DOWNLOAD_PROGRESS_INTERVAL {
+            last_progress = std::time::Instant::now();
+            progress(received, total);
+        }
+    }
+    file.flush().await.map_err(|e| format!("Failed to write file: {}", e))?;
+    progress(received, total);
+    Ok(true)
 }
 
-/// Close an interactive terminal
+/// 下载文件：发送 download-progress，完成后发送 download-complete；
+/// 内容先写入 {dest}.part，完成后再改名，cancel_download 会删除未完成的部分并发送 download-cancelled
 #[tauri::command]
-async fn close_terminal(
-    pty_writer_map: tauri::State<'_, PtyWriterMap>,
-    terminal_id: String,
-) -> Result<(), String> {
-    println!("[close_terminal] Closing terminal: {}", terminal_id);
+async fn download_file(
+    app: tauri::AppHandle,
+    abort_map: tauri::State<'_, TaskAbortMap>,
+    url: String,
+    dest_path: String,
+    download_id: String,
+) -> Result<String, String> {
+    let dest = PathBuf::from(&dest_path);
+    let file_name = dest
+        .file_name()
+        .ok_or_else(|| format!("Invalid destination path: {}", dest_path))?
+        .to_string_lossy()
+        .to_string();
+    let part = dest.with_file_name(format!("{}.part", file_name));
+    // symlink_metadata 也能发现悬空的符号链接，避免写到链接指向的位置
+    for target in [&dest, &part] {
+        if fs::symlink_metadata(target).is_ok() {
+            return Err(format!("Target path already exists: {}", target.display()));
+        }
+    }
+    if let Some(parent) = dest.parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create parent directory: {}", e))?;
+        }
+    }
 
-    // Remove writer (using tokio async Mutex with .await)
-    {
-        let mut map = pty_writer_map.lock().await;
-        map.remove(&terminal_id);
+    #[derive(serde::Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct DownloadProgress {
+        download_id: String,
+        received: u64,
+        total: Option<u64>,
     }
 
-    println!("[close_terminal] Terminal {} closed successfully", terminal_id);
-    Ok(())
+    #[derive(serde::Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct DownloadEvent {
+        download_id: String,
+        path: String,
+    }
+
+    let abort = register_abort(&abort_map, &download_id)?;
+    let result = download_to(&url, &part, &abort, |received, total| {
+        let _ = app.emit("download-progress", DownloadProgress {
+            download_id: download_id.clone(),
+            received,
+            total,
+        });
+    })
+    .await;
+
+    if let Ok(mut map) = abort_map.lock() {
+        map.remove(&download_id);
+    }
+
+    let event = DownloadEvent { download_id: download_id.clone(), path: dest_path.clone() };
+    match result {
+        Ok(true) => {
+            fs::rename(&part, &dest).map_err(|e| {
+                let _ = fs::remove_file(&part);
+                format!("Failed to move download into place: {}", e)
+            })?;
+            println!("[download_file] {} -> {}", url, dest_path);
+            let _ = app.emit("download-complete", event);
+            Ok(dest_path)
+        }
+        Ok(false) => {
+            let _ = fs::remove_file(&part);
+            println!("[download_file] Cancelled {}", download_id);
+            let _ = app.emit("download-cancelled", event);
+            Err("Download cancelled".to_string())
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&part);
+            Err(e)
+        }
+    }
 }
 
-/// Send input to an interactive terminal
+/// 取消正在进行的下载
 #[tauri::command]
-async fn terminal_input(
-    pty_writer_map: tauri::State<'_, PtyWriterMap>,
-    terminal_id: String,
-    data: String,
-) -> Result<(), String> {
-    println!("[terminal_input] START - Terminal: {}, Data length: {}, Data: {:?}", terminal_id, data.len(), data);
+fn cancel_download(abort_map: tauri::State<'_, TaskAbortMap>, download_id: String) -> Result<bool, String> {
+    abort_task(&abort_map, &download_id)
+}
 
-    // Get writer Arc from the map (using tokio async Mutex with .await)
-    let writer_arc = {
-        let map = pty_writer_map.lock().await;
-        map.get(&terminal_id)
-            .ok_or_else(|| {
-                eprintln!("[terminal_input] ERROR: Terminal {} not found in map", terminal_id);
-                format!("Terminal {} not found", terminal_id)
-            })?
-            .clone()
-    };
+/// 保存文件内容（原子写入）；backup 为 true 时先把原文件复制为 {path}.bak
+#[tauri::command]
+fn save_file(file_path: String, content: String, backup: Option<bool>) -> Result<(), String> {
+    let path = PathBuf::from(&file_path);
 
-    println!("[terminal_input] Got writer arc, spawning blocking write task...");
+    if backup.unwrap_or(false) && path.is_file() {
+        let backup_path = PathBuf::from(format!("{}.bak", file_path));
+        fs::copy(&path, &backup_path)
+            .map_err(|e| format!("Failed to create backup: {}", e))?;
+    }
 
-    // Use spawn_blocking to perform synchronous I/O operations
-    // This prevents blocking the tokio runtime
-    let data_bytes = data.into_bytes();
-    let result = tokio::task::spawn_blocking(move || {
-        use std::io::Write;
+    write_file_atomic(&path, content.as_bytes())
+}
 
-        // Block to acquire the lock in sync context
-        let rt = tokio::runtime::Handle::current();
-        let mut writer = rt.block_on(writer_arc.lock());
+/// 追加内容到文件末尾（不重写整个文件）
+#[tauri::command]
+fn append_to_file(file_path: String, content: String, create: bool) -> Result<(), String> {
+    use std::io::Write;
 
-        println!("[terminal_input] Writer locked, writing {} bytes...", data_bytes.len());
+    let path = PathBuf::from(&file_path);
 
-        // Write data to PTY
-        if let Err(e) = writer.write_all(&data_bytes) {
-            eprintln!("[terminal_input] ERROR: Failed to write to PTY: {}", e);
-            return Err(format!("Failed to write to PTY: {}", e));
-        }
+    if !path.exists() && !create {
+        return Err(format!("File does not exist: {}", file_path));
+    }
 
-        println!("[terminal_input] Data written, flushing...");
+    if path.is_dir() {
+        return Err(format!("Path is not a file: {}", file_path));
+    }
 
-        if let Err(e) = writer.flush() {
-            eprintln!("[terminal_input] ERROR: Failed to flush PTY: {}", e);
-            return Err(format!("Failed to flush PTY: {}", e));
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .create(create)
+        .open(&path)
+        .map_err(|e| format!("Failed to open file: {}", e))?;
+
+    file.write_all(content.as_bytes())
+        .map_err(|e| format!("Failed to append to file: {}", e))
+}
+
+/// 原子写入：先写同目录临时文件并落盘，再 rename 覆盖目标（保留原文件权限）
+fn write_file_atomic(path: &std::path::Path, content: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+
+    // 目标是符号链接时替换它指向的文件，链接本身保持不变
+    let resolved = fs::canonicalize(path).ok();
+    let path = resolved.as_deref().unwrap_or(path);
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("Invalid file path: {}", path.display()))?
+        .to_string_lossy();
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, suffix));
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+        .map_err(|e| format!("Failed to write temporary file: {}", e))?;
+    let written = file.write_all(content).and_then(|_| {
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
         }
+        file.sync_all()
+    });
+    drop(file);
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(format!("Failed to write temporary file: {}", e));
+    }
 
-        println!("[terminal_input] SUCCESS - Write completed");
-        Ok(())
-    }).await.map_err(|e| format!("Task join error: {}", e))?;
+    fs::rename(&tmp_path, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        format!("Failed to replace file: {}", e)
+    })
+}
 
-    result
+#[derive(serde::Deserialize)]
+struct FileWrite {
+    path: String,
+    content: String,
 }
 
-/// Kill a background process by PID
-#[tauri::command]
-async fn kill_process(
-    terminal_map: tauri::State<'_, TerminalMap>,
-    pid: u32
-) -> Result<(), String> {
-    println!("[kill_process] Attempting to kill process with PID: {}", pid);
+/// 批量写入失败时返回出错的文件
+#[derive(Debug, serde::Serialize)]
+struct TxError {
+    path: String,
+    error: String,
+    rolled_back: bool,
+}
 
-    // CRITICAL: Never kill PID 0 - it would kill the entire process group including Tauri itself!
-    if pid == 0 {
-        println!("[kill_process] Skipping PID 0 (would kill process group)");
-        return Ok(());
+/// 依次原子写入所有文件；任一失败则按快照恢复已写入的文件
+fn apply_file_writes(writes: &[FileWrite]) -> Result<(), TxError> {
+    // 先为每个目标做快照，None 表示原本不存在
+    let mut snapshots: Vec<(PathBuf, Option<Vec<u8>>)> = Vec::with_capacity(writes.len());
+    for write in writes {
+        let path = PathBuf::from(&write.path);
+        if path.is_dir() {
+            return Err(TxError {
+                path: write.path.clone(),
+                error: format!("Path is not a file: {}", write.path),
+                rolled_back: false,
+            });
+        }
+        let original = if path.exists() {
+            Some(fs::read(&path).map_err(|e| TxError {
+                path: write.path.clone(),
+                error: format!("Failed to read file: {}", e),
+                rolled_back: false,
+            })?)
+        } else {
+            None
+        };
+        snapshots.push((path, original));
     }
 
-    #[cfg(target_os = "windows")]
-    {
-        use std::process::Command as StdCommand;
-        let output = StdCommand::new("taskkill")
-            .args(["/F", "/PID", &pid.to_string()])
-            .output()
-            .map_err(|e| format!("Failed to execute taskkill: {}", e))?;
-
-        if output.status.success() {
-            println!("[kill_process] Successfully killed process {}", pid);
-            // Remove from terminal map
-            if let Ok(mut map) = terminal_map.lock() {
-                map.remove(&pid);
-                println!("[kill_process] Removed PID {} from terminal map", pid);
+    for (i, write) in writes.iter().enumerate() {
+        if let Err(error) = write_file_atomic(&snapshots[i].0, write.content.as_bytes()) {
+            let mut rolled_back = true;
+            for (path, original) in snapshots[..i].iter().rev() {
+                let restored = match original {
+                    Some(bytes) => write_file_atomic(path, bytes).is_ok(),
+                    None => fs::remove_file(path).is_ok(),
+                };
+                if !restored {
+                    println!("[write_files_transactional] Failed to restore {}", path.display());
+                    rolled_back = false;
+                }
             }
-            Ok(())
-        } else {
-            let error = String::from_utf8_lossy(&output.stderr);
-            Err(format!("Failed to kill process: {}", error))
+            return Err(TxError {
+                path: write.path.clone(),
+                error,
+                rolled_back,
+            });
         }
     }
 
-    #[cfg(not(target_os = "windows"))]
-    {
-        use nix::sys::signal::{kill, Signal};
-        use nix::unistd::Pid;
+    Ok(())
+}
 
-        let nix_pid = Pid::from_raw(pid as i32);
-        kill(nix_pid, Signal::SIGTERM)
-            .map_err(|e| format!("Failed to kill process: {}", e))?;
+/// 事务式写入多个文件：全部成功或全部恢复原状
+#[tauri::command]
+fn write_files_transactional(writes: Vec<FileWrite>) -> Result<(), TxError> {
+    apply_file_writes(&writes)
+}
 
-        println!("[kill_process] Successfully sent SIGTERM to process {}", pid);
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+struct ConflictRegion {
+    line: u32,           // 合并结果中 <<<<<<< 标记所在行（从 1 开始）
+    ours: String,
+    base: String,
+    theirs: String,
+}
 
-        // Remove from terminal map
-        if let Ok(mut map) = terminal_map.lock() {
-            map.remove(&pid);
-            println!("[kill_process] Removed PID {} from terminal map", pid);
-        }
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct MergeResult {
+    merged: String,      // 冲突处带 <<<<<<< / ======= / >>>>>>> 标记
+    conflicts: Vec<ConflictRegion>,
+}
 
-        Ok(())
+/// base 中每一行在 other 中对应的行号（未变化的行才有对应）
+fn matched_lines(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut map = vec![None; base.len()];
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, base, other) {
+        if let similar::DiffOp::Equal { old_index, new_index, len } = op {
+            for offset in 0..len {
+                map[old_index + offset] = Some(new_index + offset);
+            }
+        }
     }
+    map
 }
 
-/// Start a background process (dev server, etc.) that persists after Claude exits
-#[tauri::command]
-async fn start_background_process(
-    app: tauri::AppHandle,
-    terminal_map: tauri::State<'_, TerminalMap>,
-    command: String,
-    args: Vec<String>,
-    cwd: String,
-    terminal_id: Option<String>,
-) -> Result<u32, String> {
-    println!("[start_background_process] Starting: {} {:?}", command, args);
-    println!("[start_background_process] CWD: {}", cwd);
-    println!("[start_background_process] Terminal ID: {:?}", terminal_id);
+/// diff3 风格的三方合并：双方都未改动的行作为同步点，同步点之间只有一方改动则取改动方，
+/// 双方改成相同内容也直接采用，否则记为冲突
+fn merge_three_way(base: &str, ours: &str, theirs: &str) -> MergeResult {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let ours_map = matched_lines(&base_lines, &ours_lines);
+    let theirs_map = matched_lines(&base_lines, &theirs_lines);
+
+    let mut merged = String::new();
+    let mut merged_line_count = 0u32;
+    let mut conflicts = Vec::new();
+    let (mut i, mut j, mut k) = (0usize, 0usize, 0usize);
+
+    let mut emit = |merged: &mut String, base: &[&str], ours: &[&str], theirs: &[&str]| {
+        let chosen = if ours == base {
+            Some(theirs)
+        } else if theirs == base || ours == theirs {
+            Some(ours)
+        } else {
+            None
+        };
 
-    // Create the command
-    let mut cmd = Command::new(&command);
-    cmd.args(&args);
-    cmd.current_dir(&cwd);
-    cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::piped());
+        // Conflict sides are always newline-terminated so the markers stay on their own lines
+        let push_lines = |merged: &mut String, lines: &[&str], count: &mut u32| {
+            for line in lines {
+                merged.push_str(line);
+                *count += 1;
+            }
+            if lines.last().is_some_and(|l| !l.ends_with('\n')) {
+                merged.push('\n');
+            }
+        };
 
-    // Spawn the process
-    let mut child = cmd
-        .spawn()
-        .map_err(|e| format!("Failed to spawn process: {}", e))?;
+        match chosen {
+            Some(lines) => {
+                for line in lines {
+                    merged.push_str(line);
+                    merged_line_count += 1;
+                }
+            }
+            None => {
+                if !merged.is_empty() && !merged.ends_with('\n') {
+                    merged.push('\n');
+                }
+                conflicts.push(ConflictRegion {
+                    line: merged_line_count + 1,
+                    ours: ours.concat(),
+                    base: base.concat(),
+                    theirs: theirs.concat(),
+                });
+                merged.push_str("<<<<<<< ours\n");
+                merged_line_count += 1;
+                push_lines(merged, ours, &mut merged_line_count);
+                merged.push_str("=======\n");
+                merged_line_count += 1;
+                push_lines(merged, theirs, &mut merged_line_count);
+                merged.push_str(">>>>>>> theirs\n");
+                merged_line_count += 1;
+            }
+        }
+    };
 
-    let pid = child.id().ok_or("Failed to get process ID")?;
-    println!("[start_background_process] Spawned with PID: {}", pid);
+    loop {
+        // Next base line kept unchanged on both sides
+        let sync = (i..base_lines.len()).find_map(|b| match (ours_map[b], theirs_map[b]) {
+            (Some(o), Some(t)) if o >= j && t >= k => Some((b, o, t)),
+            _ => None,
+        });
 
-    // If terminal_id is provided, immediately store the mapping
-    if let Some(ref term_id) = terminal_id {
-        if let Ok(mut map) = terminal_map.lock() {
-            map.insert(pid, term_id.clone());
-            println!("[start_background_process] Stored mapping: PID {} -> Terminal {}", pid, term_id);
+        match sync {
+            Some((b, o, t)) => {
+                emit(&mut merged, &base_lines[i..b], &ours_lines[j..o], &theirs_lines[k..t]);
+                emit(&mut merged, &base_lines[b..b + 1], &ours_lines[o..o + 1], &theirs_lines[t..t + 1]);
+                (i, j, k) = (b + 1, o + 1, t + 1);
+            }
+            None => {
+                emit(&mut merged, &base_lines[i..], &ours_lines[j..], &theirs_lines[k..]);
+                break;
+            }
         }
     }
 
-    // Get stdout and stderr
-    let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
-    let stderr = child.stderr.take().ok_or("Failed to get stderr")?;
+    MergeResult { merged, conflicts }
+}
 
-    // Create readers
-    let stdout_reader = BufReader::new(stdout);
-    let stderr_reader = BufReader::new(stderr);
+/// 三方合并文件内容（用于在用户同时编辑时安全应用 AI 的整文件替换）
+#[tauri::command]
+fn three_way_merge(base: String, ours: String, theirs: String) -> MergeResult {
+    merge_three_way(&base, &ours, &theirs)
+}
 
-    // Clone terminal_map for async tasks
-    let terminal_map_stdout = Arc::clone(&terminal_map.inner());
-    let terminal_map_stderr = Arc::clone(&terminal_map.inner());
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SkillInfo {
+    name: String,
+    path: String,
+    token_count: Option<u64>,
+    description: Option<String>,
+    allowed_tools: Option<Vec<String>>,
+    scope: String,  // "user" (~/.claude/skills) 或 "project" (<project>/.claude/skills)
+}
 
-    // Spawn task to read stdout and emit to frontend with terminal ID
-    let app_stdout = app.clone();
-    let pid_stdout = pid;
-    tokio::spawn(async move {
-        let mut lines = stdout_reader.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            println!("[Process {}] {}", pid_stdout, line);
+/// SKILL.md 的 YAML frontmatter 中我们关心的字段
+#[derive(Default, Debug, PartialEq)]
+struct SkillFrontmatter {
+    name: Option<String>,
+    description: Option<String>,
+    allowed_tools: Option<Vec<String>>,
+}
 
-            // Get terminal ID from map
-            let terminal_id = terminal_map_stdout.lock()
-                .ok()
-                .and_then(|map| map.get(&pid_stdout).cloned())
-                .unwrap_or_else(|| format!("terminal-{}", pid_stdout));
+/// 去掉 YAML 标量两端的引号
+fn unquote_yaml(value: &str) -> String {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return value[1..value.len() - 1].to_string();
+        }
+    }
+    value.to_string()
+}
 
-            // Emit to terminal with terminal ID
-            #[derive(serde::Serialize, Clone)]
-            #[serde(rename_all = "camelCase")]
-            struct TerminalOutput {
-                terminal_id: String,
-                output: String,
+/// 按顶层逗号拆分列表，括号和引号内的逗号不拆（如 `Bash(git add:*, git commit:*)`）
+fn split_top_level_commas(value: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth = depth.saturating_sub(1),
+            (None, ',') if depth == 0 => {
+                items.push(&value[start..i]);
+                start = i + 1;
             }
-
-            let _ = app_stdout.emit("terminal-output", TerminalOutput {
-                terminal_id,
-                output: line,
-            });
+            _ => {}
         }
-    });
+    }
+    items.push(&value[start..]);
+    items
+}
 
-    // Spawn task to read stderr and emit to frontend with terminal ID
-    let app_stderr = app.clone();
-    let pid_stderr = pid;
-    tokio::spawn(async move {
-        let mut lines = stderr_reader.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            println!("[Process {} stderr] {}", pid_stderr, line);
+/// 解析 SKILL.md 开头 `---` 之间的 frontmatter（只支持简单的 key: value、
+/// `|`/`>` 多行文本、`[a, b]` 和 `- item` 列表，足够覆盖 skill 的元数据）
+fn parse_skill_frontmatter(content: &str) -> SkillFrontmatter {
+    let mut frontmatter = SkillFrontmatter::default();
+    let mut lines = content.trim_start_matches('\u{feff}').lines();
+    if lines.next().map(str::trim_end) != Some("---") {
+        return frontmatter;
+    }
+    let lines: Vec<&str> = lines.take_while(|line| line.trim_end() != "---").collect();
 
-            // Get terminal ID from map
-            let terminal_id = terminal_map_stderr.lock()
-                .ok()
-                .and_then(|map| map.get(&pid_stderr).cloned())
-                .unwrap_or_else(|| format!("terminal-{}", pid_stderr));
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        if line.starts_with([' ', '\t', '#']) {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
 
-            // Emit to terminal with terminal ID
-            #[derive(serde::Serialize, Clone)]
-            #[serde(rename_all = "camelCase")]
-            struct TerminalOutput {
-                terminal_id: String,
-                output: String,
+        // 收集缩进的后续行（多行文本或列表项）
+        let mut continuation = Vec::new();
+        while i < lines.len() && (lines[i].starts_with([' ', '\t']) || lines[i].trim().is_empty()) {
+            continuation.push(lines[i].trim());
+            i += 1;
+        }
+        if continuation.is_empty() && i < lines.len() && value.is_empty() {
+            // 列表项也可以不缩进
+            while i < lines.len() && lines[i].starts_with("- ") {
+                continuation.push(lines[i].trim());
+                i += 1;
             }
-
-            let _ = app_stderr.emit("terminal-output", TerminalOutput {
-                terminal_id,
-                output: line,
-            });
         }
-    });
 
-    // Don't wait for the process - let it run in background
-    // The process will continue running even after this function returns
+        let text = match value {
+            "|" | "|-" => Some(continuation.join("\n").trim().to_string()),
+            ">" | ">-" => Some(continuation.join(" ").split_whitespace().collect::<Vec<_>>().join(" ")),
+            "" => None,
+            _ => Some(unquote_yaml(value)),
+        };
+        let list = || -> Vec<String> {
+            let items: Vec<String> = if let Some(inner) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+                split_top_level_commas(inner).into_iter().map(unquote_yaml).collect()
+            } else if value.is_empty() {
+                continuation.iter().filter_map(|l| l.strip_prefix('-')).map(unquote_yaml).collect()
+            } else {
+                split_top_level_commas(value).into_iter().map(unquote_yaml).collect()
+            };
+            items.into_iter().filter(|item| !item.is_empty()).collect()
+        };
+
+        match key.trim() {
+            "name" => frontmatter.name = text.filter(|t| !t.is_empty()),
+            "description" => frontmatter.description = text.filter(|t| !t.is_empty()),
+            "allowed-tools" | "allowed_tools" => frontmatter.allowed_tools = Some(list()),
+            _ => {}
+        }
+    }
 
-    Ok(pid)
+    frontmatter
 }
 
-/// Execute Kiro CLI with streaming output
-#[tauri::command]
-async fn execute_kiro_streaming(
-    app: tauri::AppHandle,
-    kiro_path: String,
-    args: Vec<String>,
-    cwd: String,
-) -> Result<(), String> {
-    println!("[execute_kiro_streaming] Starting execution");
-    println!("[execute_kiro_streaming] Kiro: {}", kiro_path);
-    println!("[execute_kiro_streaming] Args: {:?}", args);
-    println!("[execute_kiro_streaming] CWD: {}", cwd);
+/// 读取单个 skill 目录的信息；SKILL.md 缺少 frontmatter 时以目录名作为名称
+fn read_skill_info(skill_path: &std::path::Path, dir_name: String, scope: &str) -> SkillInfo {
+    let frontmatter = fs::read_to_string(skill_path.join("SKILL.md"))
+        .map(|content| parse_skill_frontmatter(&content))
+        .unwrap_or_default();
+
+    SkillInfo {
+        name: frontmatter.name.unwrap_or(dir_name),
+        path: skill_path.to_string_lossy().to_string(),
+        token_count: estimate_skill_tokens(skill_path),
+        description: frontmatter.description,
+        allowed_tools: frontmatter.allowed_tools,
+        scope: scope.to_string(),
+    }
+}
 
-    // Create the command
-    let mut cmd = Command::new(&kiro_path);
-    cmd.args(&args);
-    cmd.current_dir(&cwd);
-    cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::piped());
+/// 读取一个 skills 目录下的所有 skill（目录不存在时返回空列表）
+fn collect_skills(skills_dir: &std::path::Path, scope: &str) -> Result<Vec<SkillInfo>, String> {
+    if !skills_dir.exists() {
+        return Ok(Vec::new()); // 如果目录不存在，返回空列表
+    }
 
-    // Spawn the process
-    let mut child = cmd
-        .spawn()
-        .map_err(|e| format!("Failed to spawn Kiro: {}", e))?;
+    if !skills_dir.is_dir() {
+        return Err(format!("Skills path is not a directory: {:?}", skills_dir));
+    }
 
-    // Get stdout and stderr
-    let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
-    let stderr = child.stderr.take().ok_or("Failed to get stderr")?;
+    let entries = fs::read_dir(skills_dir)
+        .map_err(|e| format!("Failed to read skills directory: {}", e))?;
 
-    let pid = child.id().unwrap_or(0);
-    println!("[execute_kiro_streaming] Spawned process with PID: {}", pid);
+    let mut skills = Vec::new();
 
-    // Create readers
-    let stdout_reader = BufReader::new(stdout);
-    let stderr_reader = BufReader::new(stderr);
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+        let metadata = entry.metadata().map_err(|e| format!("Failed to read metadata: {}", e))?;
 
-    // Spawn task to read stdout
-    let app_stdout = app.clone();
-    let stdout_task = tokio::spawn(async move {
-        let mut lines = stdout_reader.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            println!("[Kiro stdout] {}", line);
-            // Emit to frontend
-            let _ = app_stdout.emit("kiro-stream", &line);
+        // 只处理目录
+        if !metadata.is_dir() {
+            continue;
         }
-    });
 
-    // Spawn task to read stderr
-    let app_stderr = app.clone();
-    let stderr_task = tokio::spawn(async move {
-        let mut lines = stderr_reader.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            println!("[Kiro stderr] {}", line);
-            // Emit to frontend
-            let _ = app_stderr.emit("kiro-error", &line);
-        }
-    });
-
-    // Wait for both tasks to complete
-    let _ = tokio::join!(stdout_task, stderr_task);
+        let skill_name = entry.file_name().to_string_lossy().to_string();
 
-    // Wait for the process to complete
-    match child.wait().await {
-        Ok(status) => {
-            println!("[execute_kiro_streaming] Process exited with status: {}", status);
-            let _ = app.emit("kiro-complete", status.success());
-            Ok(())
-        }
-        Err(e) => {
-            println!("[execute_kiro_streaming] Process wait failed: {}", e);
-            let _ = app.emit("kiro-complete", false);
-            Err(format!("Failed to wait for Kiro process: {}", e))
+        // 跳过隐藏目录
+        if skill_name.starts_with('.') {
+            continue;
         }
+
+        skills.push(read_skill_info(&entry.path(), skill_name, scope));
     }
+
+    Ok(skills)
 }
 
-// ============================================================================
-// API Proxy Commands
-// ============================================================================
+/// 合并用户级和项目级 skills：同名时项目级覆盖用户级
+fn merge_skills(user: Vec<SkillInfo>, project: Vec<SkillInfo>) -> Vec<SkillInfo> {
+    let project_names: std::collections::HashSet<String> =
+        project.iter().map(|s| s.name.to_lowercase()).collect();
 
-/// API Proxy state - holds the client and server handle
-type ProxyServerHandle = Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>;
+    let mut skills: Vec<SkillInfo> = user
+        .into_iter()
+        .filter(|s| !project_names.contains(&s.name.to_lowercase()))
+        .chain(project)
+        .collect();
 
-fn create_proxy_server_handle() -> ProxyServerHandle {
-    Arc::new(Mutex::new(None))
+    // 按名称排序
+    skills.sort_by_key(|s| s.name.to_lowercase());
+    skills
 }
 
-/// Start the API proxy server
+/// 读取 ~/.claude/skills 下的 skills；传入 project_dir 时合并 <project>/.claude/skills
+/// 分词统计 token 较慢，放到阻塞线程池中执行，避免卡住 UI 线程
 #[tauri::command]
-async fn start_api_proxy(
-    proxy_handle: tauri::State<'_, ProxyServerHandle>,
-    port: u16,
-    preferred_provider: Option<String>,
-    big_model: Option<String>,
-    small_model: Option<String>,
-) -> Result<String, String> {
-    // Check if server is already running
-    {
-        let handle = proxy_handle.lock().await;
-        if handle.is_some() {
-            return Err("API proxy server is already running".to_string());
-        }
-    }
+async fn read_claude_skills(project_dir: Option<String>) -> Result<Vec<SkillInfo>, String> {
+    let home = std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .map_err(|e| format!("Failed to get home directory: {}", e))?;
 
-    // Build configuration
-    let config = api_proxy::ProxyConfig {
-        preferred_provider: preferred_provider
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(api_proxy::types::Provider::OpenAI),
-        big_model: big_model.unwrap_or_else(|| "gpt-4.1".to_string()),
-        small_model: small_model.unwrap_or_else(|| "gpt-4.1-mini".to_string()),
-        openai_api_key: std::env::var("OPENAI_API_KEY").ok(),
-        gemini_api_key: std::env::var("GEMINI_API_KEY").ok(),
-        anthropic_api_key: std::env::var("ANTHROPIC_API_KEY").ok(),
-        openai_base_url: std::env::var("OPENAI_BASE_URL").ok(),
-    };
+    tokio::task::spawn_blocking(move || {
+        let user_skills = collect_skills(&PathBuf::from(home).join(".claude").join("skills"), "user")?;
+        let project_skills = match project_dir {
+            Some(dir) => collect_skills(&PathBuf::from(dir).join(".claude").join("skills"), "project")?,
+            None => Vec::new(),
+        };
 
-    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+        Ok(merge_skills(user_skills, project_skills))
+    })
+    .await
+    .map_err(|e| format!("Skill scan task failed: {}", e))?
+}
 
-    // Spawn server in background
-    let handle = tokio::spawn(async move {
-        if let Err(e) = api_proxy::run_server(config, addr).await {
-            eprintln!("[API Proxy] Server error: {}", e);
-        }
-    });
+/// 超过此大小的 skill 文件不再分词，按字符数 / 4 估算
+const SKILL_TOKENIZE_MAX_BYTES: u64 = 1024 * 1024;
 
-    // Store handle
-    {
-        let mut proxy = proxy_handle.lock().await;
-        *proxy = Some(handle);
+/// 估算 skill 的 token 数量：递归统计目录下所有文本文件（跳过二进制文件和隐藏目录）
+fn estimate_skill_tokens(skill_path: &std::path::Path) -> Option<u64> {
+    fn walk(dir: &std::path::Path, total: &mut u64) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            // file_type 不跟随符号链接，避免链接成环
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if !entry.file_name().to_string_lossy().starts_with('.') {
+                    walk(&entry.path(), total);
+                }
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+            let Ok(bytes) = fs::read(entry.path()) else {
+                continue;
+            };
+            if bytes.iter().take(8192).any(|b| *b == 0) {
+                continue;
+            }
+            let text = String::from_utf8_lossy(&bytes);
+            *total += if bytes.len() as u64 > SKILL_TOKENIZE_MAX_BYTES {
+                estimate_tokens(text.len() as u64)
+            } else {
+                api_proxy::tokens::count_text_tokens(&text) as u64
+            };
+        }
     }
 
-    Ok(format!("API proxy started on http://127.0.0.1:{}", port))
-}
-
-/// Stop the API proxy server
-#[tauri::command]
-async fn stop_api_proxy(
-    proxy_handle: tauri::State<'_, ProxyServerHandle>,
-) -> Result<String, String> {
-    let mut handle = proxy_handle.lock().await;
+    let mut total_tokens = 0u64;
+    walk(skill_path, &mut total_tokens);
 
-    if let Some(h) = handle.take() {
-        h.abort();
-        Ok("API proxy server stopped".to_string())
+    if total_tokens > 0 {
+        Some(total_tokens)
     } else {
-        Err("API proxy server is not running".to_string())
+        None
     }
 }
 
-/// Send a message through the API proxy (direct call, no server needed)
-#[tauri::command]
-async fn api_proxy_send_message(
-    model: String,
-    messages: Vec<serde_json::Value>,
-    max_tokens: Option<u32>,
-    system: Option<String>,
-    temperature: Option<f32>,
-    preferred_provider: Option<String>,
-    big_model: Option<String>,
-    small_model: Option<String>,
-) -> Result<serde_json::Value, String> {
-    // Build configuration
-    let config = api_proxy::ProxyConfig {
-        preferred_provider: preferred_provider
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(api_proxy::types::Provider::OpenAI),
-        big_model: big_model.unwrap_or_else(|| "gpt-4.1".to_string()),
-        small_model: small_model.unwrap_or_else(|| "gpt-4.1-mini".to_string()),
-        openai_api_key: std::env::var("OPENAI_API_KEY").ok(),
-        gemini_api_key: std::env::var("GEMINI_API_KEY").ok(),
-        anthropic_api_key: std::env::var("ANTHROPIC_API_KEY").ok(),
-        openai_base_url: std::env::var("OPENAI_BASE_URL").ok(),
-    };
-
-    let client = api_proxy::ApiClient::new(config);
-
-    // Convert messages from JSON to proper type
-    let parsed_messages: Vec<api_proxy::Message> = messages
-        .into_iter()
-        .map(|m| serde_json::from_value(m))
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| format!("Failed to parse messages: {}", e))?;
-
-    // Build request
-    let request = api_proxy::MessagesRequest {
-        model,
-        max_tokens: max_tokens.unwrap_or(4096),
-        messages: parsed_messages,
-        system: system.map(api_proxy::types::SystemContent::Text),
-        stop_sequences: None,
-        stream: false,
-        temperature,
-        top_p: None,
-        top_k: None,
-        metadata: None,
-        tools: None,
-        tool_choice: None,
-        thinking: None,
-    };
-
-    // Send request
-    let response = client
-        .send_message(&request)
-        .await
-        .map_err(|e| format!("API request failed: {}", e))?;
+/// 粗略估计：每个 token 约 4 个字符
+fn estimate_tokens(char_count: u64) -> u64 {
+    char_count / 4
+}
 
-    // Convert to JSON
-    serde_json::to_value(response).map_err(|e| format!("Failed to serialize response: {}", e))
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ContextFile {
+    path: String,
+    scope: String,  // "global" (~/.claude), "project", or "local" (CLAUDE.local.md)
+    content: String,
+    token_estimate: u64,
 }
 
-/// Get the mapped model name for a given Claude model
+/// 读取 CLAUDE.md / AGENTS.md 等 agent 上下文文件（全局 + 项目）；token 数用与代理相同的分词器统计，
+/// 在阻塞线程池中执行
 #[tauri::command]
-fn get_mapped_model(
-    model: String,
-    preferred_provider: Option<String>,
-    big_model: Option<String>,
-    small_model: Option<String>,
-) -> Result<serde_json::Value, String> {
-    let config = api_proxy::ProxyConfig {
-        preferred_provider: preferred_provider
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(api_proxy::types::Provider::OpenAI),
-        big_model: big_model.unwrap_or_else(|| "gpt-4.1".to_string()),
-        small_model: small_model.unwrap_or_else(|| "gpt-4.1-mini".to_string()),
-        ..Default::default()
-    };
+async fn read_agent_context(project_dir: String) -> Result<Vec<ContextFile>, String> {
+    let home = std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .map_err(|e| format!("Failed to get home directory: {}", e))?;
 
-    let mapped = api_proxy::map_model(&model, &config);
+    let project = PathBuf::from(&project_dir);
+    let candidates = [
+        (PathBuf::from(home).join(".claude").join("CLAUDE.md"), "global"),
+        (project.join("CLAUDE.md"), "project"),
+        (project.join("AGENTS.md"), "project"),
+        (project.join(".claude").join("CLAUDE.md"), "project"),
+        (project.join(".claude").join("AGENTS.md"), "project"),
+        (project.join("CLAUDE.local.md"), "local"),
+    ];
 
-    Ok(serde_json::json!({
-        "original": model,
-        "provider": mapped.provider,
-        "model": mapped.model,
-        "full_name": mapped.full_name
-    }))
+    tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        for (path, scope) in candidates {
+            if !path.is_file() {
+                continue;
+            }
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            files.push(ContextFile {
+                path: path.to_string_lossy().to_string(),
+                scope: scope.to_string(),
+                token_estimate: api_proxy::tokens::count_text_tokens(&content) as u64,
+                content,
+            });
+        }
+        Ok(files)
+    })
+    .await
+    .map_err(|e| format!("Context read task failed: {}", e))?
 }
 
-// ============================================================================
-// Preview Window Command
-// ============================================================================
+#[derive(serde::Serialize, serde::Deserialize)]
+struct McpServerInfo {
+    name: String,
+    transport: String,  // "stdio", "http" or "sse"
+    disabled: Option<bool>,
+    source: String,     // "user" (~/.claude.json) or "project" (.mcp.json)
+    // stdio transport
+    command: Option<String>,
+    args: Option<Vec<String>>,
+    env: Option<HashMap<String, String>>,
+    // http transport
+    url: Option<String>,
+    headers: Option<HashMap<String, String>>,
+}
 
-/// Open external URL in a new Tauri window (bypasses iframe X-Frame-Options restrictions)
-#[tauri::command]
-async fn open_preview_window(
-    app: tauri::AppHandle,
-    url: String,
-    title: Option<String>,
-) -> Result<(), String> {
-    use tauri::WebviewWindowBuilder;
-    use tauri::WebviewUrl;
+/// Where an MCP server is configured
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+enum McpScope {
+    /// User-global servers in ~/.claude.json
+    User,
+    /// Project servers in <project_dir>/.mcp.json
+    Project(String),
+}
 
-    println!("[open_preview_window] Opening URL: {}", url);
+impl McpScope {
+    fn config_path(&self) -> Result<PathBuf, String> {
+        match self {
+            McpScope::User => {
+                let home = std::env::var("HOME")
+                    .or_else(|_| std::env::var("USERPROFILE"))
+                    .map_err(|e| format!("Failed to get home directory: {}", e))?;
+                Ok(PathBuf::from(home).join(".claude.json"))
+            }
+            McpScope::Project(project_dir) => Ok(PathBuf::from(project_dir).join(".mcp.json")),
+        }
+    }
 
-    // Parse URL
-    let parsed_url: url::Url = url.parse()
-        .map_err(|e| format!("Invalid URL: {}", e))?;
+    /// File name used in error messages
+    fn label(&self) -> &'static str {
+        match self {
+            McpScope::User => "~/.claude.json",
+            McpScope::Project(_) => ".mcp.json",
+        }
+    }
 
-    // Generate unique window label
-    let window_label = format!("preview-{}", std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis());
+    fn source(&self) -> &'static str {
+        match self {
+            McpScope::User => "user",
+            McpScope::Project(_) => "project",
+        }
+    }
+}
 
-    // Window title
-    let window_title = title.unwrap_or_else(|| {
-        parsed_url.host_str()
-            .map(|h| format!("Preview - {}", h))
-            .unwrap_or_else(|| "Preview".to_string())
-    });
+/// 读取 MCP 配置文件（文件不存在时返回 None）
+fn load_mcp_config(scope: &McpScope) -> Result<Option<serde_json::Value>, String> {
+    let config_path = scope.config_path()?;
 
-    // Create new window
-    WebviewWindowBuilder::new(&app, &window_label, WebviewUrl::External(parsed_url))
-        .title(&window_title)
-        .inner_size(1200.0, 800.0)
-        .center()
-        .build()
-        .map_err(|e| format!("Failed to create preview window: {}", e))?;
+    if !config_path.exists() {
+        return Ok(None);
+    }
 
-    println!("[open_preview_window] Window created: {}", window_label);
-    Ok(())
-}
+    let content = fs::read_to_string(&config_path)
+        .map_err(|e| format!("Failed to read {}: {}", scope.label(), e))?;
 
-// ============================================================================
-// CLI Execution Commands
-// ============================================================================
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| format!("Failed to parse {}: {}", scope.label(), e))
+}
 
-/// Execute Claude Code CLI with streaming output
-#[tauri::command]
-async fn execute_claude_streaming(
-    app: tauri::AppHandle,
-    node_path: String,
-    claude_path: String,
-    args: Vec<String>,
-    cwd: String,
-) -> Result<(), String> {
-    println!("[execute_claude_streaming] Starting execution");
-    println!("[execute_claude_streaming] Node: {}", node_path);
-    println!("[execute_claude_streaming] Claude: {}", claude_path);
-    println!("[execute_claude_streaming] Args: {:?}", args);
-    println!("[execute_claude_streaming] CWD: {}", cwd);
+/// 写回 MCP 配置文件：先把原文件备份为 <file>.bak，再原子替换，避免写入中断导致配置丢失
+fn save_mcp_config(scope: &McpScope, config: &serde_json::Value) -> Result<(), String> {
+    let config_path = scope.config_path()?;
 
-    // Create the command
-    let mut cmd = Command::new(&node_path);
-    cmd.arg(&claude_path);
-    cmd.args(&args);
-    cmd.current_dir(&cwd);
-    cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::piped());
+    let content = serde_json::to_string_pretty(config)
+        .map_err(|e| format!("Failed to serialize config: {}", e))?;
 
-    // Spawn the process
-    let mut child = cmd
-        .spawn()
-        .map_err(|e| format!("Failed to spawn Claude: {}", e))?;
+    if config_path.is_file() {
+        let mut backup_path = config_path.clone().into_os_string();
+        backup_path.push(".bak");
+        fs::copy(&config_path, &backup_path)
+            .map_err(|e| format!("Failed to back up {}: {}", scope.label(), e))?;
+    }
 
-    // Get stdout and stderr
-    let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
-    let stderr = child.stderr.take().ok_or("Failed to get stderr")?;
+    write_file_atomic(&config_path, content.as_bytes())
+        .map_err(|e| format!("Failed to write {}: {}", scope.label(), e))
+}
 
-    let pid = child.id().unwrap_or(0);
-    println!("[execute_claude_streaming] Spawned process with PID: {}", pid);
+/// 解析配置中的 mcpServers
+fn parse_mcp_servers(config: &serde_json::Value, source: &str) -> Vec<McpServerInfo> {
+    let mut servers = Vec::new();
 
-    // Create readers
-    let stdout_reader = BufReader::new(stdout);
-    let stderr_reader = BufReader::new(stderr);
+    if let Some(mcp_servers) = config.get("mcpServers").and_then(|v| v.as_object()) {
+        for (name, server_config) in mcp_servers {
+            // Use the declared type; without one a "url" field means http, otherwise stdio
+            let url = server_config.get("url").and_then(|v| v.as_str()).map(|s| s.to_string());
+            let transport = match server_config.get("type").and_then(|v| v.as_str()) {
+                Some(kind) => kind.to_string(),
+                None if url.is_some() => "http".to_string(),
+                None => "stdio".to_string(),
+            };
 
-    // Spawn task to read stdout
-    let app_stdout = app.clone();
-    let stdout_task = tokio::spawn(async move {
-        let mut lines = stdout_reader.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            println!("[Claude stdout] {}", line);
-            // Emit to frontend
-            let _ = app_stdout.emit("claude-stream", &line);
+            let command = server_config.get("command").and_then(|v| v.as_str()).map(|s| s.to_string());
+            let args = server_config.get("args").and_then(|v| v.as_array()).map(|arr| {
+                arr.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect()
+            });
+            let env = server_config.get("env").and_then(|v| v.as_object()).map(|obj| {
+                obj.iter()
+                    .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
+                    .collect()
+            });
+
+            let disabled = server_config.get("disabled").and_then(|v| v.as_bool());
+            let headers = server_config.get("headers").and_then(|v| v.as_object()).map(|obj| {
+                obj.iter()
+                    .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
+                    .collect()
+            });
+
+            servers.push(McpServerInfo {
+                name: name.clone(),
+                transport,
+                disabled,
+                source: source.to_string(),
+                command,
+                args,
+                env,
+                url,
+                headers,
+            });
         }
-    });
+    }
 
-    // Spawn task to read stderr
-    let app_stderr = app.clone();
-    let stderr_task = tokio::spawn(async move {
-        let mut lines = stderr_reader.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            println!("[Claude stderr] {}", line);
-            // Emit to frontend
-            let _ = app_stderr.emit("claude-error", &line);
+    servers
+}
+
+/// 展开字符串中的 ${VAR}、${VAR:-default} 和 $VAR；未定义的变量保持原样，名称记入 unknown
+fn expand_env_refs(value: &str, lookup: &dyn Fn(&str) -> Option<String>, unknown: &mut Vec<String>) -> String {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+
+        if let Some(braced) = after.strip_prefix('{') {
+            if let Some(end) = braced.find('}') {
+                let inner = &braced[..end];
+                let (name, default) = match inner.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (inner, None),
+                };
+                if !name.is_empty() && name.chars().all(is_name_char) {
+                    match lookup(name).or_else(|| default.map(str::to_string)) {
+                        Some(resolved) => out.push_str(&resolved),
+                        None => {
+                            unknown.push(name.to_string());
+                            out.push_str(&rest[pos..pos + 2 + end + 1]);
+                        }
+                    }
+                    rest = &braced[end + 1..];
+                    continue;
+                }
+            }
+        } else {
+            let len = after.find(|c: char| !is_name_char(c)).unwrap_or(after.len());
+            let name = &after[..len];
+            if !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) {
+                match lookup(name) {
+                    Some(resolved) => out.push_str(&resolved),
+                    None => {
+                        unknown.push(name.to_string());
+                        out.push('$');
+                        out.push_str(name);
+                    }
+                }
+                rest = &after[len..];
+                continue;
+            }
         }
-    });
 
-    // Wait for both tasks to complete
-    let _ = tokio::join!(stdout_task, stderr_task);
+        out.push('$');
+        rest = after;
+    }
 
-    // Wait for the process to complete
-    match child.wait().await {
-        Ok(status) => {
-            println!("[execute_claude_streaming] Process exited with status: {}", status);
-            let _ = app.emit("claude-complete", status.success());
-            Ok(())
+    out.push_str(rest);
+    out
+}
+
+/// 展开 server 的 env、args 和 headers 中的环境变量引用，返回未定义的变量名
+fn expand_mcp_server_vars(server: &mut McpServerInfo, lookup: &dyn Fn(&str) -> Option<String>) -> Vec<String> {
+    let mut unknown = Vec::new();
+    for value in server.args.iter_mut().flatten() {
+        *value = expand_env_refs(value, lookup, &mut unknown);
+    }
+    for map in [server.env.as_mut(), server.headers.as_mut()].into_iter().flatten() {
+        for value in map.values_mut() {
+            *value = expand_env_refs(value, lookup, &mut unknown);
         }
-        Err(e) => {
-            println!("[execute_claude_streaming] Process wait failed: {}", e);
-            let _ = app.emit("claude-complete", false);
-            Err(format!("Failed to wait for Claude process: {}", e))
+    }
+    unknown.sort();
+    unknown.dedup();
+    unknown
+}
+
+/// 对读取到的 servers 展开环境变量；默认保留原文，避免把密钥值返回给前端，expand 为 true 时才展开
+fn expand_mcp_servers(servers: &mut [McpServerInfo], expand: Option<bool>) {
+    if !expand.unwrap_or(false) {
+        return;
+    }
+    let lookup = |name: &str| std::env::var(name).ok();
+    for server in servers.iter_mut() {
+        let unknown = expand_mcp_server_vars(server, &lookup);
+        if !unknown.is_empty() {
+            println!(
+                "[read_mcp_servers] Warning: server '{}' references undefined variable(s): {}",
+                server.name,
+                unknown.join(", ")
+            );
         }
     }
 }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    // Set up panic hook to log panics
-    std::panic::set_hook(Box::new(|panic_info| {
-        eprintln!("PANIC: {:?}", panic_info);
-        if let Some(location) = panic_info.location() {
-            eprintln!("Panic occurred in file '{}' at line {}", location.file(), location.line());
+/// 读取 MCP servers 配置（默认 ~/.claude.json，project 范围读取 .mcp.json）
+/// 默认返回原始字符串；expand 为 true 时展开 env、args、headers 中的 ${VAR}
+#[tauri::command]
+fn read_mcp_servers(scope: Option<McpScope>, expand: Option<bool>) -> Result<Vec<McpServerInfo>, String> {
+    let scope = scope.unwrap_or(McpScope::User);
+
+    let Some(config) = load_mcp_config(&scope)? else {
+        return Ok(Vec::new()); // 如果配置文件不存在，返回空列表
+    };
+
+    let mut servers = parse_mcp_servers(&config, scope.source());
+    expand_mcp_servers(&mut servers, expand);
+
+    // 按名称排序
+    servers.sort_by_key(|s| s.name.to_lowercase());
+
+    Ok(servers)
+}
+
+/// 合并读取用户和项目两个范围的 MCP servers（通过 source 字段区分）
+#[tauri::command]
+fn read_all_mcp_servers(project_dir: String, expand: Option<bool>) -> Result<Vec<McpServerInfo>, String> {
+    let mut servers = Vec::new();
+
+    for scope in [McpScope::User, McpScope::Project(project_dir)] {
+        if let Some(config) = load_mcp_config(&scope)? {
+            servers.extend(parse_mcp_servers(&config, scope.source()));
         }
-        if let Some(s) = panic_info.payload().downcast_ref::<&str>() {
-            eprintln!("Panic message: {}", s);
+    }
+    expand_mcp_servers(&mut servers, expand);
+
+    // 按名称排序，同名时用户范围在前
+    servers.sort_by(|a, b| {
+        a.name.to_lowercase().cmp(&b.name.to_lowercase()).then_with(|| b.source.cmp(&a.source))
+    });
+
+    Ok(servers)
+}
+
+/// 根据 transport 构造 server 配置
+fn build_mcp_server_config(
+    transport: &str,
+    command: Option<String>,
+    args: Option<Vec<String>>,
+    env: Option<HashMap<String, String>>,
+    url: Option<String>,
+    token: Option<String>,
+) -> Result<serde_json::Value, String> {
+    let server_config = if matches!(transport, "http" | "sse") {
+        // HTTP/SSE transport - requires url
+        let url_value = url.ok_or("URL is required for HTTP transport")?;
+        let mut cfg = serde_json::json!({
+            "type": transport,
+            "url": url_value
+        });
+
+        // Add Authorization header if token is provided
+        if let Some(token_value) = token {
+            if !token_value.is_empty() {
+                cfg["headers"] = serde_json::json!({
+                    "Authorization": format!("Bearer {}", token_value)
+                });
+            }
         }
-    }));
 
-    tauri::Builder::default()
-        .plugin(tauri_plugin_shell::init())
-        .plugin(tauri_plugin_dialog::init())
-        .plugin(tauri_plugin_store::Builder::new().build())
-        .manage(create_terminal_map())
-        .manage(create_pty_writer_map())
-        .manage(create_proxy_server_handle())
-        .invoke_handler(tauri::generate_handler![
-            extract_cli,
-            get_node_path,
-            get_kiro_path,
-            get_cli_path,
-            read_directory,
-            read_file_content,
-            read_file_bytes,
-            create_file,
-            create_directory,
-            delete_path,
-            rename_path,
-            save_file,
-            read_claude_skills,
-            read_mcp_servers,
-            add_mcp_server,
-            remove_mcp_server,
-            toggle_mcp_server,
-            git_status,
-            git_log,
-            git_diff,
-            git_commit,
-            execute_claude_streaming,
-            execute_kiro_streaming,
-            start_background_process,
-            kill_process,
-            associate_terminal,
-            create_interactive_terminal,
-            close_terminal,
-            terminal_input,
-            // API Proxy commands
-            start_api_proxy,
-            stop_api_proxy,
-            api_proxy_send_message,
-            get_mapped_model,
-            // Preview window command
-            open_preview_window
-        ])
-        .setup(|app| {
-            #[cfg(debug_assertions)]
-            {
-                let window = app.get_webview_window("main").unwrap();
-                window.open_devtools();
+        cfg
+    } else {
+        // stdio transport - requires command
+        let cmd = command.ok_or("Command is required for stdio transport")?;
+        let mut cfg = serde_json::json!({
+            "type": "stdio",
+            "command": cmd
+        });
+
+        if let Some(args_vec) = args {
+            cfg["args"] = serde_json::json!(args_vec);
+        }
+
+        if let Some(env_map) = env {
+            cfg["env"] = serde_json::json!(env_map);
+        }
+
+        cfg
+    };
+
+    Ok(server_config)
+}
+
+/// 是否为可执行的普通文件（Unix 上要求有执行权限位）
+fn is_executable_file(path: &std::path::Path) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+/// 在 PATH 中查找可执行命令；带路径分隔符的命令直接检查该文件
+fn find_on_path(program: &str, path_var: &str) -> Option<PathBuf> {
+    let direct = std::path::Path::new(program);
+    if direct.components().count() > 1 {
+        return is_executable_file(direct).then(|| direct.to_path_buf());
+    }
+
+    let extensions: Vec<String> = if cfg!(target_os = "windows") {
+        std::env::var("PATHEXT")
+            .unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string())
+            .split(';')
+            .map(|ext| ext.to_string())
+            .chain(std::iter::once(String::new()))
+            .collect()
+    } else {
+        vec![String::new()]
+    };
+
+    std::env::split_paths(path_var).find_map(|dir| {
+        extensions.iter().find_map(|ext| {
+            let candidate = dir.join(format!("{}{}", program, ext));
+            is_executable_file(&candidate).then_some(candidate)
+        })
+    })
+}
+
+/// 启动 stdio server 时使用的 PATH：server 自己的 env 优先，否则用登录 shell 的 PATH
+fn mcp_path_var(env: Option<&HashMap<String, String>>) -> String {
+    env.and_then(|vars| vars.get("PATH").cloned())
+        .unwrap_or_else(|| shell_path().to_string())
+}
+
+/// 校验 MCP server 配置：http/sse 的 url 必须可解析，stdio 的 command 必须能在 PATH 中找到，
+/// 其他 transport 直接拒绝
+fn validate_mcp_server(
+    transport: &str,
+    command: Option<&str>,
+    url: Option<&str>,
+    env: Option<&HashMap<String, String>>,
+) -> Result<(), String> {
+    match transport {
+        "http" | "sse" => {
+            let url = url.ok_or("URL is required for HTTP transport")?;
+            let parsed = url::Url::parse(url).map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+            if !matches!(parsed.scheme(), "http" | "https") {
+                return Err(format!("Invalid URL '{}': scheme must be http or https", url));
             }
             Ok(())
-        })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        }
+        "stdio" => {
+            let command = command.ok_or("Command is required for stdio transport")?;
+            if command.trim().is_empty() {
+                return Err("Command is required for stdio transport".to_string());
+            }
+            match find_on_path(command, &mcp_path_var(env)) {
+                Some(_) => Ok(()),
+                None => Err(format!("Command not found on PATH: {}", command)),
+            }
+        }
+        other => Err(format!("Unsupported MCP transport: {}", other)),
+    }
+}
+
+/// 添加 MCP server（默认写入 ~/.claude.json）；同名 server 已存在时报错，除非 overwrite 为 true
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn add_mcp_server(
+    name: String,
+    transport: String,
+    command: Option<String>,
+    args: Option<Vec<String>>,
+    env: Option<HashMap<String, String>>,
+    url: Option<String>,
+    token: Option<String>,
+    scope: Option<McpScope>,
+    overwrite: Option<bool>,
+) -> Result<(), String> {
+    let scope = scope.unwrap_or(McpScope::User);
+
+    // Read existing config or create new
+    let mut config = load_mcp_config(&scope)?.unwrap_or_else(|| serde_json::json!({}));
+
+    // Ensure mcpServers object exists
+    if config.get("mcpServers").is_none() {
+        config["mcpServers"] = serde_json::json!({});
+    }
+
+    if config["mcpServers"].get(&name).is_some() && !overwrite.unwrap_or(false) {
+        return Err(format!("MCP server '{}' already exists in {}", name, scope.label()));
+    }
+
+    validate_mcp_server(&transport, command.as_deref(), url.as_deref(), env.as_ref())?;
+
+    let server_config = build_mcp_server_config(&transport, command, args, env, url, token)?;
+
+    // Add server to config
+    config["mcpServers"][&name] = server_config;
+
+    // Write back to file
+    save_mcp_config(&scope, &config)
+}
+
+/// 修改已有 MCP server：只更新传入的字段，其余字段（包括手动添加的）保持不变
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn update_mcp_server(
+    name: String,
+    command: Option<String>,
+    args: Option<Vec<String>>,
+    env: Option<HashMap<String, String>>,
+    url: Option<String>,
+    token: Option<String>,
+    scope: Option<McpScope>,
+) -> Result<(), String> {
+    let scope = scope.unwrap_or(McpScope::User);
+
+    let Some(mut config) = load_mcp_config(&scope)? else {
+        return Err("Config file does not exist".to_string());
+    };
+
+    let Some(server) = config.get_mut("mcpServers").and_then(|v| v.get_mut(&name)) else {
+        return Err(format!("MCP server '{}' not found", name));
+    };
+    if !server.is_object() {
+        return Err(format!("MCP server '{}' is not a valid config object", name));
+    }
+
+    // Validate only what is being changed
+    if let Some(url_value) = &url {
+        validate_mcp_server("http", None, Some(url_value), None)?;
+    }
+    if let Some(cmd) = &command {
+        let server_env: Option<HashMap<String, String>> = env
+            .clone()
+            .or_else(|| server.get("env").and_then(|v| serde_json::from_value(v.clone()).ok()));
+        validate_mcp_server("stdio", Some(cmd), None, server_env.as_ref())?;
+    }
+
+    if let Some(cmd) = command {
+        server["command"] = serde_json::json!(cmd);
+    }
+    if let Some(args_vec) = args {
+        server["args"] = serde_json::json!(args_vec);
+    }
+    if let Some(env_map) = env {
+        server["env"] = serde_json::json!(env_map);
+    }
+    if let Some(url_value) = url {
+        server["url"] = serde_json::json!(url_value);
+    }
+    if let Some(token_value) = token {
+        // Only the Authorization header is replaced; other headers are kept
+        if !server.get("headers").is_some_and(|h| h.is_object()) {
+            server["headers"] = serde_json::json!({});
+        }
+        if let Some(headers) = server["headers"].as_object_mut() {
+            if token_value.is_empty() {
+                headers.remove("Authorization");
+            } else {
+                headers.insert("Authorization".to_string(), serde_json::json!(format!("Bearer {}", token_value)));
+            }
+        }
+    }
+
+    save_mcp_config(&scope, &config)
+}
+
+/// How long a stdio MCP server has to stay up before it counts as started
+const MCP_STDIO_STARTUP_WAIT: std::time::Duration = std::time::Duration::from_secs(2);
+const MCP_HTTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct McpTestResult {
+    ok: bool,
+    transport: String,
+    message: String,
+    resolved_command: Option<String>,  // stdio: absolute path the command resolved to
+    exit_code: Option<i32>,            // stdio: set when the server exited during the startup wait
+    stderr: Option<String>,            // stdio: output captured from a server that exited
+    status: Option<u16>,               // http: response status
+    elapsed_ms: u64,
+}
+
+impl McpTestResult {
+    fn failed(transport: &str, message: String) -> Self {
+        McpTestResult {
+            ok: false,
+            transport: transport.to_string(),
+            message,
+            resolved_command: None,
+            exit_code: None,
+            stderr: None,
+            status: None,
+            elapsed_ms: 0,
+        }
+    }
+}
+
+/// Start a stdio server and check that it is still running after `wait`. Servers read
+/// requests from stdin, so one that exits straight away (with stdin still open) is broken.
+async fn probe_mcp_stdio(
+    command: &str,
+    args: &[String],
+    env: Option<&HashMap<String, String>>,
+    wait: std::time::Duration,
+) -> McpTestResult {
+    use tokio::io::AsyncReadExt;
+
+    let path_var = mcp_path_var(env);
+    let Some(resolved) = find_on_path(command, &path_var) else {
+        return McpTestResult::failed("stdio", format!("Command not found on PATH: {}", command));
+    };
+
+    let started = std::time::Instant::now();
+    let mut cmd = tokio::process::Command::new(&resolved);
+    cmd.args(args)
+        .env("PATH", &path_var)
+        .envs(env.into_iter().flatten())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => return McpTestResult::failed("stdio", format!("Failed to start {}: {}", command, e)),
+    };
+
+    let mut result = McpTestResult {
+        resolved_command: Some(resolved.to_string_lossy().to_string()),
+        ..McpTestResult::failed("stdio", String::new())
+    };
+
+    match tokio::time::timeout(wait, child.wait()).await {
+        Err(_) => {
+            let _ = child.kill().await;
+            result.ok = true;
+            result.message = format!("Server started and was still running after {}ms", wait.as_millis());
+        }
+        Ok(status) => {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr).await;
+            }
+            let code = status.ok().and_then(|s| s.code());
+            result.exit_code = code;
+            result.stderr = Some(stderr.trim().to_string()).filter(|s| !s.is_empty());
+            result.message = match code {
+                Some(code) => format!("Server exited during startup with code {}", code),
+                None => "Server was terminated during startup".to_string(),
+            };
+        }
+    }
+
+    result.elapsed_ms = started.elapsed().as_millis() as u64;
+    result
+}
+
+/// Check that an http MCP endpoint answers. Any HTTP response counts as reachable
+/// (many endpoints reject a bare GET), except 401/403 which mean the token is wrong.
+async fn probe_mcp_http(url: &str, token: Option<&str>) -> McpTestResult {
+    let client = match reqwest::Client::builder().timeout(MCP_HTTP_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => return McpTestResult::failed("http", format!("Failed to create HTTP client: {}", e)),
+    };
+
+    let started = std::time::Instant::now();
+    let mut request = client.get(url).header("Accept", "application/json, text/event-stream");
+    if let Some(token) = token.filter(|t| !t.is_empty()) {
+        request = request.bearer_auth(token);
+    }
+
+    let mut result = match request.send().await {
+        Ok(response) => {
+            let status = response.status();
+            let auth_failed = matches!(status.as_u16(), 401 | 403);
+            McpTestResult {
+                ok: !auth_failed,
+                status: Some(status.as_u16()),
+                message: if auth_failed {
+                    format!("Server rejected the credentials ({})", status)
+                } else {
+                    format!("Server is reachable ({})", status)
+                },
+                ..McpTestResult::failed("http", String::new())
+            }
+        }
+        Err(e) if e.is_timeout() => McpTestResult::failed("http", format!("Timed out connecting to {}", url)),
+        Err(e) => McpTestResult::failed("http", format!("Failed to connect to {}: {}", url, e)),
+    };
+
+    result.elapsed_ms = started.elapsed().as_millis() as u64;
+    result
+}
+
+/// Try an MCP server config without saving it: reachability for http, a short startup
+/// check for stdio. Configuration problems are reported in the result, not as errors.
+#[tauri::command]
+async fn test_mcp_server(
+    transport: String,
+    command: Option<String>,
+    args: Option<Vec<String>>,
+    env: Option<HashMap<String, String>>,
+    url: Option<String>,
+    token: Option<String>,
+) -> Result<McpTestResult, String> {
+    if let Err(message) = validate_mcp_server(&transport, command.as_deref(), url.as_deref(), env.as_ref()) {
+        return Ok(McpTestResult::failed(&transport, message));
+    }
+
+    let result = if matches!(transport.as_str(), "http" | "sse") {
+        probe_mcp_http(url.as_deref().unwrap_or_default(), token.as_deref()).await
+    } else {
+        probe_mcp_stdio(
+            command.as_deref().unwrap_or_default(),
+            &args.unwrap_or_default(),
+            env.as_ref(),
+            MCP_STDIO_STARTUP_WAIT,
+        )
+        .await
+    };
+
+    println!("[test_mcp_server] {} -> ok={} ({})", transport, result.ok, result.message);
+    Ok(result)
+}
+
+/// 删除 MCP server
+#[tauri::command]
+fn remove_mcp_server(name: String, scope: Option<McpScope>) -> Result<(), String> {
+    let scope = scope.unwrap_or(McpScope::User);
+
+    let Some(mut config) = load_mcp_config(&scope)? else {
+        return Err("Config file does not exist".to_string());
+    };
+
+    // Remove server from mcpServers
+    if let Some(mcp_servers) = config.get_mut("mcpServers").and_then(|v| v.as_object_mut()) {
+        if mcp_servers.remove(&name).is_none() {
+            return Err(format!("MCP server '{}' not found", name));
+        }
+    } else {
+        return Err("No MCP servers configured".to_string());
+    }
+
+    // Write back to file
+    save_mcp_config(&scope, &config)
+}
+
+/// 设置单个 server 的 disabled 字段，返回状态是否发生变化
+fn set_mcp_server_disabled(server: &mut serde_json::Value, disabled: bool) -> bool {
+    let was_disabled = server.get("disabled").and_then(|v| v.as_bool()).unwrap_or(false);
+    if disabled {
+        server["disabled"] = serde_json::json!(true);
+    } else {
+        // Remove disabled field if enabling (false = not disabled = enabled)
+        if let Some(obj) = server.as_object_mut() {
+            obj.remove("disabled");
+        }
+    }
+    was_disabled != disabled
+}
+
+/// 切换 MCP server 启用/禁用状态
+#[tauri::command]
+fn toggle_mcp_server(name: String, disabled: bool, scope: Option<McpScope>) -> Result<(), String> {
+    let scope = scope.unwrap_or(McpScope::User);
+
+    let Some(mut config) = load_mcp_config(&scope)? else {
+        return Err("Config file does not exist".to_string());
+    };
+
+    // Toggle server disabled state
+    if let Some(mcp_servers) = config.get_mut("mcpServers").and_then(|v| v.as_object_mut()) {
+        if let Some(server) = mcp_servers.get_mut(&name) {
+            set_mcp_server_disabled(server, disabled);
+        } else {
+            return Err(format!("MCP server '{}' not found", name));
+        }
+    } else {
+        return Err("No MCP servers configured".to_string());
+    }
+
+    // Write back to file
+    save_mcp_config(&scope, &config)
+}
+
+/// 批量切换多个 MCP server，一次读写完成；任一名称不存在则不写入
+/// 返回状态实际发生变化的 server 名称（按传入顺序）
+#[tauri::command]
+fn toggle_mcp_servers(changes: Vec<(String, bool)>, scope: Option<McpScope>) -> Result<Vec<String>, String> {
+    let scope = scope.unwrap_or(McpScope::User);
+
+    let Some(mut config) = load_mcp_config(&scope)? else {
+        return Err("Config file does not exist".to_string());
+    };
+
+    let Some(mcp_servers) = config.get_mut("mcpServers").and_then(|v| v.as_object_mut()) else {
+        return Err("No MCP servers configured".to_string());
+    };
+
+    // Validate every name before touching anything
+    let missing: Vec<&str> = changes
+        .iter()
+        .filter(|(name, _)| !mcp_servers.contains_key(name))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("MCP server(s) not found: {}", missing.join(", ")));
+    }
+
+    let mut changed: Vec<String> = Vec::new();
+    for (name, disabled) in &changes {
+        if let Some(server) = mcp_servers.get_mut(name) {
+            if set_mcp_server_disabled(server, *disabled) {
+                // A name listed twice may flip back; keep only the net change
+                if let Some(pos) = changed.iter().position(|n| n == name) {
+                    changed.remove(pos);
+                } else {
+                    changed.push(name.clone());
+                }
+            }
+        }
+    }
+
+    if !changed.is_empty() {
+        save_mcp_config(&scope, &config)?;
+    }
+
+    Ok(changed)
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct GitStatusFile {
+    status: String,      // e.g., "M", "A", "D", "??"
+    path: String,
+    old_path: Option<String>,  // source path of a rename/copy
+    staged: bool,
+    submodule: bool,     // entry is a submodule, not a regular file
+    conflicted: bool,    // unmerged: UU, AA, DD, AU, UA, DU, UD
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct GitStatus {
+    branch: String,
+    ahead: u32,
+    behind: u32,
+    files: Vec<GitStatusFile>,
+    is_repo: bool,
+    operation: Option<String>,  // "merge", "rebase", "cherry-pick" or "revert" in progress
+}
+
+/// 检测进行中的 merge/rebase/cherry-pick/revert（查看 git 目录中的标记文件）
+fn git_operation_in_progress(project_dir: &str) -> Option<String> {
+    // The git dir is not always <project>/.git (worktrees, submodules)
+    let output = run_git(project_dir, &["rev-parse", "--git-dir"]).ok()?;
+    let git_dir = PathBuf::from(project_dir).join(String::from_utf8_lossy(&output.stdout).trim());
+    let markers = [
+        ("rebase-merge", "rebase"),
+        ("rebase-apply", "rebase"),
+        ("MERGE_HEAD", "merge"),
+        ("CHERRY_PICK_HEAD", "cherry-pick"),
+        ("REVERT_HEAD", "revert"),
+    ];
+    markers
+        .iter()
+        .find(|(marker, _)| git_dir.join(marker).exists())
+        .map(|(_, operation)| operation.to_string())
+}
+
+/// 解析 git 引用的路径（含空格、引号或非 ASCII 字符时 git 会加引号并做 C 风格/八进制转义）
+fn unquote_git_path(raw: &str) -> String {
+    let Some(inner) = raw.strip_prefix('"').and_then(|r| r.strip_suffix('"')) else {
+        return raw.to_string();
+    };
+    // Octal escapes are raw UTF-8 bytes, so decode into bytes first
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('r') => bytes.push(b'\r'),
+            Some('a') => bytes.push(0x07),
+            Some('b') => bytes.push(0x08),
+            Some('f') => bytes.push(0x0c),
+            Some('v') => bytes.push(0x0b),
+            Some(d @ '0'..='7') => {
+                let mut value = d.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                bytes.push(value as u8);
+            }
+            Some(other) => {
+                let mut buf = [0u8; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buf).as_bytes());
+            }
+            None => bytes.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// 解析一行 `git status --porcelain=v1`：(索引状态, 工作区状态, 路径, 重命名/复制的原路径)
+fn parse_porcelain_line(line: &str) -> Option<(char, char, String, Option<String>)> {
+    let mut chars = line.chars();
+    let index_status = chars.next()?;
+    let worktree_status = chars.next()?;
+    let rest = line.get(3..).filter(|r| !r.is_empty())?;
+
+    if !matches!(index_status, 'R' | 'C') && !matches!(worktree_status, 'R' | 'C') {
+        return Some((index_status, worktree_status, unquote_git_path(rest), None));
+    }
+
+    // "old -> new"; a quoted old path may itself contain " -> "
+    let split_at = if rest.starts_with('"') {
+        let mut escaped = false;
+        rest.char_indices().skip(1).find_map(|(i, c)| match c {
+            _ if escaped => {
+                escaped = false;
+                None
+            }
+            '\\' => {
+                escaped = true;
+                None
+            }
+            '"' => Some(i + 1),
+            _ => None,
+        })
+    } else {
+        rest.find(" -> ")
+    };
+    match split_at.and_then(|i| rest[i..].strip_prefix(" -> ").map(|new| (&rest[..i], new))) {
+        Some((old, new)) => Some((
+            index_status,
+            worktree_status,
+            unquote_git_path(new),
+            Some(unquote_git_path(old)),
+        )),
+        None => Some((index_status, worktree_status, unquote_git_path(rest), None)),
+    }
+}
+
+/// 获取 git status
+#[tauri::command]
+fn git_status(project_dir: String) -> Result<GitStatus, String> {
+    use std::process::Command;
+
+    // Check if it's a git repo
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Ok(GitStatus {
+            branch: String::new(),
+            ahead: 0,
+            behind: 0,
+            files: Vec::new(),
+            is_repo: false,
+            operation: None,
+        });
+    }
+
+    // Get branch name
+    let branch_output = Command::new("git")
+        .args(["branch", "--show-current"])
+        .current_dir(&project_dir)
+        .output()
+        .map_err(|e| format!("Failed to run git branch: {}", e))?;
+
+    let branch = String::from_utf8_lossy(&branch_output.stdout).trim().to_string();
+
+    // Get ahead/behind info
+    let status_branch = Command::new("git")
+        .args(["status", "-sb"])
+        .current_dir(&project_dir)
+        .output()
+        .map_err(|e| format!("Failed to run git status: {}", e))?;
+
+    let status_line = String::from_utf8_lossy(&status_branch.stdout);
+    let first_line = status_line.lines().next().unwrap_or("");
+
+    let mut ahead = 0u32;
+    let mut behind = 0u32;
+
+    if let Some(bracket_start) = first_line.find('[') {
+        if let Some(bracket_end) = first_line.find(']') {
+            let info = &first_line[bracket_start + 1..bracket_end];
+            for part in info.split(", ") {
+                if let Some(n) = part.strip_prefix("ahead ") {
+                    ahead = n.parse().unwrap_or(0);
+                } else if let Some(n) = part.strip_prefix("behind ") {
+                    behind = n.parse().unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    // Get file status
+    let status_output = Command::new("git")
+        .args(["status", "--porcelain=v1"])
+        .current_dir(&project_dir)
+        .output()
+        .map_err(|e| format!("Failed to run git status: {}", e))?;
+
+    let status_text = String::from_utf8_lossy(&status_output.stdout);
+    let mut files = Vec::new();
+
+    // Submodule paths, so they can be flagged instead of showing up as plain modified files
+    let submodule_paths: Vec<String> = read_gitmodules(&project_dir)
+        .into_iter()
+        .map(|(_, path, _)| path)
+        .collect();
+
+    for line in status_text.lines() {
+        if line.len() < 4 {
+            continue;
+        }
+
+        let Some((index_status, worktree_status, file_path, old_path)) = parse_porcelain_line(line) else {
+            continue;
+        };
+
+        let conflicted = matches!(
+            (index_status, worktree_status),
+            ('U', _) | (_, 'U') | ('A', 'A') | ('D', 'D')
+        );
+
+        // Determine status code (single letter for cleaner display)
+        let status = match (index_status, worktree_status) {
+            _ if conflicted => "U".to_string(),  // Unmerged (conflict)
+            ('?', '?') => "N".to_string(),  // New/Untracked
+            ('M', _) | (_, 'M') => "M".to_string(),  // Modified
+            ('A', _) => "A".to_string(),  // Added (staged)
+            ('D', _) | (_, 'D') => "D".to_string(),  // Deleted
+            ('R', _) => "R".to_string(),  // Renamed
+            ('C', _) => "C".to_string(),  // Copied
+            ('!', '!') => "I".to_string(),  // Ignored
+            _ => "?".to_string(),  // Unknown
+        };
+
+        let staged = index_status != ' ' && index_status != '?' && !conflicted;
+        let submodule = submodule_paths.iter().any(|p| p == file_path.trim_end_matches('/'));
+
+        files.push(GitStatusFile {
+            status,
+            path: file_path,
+            old_path,
+            staged,
+            submodule,
+            conflicted,
+        });
+    }
+
+    Ok(GitStatus {
+        branch,
+        ahead,
+        behind,
+        files,
+        is_repo: true,
+        operation: git_operation_in_progress(&project_dir),
+    })
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+struct GitCommit {
+    graph: String,
+    short_hash: String,
+    refs: String,
+    message: String,
+    author: String,
+    email: String,
+    date: String,        // author date, ISO 8601
+}
+
+// git log 格式：hash、refs、作者、邮箱、ISO 时间、标题，用 NUL 分隔（标题中可能含有任何可见字符），标题放最后
+const GIT_LOG_FORMAT: &str = "--pretty=format:%h%x00%D%x00%an%x00%ae%x00%aI%x00%s";
+
+/// Parse one `GIT_LOG_FORMAT` record (the part after any graph prefix)
+fn parse_log_record(graph: String, record: &str) -> GitCommit {
+    let mut fields = record.splitn(6, '\0');
+    let mut next = || fields.next().unwrap_or("").to_string();
+    GitCommit {
+        graph,
+        short_hash: next(),
+        refs: next(),
+        author: next(),
+        email: next(),
+        date: next(),
+        message: next(),
+    }
+}
+
+/// 快速判断工作区是否干净（读到第一行改动即停止，不解析完整文件列表）
+#[tauri::command]
+fn git_is_clean(project_dir: String) -> Result<bool, String> {
+    use std::io::Read;
+
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let mut child = std::process::Command::new("git")
+        .args(["status", "--porcelain"])
+        .current_dir(&project_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run git status: {}", e))?;
+
+    // stderr 在后台读完，避免 git 写满管道后阻塞
+    let mut stderr = child.stderr.take().ok_or("Failed to capture git output")?;
+    let stderr_reader = std::thread::spawn(move || {
+        let mut buf = String::new();
+        let _ = stderr.read_to_string(&mut buf);
+        buf
+    });
+
+    let mut stdout = child.stdout.take().ok_or("Failed to capture git output")?;
+    let mut first = [0u8; 1];
+    let dirty = stdout
+        .read(&mut first)
+        .map_err(|e| format!("Failed to read git output: {}", e))?
+        > 0;
+
+    if dirty {
+        // No need to wait for git to list everything
+        let _ = child.kill();
+        let _ = child.wait();
+        return Ok(false);
+    }
+
+    let status = child.wait().map_err(|e| format!("Failed to run git status: {}", e))?;
+    let stderr = stderr_reader.join().unwrap_or_default();
+    if !status.success() {
+        return Err(format!("Failed to get git status: {}", stderr));
+    }
+    Ok(true)
+}
+
+/// 获取 git log with graph
+#[tauri::command]
+fn git_log(project_dir: String, limit: Option<u32>) -> Result<Vec<GitCommit>, String> {
+    use std::process::Command;
+
+    let limit = limit.unwrap_or(50);
+
+    // Check if it's a git repo
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    // Get commit log with graph
+    // Format: graph + GIT_LOG_FORMAT record
+    let log_output = Command::new("git")
+        .args([
+            "log",
+            &format!("-{}", limit),
+            "--graph",
+            GIT_LOG_FORMAT,
+            "--abbrev-commit",
+        ])
+        .current_dir(&project_dir)
+        .output()
+        .map_err(|e| format!("Failed to run git log: {}", e))?;
+
+    let log_text = String::from_utf8_lossy(&log_output.stdout);
+    let mut commits = Vec::new();
+
+    for line in log_text.lines() {
+        // Split at first occurrence of commit hash (7 chars after graph symbols)
+        // Graph chars: * | / \ space
+        let chars: Vec<char> = line.chars().collect();
+        // A line of only graph characters has no record
+        let mut graph_end = chars.len();
+
+        for (i, c) in chars.iter().enumerate() {
+            if *c != '*' && *c != '|' && *c != '/' && *c != '\\' && *c != ' ' && *c != '_' {
+                graph_end = i;
+                break;
+            }
+        }
+
+        let graph = chars[..graph_end].iter().collect::<String>();
+        let rest = chars[graph_end..].iter().collect::<String>();
+
+        if !rest.is_empty() {
+            commits.push(parse_log_record(graph, &rest));
+        } else if !graph.is_empty() {
+            // Pure graph line (continuation)
+            commits.push(GitCommit { graph, ..Default::default() });
+        }
+    }
+
+    Ok(commits)
+}
+
+/// Unified patch for one file: working tree vs index, or index vs HEAD when `staged`.
+/// An untracked file is shown as added (diffed against /dev/null) in the unstaged view.
+#[tauri::command]
+fn git_file_diff(project_dir: String, file: String, staged: bool) -> Result<String, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let untracked = run_git(
+        &project_dir,
+        &["ls-files", "--others", "--exclude-standard", "--", &file],
+    )?;
+    let is_untracked = !String::from_utf8_lossy(&untracked.stdout).trim().is_empty();
+
+    let output = if is_untracked {
+        if staged {
+            // Nothing of an untracked file is in the index
+            return Ok(String::new());
+        }
+        run_git(&project_dir, &["diff", "--no-index", "--", "/dev/null", &file])?
+    } else if staged {
+        run_git(&project_dir, &["diff", "--cached", "--", &file])?
+    } else {
+        run_git(&project_dir, &["diff", "--", &file])?
+    };
+
+    // `--no-index` exits with 1 when the files differ
+    let ok = output.status.success() || (is_untracked && output.status.code() == Some(1));
+    if !ok {
+        return Err(format!("Failed to get diff: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse `git log` output in `GIT_LOG_FORMAT` without a graph
+fn parse_log_lines(text: &str) -> Vec<GitCommit> {
+    text.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| parse_log_record(String::new(), line))
+        .collect()
+}
+
+/// Commit history of a single file, optionally continuing across renames (`--follow`).
+/// Untracked or unknown files have no history and yield an empty list.
+#[tauri::command]
+fn git_file_history(
+    project_dir: String,
+    file: String,
+    limit: u32,
+    follow_renames: bool,
+) -> Result<Vec<GitCommit>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    // A repository without commits has no history for anything
+    let has_head = run_git(&project_dir, &["rev-parse", "--verify", "--quiet", "HEAD"])?.status.success();
+    if !has_head {
+        return Ok(Vec::new());
+    }
+
+    let limit_arg = format!("-{}", limit);
+    let mut args = vec!["log", limit_arg.as_str(), GIT_LOG_FORMAT, "--abbrev-commit"];
+    if follow_renames {
+        args.push("--follow");
+    }
+    args.extend(["--", file.as_str()]);
+
+    let output = run_git(&project_dir, &args)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to run git log: {}", stderr.trim()));
+    }
+
+    Ok(parse_log_lines(&String::from_utf8_lossy(&output.stdout)))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct GitGraphNode {
+    hash: String,
+    parents: Vec<String>,
+    refs: Vec<String>,
+    message: String,
+    author: String,
+    time: i64,           // unix seconds
+    lane: u32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct GitGraph {
+    nodes: Vec<GitGraphNode>,
+    width: u32,          // number of lanes needed to draw the graph
+}
+
+/// Assign a column to each commit (given in topological order, children first).
+/// Each lane tracks the commit it is waiting for; a commit takes the leftmost lane
+/// expecting it, its first parent continues that lane and other parents open new ones.
+fn assign_graph_lanes(commits: &[(String, Vec<String>)]) -> (Vec<u32>, u32) {
+    let mut lanes: Vec<Option<String>> = Vec::new();
+    let mut assigned = Vec::with_capacity(commits.len());
+    let mut width = 0usize;
+
+    let claim_free = |lanes: &mut Vec<Option<String>>, hash: String| -> usize {
+        match lanes.iter().position(|l| l.is_none()) {
+            Some(i) => {
+                lanes[i] = Some(hash);
+                i
+            }
+            None => {
+                lanes.push(Some(hash));
+                lanes.len() - 1
+            }
+        }
+    };
+
+    for (hash, parents) in commits {
+        let lane = match lanes.iter().position(|l| l.as_deref() == Some(hash.as_str())) {
+            Some(i) => i,
+            None => claim_free(&mut lanes, hash.clone()),
+        };
+
+        // Other lanes that were waiting for this commit merge into it
+        for (i, slot) in lanes.iter_mut().enumerate() {
+            if i != lane && slot.as_deref() == Some(hash.as_str()) {
+                *slot = None;
+            }
+        }
+
+        lanes[lane] = parents.first().cloned();
+        for parent in parents.iter().skip(1) {
+            if !lanes.iter().any(|l| l.as_deref() == Some(parent.as_str())) {
+                claim_free(&mut lanes, parent.clone());
+            }
+        }
+
+        width = width.max(lanes.len());
+        while lanes.last().is_some_and(|l| l.is_none()) {
+            lanes.pop();
+        }
+        assigned.push(lane as u32);
+    }
+
+    (assigned, width as u32)
+}
+
+/// Commit graph as structured nodes with lane numbers (for a custom graph renderer)
+#[tauri::command]
+fn git_graph(project_dir: String, limit: u32) -> Result<GitGraph, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    // Unit/record separators keep messages with '|' intact
+    let limit_arg = format!("-{}", limit);
+    let output = run_git(&project_dir, &[
+        "log",
+        &limit_arg,
+        "--topo-order",
+        "--branches",
+        "--remotes",
+        "--tags",
+        "HEAD",
+        "--format=%H%x1f%P%x1f%D%x1f%an%x1f%at%x1f%s%x1e",
+    ])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to run git log: {}", stderr));
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut nodes: Vec<GitGraphNode> = text
+        .split('\x1e')
+        .filter_map(|record| {
+            let fields: Vec<&str> = record.trim_start_matches('\n').split('\x1f').collect();
+            if fields.len() < 6 || fields[0].is_empty() {
+                return None;
+            }
+            Some(GitGraphNode {
+                hash: fields[0].to_string(),
+                parents: fields[1].split_whitespace().map(|p| p.to_string()).collect(),
+                refs: fields[2]
+                    .split(", ")
+                    .filter(|r| !r.is_empty())
+                    .map(|r| r.to_string())
+                    .collect(),
+                author: fields[3].to_string(),
+                time: fields[4].parse().unwrap_or(0),
+                message: fields[5].to_string(),
+                lane: 0,
+            })
+        })
+        .collect();
+
+    let commits: Vec<(String, Vec<String>)> = nodes
+        .iter()
+        .map(|n| (n.hash.clone(), n.parents.clone()))
+        .collect();
+    let (lanes, width) = assign_graph_lanes(&commits);
+    for (node, lane) in nodes.iter_mut().zip(lanes) {
+        node.lane = lane;
+    }
+
+    Ok(GitGraph { nodes, width })
+}
+
+/// Get git diff for files (for AI commit message generation)
+#[tauri::command]
+fn git_diff(project_dir: String, files: Option<Vec<String>>) -> Result<String, String> {
+    use std::process::Command;
+
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    // Get diff for specific files or all
+    let output = if let Some(ref file_list) = files {
+        if file_list.is_empty() {
+            // Get all changes
+            Command::new("git")
+                .args(["diff", "HEAD", "--stat"])
+                .current_dir(&project_dir)
+                .output()
+                .map_err(|e| format!("Failed to get diff: {}", e))?
+        } else {
+            let mut args = vec!["diff", "HEAD", "--stat", "--"];
+            args.extend(file_list.iter().map(|s| s.as_str()));
+            Command::new("git")
+                .args(&args)
+                .current_dir(&project_dir)
+                .output()
+                .map_err(|e| format!("Failed to get diff: {}", e))?
+        }
+    } else {
+        Command::new("git")
+            .args(["diff", "HEAD", "--stat"])
+            .current_dir(&project_dir)
+            .output()
+            .map_err(|e| format!("Failed to get diff: {}", e))?
+    };
+
+    // Also get status for untracked files
+    let status_output = Command::new("git")
+        .args(["status", "--porcelain"])
+        .current_dir(&project_dir)
+        .output()
+        .map_err(|e| format!("Failed to get status: {}", e))?;
+
+    let diff_text = String::from_utf8_lossy(&output.stdout);
+    let status_text = String::from_utf8_lossy(&status_output.stdout);
+
+    // Combine diff and status info
+    let mut result = String::new();
+    if !diff_text.is_empty() {
+        result.push_str(&diff_text);
+    }
+
+    // Add untracked files info
+    let untracked: Vec<&str> = status_text
+        .lines()
+        .filter(|l| l.starts_with("??"))
+        .map(|l| l.trim_start_matches("?? "))
+        .collect();
+
+    if !untracked.is_empty() {
+        if !result.is_empty() {
+            result.push('\n');
+        }
+        result.push_str("New files:\n");
+        for f in untracked {
+            result.push_str(&format!("  {}\n", f));
+        }
+    }
+
+    Ok(result)
+}
+
+/// git command for talking to a remote without ever prompting: credential and SSH prompts
+/// fail immediately instead of hanging a UI-driven command. Messages are forced to English
+/// because failures are classified by matching stderr (see `remote_error`).
+fn git_remote_command(project_dir: &str, args: &[&str]) -> std::process::Command {
+    let mut cmd = std::process::Command::new("git");
+    cmd.args(args)
+        .current_dir(project_dir)
+        .env("LC_ALL", "C")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GCM_INTERACTIVE", "never")
+        .stdin(std::process::Stdio::null());
+    if std::env::var_os("GIT_SSH_COMMAND").is_none() {
+        cmd.env("GIT_SSH_COMMAND", "ssh -o BatchMode=yes");
+    }
+    cmd
+}
+
+/// Run git against a remote without ever prompting
+fn run_git_remote(project_dir: &str, args: &[&str]) -> Result<std::process::Output, String> {
+    git_remote_command(project_dir, args)
+        .output()
+        .map_err(|e| format!("Failed to run git {}: {}", args.first().unwrap_or(&""), e))
+}
+
+/// Turn a failed clone, push or pull into a readable error, calling out authentication problems
+fn remote_error(action: &str, remote: &str, stderr: &str) -> String {
+    let stderr = stderr.trim();
+    let auth_markers = [
+        "Authentication failed",
+        "could not read Username",
+        "could not read Password",
+        "terminal prompts disabled",
+        "Permission denied (publickey",
+        "Host key verification failed",
+    ];
+    if auth_markers.iter().any(|m| stderr.contains(m)) {
+        format!("Authentication failed for remote '{}'; configure credentials or an SSH key: {}", remote, stderr)
+    } else {
+        format!("Failed to {}: {}", action, stderr)
+    }
+}
+
+/// Push to a remote (default "origin"). With `set_upstream`, a branch without an upstream
+/// is pushed with `--set-upstream`; otherwise that case is reported as an error.
+#[tauri::command]
+fn git_push(
+    project_dir: String,
+    remote: Option<String>,
+    branch: Option<String>,
+    set_upstream: bool,
+) -> Result<String, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let remote = remote.unwrap_or_else(|| "origin".to_string());
+    let branch = match branch {
+        Some(branch) => branch,
+        None => {
+            let output = run_git(&project_dir, &["branch", "--show-current"])?;
+            let current = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if current.is_empty() {
+                return Err("Cannot push from a detached HEAD".to_string());
+            }
+            current
+        }
+    };
+    // 以 '-' 开头的值会被 git 当作选项（如 --receive-pack=<cmd>）
+    if remote.trim().is_empty() || remote.starts_with('-') {
+        return Err(format!("Invalid remote name: {}", remote));
+    }
+    if branch.trim().is_empty() || branch.starts_with('-') {
+        return Err(format!("Invalid branch name: {}", branch));
+    }
+    let has_upstream = run_git(&project_dir, &["rev-parse", "--abbrev-ref", &format!("{}@{{upstream}}", branch)])?
+        .status
+        .success();
+
+    let mut args = vec!["push"];
+    if !has_upstream {
+        if !set_upstream {
+            return Err(format!("Branch '{}' has no upstream; push with set_upstream to create {}/{}", branch, remote, branch));
+        }
+        args.push("--set-upstream");
+    }
+    args.extend(["--", remote.as_str(), branch.as_str()]);
+
+    let output = run_git_remote(&project_dir, &args)?;
+    if !output.status.success() {
+        return Err(remote_error("push", &remote, &String::from_utf8_lossy(&output.stderr)));
+    }
+    // git reports push progress and the ref summary on stderr
+    Ok(String::from_utf8_lossy(&output.stderr).trim().to_string())
+}
+
+/// Pull from a remote; without remote/branch the current branch's upstream is used
+#[tauri::command]
+fn git_pull(project_dir: String, remote: Option<String>, branch: Option<String>) -> Result<String, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    // 以 '-' 开头的值会被 git 当作选项（如 --upload-pack=<cmd>）
+    if let Some(remote) = remote.as_deref().filter(|r| r.trim().is_empty() || r.starts_with('-')) {
+        return Err(format!("Invalid remote name: {}", remote));
+    }
+    if let Some(branch) = branch.as_deref().filter(|b| b.trim().is_empty() || b.starts_with('-')) {
+        return Err(format!("Invalid branch name: {}", branch));
+    }
+
+    // --no-edit: a merge commit must never wait for an editor
+    let mut args = vec!["pull", "--no-edit", "--"];
+    if let Some(remote) = &remote {
+        args.push(remote);
+        if let Some(branch) = &branch {
+            args.push(branch);
+        }
+    }
+
+    let output = run_git_remote(&project_dir, &args)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("There is no tracking information") {
+            return Err("The current branch has no upstream; pass a remote and branch to pull from".to_string());
+        }
+        return Err(remote_error("pull", remote.as_deref().unwrap_or("upstream"), &stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Clone `url` into `dest`, passing each progress line to `progress`. Returns false when
+/// cancelled; a cancelled or failed clone leaves nothing behind at `dest`.
+async fn clone_repo(
+    url: &str,
+    dest: &std::path::Path,
+    abort: &AbortHandle,
+    mut progress: impl FnMut(&str),
+) -> Result<bool, String> {
+    use tokio::io::AsyncReadExt;
+
+    // git clone refuses a non-empty destination, so an existing one is always empty
+    let created = !dest.exists();
+    let cleanup = || {
+        if created {
+            let _ = fs::remove_dir_all(dest);
+        } else if let Ok(entries) = fs::read_dir(dest) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let _ = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+            }
+        }
+    };
+
+    let dest_arg = dest.to_string_lossy();
+    let mut child = Command::from(git_remote_command(".", &["clone", "--progress", "--", url, &dest_arg]))
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to run git clone: {}", e))?;
+    let mut stderr = child.stderr.take().ok_or("Failed to get stderr")?;
+
+    // Progress lines are rewritten in place with \r, so split on both line endings
+    let mut output = String::new();
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let read = tokio::select! {
+            biased;
+            _ = abort.wait() => {
+                let _ = child.kill().await;
+                cleanup();
+                return Ok(false);
+            }
+            read = stderr.read(&mut buf) => read.map_err(|e| format!("Failed to read git clone output: {}", e))?,
+        };
+        if read == 0 {
+            break;
+        }
+        pending.extend_from_slice(&buf[..read]);
+        while let Some(pos) = pending.iter().position(|b| *b == b'\r' || *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line[..pos]);
+            if !line.trim().is_empty() {
+                progress(line.trim_end());
+                output.push_str(&line);
+                output.push('\n');
+            }
+        }
+    }
+    output.push_str(&String::from_utf8_lossy(&pending));
+
+    let status = child.wait().await.map_err(|e| format!("Failed to run git clone: {}", e))?;
+    if !status.success() {
+        cleanup();
+        return Err(remote_error("clone", url, &output));
+    }
+    Ok(true)
+}
+
+/// Clone a repository, emitting `clone-progress` lines and `clone-complete` when done.
+/// `cancel_clone` kills git, removes the partial checkout and emits `clone-cancelled`.
+#[tauri::command]
+async fn git_clone(
+    app: tauri::AppHandle,
+    abort_map: tauri::State<'_, TaskAbortMap>,
+    url: String,
+    dest_dir: String,
+    clone_id: String,
+) -> Result<String, String> {
+    let dest = PathBuf::from(&dest_dir);
+    if dest.is_file() || fs::read_dir(&dest).map(|mut entries| entries.next().is_some()).unwrap_or(false) {
+        return Err(format!("Destination already exists and is not empty: {}", dest_dir));
+    }
+
+    #[derive(serde::Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct CloneProgress {
+        clone_id: String,
+        line: String,
+    }
+
+    #[derive(serde::Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct CloneEvent {
+        clone_id: String,
+        path: String,
+    }
+
+    let abort = register_abort(&abort_map, &clone_id)?;
+    let result = clone_repo(&url, &dest, &abort, |line| {
+        let _ = app.emit("clone-progress", CloneProgress {
+            clone_id: clone_id.clone(),
+            line: line.to_string(),
+        });
+    })
+    .await;
+
+    if let Ok(mut map) = abort_map.lock() {
+        map.remove(&clone_id);
+    }
+
+    let event = CloneEvent { clone_id: clone_id.clone(), path: dest_dir.clone() };
+    if result? {
+        println!("[git_clone] {} -> {}", url, dest_dir);
+        let _ = app.emit("clone-complete", event);
+        Ok(dest_dir)
+    } else {
+        println!("[git_clone] Cancelled {}", clone_id);
+        let _ = app.emit("clone-cancelled", event);
+        Err("Clone cancelled".to_string())
+    }
+}
+
+/// Cancel a running `git_clone`
+#[tauri::command]
+fn cancel_clone(abort_map: tauri::State<'_, TaskAbortMap>, clone_id: String) -> Result<bool, String> {
+    abort_task(&abort_map, &clone_id)
+}
+
+/// Fail unless every path is known to git (in the index or in HEAD, so staged deletions and
+/// the old side of staged renames count); lists the offending paths. Paths are literal, never
+/// globs, here and in the commands that use this check.
+fn ensure_tracked(project_dir: &str, files: &[String]) -> Result<(), String> {
+    if files.is_empty() {
+        return Err("No files selected".to_string());
+    }
+    let succeeds = |args: &[&str]| run_git(project_dir, args).map(|out| out.status.success()).unwrap_or(false);
+    let untracked: Vec<&str> = files
+        .iter()
+        .filter(|file| {
+            !succeeds(&["--literal-pathspecs", "ls-files", "--error-unmatch", "--", file])
+                && !succeeds(&["cat-file", "-e", &format!("HEAD:./{}", file)])
+        })
+        .map(|file| file.as_str())
+        .collect();
+    if untracked.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Not tracked by git: {}", untracked.join(", ")))
+    }
+}
+
+/// Remove files from the index, keeping their working-tree changes
+#[tauri::command]
+fn git_unstage(project_dir: String, files: Vec<String>) -> Result<(), String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+    ensure_tracked(&project_dir, &files)?;
+
+    // Before the first commit there is no HEAD to restore the index from
+    let has_head = run_git(&project_dir, &["rev-parse", "--verify", "--quiet", "HEAD"])?.status.success();
+    let mut args = if has_head {
+        vec!["--literal-pathspecs", "restore", "--staged", "--"]
+    } else {
+        vec!["--literal-pathspecs", "rm", "--cached", "--quiet", "--"]
+    };
+    args.extend(files.iter().map(|f| f.as_str()));
+
+    let output = run_git(&project_dir, &args)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to unstage: {}", stderr.trim()));
+    }
+    Ok(())
+}
+
+/// Revert working-tree changes to the staged (or committed) version. Irreversible, so the
+/// caller must pass `confirm: true`.
+#[tauri::command]
+fn git_discard(project_dir: String, files: Vec<String>, confirm: bool) -> Result<(), String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+    if !confirm {
+        return Err("Discarding changes cannot be undone; pass confirm to proceed".to_string());
+    }
+    ensure_tracked(&project_dir, &files)?;
+
+    let mut args = vec!["--literal-pathspecs", "restore", "--worktree", "--"];
+    args.extend(files.iter().map(|f| f.as_str()));
+    let output = run_git(&project_dir, &args)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to discard changes: {}", stderr.trim()));
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct GitBranch {
+    name: String,
+    remote: bool,
+    current: bool,
+}
+
+/// Parse `git for-each-ref --format=%(refname)%00%(HEAD)%00%(refname:short)` output
+fn parse_branch_refs(text: &str) -> Vec<GitBranch> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\0');
+            let (full, head, short) = (fields.next()?, fields.next()?, fields.next()?);
+            // refs/remotes/<remote>/HEAD only points at the remote's default branch
+            if full.starts_with("refs/remotes/") && full.ends_with("/HEAD") {
+                return None;
+            }
+            Some(GitBranch {
+                name: short.to_string(),
+                remote: full.starts_with("refs/remotes/"),
+                current: head == "*",
+            })
+        })
+        .collect()
+}
+
+/// Local and remote-tracking branches; none is marked current on a detached HEAD
+#[tauri::command]
+fn git_branches(project_dir: String) -> Result<Vec<GitBranch>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let output = run_git(
+        &project_dir,
+        &["for-each-ref", "--format=%(refname)%00%(HEAD)%00%(refname:short)", "refs/heads", "refs/remotes"],
+    )?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to list branches: {}", stderr));
+    }
+    Ok(parse_branch_refs(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Switch to a branch, or create it from HEAD when `create` is set. A remote-tracking branch
+/// such as `origin/main` switches to the local `main`, creating it to track the remote branch
+/// when it doesn't exist yet. Works from a detached HEAD too.
+#[tauri::command]
+fn git_checkout(project_dir: String, branch: String, create: bool) -> Result<String, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+    if branch.trim().is_empty() || branch.starts_with('-') {
+        return Err(format!("Invalid branch name: {}", branch));
+    }
+
+    let is_ref = |name: String| {
+        run_git(&project_dir, &["rev-parse", "--verify", "--quiet", &name]).map(|output| output.status.success())
+    };
+    let remote_only = !create && !is_ref(format!("refs/heads/{}", branch))? && is_ref(format!("refs/remotes/{}", branch))?;
+
+    let args: Vec<&str> = if create {
+        vec!["switch", "-c", &branch]
+    } else if remote_only {
+        match branch.split_once('/') {
+            Some((_, local)) if is_ref(format!("refs/heads/{}", local))? => vec!["switch", local],
+            _ => vec!["switch", "--track", &branch],
+        }
+    } else {
+        vec!["switch", &branch]
+    };
+    // Messages are forced to English: a conflict is recognised by its stderr text
+    let output = std::process::Command::new("git")
+        .args(&args)
+        .current_dir(&project_dir)
+        .env("LC_ALL", "C")
+        .output()
+        .map_err(|e| format!("Failed to run git switch: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("would be overwritten") {
+            return Err(format!("Local changes conflict with '{}'; commit or stash them first: {}", branch, stderr.trim()));
+        }
+        return Err(format!("Failed to switch to {}: {}", branch, stderr.trim()));
+    }
+    // git prints "Switched to ..." on stderr
+    Ok(String::from_utf8_lossy(&output.stderr).trim().to_string())
+}
+
+/// Git commit with message (stages selected files or all if empty). `amend` rewrites the last
+/// commit (an empty file list then only changes the message); `author` is passed as `--author`.
+#[tauri::command]
+fn git_commit(
+    project_dir: String,
+    message: String,
+    files: Option<Vec<String>>,
+    amend: Option<bool>,
+    author: Option<String>,
+) -> Result<String, String> {
+    use std::process::Command;
+
+    if message.trim().is_empty() {
+        return Err("Commit message cannot be empty".to_string());
+    }
+
+    // Check if it's a git repo
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let amend = amend.unwrap_or(false);
+
+    // Stage files
+    let add_output = if let Some(ref file_list) = files {
+        if file_list.is_empty() && !amend {
+            return Err("No files selected".to_string());
+        }
+        // Stage specific files
+        let mut args = vec!["add", "--"];
+        args.extend(file_list.iter().map(|s| s.as_str()));
+        Command::new("git")
+            .args(&args)
+            .current_dir(&project_dir)
+            .output()
+            .map_err(|e| format!("Failed to stage changes: {}", e))?
+    } else {
+        // Stage all changes
+        Command::new("git")
+            .args(["add", "-A"])
+            .current_dir(&project_dir)
+            .output()
+            .map_err(|e| format!("Failed to stage changes: {}", e))?
+    };
+
+    if !add_output.status.success() {
+        let stderr = String::from_utf8_lossy(&add_output.stderr);
+        return Err(format!("Failed to stage changes: {}", stderr));
+    }
+
+    // Commit
+    let mut commit_args = vec!["commit", "-m", &message];
+    if amend {
+        commit_args.push("--amend");
+    }
+    let author_arg = author.filter(|a| !a.trim().is_empty()).map(|a| format!("--author={}", a));
+    if let Some(author_arg) = &author_arg {
+        commit_args.push(author_arg);
+    }
+    let commit_output = Command::new("git")
+        .args(&commit_args)
+        .current_dir(&project_dir)
+        .output()
+        .map_err(|e| format!("Failed to commit: {}", e))?;
+
+    if !commit_output.status.success() {
+        let stderr = String::from_utf8_lossy(&commit_output.stderr);
+        let stdout = String::from_utf8_lossy(&commit_output.stdout);
+        if stdout.contains("nothing to commit") || stderr.contains("nothing to commit") {
+            return Err("Nothing to commit".to_string());
+        }
+        return Err(format!("Failed to commit: {}", stderr));
+    }
+
+    let stdout = String::from_utf8_lossy(&commit_output.stdout);
+    Ok(stdout.to_string())
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+struct DiffLine {
+    kind: String,        // "context", "added" or "removed"
+    content: String,
+    old_line: Option<u32>,
+    new_line: Option<u32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+struct DiffHunk {
+    header: String,
+    old_start: u32,
+    old_lines: u32,
+    new_start: u32,
+    new_lines: u32,
+    lines: Vec<DiffLine>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+struct FileDiff {
+    path: String,
+    old_path: Option<String>,
+    status: String,      // "modified", "added", "deleted" or "renamed"
+    binary: bool,
+    hunks: Vec<DiffHunk>,
+}
+
+/// Parse a `@@ -a,b +c,d @@` hunk header into (old_start, old_lines, new_start, new_lines)
+fn parse_hunk_header(line: &str) -> Option<(u32, u32, u32, u32)> {
+    let ranges = line.strip_prefix("@@ ")?;
+    let end = ranges.find(" @@")?;
+    let mut parts = ranges[..end].split(' ');
+
+    let parse_range = |range: &str| -> Option<(u32, u32)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+
+    let (old_start, old_lines) = parse_range(parts.next()?.strip_prefix('-')?)?;
+    let (new_start, new_lines) = parse_range(parts.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_lines, new_start, new_lines))
+}
+
+/// Strip the a/ or b/ prefix from a ---/+++ path, returning None for /dev/null
+fn diff_side_path(raw: &str) -> Option<String> {
+    // git appends a tab when the name contains spaces
+    let raw = raw.trim_end_matches('\t');
+    if raw == "/dev/null" {
+        return None;
+    }
+    let path = raw.strip_prefix("a/").or_else(|| raw.strip_prefix("b/")).unwrap_or(raw);
+    Some(path.to_string())
+}
+
+/// Parse unified diff output (as produced by `git diff` / `git show -p`) into per-file diffs
+fn parse_unified_diff(text: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    let mut old_line = 0u32;
+    let mut new_line = 0u32;
+
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            // Fallback path from the header; refined by ---/+++ and rename lines below
+            let path = rest
+                .rsplit_once(" b/")
+                .map(|(_, p)| p.to_string())
+                .unwrap_or_else(|| rest.to_string());
+            files.push(FileDiff {
+                path,
+                old_path: None,
+                status: "modified".to_string(),
+                binary: false,
+                hunks: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some(file) = files.last_mut() else { continue };
+
+        if let Some(hunk) = file.hunks.last_mut() {
+            let (kind, content) = match line.chars().next() {
+                Some(' ') => ("context", &line[1..]),
+                Some('+') => ("added", &line[1..]),
+                Some('-') => ("removed", &line[1..]),
+                Some('\\') => continue, // "\ No newline at end of file"
+                _ => ("", ""),
+            };
+
+            if !kind.is_empty() {
+                let (old, new) = match kind {
+                    "context" => (Some(old_line), Some(new_line)),
+                    "added" => (None, Some(new_line)),
+                    _ => (Some(old_line), None),
+                };
+                if old.is_some() {
+                    old_line += 1;
+                }
+                if new.is_some() {
+                    new_line += 1;
+                }
+                hunk.lines.push(DiffLine {
+                    kind: kind.to_string(),
+                    content: content.to_string(),
+                    old_line: old,
+                    new_line: new,
+                });
+                continue;
+            }
+        }
+
+        if line.starts_with("@@ ") {
+            if let Some((old_start, old_lines, new_start, new_lines)) = parse_hunk_header(line) {
+                old_line = old_start;
+                new_line = new_start;
+                file.hunks.push(DiffHunk {
+                    header: line.to_string(),
+                    old_start,
+                    old_lines,
+                    new_start,
+                    new_lines,
+                    lines: Vec::new(),
+                });
+            }
+        } else if line.starts_with("new file mode") {
+            file.status = "added".to_string();
+        } else if line.starts_with("deleted file mode") {
+            file.status = "deleted".to_string();
+        } else if let Some(from) = line.strip_prefix("rename from ") {
+            file.status = "renamed".to_string();
+            file.old_path = Some(from.to_string());
+        } else if let Some(to) = line.strip_prefix("rename to ") {
+            file.path = to.to_string();
+        } else if let Some(old) = line.strip_prefix("--- ") {
+            if let Some(old) = diff_side_path(old) {
+                if file.status == "renamed" || old != file.path {
+                    file.old_path = Some(old);
+                }
+            }
+        } else if let Some(new) = line.strip_prefix("+++ ") {
+            if let Some(new) = diff_side_path(new) {
+                file.path = new;
+            }
+        } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+            file.binary = true;
+        }
+    }
+
+    files
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HunkPatch {
+    hunk: DiffHunk,
+    patch: String,       // file header + this hunk, ready for `git apply --cached`
+}
+
+/// Split single-file diff text into its header and the raw text of each hunk
+fn split_diff_hunks(text: &str) -> (String, Vec<String>) {
+    let mut header = String::new();
+    let mut hunks: Vec<String> = Vec::new();
+
+    for line in text.split_inclusive('\n') {
+        if line.starts_with("@@ ") {
+            hunks.push(String::new());
+        }
+        match hunks.last_mut() {
+            Some(hunk) => hunk.push_str(line),
+            None => header.push_str(line),
+        }
+    }
+
+    (header, hunks)
+}
+
+/// Whether the working-tree `line` falls on a changed (non-context) line of the hunk.
+/// Removed lines count at the position they were removed from, so deletions can be found too.
+fn hunk_contains_line(hunk: &DiffHunk, line: u32) -> bool {
+    let mut new_pos = hunk.new_start;
+    let mut span: Option<(u32, u32)> = None;
+
+    for diff_line in &hunk.lines {
+        let pos = match diff_line.kind.as_str() {
+            "added" => diff_line.new_line.unwrap_or(new_pos),
+            "removed" => new_pos.max(1),
+            _ => {
+                new_pos = diff_line.new_line.map(|n| n + 1).unwrap_or(new_pos);
+                continue;
+            }
+        };
+        if let Some(n) = diff_line.new_line {
+            new_pos = n + 1;
+        }
+        span = Some(match span {
+            Some((lo, hi)) => (lo.min(pos), hi.max(pos)),
+            None => (pos, pos),
+        });
+    }
+
+    span.is_some_and(|(lo, hi)| lo <= line && line <= hi)
+}
+
+/// Find the unstaged hunk covering a working-tree line, for "stage this change"
+#[tauri::command]
+fn git_hunk_at_line(project_dir: String, file: String, line: u32) -> Result<Option<HunkPatch>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let output = run_git(&project_dir, &["diff", "--no-color", "--no-ext-diff", "--", &file])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to get diff: {}", stderr));
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout).to_string();
+    let Some(file_diff) = parse_unified_diff(&text).into_iter().next() else {
+        return Ok(None);
+    };
+
+    let (header, raw_hunks) = split_diff_hunks(&text);
+    let found = file_diff
+        .hunks
+        .into_iter()
+        .zip(raw_hunks)
+        .find(|(hunk, _)| hunk_contains_line(hunk, line))
+        .map(|(hunk, raw)| HunkPatch {
+            hunk,
+            patch: format!("{}{}", header, raw),
+        });
+
+    Ok(found)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum LineChangeKind {
+    Added,
+    Modified,
+    Deleted,     // content was removed after this line (0 = top of file)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+struct LineChange {
+    line: u32,
+    kind: LineChangeKind,
+}
+
+/// Gutter markers from zero-context hunks: replaced lines are modified, extra new lines added
+fn line_changes_from_hunks(hunks: &[DiffHunk]) -> Vec<LineChange> {
+    let mut changes = Vec::new();
+
+    for hunk in hunks {
+        if hunk.new_lines == 0 {
+            changes.push(LineChange { line: hunk.new_start, kind: LineChangeKind::Deleted });
+            continue;
+        }
+
+        let modified = hunk.old_lines.min(hunk.new_lines);
+        for offset in 0..hunk.new_lines {
+            let kind = if offset < modified {
+                LineChangeKind::Modified
+            } else {
+                LineChangeKind::Added
+            };
+            changes.push(LineChange { line: hunk.new_start + offset, kind });
+        }
+    }
+
+    changes
+}
+
+/// Per-line change markers for the editor gutter (working tree vs HEAD, so staged edits
+/// keep their markers until they are committed)
+#[tauri::command]
+fn git_line_changes(project_dir: String, file: String) -> Result<Vec<LineChange>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    // 还没有提交时与空树比较
+    const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+    let has_head = run_git(&project_dir, &["rev-parse", "--verify", "--quiet", "HEAD"])?.status.success();
+    let base = if has_head { "HEAD" } else { EMPTY_TREE };
+
+    let output = run_git(&project_dir, &["diff", "--no-color", "--no-ext-diff", "-U0", base, "--", &file])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to get diff: {}", stderr));
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    if let Some(file_diff) = parse_unified_diff(&text).into_iter().next() {
+        return Ok(line_changes_from_hunks(&file_diff.hunks));
+    }
+
+    // No diff: an untracked file is entirely new
+    let untracked = run_git(&project_dir, &["ls-files", "--others", "--exclude-standard", "--", &file])?;
+    if String::from_utf8_lossy(&untracked.stdout).trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(PathBuf::from(&project_dir).join(&file)).unwrap_or_default();
+    Ok((1..=content.lines().count() as u32)
+        .map(|line| LineChange { line, kind: LineChangeKind::Added })
+        .collect())
+}
+
+/// Run git in the given project directory and return its raw output
+fn run_git(project_dir: &str, args: &[&str]) -> Result<std::process::Output, String> {
+    std::process::Command::new("git")
+        .args(args)
+        .current_dir(project_dir)
+        .output()
+        .map_err(|e| format!("Failed to run git {}: {}", args.first().unwrap_or(&""), e))
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Submodule {
+    path: String,
+    name: String,
+    sha: String,
+    branch: Option<String>,
+    initialized: bool,
+    modified: bool,
+}
+
+/// Read `.gitmodules` entries as (name, path, branch)
+fn read_gitmodules(project_dir: &str) -> Vec<(String, String, Option<String>)> {
+    if !PathBuf::from(project_dir).join(".gitmodules").exists() {
+        return Vec::new();
+    }
+
+    let output = match run_git(project_dir, &["config", "--file", ".gitmodules", "--get-regexp", r"^submodule\..*\.(path|branch)$"]) {
+        Ok(output) => output,
+        Err(_) => return Vec::new(),
+    };
+
+    // Lines look like: submodule.<name>.path <path>
+    let mut modules: Vec<(String, String, Option<String>)> = Vec::new();
+    let mut branches: HashMap<String, String> = HashMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Some((key, value)) = line.split_once(' ') else { continue };
+        let Some(key) = key.strip_prefix("submodule.") else { continue };
+        if let Some(name) = key.strip_suffix(".path") {
+            modules.push((name.to_string(), value.to_string(), None));
+        } else if let Some(name) = key.strip_suffix(".branch") {
+            branches.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    for module in modules.iter_mut() {
+        module.2 = branches.remove(&module.0);
+    }
+
+    modules
+}
+
+/// Parse a `git submodule status` line, `[ -+U]<sha> <path>[ (<describe>)]`, into
+/// (flag, sha, path). Paths may contain spaces, so only the trailing describe is split off.
+fn parse_submodule_status_line(line: &str) -> Option<(char, String, String)> {
+    let mut chars = line.chars();
+    let flag = chars.next()?;
+    let (sha, rest) = chars.as_str().split_once(' ')?;
+    let path = match rest.strip_suffix(')').and_then(|r| r.rsplit_once(" (")) {
+        Some((path, _describe)) => path,
+        None => rest,
+    };
+    if sha.is_empty() || path.is_empty() {
+        return None;
+    }
+    Some((flag, sha.to_string(), path.to_string()))
+}
+
+/// Get submodule status (parses `git submodule status` and `.gitmodules`)
+#[tauri::command]
+fn git_submodule_status(project_dir: String) -> Result<Vec<Submodule>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let modules = read_gitmodules(&project_dir);
+    if modules.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let output = run_git(&project_dir, &["submodule", "status"])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to get submodule status: {}", stderr));
+    }
+
+    let mut status_by_path: HashMap<String, (char, String)> = HashMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some((flag, sha, path)) = parse_submodule_status_line(line) {
+            status_by_path.insert(path, (flag, sha));
+        }
+    }
+
+    let mut submodules: Vec<Submodule> = modules
+        .into_iter()
+        .map(|(name, path, branch)| {
+            let (flag, sha) = status_by_path
+                .remove(&path)
+                .unwrap_or(('-', String::new()));
+            Submodule {
+                path,
+                name,
+                sha,
+                branch,
+                initialized: flag != '-',
+                modified: flag == '+' || flag == 'U',
+            }
+        })
+        .collect();
+
+    submodules.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(submodules)
+}
+
+/// Run `git submodule update`, optionally with --init and --recursive
+#[tauri::command]
+fn git_submodule_update(project_dir: String, init: bool, recursive: bool) -> Result<String, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let mut args = vec!["submodule", "update"];
+    if init {
+        args.push("--init");
+    }
+    if recursive {
+        args.push("--recursive");
+    }
+
+    let output = run_git(&project_dir, &args)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to update submodules: {}", stderr));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.to_string())
+}
+
+/// Number of entries in the stash
+fn git_stash_count(project_dir: &str) -> Result<u32, String> {
+    let output = run_git(project_dir, &["stash", "list", "--format=%gd"])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to list stashes: {}", stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().count() as u32)
+}
+
+/// Show the changes in a stash entry without applying it
+#[tauri::command]
+fn git_stash_show(project_dir: String, index: u32) -> Result<Vec<FileDiff>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let count = git_stash_count(&project_dir)?;
+    if index >= count {
+        return Err(format!("Stash entry stash@{{{}}} does not exist ({} entries)", index, count));
+    }
+
+    let stash_ref = format!("stash@{{{}}}", index);
+    let output = run_git(&project_dir, &["stash", "show", "-p", "--no-color", &stash_ref])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to show stash: {}", stderr));
+    }
+
+    Ok(parse_unified_diff(&String::from_utf8_lossy(&output.stdout)))
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+struct StashEntry {
+    index: u32,
+    branch: String,
+    message: String,
+}
+
+/// Parse `git stash list --format=%gd%x00%gs` lines ("stash@{0}\0On main: msg")
+fn parse_stash_list(text: &str) -> Vec<StashEntry> {
+    text.lines()
+        .filter_map(|line| {
+            let (reference, subject) = line.split_once('\0')?;
+            let index = reference.strip_prefix("stash@{")?.strip_suffix('}')?.parse().ok()?;
+            // "On <branch>: <message>" for `stash push -m`, "WIP on <branch>: <sha> <subject>" otherwise
+            let rest = subject
+                .strip_prefix("On ")
+                .or_else(|| subject.strip_prefix("WIP on "))
+                .unwrap_or(subject);
+            let (branch, message) = rest.split_once(": ").unwrap_or(("", rest));
+            Some(StashEntry { index, branch: branch.to_string(), message: message.to_string() })
+        })
+        .collect()
+}
+
+/// List stash entries, newest first
+#[tauri::command]
+fn git_stash_list(project_dir: String) -> Result<Vec<StashEntry>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let output = run_git(&project_dir, &["stash", "list", "--format=%gd%x00%gs"])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to list stashes: {}", stderr));
+    }
+    Ok(parse_stash_list(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Stash local changes (`git stash push`), optionally with untracked files and a message.
+/// A clean tree is reported as the error "No local changes to save".
+#[tauri::command]
+fn git_stash(project_dir: String, message: Option<String>, include_untracked: bool) -> Result<String, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let mut args = vec!["stash", "push"];
+    if include_untracked {
+        args.push("-u");
+    }
+    if let Some(message) = message.as_deref().filter(|m| !m.trim().is_empty()) {
+        args.extend(["-m", message]);
+    }
+
+    // git exits 0 when there was nothing to stash; its message is localized, so check
+    // whether refs/stash moved instead
+    let stash_head = || -> Result<String, String> {
+        let output = run_git(&project_dir, &["rev-parse", "--verify", "--quiet", "refs/stash"])?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let before = stash_head()?;
+
+    let output = run_git(&project_dir, &args)?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to stash: {}", stderr));
+    }
+    if stash_head()? == before {
+        return Err("No local changes to save".to_string());
+    }
+    Ok(stdout)
+}
+
+/// Apply and drop a stash entry (default: the newest). On conflicts the entry is kept.
+#[tauri::command]
+fn git_stash_pop(project_dir: String, index: Option<u32>) -> Result<String, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let index = index.unwrap_or(0);
+    let count = git_stash_count(&project_dir)?;
+    if index >= count {
+        return Err(format!("Stash entry stash@{{{}}} does not exist ({} entries)", index, count));
+    }
+
+    let stash_ref = format!("stash@{{{}}}", index);
+    let output = run_git(&project_dir, &["stash", "pop", &stash_ref])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        return Err(format!("Failed to pop {}: {}{}", stash_ref, stdout, stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Resolve a revision to a full commit sha, failing if it doesn't name a commit
+fn resolve_commit(project_dir: &str, rev: &str) -> Result<String, String> {
+    let spec = format!("{}^{{commit}}", rev);
+    let output = run_git(project_dir, &["rev-parse", "--verify", "--quiet", &spec])?;
+    if !output.status.success() {
+        return Err(format!("Unknown revision: {}", rev));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Best common ancestor of two revisions; `None` for unrelated histories
+fn merge_base(project_dir: &str, a: &str, b: &str) -> Result<Option<String>, String> {
+    let a = resolve_commit(project_dir, a)?;
+    let b = resolve_commit(project_dir, b)?;
+
+    let output = run_git(project_dir, &["merge-base", &a, &b])?;
+    match output.status.code() {
+        Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string())),
+        // Exit status 1 with no output means there is no common ancestor
+        Some(1) if output.stderr.is_empty() => Ok(None),
+        _ => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(format!("Failed to find merge base: {}", stderr))
+        }
+    }
+}
+
+/// Commit where `a` and `b` diverged, or `None` when they share no history
+#[tauri::command]
+fn git_merge_base(project_dir: String, a: String, b: String) -> Result<Option<String>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    merge_base(&project_dir, &a, &b)
+}
+
+/// Group `Key: value` lines from `git interpret-trailers --parse` by key
+fn parse_trailers(output: &str) -> HashMap<String, Vec<String>> {
+    let mut trailers: HashMap<String, Vec<String>> = HashMap::new();
+    for line in output.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            trailers
+                .entry(key.trim().to_string())
+                .or_default()
+                .push(value.trim().to_string());
+        }
+    }
+    trailers
+}
+
+/// Trailers (`Co-authored-by`, `Change-Id`, ...) from a commit's message
+#[tauri::command]
+fn git_commit_trailers(project_dir: String, hash: String) -> Result<HashMap<String, Vec<String>>, String> {
+    use std::io::Write;
+
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let commit = resolve_commit(&project_dir, &hash)?;
+    let output = run_git(&project_dir, &["log", "-1", "--format=%B", &commit])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to read commit message: {}", stderr));
+    }
+
+    let mut child = std::process::Command::new("git")
+        .args(["interpret-trailers", "--parse"])
+        .current_dir(&project_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run git interpret-trailers: {}", e))?;
+    // Messages are small; write everything, then close stdin so git sees EOF
+    child
+        .stdin
+        .take()
+        .ok_or("Failed to open git stdin")?
+        .write_all(&output.stdout)
+        .map_err(|e| format!("Failed to pass commit message to git: {}", e))?;
+
+    let parsed = child.wait_with_output().map_err(|e| format!("Failed to run git interpret-trailers: {}", e))?;
+    if !parsed.status.success() {
+        let stderr = String::from_utf8_lossy(&parsed.stderr);
+        return Err(format!("Failed to parse trailers: {}", stderr));
+    }
+
+    Ok(parse_trailers(&String::from_utf8_lossy(&parsed.stdout)))
+}
+
+/// The git note attached to a commit, if any
+#[tauri::command]
+fn git_notes(project_dir: String, hash: String) -> Result<Option<String>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let commit = resolve_commit(&project_dir, &hash)?;
+
+    // `git notes list` prints "<note> <commit>" pairs and succeeds even when there are none;
+    // checking it avoids matching git's (localized) "no note found" error
+    let listed = run_git(&project_dir, &["notes", "list"])?;
+    if !listed.status.success() {
+        return Err(format!("Failed to read notes: {}", String::from_utf8_lossy(&listed.stderr).trim()));
+    }
+    let has_note = String::from_utf8_lossy(&listed.stdout)
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(commit.as_str()));
+    if !has_note {
+        return Ok(None);
+    }
+
+    let output = run_git(&project_dir, &["notes", "show", &commit])?;
+    if !output.status.success() {
+        return Err(format!("Failed to read notes: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).trim_end().to_string()))
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct BlameHunk {
+    sha: String,
+    start_line: u32,     // 1-based line in the working-tree file
+    line_count: u32,
+    author: String,
+    author_mail: String,
+    author_time: i64,
+    summary: String,
+}
+
+/// Line-by-line parser for `git blame --incremental` output. Commit details are only printed
+/// the first time a commit appears, so they're remembered per sha.
+#[derive(Default)]
+struct BlameParser {
+    commits: HashMap<String, (String, String, i64, String)>,
+    current: Option<(String, u32, u32)>,
+}
+
+impl BlameParser {
+    /// Feed one line; returns the hunk once its closing `filename` line has been read
+    fn push_line(&mut self, line: &str) -> Option<BlameHunk> {
+        let Some((sha, _, _)) = self.current.as_ref() else {
+            let mut fields = line.split(' ');
+            let (Some(sha), Some(_orig), Some(start), Some(count)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return None;
+            };
+            if let (Ok(start), Ok(count)) = (start.parse(), count.parse()) {
+                self.current = Some((sha.to_string(), start, count));
+            }
+            return None;
+        };
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        let commit = self.commits.entry(sha.clone()).or_default();
+        match key {
+            "author" => commit.0 = value.to_string(),
+            "author-mail" => commit.1 = value.trim_matches(|c| c == '<' || c == '>').to_string(),
+            "author-time" => commit.2 = value.parse().unwrap_or(0),
+            "summary" => commit.3 = value.to_string(),
+            // Each hunk ends with its filename
+            "filename" => {
+                let (author, author_mail, author_time, summary) = commit.clone();
+                let (sha, start_line, line_count) = self.current.take()?;
+                return Some(BlameHunk { sha, start_line, line_count, author, author_mail, author_time, summary });
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Run `git blame --incremental` on a file's working-tree content, passing each hunk to
+/// `on_hunk` as git finds it (out of order). Returns all hunks sorted by line.
+async fn stream_blame(
+    project_dir: &str,
+    file: &str,
+    mut on_hunk: impl FnMut(&BlameHunk),
+) -> Result<Vec<BlameHunk>, String> {
+    let mut child = Command::new("git")
+        .args(["blame", "--incremental", "--", file])
+        .current_dir(project_dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to run git blame: {}", e))?;
+    let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
+
+    // Drain stderr alongside stdout so a chatty git can't block on a full pipe
+    let mut stderr = child.stderr.take().ok_or("Failed to get stderr")?;
+    let stderr_task = tokio::spawn(async move {
+        use tokio::io::AsyncReadExt;
+        let mut text = String::new();
+        let _ = stderr.read_to_string(&mut text).await;
+        text
+    });
+
+    let mut parser = BlameParser::default();
+    let mut hunks = Vec::new();
+    let mut reader = BufReader::new(stdout);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(|e| format!("Failed to read git blame output: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        if let Some(hunk) = parser.push_line(String::from_utf8_lossy(&line).trim_end_matches('\n')) {
+            on_hunk(&hunk);
+            hunks.push(hunk);
+        }
+    }
+
+    let status = child.wait().await.map_err(|e| format!("Failed to run git blame: {}", e))?;
+    let stderr = stderr_task.await.unwrap_or_default();
+    if !status.success() {
+        return Err(format!("Failed to blame {}: {}", file, stderr.trim()));
+    }
+    hunks.sort_by_key(|hunk| hunk.start_line);
+    Ok(hunks)
+}
+
+/// Blame for a file's working-tree content, cached until HEAD or the file's content changes.
+/// While git computes it, each hunk is emitted as a `git-blame-hunk` event so the gutter can
+/// fill in progressively; a cached result is returned at once without events.
+#[tauri::command]
+async fn git_blame_incremental(
+    app: tauri::AppHandle,
+    blame_cache: tauri::State<'_, BlameCache>,
+    project_dir: String,
+    file: String,
+) -> Result<Vec<BlameHunk>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let head = resolve_commit(&project_dir, "HEAD")?;
+    let output = run_git(&project_dir, &["hash-object", "--", &file])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to hash {}: {}", file, stderr));
+    }
+    let blob = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    let key = (project_dir.clone(), file.clone());
+    if let Some(entry) = blame_cache.lock().map_err(|e| format!("Lock error: {}", e))?.get_mut(&key) {
+        if entry.head == head && entry.blob == blob {
+            entry.last_used = std::time::Instant::now();
+            return Ok(entry.hunks.clone());
+        }
+    }
+
+    #[derive(serde::Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct BlameHunkEvent<'a> {
+        project_dir: &'a str,
+        file: &'a str,
+        hunk: &'a BlameHunk,
+    }
+
+    let hunks = stream_blame(&project_dir, &file, |hunk| {
+        let _ = app.emit("git-blame-hunk", BlameHunkEvent { project_dir: &project_dir, file: &file, hunk });
+    })
+    .await?;
+
+    let entry = BlameCacheEntry { head, blob, hunks: hunks.clone(), last_used: std::time::Instant::now() };
+    insert_blame_cache(&mut *blame_cache.lock().map_err(|e| format!("Lock error: {}", e))?, key, entry);
+    Ok(hunks)
+}
+
+/// Add a blame result, evicting the least recently used entries beyond BLAME_CACHE_MAX_ENTRIES
+fn insert_blame_cache(
+    cache: &mut HashMap<(String, String), BlameCacheEntry>,
+    key: (String, String),
+    entry: BlameCacheEntry,
+) {
+    cache.insert(key, entry);
+    while cache.len() > BLAME_CACHE_MAX_ENTRIES {
+        let Some(oldest) = cache.iter().min_by_key(|(_, e)| e.last_used).map(|(k, _)| k.clone()) else {
+            break;
+        };
+        cache.remove(&oldest);
+    }
+}
+
+/// Run `git diff` against `base` and pass each file's diff to `on_file` as soon as git has
+/// written it. Returns false when cancelled.
+async fn stream_diff_files(
+    project_dir: &str,
+    base: &str,
+    abort: &AbortHandle,
+    mut on_file: impl FnMut(FileDiff),
+) -> Result<bool, String> {
+    // A leading dash would be read by git as an option
+    if base.starts_with('-') {
+        return Err(format!("Unknown revision: {}", base));
+    }
+    let base = resolve_commit(project_dir, base)?;
+
+    let mut child = Command::new("git")
+        .args(["diff", "--no-color", "--end-of-options", &base])
+        .current_dir(project_dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to run git diff: {}", e))?;
+    let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
+
+    // Drain stderr alongside stdout so a chatty git can't block on a full pipe
+    let mut stderr = child.stderr.take().ok_or("Failed to get stderr")?;
+    let stderr_task = tokio::spawn(async move {
+        use tokio::io::AsyncReadExt;
+        let mut text = String::new();
+        let _ = stderr.read_to_string(&mut text).await;
+        text
+    });
+
+    // Buffer one file's worth of output at a time and flush it at the next `diff --git` header
+    let mut reader = BufReader::new(stdout);
+    let mut current = String::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = tokio::select! {
+            biased;
+            _ = abort.wait() => {
+                let _ = child.kill().await;
+                return Ok(false);
+            }
+            read = reader.read_until(b'\n', &mut line) => read,
+        };
+        match read.map_err(|e| format!("Failed to read git diff output: {}", e))? {
+            0 => break,
+            _ => {
+                let text = String::from_utf8_lossy(&line);
+                if text.starts_with("diff --git ") && !current.is_empty() {
+                    parse_unified_diff(&current).into_iter().for_each(&mut on_file);
+                    current.clear();
+                }
+                current.push_str(&text);
+            }
+        }
+    }
+
+    let status = child.wait().await.map_err(|e| format!("Failed to run git diff: {}", e))?;
+    let stderr = stderr_task.await.unwrap_or_default();
+    if !status.success() {
+        return Err(format!("git diff failed: {}", stderr.trim()));
+    }
+    parse_unified_diff(&current).into_iter().for_each(&mut on_file);
+    Ok(true)
+}
+
+/// Stream `git diff` output file by file: emits `git-diff-file` per file and `git-diff-done` at the end
+#[tauri::command]
+async fn git_diff_stream(
+    app: tauri::AppHandle,
+    abort_map: tauri::State<'_, TaskAbortMap>,
+    project_dir: String,
+    base: Option<String>,
+    stream_id: String,
+) -> Result<(), String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    #[derive(serde::Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct DiffFileEvent {
+        stream_id: String,
+        diff: FileDiff,
+    }
+
+    #[derive(serde::Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    struct DiffDoneEvent {
+        stream_id: String,
+        files: usize,
+        cancelled: bool,
+    }
+
+    let base = base.unwrap_or_else(|| "HEAD".to_string());
+    let abort = register_abort(&abort_map, &stream_id)?;
+    let mut count = 0usize;
+    let result = stream_diff_files(&project_dir, &base, &abort, |diff| {
+        count += 1;
+        let _ = app.emit("git-diff-file", DiffFileEvent {
+            stream_id: stream_id.clone(),
+            diff,
+        });
+    })
+    .await;
+
+    if let Ok(mut map) = abort_map.lock() {
+        map.remove(&stream_id);
+    }
+
+    let cancelled = matches!(result, Ok(false));
+    println!("[git_diff_stream] {} files, cancelled: {}", count, cancelled);
+    let _ = app.emit("git-diff-done", DiffDoneEvent {
+        stream_id: stream_id.clone(),
+        files: count,
+        cancelled,
+    });
+
+    result.map(|_| ())
+}
+
+/// Cancel a running `git_diff_stream`
+#[tauri::command]
+fn cancel_git_diff_stream(abort_map: tauri::State<'_, TaskAbortMap>, stream_id: String) -> Result<bool, String> {
+    abort_task(&abort_map, &stream_id)
+}
+
+/// Default branch ref: origin/HEAD when known, otherwise local main/master
+fn git_default_branch(project_dir: &str) -> Option<String> {
+    if let Ok(output) = run_git(project_dir, &["symbolic-ref", "--short", "refs/remotes/origin/HEAD"]) {
+        if output.status.success() {
+            let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !name.is_empty() {
+                return Some(name);
+            }
+        }
+    }
+
+    ["main", "master"].iter().find_map(|branch| {
+        let refname = format!("refs/heads/{}", branch);
+        run_git(project_dir, &["show-ref", "--verify", "--quiet", &refname])
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|_| branch.to_string())
+    })
+}
+
+/// Write commits since `base` as .patch files (default base: merge-base with the default branch)
+#[tauri::command]
+fn git_format_patch(project_dir: String, base: Option<String>, dest_dir: String) -> Result<Vec<String>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    let base = match base {
+        // A leading dash would be read by git as an option
+        Some(base) if base.starts_with('-') => return Err(format!("Unknown revision: {}", base)),
+        Some(base) => resolve_commit(&project_dir, &base)?,
+        None => {
+            let default_branch = git_default_branch(&project_dir)
+                .ok_or_else(|| "Could not determine the default branch; pass a base".to_string())?;
+            merge_base(&project_dir, "HEAD", &default_branch)?
+                .ok_or_else(|| format!("HEAD shares no history with {}", default_branch))?
+        }
+    };
+
+    // Resolve a relative dest_dir against the project once, so the directory created here is
+    // the one git writes into
+    let dest = PathBuf::from(&project_dir).join(&dest_dir);
+    fs::create_dir_all(&dest)
+        .map_err(|e| format!("Failed to create destination directory: {}", e))?;
+    let dest = dest.to_string_lossy().to_string();
+
+    let range = format!("{}..HEAD", base);
+    let output = run_git(&project_dir, &["format-patch", &range, "-o", &dest])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to format patches: {}", stderr));
+    }
+
+    // git prints one generated file per line, under the absolute dest
+    let patches = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.trim().to_string())
+        .collect();
+
+    Ok(patches)
+}
+
+/// Apply an mbox/format-patch file with `git am`; aborts the session on failure
+#[tauri::command]
+fn git_apply_mbox(project_dir: String, patch_path: String) -> Result<String, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    if !PathBuf::from(&patch_path).is_file() {
+        return Err(format!("File does not exist: {}", patch_path));
+    }
+
+    let output = run_git(&project_dir, &["am", "--3way", &patch_path])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        // Leave the repository as it was rather than mid-am
+        let _ = run_git(&project_dir, &["am", "--abort"]);
+        return Err(format!("Failed to apply patch: {}{}", stdout, stderr));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+const GIT_HOOK_NAMES: &[&str] = &[
+    "applypatch-msg",
+    "pre-applypatch",
+    "post-applypatch",
+    "pre-commit",
+    "pre-merge-commit",
+    "prepare-commit-msg",
+    "commit-msg",
+    "post-commit",
+    "pre-rebase",
+    "post-checkout",
+    "post-merge",
+    "pre-push",
+    "pre-auto-gc",
+    "post-rewrite",
+];
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HookInfo {
+    name: String,
+    installed: bool,    // hook file exists (samples don't count)
+    executable: bool,   // git will actually run it
+    path: String,
+}
+
+/// List the standard git hooks and whether each one will run (respects core.hooksPath)
+#[tauri::command]
+fn git_hooks(project_dir: String) -> Result<Vec<HookInfo>, String> {
+    let git_dir = PathBuf::from(&project_dir).join(".git");
+    if !git_dir.exists() {
+        return Err("Not a git repository".to_string());
+    }
+
+    // --git-path resolves core.hooksPath and worktree layouts for us
+    let output = run_git(&project_dir, &["rev-parse", "--git-path", "hooks"])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to locate hooks directory: {}", stderr));
+    }
+    let hooks_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let hooks_dir = PathBuf::from(&project_dir).join(hooks_path);
+
+    let hooks = GIT_HOOK_NAMES
+        .iter()
+        .map(|name| {
+            let path = hooks_dir.join(name);
+            let metadata = fs::metadata(&path).ok().filter(|m| m.is_file());
+            let installed = metadata.is_some();
+
+            #[cfg(unix)]
+            let executable = {
+                use std::os::unix::fs::PermissionsExt;
+                metadata.map(|m| m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+            };
+            // Git for Windows runs any hook file through its bundled shell
+            #[cfg(not(unix))]
+            let executable = installed;
+
+            HookInfo {
+                name: name.to_string(),
+                installed,
+                executable,
+                path: path.to_string_lossy().to_string(),
+            }
+        })
+        .collect();
+
+    Ok(hooks)
+}
+
+/// Strip trailing whitespace from a .gitignore line, keeping an escaped trailing space
+fn trim_gitignore_line(line: &str) -> String {
+    let trimmed = line.trim_end();
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        format!("{} ", trimmed)
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Broader patterns that make `pattern` redundant when present
+fn gitignore_covering_patterns(pattern: &str) -> Vec<String> {
+    let mut covering = Vec::new();
+    if let Some(base) = pattern.strip_suffix("/**") {
+        covering.push(format!("{}/", base));
+    }
+    if let Some(base) = pattern.strip_suffix('/') {
+        covering.push(base.to_string());
+    }
+    // Unanchored single-segment patterns already match at every level
+    for prefix in ["**/", "/"] {
+        if let Some(rest) = pattern.strip_prefix(prefix) {
+            if !rest.trim_end_matches('/').contains('/') && !rest.is_empty() {
+                covering.push(rest.to_string());
+            }
+        }
+    }
+
+    let direct = covering.clone();
+    for broader in direct {
+        covering.extend(gitignore_covering_patterns(&broader));
+    }
+    covering
+}
+
+/// Grouping order within a block of patterns: directories, then globs, then plain names
+fn gitignore_pattern_group(pattern: &str) -> u8 {
+    if pattern.ends_with('/') {
+        0
+    } else if pattern.contains(['*', '?', '[']) {
+        1
+    } else {
+        2
+    }
+}
+
+/// Remove duplicate/redundant patterns and extra blank lines, keeping comments and sections.
+/// Negation patterns split the file into segments; patterns are only compared within a segment
+/// so the order-sensitive `!` rules keep their meaning.
+/// Each run of consecutive patterns is grouped (see `gitignore_pattern_group`), never across a
+/// comment, blank line or negation. CRLF files stay CRLF.
+fn tidy_gitignore_content(content: &str) -> String {
+    let newline = if content.contains("\r\n") { "\r\n" } else { "\n" };
+    let lines: Vec<String> = content.lines().map(trim_gitignore_line).collect();
+
+    let is_pattern = |line: &str| !line.is_empty() && !line.starts_with('#');
+    let mut keep = vec![true; lines.len()];
+
+    let mut segment_start = 0;
+    while segment_start < lines.len() {
+        let segment_end = (segment_start..lines.len())
+            .find(|&i| i > segment_start && lines[i].starts_with('!'))
+            .unwrap_or(lines.len());
+
+        let patterns: std::collections::HashSet<&str> = (segment_start..segment_end)
+            .map(|i| lines[i].as_str())
+            .filter(|line| is_pattern(line) && !line.starts_with('!'))
+            .collect();
+
+        let mut seen = std::collections::HashSet::new();
+        for i in segment_start..segment_end {
+            let line = lines[i].as_str();
+            if !is_pattern(line) || line.starts_with('!') {
+                continue;
+            }
+            let redundant = gitignore_covering_patterns(line)
+                .iter()
+                .any(|p| patterns.contains(p.as_str()));
+            if redundant || !seen.insert(line) {
+                keep[i] = false;
+            }
+        }
+
+        segment_start = segment_end;
+    }
+
+    // Collapse runs of blank lines and drop leading/trailing ones
+    let mut result: Vec<&str> = Vec::new();
+    for (line, keep) in lines.iter().zip(keep) {
+        if !keep || (line.is_empty() && result.last().is_none_or(|l| l.is_empty())) {
+            continue;
+        }
+        result.push(line);
+    }
+    while result.last().is_some_and(|l| l.is_empty()) {
+        result.pop();
+    }
+
+    // Without a negation in between, the order of patterns doesn't matter
+    for run in result.chunk_by_mut(|a, b| is_pattern(a) == is_pattern(b) && !b.starts_with('!')) {
+        if is_pattern(run[0]) {
+            let start = usize::from(run[0].starts_with('!'));
+            run[start..].sort_by_key(|line| gitignore_pattern_group(line));
+        }
+    }
+
+    if result.is_empty() {
+        String::new()
+    } else {
+        format!("{}{}", result.join(newline), newline)
+    }
+}
+
+/// Tidy the project's .gitignore; returns the new content and only writes it when `write` is set
+#[tauri::command]
+fn tidy_gitignore(project_dir: String, write: Option<bool>) -> Result<String, String> {
+    let path = PathBuf::from(&project_dir).join(".gitignore");
+    if !path.exists() {
+        return Err(format!("File does not exist: {}", path.display()));
+    }
+
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read .gitignore: {}", e))?;
+    let tidied = tidy_gitignore_content(&content);
+
+    if write.unwrap_or(false) && tidied != content {
+        write_file_atomic(&path, tidied.as_bytes())?;
+    }
+
+    Ok(tidied)
+}
+
+/// PATH as seen by the user's login shell, resolved once and cached.
+/// GUI apps usually don't inherit the shell-configured PATH, so `node`/`git` etc. go missing.
+/// Resolving can take seconds, so `run()` warms this on a background thread at startup.
+fn shell_path() -> &'static str {
+    static SHELL_PATH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    SHELL_PATH.get_or_init(|| {
+        let inherited = std::env::var("PATH").unwrap_or_default();
+        if cfg!(target_os = "windows") {
+            return inherited;
+        }
+        resolve_login_shell_path().unwrap_or(inherited)
+    })
+}
+
+/// Ask the login shell for its PATH (bounded, in case a profile script hangs)
+fn resolve_login_shell_path() -> Option<String> {
+    const MARKER: &str = "__VOLTCODE_PATH__";
+
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string());
+    let mut child = std::process::Command::new(&shell)
+        .args(["-l", "-c", &format!("printf '{}%s' \"$PATH\"", MARKER)])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if std::time::Instant::now() < deadline => {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                println!("[shell_path] Login shell did not report PATH in time, using inherited PATH");
+                return None;
+            }
+        }
+    }
+
+    let output = child.wait_with_output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Profile scripts may print banners; only trust what follows the marker
+    let path = stdout.rsplit_once(MARKER)?.1.trim().to_string();
+    if path.is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Get the PATH that spawned processes will see
+#[tauri::command]
+fn get_shell_path() -> String {
+    shell_path().to_string()
+}
+
+/// Associate a PID with a terminal ID
+#[tauri::command]
+fn associate_terminal(
+    terminal_map: tauri::State<TerminalMap>,
+    pid: u32,
+    terminal_id: String
+) -> Result<(), String> {
+    println!("[associate_terminal] Associating PID {} with terminal {}", pid, terminal_id);
+
+    let mut map = terminal_map.lock().map_err(|e| format!("Failed to lock terminal map: {}", e))?;
+    map.insert(pid, terminal_id);
+
+    println!("[associate_terminal] Current mappings: {:?}", *map);
+    Ok(())
+}
+
+/// Decodes PTY output as UTF-8 across reads, holding back a multibyte sequence split at a read boundary
+#[derive(Default)]
+struct Utf8Carry {
+    pending: Vec<u8>,
+}
+
+impl Utf8Carry {
+    fn decode(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+        let mut output = String::new();
+        let mut rest: &[u8] = &self.pending;
+
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(text) => {
+                    output.push_str(text);
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, tail) = rest.split_at(e.valid_up_to());
+                    output.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                    match e.error_len() {
+                        // Genuinely invalid bytes: replace them and keep going
+                        Some(len) => {
+                            output.push(char::REPLACEMENT_CHARACTER);
+                            rest = &tail[len..];
+                        }
+                        // Incomplete sequence at the end: wait for the next read
+                        None => {
+                            rest = tail;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.pending = rest.to_vec();
+        output
+    }
+}
+
+/// The user's shell as a login shell, so profile scripts set up the environment
+#[cfg(not(windows))]
+fn default_shell_command() -> CommandBuilder {
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string());
+    let mut cmd = CommandBuilder::new(shell);
+    cmd.arg("-l");
+    cmd
+}
+
+/// `%COMSPEC%` (normally cmd.exe); Windows shells have no login-shell flag
+#[cfg(windows)]
+fn default_shell_command() -> CommandBuilder {
+    let shell = std::env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".to_string());
+    CommandBuilder::new(shell)
+}
+
+/// Wait for a PTY child on its own thread, then call `on_exit` with its exit code (None if
+/// waiting failed). The call is held until `output_done` disconnects so the exit is reported
+/// after the final output, but only briefly: background jobs can keep the PTY open.
+fn spawn_pty_exit_waiter(
+    mut child: Box<dyn portable_pty::Child + Send + Sync>,
+    output_done: std::sync::mpsc::Receiver<()>,
+    on_exit: impl FnOnce(Option<u32>) + Send + 'static,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let exit_code = child.wait().ok().map(|status| status.exit_code());
+        let _ = output_done.recv_timeout(std::time::Duration::from_secs(1));
+        on_exit(exit_code);
+    })
+}
+
+/// Create a new interactive terminal with PTY
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn create_interactive_terminal(
+    app: tauri::AppHandle,
+    pty_writer_map: tauri::State<'_, PtyWriterMap>,
+    pty_master_map: tauri::State<'_, PtyMasterMap>,
+    pty_child_map: tauri::State<'_, PtyChildMap>,
+    pty_meta_map: tauri::State<'_, PtyMetaMap>,
+    terminal_id: String,
+    cwd: Option<String>,
+    env: Option<HashMap<String, String>>,
+    rows: Option<u16>,
+    cols: Option<u16>,
+) -> Result<(), String> {
+    println!("[create_interactive_terminal] Creating terminal: {}", terminal_id);
+    println!("[create_interactive_terminal] Working directory: {:?}", cwd);
+
+    // Create PTY system
+    let pty_system = native_pty_system();
+
+    // Create a new PTY with the frontend's initial size; terminal_resize keeps it in sync afterwards
+    let rows = rows.unwrap_or(24);
+    let cols = cols.unwrap_or(80);
+    let pair = pty_system
+        .openpty(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+        .map_err(|e| format!("Failed to create PTY: {}", e))?;
+
+    // Build shell command
+    let mut cmd = default_shell_command();
+    println!("[create_interactive_terminal] Using shell: {:?}", cmd.get_argv());
+    cmd.env("PATH", shell_path());
+
+    // Terminal capabilities for TUI apps (editors, pagers); callers can override via env
+    cmd.env("TERM", "xterm-256color");
+    cmd.env("COLORTERM", "truecolor");
+    cmd.env("TERM_PROGRAM", "voltcode");
+    for (key, value) in env.unwrap_or_default() {
+        cmd.env(key, value);
+    }
+
+    if let Some(dir) = &cwd {
+        cmd.cwd(dir);
+    }
+
+    // Spawn the shell in the PTY
+    let child = pair.slave.spawn_command(cmd)
+        .map_err(|e| format!("Failed to spawn shell: {}", e))?;
+
+    let shell_pid = child.process_id();
+    println!("[create_interactive_terminal] Shell spawned with PID: {:?}", shell_pid);
+    pty_child_map
+        .lock()
+        .map_err(|e| format!("Lock error: {}", e))?
+        .insert(terminal_id.clone(), PtyProcess {
+            pid: shell_pid,
+            #[cfg(target_os = "windows")]
+            killer: child.clone_killer(),
+        });
+
+    // Get the master PTY reader
+    let mut reader = pair.master.try_clone_reader()
+        .map_err(|e| format!("Failed to clone reader: {}", e))?;
+
+    // Get and store the writer
+    let writer = pair.master.take_writer()
+        .map_err(|e| format!("Failed to get writer: {}", e))?;
+
+    // Store writer (using tokio async Mutex with .await)
+    {
+        let mut map = pty_writer_map.lock().await;
+        map.insert(terminal_id.clone(), Arc::new(Mutex::new(writer)));
+    }
+
+    // Keep the master for resizing (dropping it would also close the PTY)
+    pty_master_map
+        .lock()
+        .map_err(|e| format!("Lock error: {}", e))?
+        .insert(terminal_id.clone(), pair.master);
+
+    pty_meta_map
+        .lock()
+        .map_err(|e| format!("Lock error: {}", e))?
+        .insert(terminal_id.clone(), TerminalMeta { cwd, title: None, rows, cols });
+
+    // Report the exit code once the shell exits and its remaining output has been emitted
+    let (output_done_tx, output_done_rx) = std::sync::mpsc::channel::<()>();
+    let exit_app = app.clone();
+    let exit_terminal_id = terminal_id.clone();
+    spawn_pty_exit_waiter(child, output_done_rx, move |exit_code| {
+        println!("[Terminal {}] Shell exited with code {:?}", exit_terminal_id, exit_code);
+        if let Ok(mut children) = exit_app.state::<PtyChildMap>().lock() {
+            // The id may already belong to a new terminal
+            if children.get(&exit_terminal_id).is_some_and(|p| p.pid == shell_pid) {
+                children.remove(&exit_terminal_id);
+            }
+        }
+        let _ = exit_app.emit("terminal-exit", serde_json::json!({
+            "terminalId": exit_terminal_id,
+            "exitCode": exit_code
+        }));
+    });
+
+    // Spawn task to read PTY output and emit to frontend
+    // Use tokio::task::spawn_blocking for blocking PTY read operations
+    let terminal_id_clone = terminal_id.clone();
+    let meta_map = pty_meta_map.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        use std::io::Read;
+        // Dropped when reading stops, which releases the exit waiter
+        let _output_done = output_done_tx;
+        let mut buffer = [0u8; 8192];
+        let mut utf8 = Utf8Carry::default();
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => {
+                    // EOF - shell exited
+                    println!("[Terminal {}] Shell exited", terminal_id_clone);
+                    let _ = app.emit("terminal-output", serde_json::json!({
+                        "terminalId": terminal_id_clone,
+                        "output": "\r\n[Process exited]\r\n"
+                    }));
+                    break;
+                }
+                Ok(n) => {
+                    // PTY output is usually UTF-8; a character split across reads is completed next time
+                    let output = utf8.decode(&buffer[..n]);
+                    if output.is_empty() {
+                        continue;
+                    }
+
+                    // Shells report the window title (OSC 0/2) and, when configured, the cwd (OSC 7)
+                    let (title, cwd) = parse_osc_updates(&output);
+                    if title.is_some() || cwd.is_some() {
+                        if let Ok(mut meta) = meta_map.lock() {
+                            if let Some(entry) = meta.get_mut(&terminal_id_clone) {
+                                if title.is_some() {
+                                    entry.title = title;
+                                }
+                                if cwd.is_some() {
+                                    entry.cwd = cwd;
+                                }
+                            }
+                        }
+                    }
+
+                    // Emit to frontend
+                    let _ = app.emit("terminal-output", serde_json::json!({
+                        "terminalId": terminal_id_clone,
+                        "output": output
+                    }));
+                }
+                Err(e) => {
+                    println!("[Terminal {}] Read error: {}", terminal_id_clone, e);
+                    break;
+                }
+            }
+        }
+    });
+
+    println!("[create_interactive_terminal] Terminal {} created successfully", terminal_id);
+    Ok(())
+}
+
+/// Close an interactive terminal
+#[tauri::command]
+async fn close_terminal(
+    pty_writer_map: tauri::State<'_, PtyWriterMap>,
+    pty_master_map: tauri::State<'_, PtyMasterMap>,
+    pty_child_map: tauri::State<'_, PtyChildMap>,
+    pty_meta_map: tauri::State<'_, PtyMetaMap>,
+    terminal_id: String,
+) -> Result<(), String> {
+    println!("[close_terminal] Closing terminal: {}", terminal_id);
+
+    // Remove writer (using tokio async Mutex with .await)
+    {
+        let mut map = pty_writer_map.lock().await;
+        map.remove(&terminal_id);
+    }
+    if let Ok(mut masters) = pty_master_map.lock() {
+        masters.remove(&terminal_id);
+    }
+    if let Ok(mut children) = pty_child_map.lock() {
+        children.remove(&terminal_id);
+    }
+    if let Ok(mut meta) = pty_meta_map.lock() {
+        meta.remove(&terminal_id);
+    }
+
+    println!("[close_terminal] Terminal {} closed successfully", terminal_id);
+    Ok(())
+}
+
+/// Resize an interactive terminal's PTY to match the frontend (xterm) dimensions
+#[tauri::command]
+fn terminal_resize(
+    pty_master_map: tauri::State<'_, PtyMasterMap>,
+    pty_meta_map: tauri::State<'_, PtyMetaMap>,
+    terminal_id: String,
+    rows: u16,
+    cols: u16,
+    pixel_width: Option<u16>,
+    pixel_height: Option<u16>,
+) -> Result<(), String> {
+    let masters = pty_master_map.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let master = masters
+        .get(&terminal_id)
+        .ok_or_else(|| format!("Terminal {} not found", terminal_id))?;
+
+    master
+        .resize(PtySize {
+            rows,
+            cols,
+            pixel_width: pixel_width.unwrap_or(0),
+            pixel_height: pixel_height.unwrap_or(0),
+        })
+        .map_err(|e| format!("Failed to resize PTY: {}", e))?;
+
+    if let Some(meta) = pty_meta_map.lock().ok().as_mut().and_then(|m| m.get_mut(&terminal_id)) {
+        meta.rows = rows;
+        meta.cols = cols;
+    }
+    Ok(())
+}
+
+/// Deliver a signal to a terminal's foreground job (falling back to the shell itself).
+/// Returns the normalized signal name.
+fn signal_terminal(
+    children: &PtyChildMap,
+    masters: &PtyMasterMap,
+    terminal_id: &str,
+    signal: &str,
+) -> Result<String, String> {
+    let upper = signal.trim().to_uppercase();
+    let name = if upper.starts_with("SIG") { upper } else { format!("SIG{}", upper) };
+
+    let mut children = children.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let process = children
+        .get_mut(terminal_id)
+        .ok_or_else(|| format!("Terminal {} not found", terminal_id))?;
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        use nix::sys::signal::{kill, killpg, Signal};
+        use nix::unistd::Pid;
+
+        let sig: Signal = name.parse().map_err(|_| format!("Unknown signal: {}", signal))?;
+
+        // The PTY's foreground process group is whatever is running in it (or the shell when idle)
+        let group = masters
+            .lock()
+            .ok()
+            .and_then(|masters| masters.get(terminal_id).and_then(|m| m.process_group_leader()));
+        let result = match group {
+            Some(pgrp) => killpg(Pid::from_raw(pgrp), sig),
+            None => {
+                let pid = process.pid.ok_or("Terminal process has already exited")?;
+                kill(Pid::from_raw(pid as i32), sig)
+            }
+        };
+        result.map_err(|e| format!("Failed to send {}: {}", name, e))?;
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        // Windows has no signals to forward; terminating is the only structured option
+        let _ = masters;
+        match name.as_str() {
+            "SIGINT" | "SIGTERM" | "SIGKILL" | "SIGHUP" => {
+                process.killer.kill().map_err(|e| format!("Failed to terminate terminal process: {}", e))?;
+            }
+            _ => return Err(format!("Unsupported signal on Windows: {}", signal)),
+        }
+    }
+
+    Ok(name)
+}
+
+/// Send a signal (e.g. "SIGINT", "SIGTERM") to an interactive terminal's running program
+#[tauri::command]
+fn terminal_signal(
+    app: tauri::AppHandle,
+    pty_child_map: tauri::State<'_, PtyChildMap>,
+    pty_master_map: tauri::State<'_, PtyMasterMap>,
+    terminal_id: String,
+    signal: String,
+) -> Result<(), String> {
+    let name = signal_terminal(&pty_child_map, &pty_master_map, &terminal_id, &signal)?;
+    println!("[terminal_signal] Sent {} to terminal {}", name, terminal_id);
+
+    let _ = app.emit("terminal-output", serde_json::json!({
+        "terminalId": terminal_id,
+        "output": format!("\r\n[{}]\r\n", name)
+    }));
+    Ok(())
+}
+
+/// Latest window title (OSC 0/2) and working directory (OSC 7) announced in a chunk of PTY output.
+/// Sequences split across reads are missed; the shell repeats them at the next prompt.
+fn parse_osc_updates(output: &str) -> (Option<String>, Option<String>) {
+    let mut title = None;
+    let mut cwd = None;
+    let mut rest = output;
+    while let Some(start) = rest.find("\x1b]") {
+        let body = &rest[start + 2..];
+        let Some(end) = body.find(['\x07', '\x1b']) else { break };
+        if let Some((code, value)) = body[..end].split_once(';') {
+            match code {
+                "0" | "2" => title = Some(value.to_string()),
+                // file://host/path -> /path (shells percent-encode spaces and non-ASCII)
+                "7" => {
+                    if let Some(path) = value
+                        .strip_prefix("file://")
+                        .and_then(|v| v.find('/').map(|i| &v[i..]))
+                    {
+                        cwd = Some(percent_decode(path));
+                    }
+                }
+                _ => {}
+            }
+        }
+        rest = &body[end..];
+    }
+    (title, cwd)
+}
+
+/// Decode %XX escapes; malformed escapes are kept as-is
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// 前端可见的终端信息
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TerminalInfo {
+    terminal_id: String,
+    alive: bool,
+    cwd: Option<String>,
+    title: Option<String>,
+    rows: u16,
+    cols: u16,
+}
+
+/// Snapshot of the PTY sessions, sorted by id. A terminal whose shell has exited is reported
+/// once with `alive: false` and then dropped from the maps.
+async fn collect_terminals(
+    pty_writer_map: &PtyWriterMap,
+    pty_master_map: &PtyMasterMap,
+    pty_child_map: &PtyChildMap,
+    pty_meta_map: &PtyMetaMap,
+) -> Result<Vec<TerminalInfo>, String> {
+    let mut writers = pty_writer_map.lock().await;
+    let mut ids: Vec<String> = writers.keys().cloned().collect();
+    ids.sort();
+
+    let children = pty_child_map.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let mut meta = pty_meta_map.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let mut terminals = Vec::with_capacity(ids.len());
+    let mut exited = Vec::new();
+    for id in ids {
+        // The exit waiter removes the child entry once the shell is gone
+        let alive = children.contains_key(&id);
+        let info = meta.get(&id).cloned().unwrap_or_default();
+        if !alive {
+            writers.remove(&id);
+            meta.remove(&id);
+            exited.push(id.clone());
+        }
+        terminals.push(TerminalInfo {
+            terminal_id: id,
+            alive,
+            cwd: info.cwd,
+            title: info.title,
+            rows: info.rows,
+            cols: info.cols,
+        });
+    }
+
+    // terminal_resize locks masters before meta, so never hold meta while taking masters
+    drop(meta);
+    drop(children);
+    if !exited.is_empty() {
+        if let Ok(mut masters) = pty_master_map.lock() {
+            for id in &exited {
+                masters.remove(id);
+            }
+        }
+    }
+    Ok(terminals)
+}
+
+/// List the interactive terminals (e.g. to restore tabs after a reload)
+#[tauri::command]
+async fn list_terminals(
+    pty_writer_map: tauri::State<'_, PtyWriterMap>,
+    pty_master_map: tauri::State<'_, PtyMasterMap>,
+    pty_child_map: tauri::State<'_, PtyChildMap>,
+    pty_meta_map: tauri::State<'_, PtyMetaMap>,
+) -> Result<Vec<TerminalInfo>, String> {
+    collect_terminals(&pty_writer_map, &pty_master_map, &pty_child_map, &pty_meta_map).await
+}
+
+/// Send input to an interactive terminal
+#[tauri::command]
+async fn terminal_input(
+    pty_writer_map: tauri::State<'_, PtyWriterMap>,
+    terminal_id: String,
+    data: String,
+) -> Result<(), String> {
+    println!("[terminal_input] START - Terminal: {}, Data length: {}, Data: {:?}", terminal_id, data.len(), data);
+
+    // Get writer Arc from the map (using tokio async Mutex with .await)
+    let writer_arc = {
+        let map = pty_writer_map.lock().await;
+        map.get(&terminal_id)
+            .ok_or_else(|| {
+                eprintln!("[terminal_input] ERROR: Terminal {} not found in map", terminal_id);
+                format!("Terminal {} not found", terminal_id)
+            })?
+            .clone()
+    };
+
+    println!("[terminal_input] Got writer arc, spawning blocking write task...");
+
+    // Use spawn_blocking to perform synchronous I/O operations
+    // This prevents blocking the tokio runtime
+    let data_bytes = data.into_bytes();
+    let result = tokio::task::spawn_blocking(move || {
+        use std::io::Write;
+
+        // Block to acquire the lock in sync context
+        let rt = tokio::runtime::Handle::current();
+        let mut writer = rt.block_on(writer_arc.lock());
+
+        println!("[terminal_input] Writer locked, writing {} bytes...", data_bytes.len());
+
+        // Write data to PTY
+        if let Err(e) = writer.write_all(&data_bytes) {
+            eprintln!("[terminal_input] ERROR: Failed to write to PTY: {}", e);
+            return Err(format!("Failed to write to PTY: {}", e));
+        }
+
+        println!("[terminal_input] Data written, flushing...");
+
+        if let Err(e) = writer.flush() {
+            eprintln!("[terminal_input] ERROR: Failed to flush PTY: {}", e);
+            return Err(format!("Failed to flush PTY: {}", e));
+        }
+
+        println!("[terminal_input] SUCCESS - Write completed");
+        Ok(())
+    }).await.map_err(|e| format!("Task join error: {}", e))?;
+
+    result
+}
+
+/// How a process ended after `terminate_process`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum KillOutcome {
+    /// Exited within the grace period after SIGTERM
+    Terminated,
+    /// Still running after the grace period and was sent SIGKILL (always the case on Windows)
+    Killed,
+    /// No such process
+    NotFound,
+}
+
+/// Kill a background process by PID: SIGTERM, then SIGKILL after `grace_ms` (default 3000)
+#[tauri::command]
+async fn kill_process(
+    terminal_map: tauri::State<'_, TerminalMap>,
+    process_specs: tauri::State<'_, ProcessSpecMap>,
+    pid: u32,
+    grace_ms: Option<u64>,
+) -> Result<KillOutcome, String> {
+    println!("[kill_process] Attempting to kill process with PID: {}", pid);
+
+    // CRITICAL: Never kill PID 0 - it would kill the entire process group including Tauri itself!
+    if pid == 0 {
+        println!("[kill_process] Skipping PID 0 (would kill process group)");
+        return Ok(KillOutcome::NotFound);
+    }
+
+    if let Ok(mut specs) = process_specs.lock() {
+        specs.remove(&pid);
+    }
+
+    let grace = std::time::Duration::from_millis(grace_ms.unwrap_or(3000));
+    let outcome = terminate_process(pid, grace).await?;
+    println!("[kill_process] PID {} -> {:?}", pid, outcome);
+
+    // Remove from terminal map
+    if let Ok(mut map) = terminal_map.lock() {
+        map.remove(&pid);
+        println!("[kill_process] Removed PID {} from terminal map", pid);
+    }
+
+    Ok(outcome)
+}
+
+/// Stop a process and everything in its process group: SIGTERM, then SIGKILL if anything is
+/// still running after `grace`. A PID that does not lead a group is signalled on its own.
+async fn terminate_process(pid: u32, grace: std::time::Duration) -> Result<KillOutcome, String> {
+    #[cfg(target_os = "windows")]
+    {
+        let _ = grace;
+        let output = std::process::Command::new("taskkill")
+            .args(["/F", "/T", "/PID", &pid.to_string()])
+            .output()
+            .map_err(|e| format!("Failed to execute taskkill: {}", e))?;
+        if !output.status.success() {
+            // taskkill exits with 128 when the PID does not exist
+            if output.status.code() == Some(128) {
+                return Ok(KillOutcome::NotFound);
+            }
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Failed to kill process: {}", error));
+        }
+        Ok(KillOutcome::Killed)
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        use nix::errno::Errno;
+        use nix::sys::signal::{kill, killpg, Signal};
+        use nix::unistd::Pid;
+
+        let nix_pid = Pid::from_raw(pid as i32);
+        // Background processes lead their own group; anything else only gets the single PID
+        let group = killpg(nix_pid, None).is_ok();
+        let signal = |sig: Option<Signal>| {
+            if group {
+                killpg(nix_pid, sig)
+            } else {
+                kill(nix_pid, sig)
+            }
+        };
+        match signal(Some(Signal::SIGTERM)) {
+            Ok(()) => {}
+            Err(Errno::ESRCH) => return Ok(KillOutcome::NotFound),
+            Err(e) => return Err(format!("Failed to kill process: {}", e)),
+        }
+
+        let alive = || signal(None).is_ok();
+        let started = tokio::time::Instant::now();
+        while alive() && started.elapsed() < grace {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        if !alive() {
+            return Ok(KillOutcome::Terminated);
+        }
+
+        println!("[terminate_process] PID {} ignored SIGTERM, sending SIGKILL", pid);
+        match signal(Some(Signal::SIGKILL)) {
+            Ok(()) => {}
+            // Exited between the last check and SIGKILL
+            Err(Errno::ESRCH) => return Ok(KillOutcome::Terminated),
+            Err(e) => return Err(format!("Failed to kill process: {}", e)),
+        }
+        let started = tokio::time::Instant::now();
+        while alive() && started.elapsed() < std::time::Duration::from_secs(1) {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        Ok(KillOutcome::Killed)
+    }
+}
+
+/// Remove ANSI escape sequences (CSI, OSC/DCS strings and short escapes) from text
+fn strip_ansi_codes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                Some('[') => skip_ansi_csi(&mut chars),
+                Some(']') | Some('P') | Some('X') | Some('^') | Some('_') => skip_ansi_string(&mut chars),
+                // Charset designation and similar escapes carry one more byte
+                Some('(') | Some(')') | Some('*') | Some('+') | Some('#') | Some('%') => {
+                    chars.next();
+                }
+                _ => {}
+            },
+            // 8-bit C1 forms of CSI and OSC
+            '\u{9b}' => skip_ansi_csi(&mut chars),
+            '\u{9d}' => skip_ansi_string(&mut chars),
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Skip CSI parameters up to and including the final byte; stops early on malformed input
+fn skip_ansi_csi(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) {
+    while let Some(&c) = chars.peek() {
+        if ('\x40'..='\x7e').contains(&c) {
+            chars.next();
+            break;
+        } else if ('\x20'..='\x3f').contains(&c) {
+            chars.next();
+        } else {
+            break;
+        }
+    }
+}
+
+/// Skip an OSC/DCS-style string up to BEL or ST (ESC \)
+fn skip_ansi_string(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) {
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\x07' | '\u{9c}' => {
+                chars.next();
+                break;
+            }
+            // ESC either starts ST (ESC \) or a new sequence; leave it for the caller
+            '\x1b' => break,
+            _ => {
+                chars.next();
+            }
+        }
+    }
+}
+
+/// Strip ANSI escape sequences from captured output ("copy without colors")
+#[tauri::command]
+fn strip_ansi(text: String) -> String {
+    strip_ansi_codes(&text)
+}
+
+/// Quote one argument for a POSIX shell: bare when it only has safe characters, else single-quoted
+fn posix_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Quote one argument for cmd.exe: CommandLineToArgvW quoting first, then every cmd
+/// metacharacter (quotes included) is caret-escaped. cmd does not honour `\"` inside quotes,
+/// so plain quoting alone would still let `&`, `|` or `%VAR%` through.
+fn windows_quote(arg: &str) -> String {
+    let quoted = if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        arg.to_string()
+    } else {
+        argv_quote(arg)
+    };
+    let mut out = String::with_capacity(quoted.len());
+    for c in quoted.chars() {
+        if "()%!^\"<>&|".contains(c) {
+            out.push('^');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Wrap one argument in quotes following the CommandLineToArgvW rules
+fn argv_quote(arg: &str) -> String {
+    let mut out = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes before a quote are escapes, so double them and escape the quote
+                out.push_str(&"\\".repeat(backslashes * 2 + 1));
+                out.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                out.push_str(&"\\".repeat(backslashes));
+                out.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    // Trailing backslashes would escape the closing quote
+    out.push_str(&"\\".repeat(backslashes * 2));
+    out.push('"');
+    out
+}
+
+/// Join arguments into one command line quoted for the platform's shell
+fn quote_command_line<S: AsRef<str>>(args: &[S]) -> String {
+    let quote = if cfg!(target_os = "windows") { windows_quote } else { posix_quote };
+    args.iter().map(|a| quote(a.as_ref())).collect::<Vec<_>>().join(" ")
+}
+
+/// Build a correctly quoted command line (POSIX shell, or cmd on Windows) for display or execution
+#[tauri::command]
+fn shell_quote(args: Vec<String>) -> String {
+    quote_command_line(&args)
+}
+
+/// Apply a background process's output filter, returning None when the line should be dropped
+fn filter_output_line(line: String, filter: Option<&regex::Regex>, strip_ansi: bool) -> Option<String> {
+    if filter.is_none() && !strip_ansi {
+        return Some(line);
+    }
+
+    // Match against the visible text so color codes don't break patterns
+    let plain = strip_ansi_codes(&line);
+    if let Some(re) = filter {
+        if !re.is_match(&plain) {
+            return None;
+        }
+    }
+
+    Some(if strip_ansi { plain } else { line })
+}
+
+/// Launch parameters of a background process, kept so it can be restarted
+#[derive(Clone, Debug)]
+struct ProcessSpec {
+    command: String,
+    args: Vec<String>,
+    cwd: String,
+    terminal_id: Option<String>,
+    filter_regex: Option<String>,
+    strip_ansi: bool,
+    /// Set while restart_process is stopping the process, so its exit is not reported
+    restarting: bool,
+}
+
+/// Start a background process (dev server, etc.) that persists after Claude exits
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn start_background_process(
+    app: tauri::AppHandle,
+    terminal_map: tauri::State<'_, TerminalMap>,
+    process_specs: tauri::State<'_, ProcessSpecMap>,
+    command: String,
+    args: Vec<String>,
+    cwd: String,
+    terminal_id: Option<String>,
+    filter_regex: Option<String>,
+    strip_ansi: Option<bool>,
+) -> Result<u32, String> {
+    let spec = ProcessSpec {
+        command,
+        args,
+        cwd,
+        terminal_id,
+        filter_regex,
+        strip_ansi: strip_ansi.unwrap_or(false),
+        restarting: false,
+    };
+
+    spawn_background_process(&app, terminal_map.inner(), process_specs.inner(), spec)
+}
+
+/// Spawn a background process and forward its output as terminal-output events.
+/// The spec is recorded for restart_process until the process exits.
+fn spawn_background_process(
+    app: &tauri::AppHandle,
+    terminal_map: &TerminalMap,
+    process_specs: &ProcessSpecMap,
+    spec: ProcessSpec,
+) -> Result<u32, String> {
+    println!(
+        "[start_background_process] Starting: {}",
+        quote_command_line(&[std::slice::from_ref(&spec.command), spec.args.as_slice()].concat())
+    );
+    println!("[start_background_process] CWD: {}", spec.cwd);
+    println!("[start_background_process] Terminal ID: {:?}", spec.terminal_id);
+
+    // Compile the output filter up front so an invalid pattern fails the spawn
+    let filter = spec
+        .filter_regex
+        .as_deref()
+        .map(regex::Regex::new)
+        .transpose()
+        .map_err(|e| format!("Invalid filter regex: {}", e))?;
+    let strip_ansi = spec.strip_ansi;
+
+    // Create the command
+    let mut cmd = Command::new(&spec.command);
+    cmd.args(&spec.args);
+    cmd.current_dir(&spec.cwd);
+    cmd.env("PATH", shell_path());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    // Own process group, so kill_process also reaches whatever it forks (e.g. npm -> node)
+    #[cfg(unix)]
+    cmd.process_group(0);
+    #[cfg(target_os = "windows")]
+    {
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+
+    // Spawn the process
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("Failed to spawn process: {}", e))?;
+
+    let pid = child.id().ok_or("Failed to get process ID")?;
+    println!("[start_background_process] Spawned with PID: {}", pid);
+
+    // If terminal_id is provided, immediately store the mapping
+    if let Some(ref term_id) = spec.terminal_id {
+        if let Ok(mut map) = terminal_map.lock() {
+            map.insert(pid, term_id.clone());
+            println!("[start_background_process] Stored mapping: PID {} -> Terminal {}", pid, term_id);
+        }
+    }
+    // Record before the exit watcher starts, so a process that exits at once can't leave a stale entry
+    if let Ok(mut specs) = process_specs.lock() {
+        specs.insert(pid, spec);
+    }
+
+    // Get stdout and stderr
+    let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
+    let stderr = child.stderr.take().ok_or("Failed to get stderr")?;
+    // Create readers
+    let stdout_reader = BufReader::new(stdout);
+    let stderr_reader = BufReader::new(stderr);
+
+    // Clone terminal_map for async tasks
+    let terminal_map_stdout = Arc::clone(terminal_map);
+    let terminal_map_stderr = Arc::clone(terminal_map);
+
+    // Spawn task to read stdout and emit to frontend with terminal ID
+    let app_stdout = app.clone();
+    let pid_stdout = pid;
+    let filter_stdout = filter.clone();
+    let stdout_task = tokio::spawn(async move {
+        let mut lines = stdout_reader.lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let Some(line) = filter_output_line(line, filter_stdout.as_ref(), strip_ansi) else {
+                continue;
+            };
+            println!("[Process {}] {}", pid_stdout, line);
+
+            // Get terminal ID from map
+            let terminal_id = terminal_map_stdout.lock()
+                .ok()
+                .and_then(|map| map.get(&pid_stdout).cloned())
+                .unwrap_or_else(|| format!("terminal-{}", pid_stdout));
+
+            // Emit to terminal with terminal ID
+            #[derive(serde::Serialize, Clone)]
+            #[serde(rename_all = "camelCase")]
+            struct TerminalOutput {
+                terminal_id: String,
+                output: String,
+            }
+
+            let _ = app_stdout.emit("terminal-output", TerminalOutput {
+                terminal_id,
+                output: line,
+            });
+        }
+    });
+
+    // Spawn task to read stderr and emit to frontend with terminal ID
+    let app_stderr = app.clone();
+    let pid_stderr = pid;
+    let filter_stderr = filter;
+    let stderr_task = tokio::spawn(async move {
+        let mut lines = stderr_reader.lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let Some(line) = filter_output_line(line, filter_stderr.as_ref(), strip_ansi) else {
+                continue;
+            };
+            println!("[Process {} stderr] {}", pid_stderr, line);
+
+            // Get terminal ID from map
+            let terminal_id = terminal_map_stderr.lock()
+                .ok()
+                .and_then(|map| map.get(&pid_stderr).cloned())
+                .unwrap_or_else(|| format!("terminal-{}", pid_stderr));
+
+            // Emit to terminal with terminal ID
+            #[derive(serde::Serialize, Clone)]
+            #[serde(rename_all = "camelCase")]
+            struct TerminalOutput {
+                terminal_id: String,
+                output: String,
+            }
+
+            let _ = app_stderr.emit("terminal-output", TerminalOutput {
+                terminal_id,
+                output: line,
+            });
+        }
+    });
+
+    // Don't block on the process - let it run in background. Waiting in a task reaps it
+    // on exit, so a stopped process doesn't linger as a zombie under its old PID.
+    let app_exit = app.clone();
+    let terminal_map_exit = Arc::clone(terminal_map);
+    let process_specs_exit = Arc::clone(process_specs);
+    spawn_process_exit_waiter(child, vec![stdout_task, stderr_task], move |exit_code| {
+        println!("[Process {}] exited with code {:?}", pid, exit_code);
+        let restarting = process_specs_exit
+            .lock()
+            .ok()
+            .and_then(|mut specs| specs.remove(&pid))
+            .is_some_and(|spec| spec.restarting);
+        let terminal_id = terminal_map_exit
+            .lock()
+            .ok()
+            .and_then(|mut map| map.remove(&pid))
+            .unwrap_or_else(|| format!("terminal-{}", pid));
+        // A restarted process lives on under a new PID in the same terminal
+        if restarting {
+            return;
+        }
+        let _ = app_exit.emit("process-exit", serde_json::json!({
+            "terminalId": terminal_id,
+            "pid": pid,
+            "exitCode": exit_code
+        }));
+    });
+
+    Ok(pid)
+}
+
+/// Wait for a background process to exit, then for its output tasks to drain (so the last
+/// lines are emitted first), and report the exit code (`None` when killed by a signal).
+fn spawn_process_exit_waiter(
+    mut child: tokio::process::Child,
+    output_tasks: Vec<tokio::task::JoinHandle<()>>,
+    on_exit: impl FnOnce(Option<i32>) + Send + 'static,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let exit_code = match child.wait().await {
+            Ok(status) => status.code(),
+            Err(e) => {
+                println!("[spawn_process_exit_waiter] wait failed: {}", e);
+                None
+            }
+        };
+        for task in output_tasks {
+            // Grandchildren can keep the pipes open; don't let them hold back the exit event
+            let _ = tokio::time::timeout(std::time::Duration::from_secs(1), task).await;
+        }
+        on_exit(exit_code);
+    })
+}
+
+/// Restart a background process with the command, args and cwd it was started with.
+/// The new process reports output under the same terminal id; returns the new PID.
+#[tauri::command]
+async fn restart_process(
+    app: tauri::AppHandle,
+    terminal_map: tauri::State<'_, TerminalMap>,
+    process_specs: tauri::State<'_, ProcessSpecMap>,
+    pid: u32,
+) -> Result<u32, String> {
+    if pid == 0 {
+        return Err("Invalid PID 0".to_string());
+    }
+
+    // The old process's exit waiter drops its spec and terminal mapping, but skips the
+    // process-exit event while restarting is set
+    let mut spec = {
+        let mut specs = process_specs
+            .lock()
+            .map_err(|e| format!("Failed to lock process map: {}", e))?;
+        let recorded = specs
+            .get_mut(&pid)
+            .ok_or_else(|| format!("No launch parameters recorded for PID {}", pid))?;
+        recorded.restarting = true;
+        ProcessSpec { restarting: false, ..recorded.clone() }
+    };
+
+    // Keep output in the same terminal even if it was only implied by the old PID
+    let old_terminal_id = terminal_map.lock().ok().and_then(|map| map.get(&pid).cloned());
+    spec.terminal_id = spec
+        .terminal_id
+        .or(old_terminal_id)
+        .or_else(|| Some(format!("terminal-{}", pid)));
+
+    println!("[restart_process] Stopping PID {}", pid);
+    if let Err(e) = terminate_process(pid, std::time::Duration::from_secs(5)).await {
+        // Still running: its exit should be reported as usual
+        if let Ok(mut specs) = process_specs.lock() {
+            if let Some(recorded) = specs.get_mut(&pid) {
+                recorded.restarting = false;
+            }
+        }
+        return Err(e);
+    }
+
+    let new_pid = spawn_background_process(&app, terminal_map.inner(), process_specs.inner(), spec)?;
+
+    println!("[restart_process] PID {} restarted as {}", pid, new_pid);
+    Ok(new_pid)
+}
+
+/// Execute Kiro CLI with streaming output; returns the session id for cancel_claude_execution
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn execute_kiro_streaming(
+    app: tauri::AppHandle,
+    cli_sessions: tauri::State<'_, CliSessionMap>,
+    kiro_path: String,
+    args: Vec<String>,
+    cwd: String,
+    env: Option<HashMap<String, String>>,
+    env_clear: Option<bool>,
+) -> Result<String, String> {
+    let env = CliEnv { vars: env.unwrap_or_default(), clear: env_clear.unwrap_or(false) };
+    execute_cli_streaming(app, cli_sessions.inner(), kiro_path, args, cwd, env, "kiro")
+}
+
+// ============================================================================
+// API Proxy Commands
+// ============================================================================
+
+/// A running proxy server: its task, the graceful-shutdown trigger and what it was started with
+struct RunningProxy {
+    handle: tokio::task::JoinHandle<()>,
+    shutdown: tokio::sync::oneshot::Sender<()>,
+    port: u16,
+    provider: String,
+    config: api_proxy::ProxyConfig,
+}
+
+/// API Proxy state - holds the running server, if any
+type ProxyServerHandle = Arc<Mutex<Option<RunningProxy>>>;
+
+fn create_proxy_server_handle() -> ProxyServerHandle {
+    Arc::new(Mutex::new(None))
+}
+
+/// Proxy settings from the UI; anything left unset falls back to the environment
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct ProxyConfigInput {
+    preferred_provider: Option<String>,
+    big_model: Option<String>,
+    small_model: Option<String>,
+    openai_api_key: Option<String>,
+    gemini_api_key: Option<String>,
+    anthropic_api_key: Option<String>,
+    openai_base_url: Option<String>,
+}
+
+impl ProxyConfigInput {
+    fn into_config(self) -> Result<api_proxy::ProxyConfig, String> {
+        let env = api_proxy::ProxyConfig::from_env();
+        let preferred_provider = match self.preferred_provider {
+            Some(provider) => provider.parse()?,
+            None => env.preferred_provider,
+        };
+
+        Ok(api_proxy::ProxyConfig {
+            preferred_provider,
+            big_model: self.big_model.unwrap_or(env.big_model.clone()),
+            small_model: self.small_model.unwrap_or(env.small_model.clone()),
+            openai_api_key: self.openai_api_key.or(env.openai_api_key.clone()),
+            gemini_api_key: self.gemini_api_key.or(env.gemini_api_key.clone()),
+            anthropic_api_key: self.anthropic_api_key.or(env.anthropic_api_key.clone()),
+            openai_base_url: self.openai_base_url.or(env.openai_base_url.clone()),
+            ..env
+        })
+    }
+}
+
+/// Bind the port and start serving in the background
+async fn launch_proxy(
+    proxy_handle: &ProxyServerHandle,
+    port: u16,
+    config: api_proxy::ProxyConfig,
+) -> Result<String, String> {
+    let mut proxy = proxy_handle.lock().await;
+    if proxy.as_ref().is_some_and(|p| !p.handle.is_finished()) {
+        return Err("API proxy server is already running".to_string());
+    }
+
+    // A bad upstream proxy or CA certificate fails the start instead of silently connecting directly
+    let client = api_proxy::ApiClient::try_new(config.clone())
+        .map_err(|e| format!("Failed to start API proxy: {}", e))?;
+
+    // Bind up front so a port conflict is reported instead of failing silently in the task
+    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to bind 127.0.0.1:{}: {}", port, e))?;
+
+    let provider = config.preferred_provider.to_string();
+    let (shutdown, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let handle = tokio::spawn(async move {
+        let shutdown_signal = async move {
+            let _ = shutdown_rx.await;
+        };
+        if let Err(e) = api_proxy::run_server_with_shutdown(client, listener, shutdown_signal).await {
+            eprintln!("[API Proxy] Server error: {}", e);
+        }
+    });
+
+    *proxy = Some(RunningProxy { handle, shutdown, port, provider, config });
+    Ok(format!("API proxy started on http://127.0.0.1:{}", port))
+}
+
+/// Stop the server, giving in-flight requests a few seconds to finish
+async fn shutdown_proxy(proxy_handle: &ProxyServerHandle) -> Result<String, String> {
+    let running = proxy_handle.lock().await.take();
+    let Some(running) = running else {
+        return Err("API proxy server is not running".to_string());
+    };
+
+    let _ = running.shutdown.send(());
+    let mut handle = running.handle;
+    if tokio::time::timeout(std::time::Duration::from_secs(5), &mut handle).await.is_err() {
+        println!("[stop_proxy] Graceful shutdown timed out, aborting");
+        handle.abort();
+    }
+
+    Ok("API proxy server stopped".to_string())
+}
+
+/// Start the API proxy server
+#[tauri::command]
+async fn start_api_proxy(
+    proxy_handle: tauri::State<'_, ProxyServerHandle>,
+    port: u16,
+    preferred_provider: Option<String>,
+    big_model: Option<String>,
+    small_model: Option<String>,
+) -> Result<String, String> {
+    // Build configuration
+    let config = api_proxy::ProxyConfig {
+        preferred_provider: preferred_provider
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(api_proxy::types::Provider::OpenAI),
+        big_model: big_model.unwrap_or_else(|| "gpt-4.1".to_string()),
+        small_model: small_model.unwrap_or_else(|| "gpt-4.1-mini".to_string()),
+        // API keys, base URL and other settings come from the environment
+        ..api_proxy::ProxyConfig::from_env()
+    };
+
+    launch_proxy(proxy_handle.inner(), port, config).await
+}
+
+/// Stop the API proxy server
+#[tauri::command]
+async fn stop_api_proxy(
+    proxy_handle: tauri::State<'_, ProxyServerHandle>,
+) -> Result<String, String> {
+    shutdown_proxy(proxy_handle.inner()).await
+}
+
+/// Start the API proxy server with settings from the UI (keys, provider, models)
+#[tauri::command]
+async fn start_proxy(
+    proxy_handle: tauri::State<'_, ProxyServerHandle>,
+    port: u16,
+    config: ProxyConfigInput,
+) -> Result<String, String> {
+    launch_proxy(proxy_handle.inner(), port, config.into_config()?).await
+}
+
+/// Gracefully stop the API proxy server
+#[tauri::command]
+async fn stop_proxy(
+    proxy_handle: tauri::State<'_, ProxyServerHandle>,
+) -> Result<String, String> {
+    shutdown_proxy(proxy_handle.inner()).await
+}
+
+#[derive(serde::Serialize)]
+struct ProxyStatus {
+    running: bool,
+    port: Option<u16>,
+    provider: Option<String>,
+}
+
+/// Whether the API proxy server is running, and on which port/provider
+#[tauri::command]
+async fn proxy_status(
+    proxy_handle: tauri::State<'_, ProxyServerHandle>,
+) -> Result<ProxyStatus, String> {
+    let proxy = proxy_handle.lock().await;
+    Ok(match proxy.as_ref().filter(|p| !p.handle.is_finished()) {
+        Some(running) => ProxyStatus {
+            running: true,
+            port: Some(running.port),
+            provider: Some(running.provider.clone()),
+        },
+        None => ProxyStatus {
+            running: false,
+            port: None,
+            provider: None,
+        },
+    })
+}
+
+/// Effective proxy configuration and where each value came from (env, model-map file, default,
+/// or the UI settings of the running server). Secrets are reported as `has_*` booleans.
+#[tauri::command]
+async fn proxy_effective_config(
+    proxy_handle: tauri::State<'_, ProxyServerHandle>,
+) -> Result<Vec<api_proxy::ConfigField>, String> {
+    let proxy = proxy_handle.lock().await;
+    let config = match proxy.as_ref().filter(|p| !p.handle.is_finished()) {
+        Some(running) => running.config.clone(),
+        None => api_proxy::ProxyConfig::from_env(),
+    };
+    Ok(config.effective_fields())
+}
+
+/// Send a message through the API proxy (direct call, no server needed)
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn api_proxy_send_message(
+    model: String,
+    messages: Vec<serde_json::Value>,
+    max_tokens: Option<u32>,
+    system: Option<String>,
+    temperature: Option<f32>,
+    preferred_provider: Option<String>,
+    big_model: Option<String>,
+    small_model: Option<String>,
+) -> Result<serde_json::Value, String> {
+    // Build configuration
+    let config = api_proxy::ProxyConfig {
+        preferred_provider: preferred_provider
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(api_proxy::types::Provider::OpenAI),
+        big_model: big_model.unwrap_or_else(|| "gpt-4.1".to_string()),
+        small_model: small_model.unwrap_or_else(|| "gpt-4.1-mini".to_string()),
+        // API keys, base URL and other settings come from the environment
+        ..api_proxy::ProxyConfig::from_env()
+    };
+
+    let client = api_proxy::ApiClient::try_new(config).map_err(|e| e.to_string())?;
+
+    // Convert messages from JSON to proper type
+    let parsed_messages: Vec<api_proxy::Message> = messages
+        .into_iter()
+        .map(serde_json::from_value)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to parse messages: {}", e))?;
+
+    // Build request
+    let request = api_proxy::MessagesRequest {
+        model,
+        max_tokens: max_tokens.unwrap_or(4096),
+        messages: parsed_messages,
+        system: system.map(api_proxy::types::SystemContent::Text),
+        stop_sequences: None,
+        stream: false,
+        temperature,
+        top_p: None,
+        top_k: None,
+        metadata: None,
+        tools: None,
+        tool_choice: None,
+        thinking: None,
+        logprobs: None,
+        top_logprobs: None,
+    };
+
+    // Send request
+    let response = client
+        .send_message(&request)
+        .await
+        .map_err(|e| format!("API request failed: {}", e))?;
+
+    // Convert to JSON
+    serde_json::to_value(response).map_err(|e| format!("Failed to serialize response: {}", e))
+}
+
+/// Verify an API key without making a billable call (lists models)
+#[tauri::command]
+async fn verify_api_key(
+    provider: String,
+    api_key: String,
+    base_url: Option<String>,
+) -> Result<api_proxy::KeyStatus, String> {
+    Ok(api_proxy::verify_api_key(&provider, &api_key, base_url.as_deref()).await)
+}
+
+/// Get the mapped model name for a given Claude model
+#[tauri::command]
+fn get_mapped_model(
+    model: String,
+    preferred_provider: Option<String>,
+    big_model: Option<String>,
+    small_model: Option<String>,
+) -> Result<serde_json::Value, String> {
+    let config = api_proxy::ProxyConfig {
+        preferred_provider: preferred_provider
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(api_proxy::types::Provider::OpenAI),
+        big_model: big_model.unwrap_or_else(|| "gpt-4.1".to_string()),
+        small_model: small_model.unwrap_or_else(|| "gpt-4.1-mini".to_string()),
+        ..Default::default()
+    };
+
+    let mapped = api_proxy::map_model(&model, &config);
+
+    Ok(serde_json::json!({
+        "original": model,
+        "provider": mapped.provider,
+        "model": mapped.model,
+        "full_name": mapped.full_name
+    }))
+}
+
+// ============================================================================
+// Preview Window Command
+// ============================================================================
+
+/// Open external URL in a new Tauri window (bypasses iframe X-Frame-Options restrictions)
+#[tauri::command]
+async fn open_preview_window(
+    app: tauri::AppHandle,
+    url: String,
+    title: Option<String>,
+) -> Result<(), String> {
+    use tauri::WebviewWindowBuilder;
+    use tauri::WebviewUrl;
+
+    println!("[open_preview_window] Opening URL: {}", url);
+
+    // Parse URL
+    let parsed_url: url::Url = url.parse()
+        .map_err(|e| format!("Invalid URL: {}", e))?;
+
+    // Generate unique window label
+    let window_label = format!("preview-{}", std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis());
+
+    // Window title
+    let window_title = title.unwrap_or_else(|| {
+        parsed_url.host_str()
+            .map(|h| format!("Preview - {}", h))
+            .unwrap_or_else(|| "Preview".to_string())
+    });
+
+    // Create new window
+    WebviewWindowBuilder::new(&app, &window_label, WebviewUrl::External(parsed_url))
+        .title(&window_title)
+        .inner_size(1200.0, 800.0)
+        .center()
+        .build()
+        .map_err(|e| format!("Failed to create preview window: {}", e))?;
+
+    println!("[open_preview_window] Window created: {}", window_label);
+    Ok(())
+}
+
+// ============================================================================
+// CLI Execution Commands
+// ============================================================================
+
+/// Execute Claude Code CLI with streaming output; returns the session id for cancel_claude_execution
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn execute_claude_streaming(
+    app: tauri::AppHandle,
+    cli_sessions: tauri::State<'_, CliSessionMap>,
+    node_path: String,
+    claude_path: String,
+    args: Vec<String>,
+    cwd: String,
+    env: Option<HashMap<String, String>>,
+    env_clear: Option<bool>,
+) -> Result<String, String> {
+    let args = std::iter::once(claude_path).chain(args).collect();
+    let env = CliEnv { vars: env.unwrap_or_default(), clear: env_clear.unwrap_or(false) };
+    execute_cli_streaming(app, cli_sessions.inner(), node_path, args, cwd, env, "claude")
+}
+
+/// Stop a running Claude (or Kiro) CLI session; `{prefix}-complete` is still emitted with `false`
+#[tauri::command]
+fn cancel_claude_execution(
+    cli_sessions: tauri::State<'_, CliSessionMap>,
+    session_id: String,
+) -> Result<(), String> {
+    let cancel = cli_sessions
+        .lock()
+        .map_err(|e| format!("Lock error: {}", e))?
+        .get(&session_id)
+        .cloned()
+        .ok_or_else(|| format!("No running execution {}", session_id))?;
+    // notify_one keeps the permit if the session task is not waiting yet
+    cancel.notify_one();
+    println!("[cancel_claude_execution] Cancelling session {}", session_id);
+    Ok(())
+}
+
+/// Extra environment for a CLI run, e.g. `ANTHROPIC_BASE_URL` pointing at the proxy
+#[derive(Debug, Clone, Default)]
+struct CliEnv {
+    vars: HashMap<String, String>,
+    /// Start from an empty environment instead of inheriting the app's (PATH is still set)
+    clear: bool,
+}
+
+/// Output of a CLI run by `stream_cli`
+#[derive(Debug, Clone, PartialEq)]
+enum CliEvent {
+    Stdout(String),
+    Stderr(String),
+    Complete(bool),
+}
+
+/// Start a CLI with piped stdout/stderr. `label` is only used for logs and error messages.
+fn spawn_cli(
+    program: &str,
+    args: &[String],
+    cwd: &str,
+    env: &CliEnv,
+    label: &str,
+) -> Result<tokio::process::Child, String> {
+    println!("[{}] Starting: {}", label, quote_command_line(&[&[program.to_string()], args].concat()));
+    println!("[{}] CWD: {}", label, cwd);
+
+    // Create the command
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    cmd.current_dir(cwd);
+    if env.clear {
+        cmd.env_clear();
+    }
+    cmd.env("PATH", shell_path());
+    cmd.envs(&env.vars);
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    // Spawn the process
+    let child = cmd
+        .spawn()
+        .map_err(|e| format!("Failed to spawn {}: {}", label, e))?;
+    println!("[{}] Spawned process with PID: {}", label, child.id().unwrap_or(0));
+    Ok(child)
+}
+
+/// Report each stdout/stderr line of a CLI started by `spawn_cli`, and finally whether it
+/// succeeded. A `cancel` notification kills it, drops any unread output and reports `false`.
+async fn stream_cli(
+    mut child: tokio::process::Child,
+    label: &str,
+    cancel: Arc<tokio::sync::Notify>,
+    on_event: impl Fn(CliEvent) + Send + Sync + 'static,
+) -> Result<(), String> {
+    // Get stdout and stderr
+    let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
+    let stderr = child.stderr.take().ok_or("Failed to get stderr")?;
+
+    // Create readers
+    let stdout_reader = BufReader::new(stdout);
+    let stderr_reader = BufReader::new(stderr);
+    let on_event = Arc::new(on_event);
+
+    // Spawn task to read stdout
+    let stdout_events = Arc::clone(&on_event);
+    let stdout_label = label.to_string();
+    let stdout_task = tokio::spawn(async move {
+        let mut lines = stdout_reader.lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            println!("[{} stdout] {}", stdout_label, line);
+            stdout_events(CliEvent::Stdout(line));
+        }
+    });
+
+    // Spawn task to read stderr
+    let stderr_events = Arc::clone(&on_event);
+    let stderr_label = label.to_string();
+    let stderr_task = tokio::spawn(async move {
+        let mut lines = stderr_reader.lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            println!("[{} stderr] {}", stderr_label, line);
+            stderr_events(CliEvent::Stderr(line));
+        }
+    });
+
+    // Wait for the process to complete, or for a cancel request
+    let status = tokio::select! {
+        status = child.wait() => status,
+        _ = cancel.notified() => {
+            println!("[{}] Cancelled, killing process", label);
+            let _ = child.kill().await;
+            stdout_task.abort();
+            stderr_task.abort();
+            on_event(CliEvent::Complete(false));
+            return Ok(());
+        }
+    };
+
+    // Let the readers drain what the process wrote before exiting
+    let _ = tokio::join!(stdout_task, stderr_task);
+
+    match status {
+        Ok(status) => {
+            println!("[{}] Process exited with status: {}", label, status);
+            on_event(CliEvent::Complete(status.success()));
+            Ok(())
+        }
+        Err(e) => {
+            println!("[{}] Process wait failed: {}", label, e);
+            on_event(CliEvent::Complete(false));
+            Err(format!("Failed to wait for {} process: {}", label, e))
+        }
+    }
+}
+
+/// Start a CLI and stream its output to the frontend as `{prefix}-stream` (stdout lines),
+/// `{prefix}-error` (stderr lines) and `{prefix}-complete` (success bool). Returns once the
+/// process is spawned, with a session id that cancel_claude_execution accepts.
+fn execute_cli_streaming(
+    app: tauri::AppHandle,
+    cli_sessions: &CliSessionMap,
+    program: String,
+    args: Vec<String>,
+    cwd: String,
+    env: CliEnv,
+    event_prefix: &str,
+) -> Result<String, String> {
+    let child = spawn_cli(&program, &args, &cwd, &env, event_prefix)?;
+
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let cancel = Arc::new(tokio::sync::Notify::new());
+    cli_sessions
+        .lock()
+        .map_err(|e| format!("Lock error: {}", e))?
+        .insert(session_id.clone(), Arc::clone(&cancel));
+
+    let prefix = event_prefix.to_string();
+    let sessions = Arc::clone(cli_sessions);
+    let task_session_id = session_id.clone();
+    tokio::spawn(async move {
+        let label = prefix.clone();
+        let result = stream_cli(child, &label, cancel, move |event| {
+            let _ = match event {
+                CliEvent::Stdout(line) => app.emit(&format!("{}-stream", prefix), line),
+                CliEvent::Stderr(line) => app.emit(&format!("{}-error", prefix), line),
+                CliEvent::Complete(success) => app.emit(&format!("{}-complete", prefix), success),
+            };
+        })
+        .await;
+        if let Err(e) = result {
+            println!("[{}] {}", label, e);
+        }
+        if let Ok(mut sessions) = sessions.lock() {
+            sessions.remove(&task_session_id);
+        }
+    });
+
+    Ok(session_id)
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    // Set up panic hook to log panics
+    std::panic::set_hook(Box::new(|panic_info| {
+        eprintln!("PANIC: {:?}", panic_info);
+        if let Some(location) = panic_info.location() {
+            eprintln!("Panic occurred in file '{}' at line {}", location.file(), location.line());
+        }
+        if let Some(s) = panic_info.payload().downcast_ref::<&str>() {
+            eprintln!("Panic message: {}", s);
+        }
+    }));
+
+    tauri::Builder::default()
+        .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_store::Builder::new().build())
+        .manage(create_terminal_map())
+        .manage(create_pty_writer_map())
+        .manage(create_pty_master_map())
+        .manage(create_pty_child_map())
+        .manage(create_pty_meta_map())
+        .manage(create_blame_cache())
+        .manage(create_task_abort_map())
+        .manage(create_cli_session_map())
+        .manage(create_fs_watcher_map())
+        .manage(create_process_spec_map())
+        .manage(create_proxy_server_handle())
+        .invoke_handler(tauri::generate_handler![
+            extract_cli,
+            get_node_path,
+            get_kiro_path,
+            get_cli_path,
+            get_shell_path,
+            strip_ansi,
+            shell_quote,
+            read_directory,
+            watch_directory,
+            unwatch_directory,
+            read_file_content,
+            read_file_text,
+            read_file_bytes,
+            classify_files,
+            export_transcript,
+            highlight_file,
+            create_file,
+            create_directory,
+            delete_path,
+            rename_path,
+            copy_path,
+            hash_file,
+            download_file,
+            cancel_download,
+            save_file,
+            append_to_file,
+            write_files_transactional,
+            three_way_merge,
+            read_claude_skills,
+            read_agent_context,
+            list_archive_entries,
+            extract_archive_entry,
+            get_recent_projects,
+            touch_recent_project,
+            index_project_files_stream,
+            cancel_index,
+            search_files,
+            quick_file_count,
+            git_diff_stream,
+            cancel_git_diff_stream,
+            read_mcp_servers,
+            read_all_mcp_servers,
+            add_mcp_server,
+            update_mcp_server,
+            test_mcp_server,
+            remove_mcp_server,
+            toggle_mcp_server,
+            toggle_mcp_servers,
+            git_status,
+            git_is_clean,
+            git_log,
+            git_file_history,
+            git_file_diff,
+            git_diff,
+            git_commit,
+            git_unstage,
+            git_discard,
+            git_branches,
+            git_checkout,
+            git_push,
+            git_pull,
+            git_clone,
+            cancel_clone,
+            git_submodule_status,
+            git_submodule_update,
+            git_stash_show,
+            git_stash_list,
+            git_stash,
+            git_stash_pop,
+            git_merge_base,
+            git_commit_trailers,
+            git_notes,
+            git_blame_incremental,
+            git_hooks,
+            git_format_patch,
+            git_apply_mbox,
+            git_hunk_at_line,
+            git_line_changes,
+            git_graph,
+            tidy_gitignore,
+            execute_claude_streaming,
+            cancel_claude_execution,
+            execute_kiro_streaming,
+            start_background_process,
+            kill_process,
+            restart_process,
+            associate_terminal,
+            create_interactive_terminal,
+            close_terminal,
+            terminal_input,
+            terminal_resize,
+            terminal_signal,
+            list_terminals,
+            // API Proxy commands
+            start_api_proxy,
+            stop_api_proxy,
+            start_proxy,
+            stop_proxy,
+            proxy_status,
+            proxy_effective_config,
+            api_proxy_send_message,
+            get_mapped_model,
+            verify_api_key,
+            // Preview window command
+            open_preview_window
+        ])
+        .setup(|app| {
+            // Resolve the login-shell PATH off the main thread before any command needs it
+            std::thread::spawn(|| {
+                shell_path();
+            });
+
+            #[cfg(debug_assertions)]
+            {
+                let window = app.get_webview_window("main").unwrap();
+                window.open_devtools();
+            }
+            Ok(())
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scratch directory under the system temp dir, removed on drop so a failing
+    /// assertion doesn't leave it behind
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(label: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("voltcode-{}-{}", label, uuid::Uuid::new_v4().simple()));
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = std::path::Path;
+
+        fn deref(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<std::path::Path> for TempDir {
+        fn as_ref(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Empty file that counts as a command on PATH
+    fn write_executable(path: &std::path::Path) {
+        fs::write(path, "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    /// Run git with a throwaway identity, asserting that it succeeds; returns trimmed stdout
+    fn git_ok(dir: &std::path::Path, args: &[&str]) -> String {
+        let dir = dir.to_string_lossy();
+        let out = run_git(&dir, &[&["-c", "user.name=t", "-c", "user.email=t@t"], args].concat()).unwrap();
+        assert!(out.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&out.stderr));
+        String::from_utf8_lossy(&out.stdout).trim().to_string()
+    }
+
+    /// Fresh repository in a `TempDir`, with an identity configured so the commands under
+    /// test can create commits
+    struct TempRepo {
+        dir: TempDir,
+        project: String,
+    }
+
+    impl TempRepo {
+        fn new(label: &str) -> Self {
+            let dir = TempDir::new(label);
+            let project = dir.to_string_lossy().to_string();
+            git_ok(&dir, &["init", "-q"]);
+            git_ok(&dir, &["config", "user.name", "t"]);
+            git_ok(&dir, &["config", "user.email", "t@t"]);
+            TempRepo { dir, project }
+        }
+
+        /// Repository whose first commit ("init") adds `a.txt` containing "one\n"
+        fn with_commit(label: &str) -> Self {
+            let repo = Self::new(label);
+            repo.commit_file("a.txt", "one\n", "init");
+            repo
+        }
+
+        fn git(&self, args: &[&str]) -> String {
+            git_ok(&self.dir, args)
+        }
+
+        /// Run git without asserting success
+        fn try_git(&self, args: &[&str]) -> std::process::Output {
+            run_git(&self.project, args).unwrap()
+        }
+
+        fn commit_file(&self, name: &str, contents: &str, message: &str) {
+            fs::write(self.dir.join(name), contents).unwrap();
+            self.git(&["add", name]);
+            self.git(&["commit", "-q", "-m", message]);
+        }
+    }
+
+    impl std::ops::Deref for TempRepo {
+        type Target = std::path::Path;
+
+        fn deref(&self) -> &std::path::Path {
+            &self.dir
+        }
+    }
+
+    /// Handle that is already cancelled, for exercising the cancel paths
+    fn aborted() -> AbortHandle {
+        let abort = AbortHandle::default();
+        abort.abort();
+        abort
+    }
+
+    #[test]
+    fn test_tidy_gitignore_content() {
+        let input = "# Build\ntarget/\ntarget\n/node_modules\nnode_modules\n\n\n\n# Logs\n*.log  \n*.log\n!keep.log\n*.log\n\n";
+        let expected = "# Build\ntarget\nnode_modules\n\n# Logs\n*.log\n!keep.log\n*.log\n";
+        assert_eq!(tidy_gitignore_content(input), expected);
+
+        // Patterns are grouped within a block but never moved across a negation or comment
+        let input = "out.txt\n*.tmp\ndist/\n!dist/keep\nnotes.md\nbuild/\n# Editors\n.idea/\n*.swp\n";
+        let expected = "dist/\n*.tmp\nout.txt\n!dist/keep\nbuild/\nnotes.md\n# Editors\n.idea/\n*.swp\n";
+        assert_eq!(tidy_gitignore_content(input), expected);
+
+        assert_eq!(
+            tidy_gitignore_content("# Build\r\ntarget\r\ntarget\r\n\r\n\r\n*.log\r\n"),
+            "# Build\r\ntarget\r\n\r\n*.log\r\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_unified_diff() {
+        let diff = "diff --git a/src/main.rs b/src/main.rs
+index 1111111..2222222 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,3 +1,4 @@ fn main() {
+ line one
+-line two
++line 2
++line three
+ line four
+diff --git a/new.txt b/new.txt
+new file mode 100644
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++hello
+\\ No newline at end of file
+";
+        let files = parse_unified_diff(diff);
+        assert_eq!(files.len(), 2);
+
+        let main = &files[0];
+        assert_eq!(main.path, "src/main.rs");
+        assert_eq!(main.status, "modified");
+        assert_eq!(main.hunks.len(), 1);
+        let hunk = &main.hunks[0];
+        assert_eq!((hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines), (1, 3, 1, 4));
+        assert_eq!(hunk.lines.len(), 5);
+        assert_eq!(hunk.lines[1].kind, "removed");
+        assert_eq!(hunk.lines[1].old_line, Some(2));
+        assert_eq!(hunk.lines[3].kind, "added");
+        assert_eq!(hunk.lines[3].new_line, Some(3));
+        assert_eq!(hunk.lines[4].old_line, Some(3));
+        assert_eq!(hunk.lines[4].new_line, Some(4));
+
+        let new_file = &files[1];
+        assert_eq!(new_file.path, "new.txt");
+        assert_eq!(new_file.status, "added");
+        assert_eq!(new_file.old_path, None);
+        assert_eq!(new_file.hunks[0].lines.len(), 1);
+    }
+
+    #[test]
+    fn test_hunk_contains_line() {
+        let diff = "diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -1,4 +1,4 @@
+ one
+-two
++TWO
+ three
+ four
+@@ -10,3 +10,2 @@
+ ten
+-eleven
+ twelve
+";
+        let files = parse_unified_diff(diff);
+        let hunks = &files[0].hunks;
+        assert!(hunk_contains_line(&hunks[0], 2));
+        assert!(!hunk_contains_line(&hunks[0], 1));
+        assert!(!hunk_contains_line(&hunks[0], 3));
+        // Deleted line 11 sits where "twelve" now is
+        assert!(hunk_contains_line(&hunks[1], 11));
+        assert!(!hunk_contains_line(&hunks[1], 10));
+
+        let (header, raw) = split_diff_hunks(diff);
+        assert!(header.ends_with("+++ b/a.txt\n"));
+        assert_eq!(raw.len(), 2);
+        assert!(raw[1].starts_with("@@ -10,3 +10,2 @@"));
+    }
+
+    #[test]
+    fn test_walk_project_files_respects_gitignore() {
+        let dir = TempDir::new("walk");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(dir.join("src/main.rs"), "").unwrap();
+        fs::write(dir.join("target/out.bin"), "").unwrap();
+        fs::write(dir.join("debug.log"), "").unwrap();
+
+        let abort = AbortHandle::default();
+        let mut files = walk_project_files(&dir, &abort, |_| {});
+        files.sort();
+
+        assert_eq!(files, vec![".gitignore".to_string(), "src/main.rs".to_string()]);
+    }
+
+    #[test]
+    fn test_quick_file_count() {
+        let dir = TempDir::new("quick-count");
+        fs::write(dir.join(".gitignore"), "*.log\n").unwrap();
+        fs::write(dir.join("a.txt"), "").unwrap();
+        fs::write(dir.join("b.txt"), "").unwrap();
+        fs::write(dir.join("debug.log"), "").unwrap();
+
+        let count = quick_file_count(dir.to_string_lossy().to_string()).unwrap();
+        assert_eq!((count.count, count.capped), (3, false));
+
+        // Exactly at the cap is still an exact count; one more file is "cap+"
+        let at_cap = count_files_capped(&dir, 3);
+        assert_eq!((at_cap.count, at_cap.capped), (3, false));
+        let over = count_files_capped(&dir, 2);
+        assert_eq!((over.count, over.capped), (2, true));
+
+        // In a git repository untracked but not ignored files count too
+        let repo = TempRepo::with_commit("quick-count-git");
+        fs::write(repo.join("new.txt"), "").unwrap();
+        let count = quick_file_count(repo.project.clone()).unwrap();
+        assert_eq!((count.count, count.capped), (2, false));
+    }
+
+    #[test]
+    fn test_extract_archive_entry() {
+        use std::io::Write;
+
+        let dir = TempDir::new("extract-entry");
+        let zip_path = dir.join("bundle.zip");
+        let mut writer = zip::ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        writer.start_file("./a.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        writer.write_all(b"alpha").unwrap();
+        writer.finish().unwrap();
+        let zip_path = zip_path.to_string_lossy().to_string();
+
+        let dest = dir.join("out");
+        let dest_str = dest.to_string_lossy().to_string();
+        // "./a.txt" in the archive and "a.txt" name the same entry
+        let extracted = extract_archive_entry(zip_path.clone(), "a.txt".into(), dest_str.clone()).unwrap();
+        assert_eq!(PathBuf::from(&extracted), dest.join("a.txt"));
+        assert_eq!(fs::read_to_string(&extracted).unwrap(), "alpha");
+
+        // An existing file is never overwritten
+        fs::write(&extracted, "mine").unwrap();
+        let err = extract_archive_entry(zip_path.clone(), "./a.txt".into(), dest_str.clone()).unwrap_err();
+        assert!(err.starts_with("Target path already exists"), "{}", err);
+        assert_eq!(fs::read_to_string(&extracted).unwrap(), "mine");
+
+        assert!(extract_archive_entry(zip_path.clone(), "../a.txt".into(), dest_str.clone()).is_err());
+        assert!(extract_archive_entry(zip_path, "b.txt".into(), dest_str.clone()).is_err());
+
+        // A truncated tar.gz fails mid-entry without leaving a partial file
+        let tar_path = dir.join("bundle.tar.gz");
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::none()));
+        let data: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "./big.bin", data.as_slice()).unwrap();
+        let gz = builder.into_inner().unwrap().finish().unwrap();
+        fs::write(&tar_path, &gz[..gz.len() / 2]).unwrap();
+
+        let empty = dir.join("empty");
+        fs::create_dir(&empty).unwrap();
+        let err = extract_archive_entry(
+            tar_path.to_string_lossy().to_string(),
+            "big.bin".into(),
+            empty.to_string_lossy().to_string(),
+        )
+        .unwrap_err();
+        assert!(err.starts_with("Failed to extract entry"), "{}", err);
+        assert_eq!(fs::read_dir(&empty).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_walk_project_files_reports_batches() {
+        let dir = TempDir::new("walk-batches");
+        for i in 0..1200 {
+            fs::write(dir.join(format!("f{}.txt", i)), "").unwrap();
+        }
+
+        let mut batches: Vec<Vec<String>> = Vec::new();
+        let files = walk_project_files(&dir, &AbortHandle::default(), |batch| batches.push(batch.to_vec()));
+        assert_eq!(files.len(), 1200);
+        // Every full batch is reported once, in walk order; the remainder only in the result
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), vec![500, 500]);
+        assert_eq!(batches.concat(), files[..1000]);
+    }
+
+    #[test]
+    fn test_assign_graph_lanes() {
+        // d merges c (feature) into b; both descend from a
+        let commit = |hash: &str, parents: &[&str]| {
+            (hash.to_string(), parents.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+        };
+        let commits = vec![
+            commit("d", &["b", "c"]),
+            commit("c", &["a"]),
+            commit("b", &["a"]),
+            commit("a", &[]),
+        ];
+        let (lanes, width) = assign_graph_lanes(&commits);
+        assert_eq!(lanes, vec![0, 1, 0, 0]);
+        assert_eq!(width, 2);
+    }
+
+    #[test]
+    fn test_safe_entry_path_rejects_traversal() {
+        assert!(safe_entry_path("bin/tool").is_ok());
+        assert!(safe_entry_path("./README.md").is_ok());
+        assert!(safe_entry_path("../evil").is_err());
+        assert!(safe_entry_path("bin/../../evil").is_err());
+        assert!(safe_entry_path("/etc/passwd").is_err());
+        assert!(safe_entry_path("").is_err());
+    }
+
+    #[test]
+    fn test_line_changes_from_hunks() {
+        let diff = "diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -2 +2,2 @@
+-two
++TWO
++two and a half
+@@ -5,2 +5,0 @@
+-five
+-six
+@@ -9,0 +8 @@
++eight
+";
+        let files = parse_unified_diff(diff);
+        let changes = line_changes_from_hunks(&files[0].hunks);
+        assert_eq!(changes, vec![
+            LineChange { line: 2, kind: LineChangeKind::Modified },
+            LineChange { line: 3, kind: LineChangeKind::Added },
+            LineChange { line: 5, kind: LineChangeKind::Deleted },
+            LineChange { line: 8, kind: LineChangeKind::Added },
+        ]);
+    }
+
+    #[test]
+    fn test_git_line_changes() {
+        let repo = TempRepo::with_commit("line-changes");
+        let project = repo.project.clone();
+
+        // Staged edits still count: the diff is against HEAD, not the index
+        fs::write(repo.join("a.txt"), "ONE
+two
+").unwrap();
+        repo.git(&["add", "a.txt"]);
+        assert_eq!(git_line_changes(project.clone(), "a.txt".to_string()).unwrap(), vec![
+            LineChange { line: 1, kind: LineChangeKind::Modified },
+            LineChange { line: 2, kind: LineChangeKind::Added },
+        ]);
+        assert!(git_line_changes(project.clone(), "missing.txt".to_string()).unwrap().is_empty());
+
+        // Before the first commit everything staged is new
+        let empty = TempRepo::new("line-changes-unborn");
+        fs::write(empty.join("b.txt"), "x
+y
+").unwrap();
+        empty.git(&["add", "b.txt"]);
+        assert_eq!(git_line_changes(empty.project.clone(), "b.txt".to_string()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_classify_files() {
+        let dir = TempDir::new("classify");
+        fs::write(dir.join("a.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.join("empty"), "").unwrap();
+        fs::write(dir.join("blob.bin"), [0x7F, b'E', b'L', b'F', 0, 0, 1]).unwrap();
+        fs::write(dir.join("logo.png"), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        fs::write(dir.join("bmw.txt"), "BMW notes: service due in May\n").unwrap();
+        let mut bmp = b"BM\x3a\0\0\0\0\0\0\0\x36\0\0\0".to_vec();
+        bmp.resize(0x3a, 0);
+        fs::write(dir.join("pixel.bmp"), bmp).unwrap();
+
+        let paths = ["a.rs", "empty", "blob.bin", "logo.png", "bmw.txt", "pixel.bmp", "gone.txt"]
+            .iter()
+            .map(|name| dir.join(name).to_string_lossy().to_string())
+            .collect();
+        let kinds: Vec<FileKind> = classify_files(paths).into_iter().map(|c| c.kind).collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                FileKind::Text,
+                FileKind::Empty,
+                FileKind::Binary,
+                FileKind::Image,
+                FileKind::Text,
+                FileKind::Image,
+                FileKind::Missing,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_file_writes_rolls_back_on_failure() {
+        let dir = TempDir::new("tx");
+        fs::write(dir.join("a.txt"), "old").unwrap();
+
+        let writes = vec![
+            FileWrite { path: dir.join("a.txt").to_string_lossy().to_string(), content: "new".to_string() },
+            FileWrite { path: dir.join("b.txt").to_string_lossy().to_string(), content: "created".to_string() },
+            FileWrite { path: dir.join("missing/c.txt").to_string_lossy().to_string(), content: "x".to_string() },
+        ];
+        let err = apply_file_writes(&writes).unwrap_err();
+        let a = fs::read_to_string(dir.join("a.txt")).unwrap();
+        let b_exists = dir.join("b.txt").exists();
+
+        assert!(err.path.ends_with("c.txt"));
+        assert!(err.rolled_back);
+        assert_eq!(a, "old");
+        assert!(!b_exists);
+    }
+
+    #[test]
+    fn test_merge_three_way() {
+        let base = "a\nb\nc\nd\ne\n";
+
+        // Edits to different regions merge cleanly
+        let result = merge_three_way(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\nf\n");
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.merged, "A\nb\nc\nd\nE\nf\n");
+
+        // Identical changes on both sides are not a conflict
+        let result = merge_three_way(base, "a\nB\nc\nd\ne\n", "a\nB\nc\nd\ne\n");
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.merged, "a\nB\nc\nd\ne\n");
+
+        // Both sides changing the same line conflicts
+        let result = merge_three_way(base, "a\nb\nours\nd\ne\n", "a\nb\ntheirs\nd\ne\n");
+        assert_eq!(
+            result.conflicts,
+            vec![ConflictRegion {
+                line: 3,
+                ours: "ours\n".to_string(),
+                base: "c\n".to_string(),
+                theirs: "theirs\n".to_string(),
+            }]
+        );
+        assert_eq!(
+            result.merged,
+            "a\nb\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\nd\ne\n"
+        );
+    }
+
+    #[test]
+    fn test_utf8_carry_joins_split_characters() {
+        let bytes = "终端 ok".as_bytes();
+        let mut utf8 = Utf8Carry::default();
+        // Split inside the first 3-byte character
+        assert_eq!(utf8.decode(&bytes[..2]), "");
+        assert_eq!(utf8.decode(&bytes[2..4]), "终");
+        assert_eq!(utf8.decode(&bytes[4..]), "端 ok");
+
+        // Invalid bytes are still replaced rather than held forever
+        assert_eq!(utf8.decode(&[b'a', 0xFF, b'b']), "a\u{FFFD}b");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_default_shell_command_is_login_shell() {
+        let cmd = default_shell_command();
+        assert_eq!(cmd.get_argv().get(1).map(|a| a.as_os_str()), Some(std::ffi::OsStr::new("-l")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_default_shell_command_has_no_login_flag() {
+        let cmd = default_shell_command();
+        assert!(cmd.get_argv().iter().all(|a| a != "-l"));
+    }
+
+    #[test]
+    fn test_strip_ansi_codes() {
+        assert_eq!(strip_ansi_codes("\x1b[1;32mgreen\x1b[0m plain"), "green plain");
+        // OSC title (BEL-terminated) and OSC 8 hyperlink (ST-terminated) around colored text
+        assert_eq!(
+            strip_ansi_codes("\x1b]0;title\x07\x1b]8;;https://example.com\x1b\\\x1b[4mlink\x1b[24m\x1b]8;;\x1b\\!"),
+            "link!"
+        );
+        // A CSI cut short by another escape doesn't swallow the text after it
+        assert_eq!(strip_ansi_codes("\x1b[31\x1b[0mok"), "ok");
+        assert_eq!(strip_ansi_codes("\x1b[38;5;196mred\x1b(Bdone\x1b"), "reddone");
+        // 8-bit CSI and unterminated OSC at the end of a capture
+        assert_eq!(strip_ansi_codes("a\u{9b}2Kb\x1b]2;never closed"), "ab");
+        assert_eq!(strip_ansi("no escapes".to_string()), "no escapes");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pty_exit_waiter_reports_exit_code() {
+        let pair = native_pty_system()
+            .openpty(PtySize { rows: 24, cols: 80, pixel_width: 0, pixel_height: 0 })
+            .unwrap();
+        let mut cmd = CommandBuilder::new("sh");
+        cmd.args(["-c", "exit 3"]);
+        let child = pair.slave.spawn_command(cmd).unwrap();
+        drop(pair.slave);
+
+        let (_output_done_tx, output_done_rx) = std::sync::mpsc::channel();
+        let (exit_tx, exit_rx) = std::sync::mpsc::channel();
+        drop(_output_done_tx);
+        spawn_pty_exit_waiter(child, output_done_rx, move |code| {
+            let _ = exit_tx.send(code);
+        });
+
+        let code = exit_rx.recv_timeout(std::time::Duration::from_secs(10)).unwrap();
+        assert_eq!(code, Some(3));
+    }
+
+    // PtyProcess needs a real ChildKiller on Windows
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_list_terminals_reports_and_prunes_exited() {
+        let writers = create_pty_writer_map();
+        let masters = create_pty_master_map();
+        let children = create_pty_child_map();
+        let meta = create_pty_meta_map();
+        for id in ["term-b", "term-a"] {
+            let writer: PtyWriter = Box::new(std::io::sink());
+            writers.lock().await.insert(id.to_string(), Arc::new(Mutex::new(writer)));
+            meta.lock().unwrap().insert(id.to_string(), TerminalMeta {
+                cwd: Some("/tmp".to_string()),
+                title: None,
+                rows: 24,
+                cols: 80,
+            });
+        }
+        // Only term-a's shell is still running
+        children.lock().unwrap().insert("term-a".to_string(), PtyProcess { pid: Some(1) });
+
+        let listed = collect_terminals(&writers, &masters, &children, &meta).await.unwrap();
+        let summary: Vec<(&str, bool)> =
+            listed.iter().map(|t| (t.terminal_id.as_str(), t.alive)).collect();
+        assert_eq!(summary, vec![("term-a", true), ("term-b", false)]);
+        assert_eq!(listed[0].cwd.as_deref(), Some("/tmp"));
+        assert_eq!((listed[0].rows, listed[0].cols), (24, 80));
+
+        let listed = collect_terminals(&writers, &masters, &children, &meta).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(!meta.lock().unwrap().contains_key("term-b"));
+    }
+
+    #[test]
+    fn test_parse_submodule_status_line() {
+        assert_eq!(
+            parse_submodule_status_line("+1a2b3c libs/my lib (heads/main)"),
+            Some(('+', "1a2b3c".to_string(), "libs/my lib".to_string()))
+        );
+        assert_eq!(
+            parse_submodule_status_line("-1a2b3c vendor/not yet"),
+            Some(('-', "1a2b3c".to_string(), "vendor/not yet".to_string()))
+        );
+        assert_eq!(parse_submodule_status_line(""), None);
+    }
+
+    #[test]
+    fn test_parse_osc_updates() {
+        let output = "\x1b]0;old\x07prompt\x1b]2;vim main.rs\x1b\\\x1b]7;file://host/home/me/src\x07$ ";
+        assert_eq!(
+            parse_osc_updates(output),
+            (Some("vim main.rs".to_string()), Some("/home/me/src".to_string()))
+        );
+        assert_eq!(parse_osc_updates("plain output"), (None, None));
+        assert_eq!(
+            parse_osc_updates("\x1b]7;file://host/home/me/My%20Project/caf%C3%A9\x07"),
+            (None, Some("/home/me/My Project/café".to_string()))
+        );
+        assert_eq!(percent_decode("100%zz%2"), "100%zz%2");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_terminate_process_escalates_to_sigkill() {
+        // The ignored disposition survives exec, so sleep itself ignores SIGTERM
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "trap '' TERM; exec sleep 30"])
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        // Reap in the background so the pid disappears instead of lingering as a zombie
+        let reaper = std::thread::spawn(move || child.wait().unwrap());
+
+        let outcome = terminate_process(pid, std::time::Duration::from_millis(300))
+            .await
+            .unwrap();
+        assert_eq!(outcome, KillOutcome::Killed);
+
+        let status = reaper.join().unwrap();
+        use std::os::unix::process::ExitStatusExt;
+        assert_eq!(status.signal(), Some(nix::sys::signal::Signal::SIGKILL as i32));
+
+        let again = terminate_process(pid, std::time::Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert_eq!(again, KillOutcome::NotFound);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_terminate_process_kills_process_group() {
+        use std::io::BufRead;
+        use std::os::unix::process::CommandExt;
+
+        // Same setup as spawn_background_process: the shell leads its own group and forks a child
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "sleep 30 & echo $!; wait"])
+            .stdout(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let mut line = String::new();
+        std::io::BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+        let grandchild: i32 = line.trim().parse().unwrap();
+        let reaper = std::thread::spawn(move || child.wait().unwrap());
+
+        let outcome = terminate_process(pid, std::time::Duration::from_secs(2)).await.unwrap();
+        assert_ne!(outcome, KillOutcome::NotFound);
+        reaper.join().unwrap();
+
+        // The orphaned sleep may linger as a zombie until init reaps it, but must not be running
+        let gone = || {
+            std::fs::read_to_string(format!("/proc/{}/stat", grandchild))
+                .map(|stat| stat.rsplit(") ").next().is_some_and(|rest| rest.starts_with('Z')))
+                .unwrap_or(true)
+        };
+        let started = std::time::Instant::now();
+        while !gone() && started.elapsed() < std::time::Duration::from_secs(2) {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        assert!(gone(), "grandchild {} survived", grandchild);
+    }
+
+    #[test]
+    fn test_signal_unknown_terminal_errors() {
+        let children = create_pty_child_map();
+        let masters = create_pty_master_map();
+        let err = signal_terminal(&children, &masters, "missing", "SIGINT").unwrap_err();
+        assert_eq!(err, "Terminal missing not found");
+    }
+
+    #[test]
+    fn test_blame_parser() {
+        let output = "\
+a010802dafb7039db0ca09060028f068f34bf29d 2 2 1
+author Ada
+author-mail <ada@example.com>
+author-time 1700000100
+summary second
+previous 01bc748e5a8874cbff728ea705aa5806757fa08f f
+filename f
+01bc748e5a8874cbff728ea705aa5806757fa08f 1 1 1
+author Bob
+author-mail <bob@example.com>
+author-time 1700000000
+summary first
+boundary
+filename f
+01bc748e5a8874cbff728ea705aa5806757fa08f 3 3 2
+filename f
+";
+        let mut parser = BlameParser::default();
+        let mut hunks: Vec<BlameHunk> = output.lines().filter_map(|line| parser.push_line(line)).collect();
+        hunks.sort_by_key(|hunk| hunk.start_line);
+        assert_eq!(hunks.iter().map(|h| (h.start_line, h.line_count)).collect::<Vec<_>>(), vec![(1, 1), (2, 1), (3, 2)]);
+        assert_eq!(hunks[0].author, "Bob");
+        assert_eq!(hunks[1].author_mail, "ada@example.com");
+        // Repeated commits reuse the details printed with their first hunk
+        assert_eq!(hunks[2].summary, "first");
+        assert_eq!(hunks[2].author_time, 1700000000);
+    }
+
+    #[tokio::test]
+    async fn test_stream_blame() {
+        let repo = TempRepo::with_commit("blame");
+        let project = repo.project.clone();
+        repo.commit_file("a.txt", "one\ntwo\nthree\n", "add lines");
+
+        let mut streamed = Vec::new();
+        let hunks = stream_blame(&project, "a.txt", |hunk| streamed.push(hunk.clone())).await.unwrap();
+        assert_eq!(hunks.iter().map(|h| (h.start_line, h.line_count)).collect::<Vec<_>>(), vec![(1, 1), (2, 2)]);
+        assert_eq!((hunks[0].summary.as_str(), hunks[1].summary.as_str()), ("init", "add lines"));
+        // Every hunk was reported while git ran
+        streamed.sort_by_key(|hunk| hunk.start_line);
+        assert_eq!(streamed, hunks);
+
+        assert!(stream_blame(&project, "missing.txt", |_| {}).await.is_err());
+    }
+
+    #[test]
+    fn test_blame_cache_evicts_least_recently_used() {
+        let mut cache = HashMap::new();
+        let start = std::time::Instant::now();
+        let entry = |age: u64| BlameCacheEntry {
+            head: String::new(),
+            blob: String::new(),
+            hunks: Vec::new(),
+            last_used: start + std::time::Duration::from_secs(age),
+        };
+        for i in 0..BLAME_CACHE_MAX_ENTRIES {
+            insert_blame_cache(&mut cache, ("p".into(), format!("f{}", i)), entry(i as u64 + 1));
+        }
+        // f0 is the oldest, so it goes when one more file is cached
+        insert_blame_cache(&mut cache, ("p".into(), "new".into()), entry(1000));
+        assert_eq!(cache.len(), BLAME_CACHE_MAX_ENTRIES);
+        assert!(!cache.contains_key(&("p".to_string(), "f0".to_string())));
+        assert!(cache.contains_key(&("p".to_string(), "f1".to_string())));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_process_exit_waiter_reports_exit_code() {
+        let child = Command::new("sh").args(["-c", "exit 7"]).spawn().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        spawn_process_exit_waiter(child, Vec::new(), move |code| tx.send(code).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), Some(7));
+    }
+
+    #[test]
+    fn test_posix_quote() {
+        let args = ["ls", "-la", "my dir", "it's", "$HOME", "a\\b", ""];
+        assert_eq!(
+            args.iter().map(|a| posix_quote(a)).collect::<Vec<_>>().join(" "),
+            r#"ls -la 'my dir' 'it'\''s' '$HOME' 'a\b' ''"#
+        );
+    }
+
+    #[test]
+    fn test_windows_quote() {
+        assert_eq!(windows_quote("C:\\Program Files\\app"), r#"^"C:\Program Files\app^""#);
+        assert_eq!(windows_quote(r#"say "hi""#), r#"^"say \^"hi\^"^""#);
+        assert_eq!(windows_quote(r#"dir\"#), r#"dir\"#);
+        assert_eq!(windows_quote(r#"my dir\"#), r#"^"my dir\\^""#);
+        assert_eq!(windows_quote("a&b"), "a^&b");
+        assert_eq!(windows_quote("%PATH%!x!"), "^%PATH^%^!x^!");
+        assert_eq!(windows_quote("a & b|c"), r#"^"a ^& b^|c^""#);
+        assert_eq!(windows_quote(""), r#"^"^""#);
+    }
+
+    /// Run a CLI through spawn_cli/stream_cli and collect what it reports
+    async fn collect_cli_events(program: &str, args: &[&str], env: &CliEnv) -> Vec<CliEvent> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let child = spawn_cli(program, &args, ".", env, "test").unwrap();
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        stream_cli(child, "test", Arc::new(tokio::sync::Notify::new()), move |event| {
+            sink.lock().unwrap().push(event)
+        })
+        .await
+        .unwrap();
+        let events = events.lock().unwrap().clone();
+        events
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stream_cli_emits_output_and_completion() {
+        assert_eq!(
+            collect_cli_events("echo", &["hello"], &CliEnv::default()).await,
+            vec![CliEvent::Stdout("hello".to_string()), CliEvent::Complete(true)]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_spawn_cli_passes_env() {
+        std::env::set_var("VOLTCODE_TEST_INHERITED", "1");
+        let env = CliEnv {
+            vars: HashMap::from([("FOO".to_string(), "bar baz".to_string())]),
+            clear: false,
+        };
+        let script = ["-c", "echo $FOO; echo ${VOLTCODE_TEST_INHERITED:-unset}"];
+        let stdout = |events: Vec<CliEvent>| -> Vec<String> {
+            events
+                .into_iter()
+                .filter_map(|e| match e {
+                    CliEvent::Stdout(line) => Some(line),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert_eq!(stdout(collect_cli_events("sh", &script, &env).await), vec!["bar baz", "1"]);
+        let cleared = CliEnv { clear: true, ..env };
+        assert_eq!(stdout(collect_cli_events("sh", &script, &cleared).await), vec!["bar baz", "unset"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stream_cli_cancel_kills_process() {
+        let child = spawn_cli("sleep", &["30".to_string()], ".", &CliEnv::default(), "test").unwrap();
+        let cancel = Arc::new(tokio::sync::Notify::new());
+        // Cancelling before the stream starts waiting must still take effect
+        cancel.notify_one();
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let started = std::time::Instant::now();
+        stream_cli(child, "test", cancel, move |event| sink.lock().unwrap().push(event))
+            .await
+            .unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(*events.lock().unwrap(), vec![CliEvent::Complete(false)]);
+    }
+
+    #[test]
+    fn test_git_file_diff() {
+        let repo = TempRepo::with_commit("diff");
+        let project = repo.project.clone();
+
+        std::fs::write(repo.join("a.txt"), "one\nadded line\n").unwrap();
+        let patch = git_file_diff(project.clone(), "a.txt".into(), false).unwrap();
+        assert!(patch.lines().any(|l| l == "+added line"), "{}", patch);
+        assert_eq!(git_file_diff(project.clone(), "a.txt".into(), true).unwrap(), "");
+
+        repo.git(&["add", "a.txt"]);
+        let staged = git_file_diff(project.clone(), "a.txt".into(), true).unwrap();
+        assert!(staged.lines().any(|l| l == "+added line"), "{}", staged);
+
+        std::fs::write(repo.join("new.txt"), "fresh\n").unwrap();
+        let untracked = git_file_diff(project.clone(), "new.txt".into(), false).unwrap();
+        assert!(untracked.contains("--- /dev/null") && untracked.lines().any(|l| l == "+fresh"), "{}", untracked);
+    }
+
+    #[tokio::test]
+    async fn test_stream_diff_files() {
+        let repo = TempRepo::with_commit("diff-stream");
+        let project = repo.project.clone();
+        repo.commit_file("b.txt", "two\n", "add b");
+        fs::write(repo.join("a.txt"), "one\nmore\n").unwrap();
+        fs::write(repo.join("b.txt"), "changed\n").unwrap();
+
+        let mut files = Vec::new();
+        let done = stream_diff_files(&project, "HEAD", &AbortHandle::default(), |diff| files.push(diff.path)).await;
+        assert!(done.unwrap());
+        assert_eq!(files, vec!["a.txt", "b.txt"]);
+
+        assert!(!stream_diff_files(&project, "HEAD", &aborted(), |_| {}).await.unwrap());
+        assert!(stream_diff_files(&project, "no-such-ref", &AbortHandle::default(), |_| {}).await.is_err());
+
+        // An option-shaped base is rejected instead of reaching git
+        let target = repo.join("injected.txt");
+        let base = format!("--output={}", target.display());
+        assert!(stream_diff_files(&project, &base, &AbortHandle::default(), |_| {}).await.is_err());
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn test_parse_porcelain_line() {
+        assert_eq!(
+            parse_porcelain_line("R  src/old.rs -> src/new.rs"),
+            Some(('R', ' ', "src/new.rs".to_string(), Some("src/old.rs".to_string())))
+        );
+        assert_eq!(
+            parse_porcelain_line(" M \"my file.txt\""),
+            Some((' ', 'M', "my file.txt".to_string(), None))
+        );
+        assert_eq!(
+            parse_porcelain_line("R  \"a -> b.txt\" -> \"with \\\"quote\\\".txt\""),
+            Some(('R', ' ', "with \"quote\".txt".to_string(), Some("a -> b.txt".to_string())))
+        );
+        // Non-ASCII names are octal-escaped UTF-8
+        assert_eq!(
+            parse_porcelain_line("?? \"caf\\303\\251.md\""),
+            Some(('?', '?', "café.md".to_string(), None))
+        );
+        assert_eq!(parse_porcelain_line("M"), None);
+    }
+
+    #[test]
+    fn test_git_push_and_pull_with_local_remote() {
+        let root = TempDir::new("sync");
+        let (bare, work, other) = (root.join("remote.git"), root.join("work"), root.join("other"));
+        for dir in [&bare, &work] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let project = work.to_string_lossy().to_string();
+
+        git_ok(&bare, &["init", "-q", "--bare"]);
+        git_ok(&work, &["init", "-q"]);
+        git_ok(&work, &["remote", "add", "origin", &bare.to_string_lossy()]);
+        std::fs::write(work.join("a.txt"), "one\n").unwrap();
+        git_ok(&work, &["add", "a.txt"]);
+        git_ok(&work, &["commit", "-q", "-m", "first commit"]);
+
+        // No upstream yet: refused unless set_upstream is requested
+        assert!(git_push(project.clone(), None, None, false).unwrap_err().contains("no upstream"));
+        git_push(project.clone(), None, None, true).unwrap();
+        assert!(git_ok(&bare, &["log", "--all", "--oneline"]).contains("first commit"));
+
+        // A commit pushed from another clone shows up after pull
+        git_ok(&root, &["clone", "-q", &bare.to_string_lossy(), &other.to_string_lossy()]);
+        std::fs::write(other.join("b.txt"), "two\n").unwrap();
+        git_ok(&other, &["add", "b.txt"]);
+        git_ok(&other, &["commit", "-q", "-m", "second commit"]);
+        git_push(other.to_string_lossy().to_string(), None, None, false).unwrap();
+
+        git_pull(project.clone(), None, None).unwrap();
+        assert!(work.join("b.txt").exists());
+
+        // Option-like remotes and branches never reach git
+        let marker = root.join("pwned");
+        let cmd = format!("--receive-pack=touch {}", marker.display());
+        assert!(git_push(project.clone(), Some(cmd), None, false).unwrap_err().contains("Invalid remote name"));
+        let cmd = format!("--upload-pack=touch {}", marker.display());
+        assert!(git_pull(project.clone(), Some(cmd), None).unwrap_err().contains("Invalid remote name"));
+        assert!(git_pull(project.clone(), Some("origin".to_string()), Some("-x".to_string()))
+            .unwrap_err()
+            .contains("Invalid branch name"));
+        assert!(!marker.exists());
+    }
+
+    #[tokio::test]
+    async fn test_clone_repo_and_cancel() {
+        let source = TempRepo::with_commit("clone");
+        let project = source.project.clone();
+        let root = TempDir::new("clone-dest");
+
+        let mut lines = Vec::new();
+        let cloned = clone_repo(&project, &root.join("copy"), &AbortHandle::default(), |line| {
+            lines.push(line.to_string())
+        })
+        .await
+        .unwrap();
+        assert!(cloned);
+        assert!(!lines.is_empty());
+        assert_eq!(std::fs::read_to_string(root.join("copy/a.txt")).unwrap(), "one\n");
+
+        // A cancelled clone removes the directory it created
+        let cancelled = clone_repo(&project, &root.join("cancelled"), &aborted(), |_| {}).await.unwrap();
+        assert!(!cancelled);
+        assert!(!root.join("cancelled").exists());
+
+        assert!(clone_repo(&root.join("missing").to_string_lossy(), &root.join("failed"), &AbortHandle::default(), |_| {})
+            .await
+            .is_err());
+        assert!(!root.join("failed").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_to_and_cancel() {
+        let app = axum::Router::new().route("/file", axum::routing::get(|| async { "downloaded body" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let dir = TempDir::new("download");
+        let part = dir.join("file.part");
+
+        let mut last = None;
+        let done = download_to(&url, &part, &AbortHandle::default(), |received, total| last = Some((received, total)))
+            .await
+            .unwrap();
+        assert!(done);
+        assert_eq!(std::fs::read_to_string(&part).unwrap(), "downloaded body");
+        assert_eq!(last, Some((15, Some(15))));
+
+        assert!(!download_to(&url, &dir.join("cancelled.part"), &aborted(), |_, _| {}).await.unwrap());
+        assert!(download_to(&format!("{}-missing", url), &dir.join("missing.part"), &AbortHandle::default(), |_, _| {})
+            .await
+            .unwrap_err()
+            .contains("404"));
+
+        // An existing part file is never overwritten
+        std::fs::write(dir.join("taken.part"), "keep").unwrap();
+        assert!(download_to(&url, &dir.join("taken.part"), &AbortHandle::default(), |_, _| {}).await.is_err());
+        assert_eq!(std::fs::read_to_string(dir.join("taken.part")).unwrap(), "keep");
+    }
+
+    #[tokio::test]
+    async fn test_abort_handle_wakes_waiter() {
+        let abort = Arc::new(AbortHandle::default());
+        let waiter = tokio::spawn({
+            let abort = Arc::clone(&abort);
+            async move { abort.wait().await }
+        });
+        tokio::task::yield_now().await;
+        abort.abort();
+        tokio::time::timeout(std::time::Duration::from_secs(5), waiter).await.unwrap().unwrap();
+
+        // Waiting after the abort returns immediately
+        abort.wait().await;
+        assert!(abort.is_aborted());
+    }
+
+    #[test]
+    fn test_git_notes() {
+        let repo = TempRepo::with_commit("notes");
+        let project = repo.project.clone();
+
+        // No notes ref at all yet
+        assert_eq!(git_notes(project.clone(), "HEAD".into()).unwrap(), None);
+
+        repo.git(&["notes", "add", "-m", "reviewed", "HEAD"]);
+        assert_eq!(git_notes(project.clone(), "HEAD".into()).unwrap().as_deref(), Some("reviewed"));
+
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "second"]);
+        assert_eq!(git_notes(project.clone(), "HEAD".into()).unwrap(), None);
+        assert!(git_notes(project.clone(), "nope".into()).is_err());
+    }
+
+    #[test]
+    fn test_git_format_patch() {
+        let repo = TempRepo::with_commit("patch");
+        let project = repo.project.clone();
+        std::fs::write(repo.join("a.txt"), "two\n").unwrap();
+        repo.git(&["commit", "-q", "-am", "second"]);
+
+        // A relative dest_dir lands inside the project, where the returned paths point
+        let patches = git_format_patch(project.clone(), Some("HEAD~1".into()), "patches".into()).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert!(PathBuf::from(&patches[0]).starts_with(repo.join("patches")));
+        assert!(PathBuf::from(&patches[0]).is_file());
+
+        assert!(git_format_patch(project.clone(), Some("--stdout".into()), "patches".into()).is_err());
+    }
+
+    #[test]
+    fn test_git_is_clean() {
+        let repo = TempRepo::with_commit("is-clean");
+        let project = repo.project.clone();
+        assert!(git_is_clean(project.clone()).unwrap());
+
+        fs::write(repo.join("new.txt"), "untracked\n").unwrap();
+        assert!(!git_is_clean(project.clone()).unwrap());
+
+        let dir = TempDir::new("is-clean-plain");
+        assert_eq!(git_is_clean(dir.to_string_lossy().to_string()).unwrap_err(), "Not a git repository");
+
+        // An empty .git makes git itself fail; its stderr ends up in the error
+        fs::create_dir(dir.join(".git")).unwrap();
+        let err = git_is_clean(dir.to_string_lossy().to_string()).unwrap_err();
+        assert!(err.starts_with("Failed to get git status: "), "{}", err);
+        assert!(err.contains("not a git repository"), "{}", err);
+    }
+
+    #[test]
+    fn test_git_file_history_follows_renames() {
+        let repo = TempRepo::with_commit("file-history");
+        let project = repo.project.clone();
+        repo.commit_file("a.txt", "one\ntwo\n", "edit a");
+        repo.git(&["mv", "a.txt", "b.txt"]);
+        repo.git(&["commit", "-q", "-m", "rename a to b"]);
+
+        let messages = |follow| -> Vec<String> {
+            git_file_history(project.clone(), "b.txt".into(), 10, follow)
+                .unwrap()
+                .into_iter()
+                .map(|c| c.message)
+                .collect()
+        };
+        assert_eq!(messages(true), ["rename a to b", "edit a", "init"]);
+        assert_eq!(messages(false), ["rename a to b"]);
+
+        assert!(git_file_history(project.clone(), "missing.txt".into(), 10, true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_git_stash_round_trip() {
+        let repo = TempRepo::with_commit("stash");
+        let project = repo.project.clone();
+
+        assert_eq!(git_stash(project.clone(), None, false).unwrap_err(), "No local changes to save");
+
+        std::fs::write(repo.join("a.txt"), "one\ntwo\n").unwrap();
+        std::fs::write(repo.join("new.txt"), "untracked\n").unwrap();
+        git_stash(project.clone(), Some("wip edits".into()), true).unwrap();
+        assert!(git_is_clean(project.clone()).unwrap());
+        assert!(!repo.join("new.txt").exists());
+        // Still nothing to save when refs/stash already exists
+        assert_eq!(git_stash(project.clone(), None, false).unwrap_err(), "No local changes to save");
+
+        let entries = git_stash_list(project.clone()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].index, entries[0].message.as_str()), (0, "wip edits"));
+        assert!(!entries[0].branch.is_empty());
+
+        git_stash_pop(project.clone(), None).unwrap();
+        assert_eq!(std::fs::read_to_string(repo.join("a.txt")).unwrap(), "one\ntwo\n");
+        assert!(repo.join("new.txt").exists());
+        assert!(git_stash_list(project.clone()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_stash_list() {
+        let text = "stash@{0}\0On main: before rebase\nstash@{1}\0WIP on feature/x: 1a2b3c4 Add parser\n";
+        assert_eq!(
+            parse_stash_list(text),
+            vec![
+                StashEntry { index: 0, branch: "main".into(), message: "before rebase".into() },
+                StashEntry { index: 1, branch: "feature/x".into(), message: "1a2b3c4 Add parser".into() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_git_checkout_creates_and_switches_branches() {
+        let repo = TempRepo::with_commit("branch");
+        let project = repo.project.clone();
+        let original = git_status(project.clone()).unwrap().branch;
+
+        git_checkout(project.clone(), "feature/login".into(), true).unwrap();
+        assert_eq!(git_status(project.clone()).unwrap().branch, "feature/login");
+        assert!(git_checkout(project.clone(), "feature/login".into(), true).is_err());
+
+        // A change that the other branch would overwrite blocks the switch
+        std::fs::write(repo.join("a.txt"), "two\n").unwrap();
+        repo.git(&["commit", "-q", "-am", "change on feature"]);
+        std::fs::write(repo.join("a.txt"), "dirty\n").unwrap();
+        let err = git_checkout(project.clone(), original.clone(), false).unwrap_err();
+        assert!(err.contains("Local changes conflict"), "{}", err);
+        repo.git(&["checkout", "--", "a.txt"]);
+
+        git_checkout(project.clone(), original.clone(), false).unwrap();
+        let branches = git_branches(project.clone()).unwrap();
+        let current: Vec<_> = branches.iter().filter(|b| b.current).map(|b| b.name.as_str()).collect();
+        assert_eq!(current, vec![original.as_str()]);
+        assert!(branches.iter().any(|b| b.name == "feature/login" && !b.remote));
+
+        // A remote-tracking branch checks out as a local branch tracking it
+        let origin = TempRepo::with_commit("branch-origin");
+        origin.git(&["branch", "topic"]);
+        repo.git(&["remote", "add", "origin", &origin.project]);
+        repo.git(&["fetch", "-q", "origin"]);
+        git_checkout(project.clone(), "origin/topic".into(), false).unwrap();
+        assert_eq!(git_status(project.clone()).unwrap().branch, "topic");
+        assert_eq!(repo.git(&["rev-parse", "--abbrev-ref", "topic@{upstream}"]), "origin/topic");
+
+        // ...and switches to that local branch once it exists
+        git_checkout(project.clone(), original.clone(), false).unwrap();
+        git_checkout(project.clone(), "origin/topic".into(), false).unwrap();
+        assert_eq!(git_status(project.clone()).unwrap().branch, "topic");
+    }
+
+    #[test]
+    fn test_parse_branch_refs() {
+        let text = "refs/heads/main\0*\0main\nrefs/remotes/origin/HEAD\0 \0origin\nrefs/remotes/origin/main\0 \0origin/main\n";
+        assert_eq!(
+            parse_branch_refs(text),
+            vec![
+                GitBranch { name: "main".into(), remote: false, current: true },
+                GitBranch { name: "origin/main".into(), remote: true, current: false },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_git_commit_amend_and_author() {
+        let repo = TempRepo::with_commit("amend");
+        let project = repo.project.clone();
+
+        std::fs::write(repo.join("b.txt"), "two\n").unwrap();
+        git_commit(project.clone(), "add b wiht typo".into(), None, None, None).unwrap();
+        let parent = repo.git(&["rev-parse", "HEAD~1"]);
+
+        git_commit(project.clone(), "Add b".into(), Some(Vec::new()), Some(true), Some("Jane Doe <jane@example.com>".into()))
+            .unwrap();
+        assert_eq!(repo.git(&["log", "-1", "--format=%s|%an <%ae>"]), "Add b|Jane Doe <jane@example.com>");
+        assert_eq!(repo.git(&["rev-parse", "HEAD~1"]), parent);
+        assert_eq!(repo.git(&["rev-list", "--count", "HEAD"]), "2");
+
+        assert_eq!(git_commit(project.clone(), "again".into(), None, None, None).unwrap_err(), "Nothing to commit");
+    }
+
+    #[test]
+    fn test_git_unstage_and_discard() {
+        let repo = TempRepo::with_commit("unstage");
+        let project = repo.project.clone();
+
+        std::fs::write(repo.join("a.txt"), "two\n").unwrap();
+        repo.git(&["add", "a.txt"]);
+        let status = |path: &str| {
+            git_status(project.clone()).unwrap().files.into_iter().find(|f| f.path == path).unwrap()
+        };
+        assert!(status("a.txt").staged);
+
+        git_unstage(project.clone(), vec!["a.txt".into()]).unwrap();
+        let file = status("a.txt");
+        assert_eq!((file.status.as_str(), file.staged), ("M", false));
+
+        std::fs::write(repo.join("loose.txt"), "x\n").unwrap();
+        assert!(git_unstage(project.clone(), vec!["loose.txt".into()]).unwrap_err().contains("loose.txt"));
+
+        assert!(git_discard(project.clone(), vec!["a.txt".into()], false).is_err());
+        // A glob-looking name is taken literally and does not match a.txt
+        let err = git_discard(project.clone(), vec!["*.txt".into()], true).unwrap_err();
+        assert!(err.contains("Not tracked"), "{}", err);
+        assert!(git_unstage(project.clone(), vec![":(glob)*.txt".into()]).is_err());
+        assert_eq!(std::fs::read_to_string(repo.join("a.txt")).unwrap(), "two\n");
+
+        git_discard(project.clone(), vec!["a.txt".into()], true).unwrap();
+        assert_eq!(std::fs::read_to_string(repo.join("a.txt")).unwrap(), "one\n");
+
+        // A staged deletion is only in HEAD, not in the index
+        repo.git(&["rm", "-q", "a.txt"]);
+        assert!(status("a.txt").staged);
+        git_unstage(project.clone(), vec!["a.txt".into()]).unwrap();
+        let file = status("a.txt");
+        assert_eq!((file.status.as_str(), file.staged), ("D", false));
+    }
+
+    #[test]
+    fn test_git_status_reports_merge_conflicts() {
+        let repo = TempRepo::new("conflict");
+        let project = repo.project.clone();
+        repo.commit_file("a.txt", "base\n", "base");
+        let main = git_status(project.clone()).unwrap().branch;
+        assert_eq!(git_status(project.clone()).unwrap().operation, None);
+
+        repo.git(&["switch", "-q", "-c", "other"]);
+        repo.commit_file("a.txt", "theirs\n", "theirs");
+        repo.git(&["switch", "-q", &main]);
+        repo.commit_file("a.txt", "ours\n", "ours");
+        assert!(!repo.try_git(&["merge", "other"]).status.success());
+
+        let status = git_status(project.clone()).unwrap();
+        assert_eq!(status.operation.as_deref(), Some("merge"));
+        let file = status.files.iter().find(|f| f.path == "a.txt").unwrap();
+        assert!(file.conflicted && !file.staged);
+        assert_eq!(file.status, "U");
+    }
+
+    #[test]
+    fn test_read_directory_show_hidden() {
+        let dir = TempDir::new("hidden");
+        std::fs::create_dir_all(dir.join(".github")).unwrap();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join(".env"), "KEY=1\n").unwrap();
+        std::fs::write(dir.join("README.md"), "# hi\n").unwrap();
+        let directory = dir.to_string_lossy().to_string();
+
+        let names = |items: Vec<FileItem>| items.into_iter().map(|f| (f.name, f.is_hidden)).collect::<Vec<_>>();
+        assert_eq!(
+            names(read_directory(directory.clone(), None).unwrap()),
+            vec![("src".to_string(), false), ("README.md".to_string(), false)]
+        );
+        assert_eq!(
+            names(read_directory(directory.clone(), Some(true)).unwrap()),
+            vec![
+                (".github".to_string(), true),
+                ("src".to_string(), false),
+                (".env".to_string(), true),
+                ("README.md".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fs_watcher_reports_created_file() {
+        let dir = TempDir::new("watch");
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let watcher = spawn_fs_watcher(
+            &dir.to_string_lossy(),
+            true,
+            std::time::Duration::from_millis(50),
+            move |changes| {
+                let _ = tx.send(changes);
+            },
+        )
+        .unwrap();
+        std::fs::write(dir.join("created.txt"), "hi").unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let mut created = false;
+        while !created && std::time::Instant::now() < deadline {
+            if let Ok(changes) = rx.recv_timeout(std::time::Duration::from_millis(200)) {
+                created = changes.iter().any(|c| c.kind == "create" && c.path.ends_with("created.txt"));
+            }
+        }
+        assert!(created, "no create event for created.txt");
+
+        drop(watcher);
+    }
+
+    #[test]
+    fn test_search_tree() {
+        let root = TempDir::new("search");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {\n    let needle = 1;\n    // Needle again\n}\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "pub fn needles() {}\n").unwrap();
+        fs::write(root.join("notes.txt"), "a needle in the hay\n").unwrap();
+        fs::write(root.join("target/out.rs"), "needle\n").unwrap();
+        fs::write(root.join("blob.bin"), b"needle\x00\x01\x02").unwrap();
+
+        let search = |query: &str, options: SearchOptions| {
+            let regex = build_search_regex(query, &options).unwrap();
+            search_tree(&root, &regex, &options).unwrap()
+        };
+
+        // Case-insensitive by default; gitignored and binary files are skipped
+        assert_eq!(search("needle", SearchOptions::default()).len(), 4);
+
+        let exact = search("needle", SearchOptions { case_sensitive: true, whole_word: true, ..Default::default() });
+        assert_eq!(exact.len(), 2);
+        let main = exact.iter().find(|m| m.path.ends_with("main.rs")).unwrap();
+        assert_eq!((main.line_number, main.column), (2, 9));
+        assert_eq!(main.line_text, "    let needle = 1;");
+
+        let rust_only = search("needle", SearchOptions { include: vec!["*.rs".into()], ..Default::default() });
+        assert_eq!(rust_only.len(), 3);
+        let no_src = search("needle", SearchOptions { exclude: vec!["src".into()], ..Default::default() });
+        assert_eq!(no_src.len(), 1);
+
+        assert_eq!(search("needle", SearchOptions { include_ignored: true, ..Default::default() }).len(), 5);
+        assert_eq!(search(r"needles?\(", SearchOptions { regex: true, ..Default::default() }).len(), 1);
+        assert_eq!(search("needle", SearchOptions { max_results: Some(2), ..Default::default() }).len(), 2);
+        assert!(build_search_regex("(", &SearchOptions { regex: true, ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_read_file_text() {
+        let dir = TempDir::new("text");
+        let read = |name: &str, encoding: Option<&str>, max_bytes: Option<u64>| {
+            read_file_text(dir.join(name).to_string_lossy().to_string(), encoding.map(String::from), max_bytes).unwrap()
+        };
+
+        // UTF-16LE with BOM
+        let mut utf16 = vec![0xFF, 0xFE];
+        utf16.extend("héllo\n世界".encode_utf16().flat_map(|u| u.to_le_bytes()));
+        fs::write(dir.join("utf16.txt"), &utf16).unwrap();
+        let text = read("utf16.txt", None, None);
+        assert_eq!((text.content.as_str(), text.encoding.as_str()), ("héllo\n世界", "utf-16le"));
+        assert!(!text.truncated && !text.lossy);
+
+        // UTF-16BE without BOM
+        let utf16be: Vec<u8> = "plain ascii".encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+        fs::write(dir.join("utf16be.txt"), &utf16be).unwrap();
+        assert_eq!(read("utf16be.txt", None, None).encoding, "utf-16be");
+        assert_eq!(read("utf16be.txt", None, None).content, "plain ascii");
+
+        // Invalid UTF-8 falls back to latin-1, or decodes lossily when UTF-8 is forced
+        fs::write(dir.join("latin1.txt"), b"caf\xe9").unwrap();
+        let text = read("latin1.txt", None, None);
+        assert_eq!((text.content.as_str(), text.encoding.as_str()), ("café", "latin-1"));
+        let forced = read("latin1.txt", Some("utf-8"), None);
+        assert_eq!(forced.content, "caf\u{FFFD}");
+        assert!(forced.lossy);
+
+        // Larger than the limit: truncated without splitting a multi-byte character
+        fs::write(dir.join("big.txt"), "aé".repeat(100)).unwrap();
+        let text = read("big.txt", None, Some(4));
+        assert!(text.truncated);
+        assert_eq!((text.content.as_str(), text.size, text.encoding.as_str()), ("aéa", 300, "utf-8"));
+        assert!(!text.lossy);
+
+        assert!(read_file_text(dir.join("big.txt").to_string_lossy().to_string(), Some("ebcdic".into()), None).is_err());
+    }
+
+    #[test]
+    fn test_copy_path() {
+        let dir = TempDir::new("copy");
+        let path = |p: &str| dir.join(p).to_string_lossy().to_string();
+        fs::create_dir_all(dir.join("tree/a/b")).unwrap();
+        fs::write(dir.join("file.txt"), "hello").unwrap();
+        fs::write(dir.join("tree/top.txt"), "top").unwrap();
+        fs::write(dir.join("tree/a/b/deep.txt"), "deep").unwrap();
+
+        // Single file, into a parent that does not exist yet
+        copy_path(path("file.txt"), path("out/copy.txt"), None).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("out/copy.txt")).unwrap(), "hello");
+        assert!(dir.join("file.txt").exists());
+
+        // Existing destination needs overwrite
+        fs::write(dir.join("other.txt"), "other").unwrap();
+        assert!(copy_path(path("other.txt"), path("out/copy.txt"), None).is_err());
+        copy_path(path("other.txt"), path("out/copy.txt"), Some(true)).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("out/copy.txt")).unwrap(), "other");
+
+        // Nested directory tree
+        copy_path(path("tree"), path("tree-copy"), None).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("tree-copy/top.txt")).unwrap(), "top");
+        assert_eq!(fs::read_to_string(dir.join("tree-copy/a/b/deep.txt")).unwrap(), "deep");
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("top.txt", dir.join("tree/link")).unwrap();
+            copy_path(path("tree"), path("tree-copy"), Some(true)).unwrap();
+            assert_eq!(fs::read_link(dir.join("tree-copy/link")).unwrap(), PathBuf::from("top.txt"));
+        }
+
+        assert!(copy_path(path("tree"), path("tree/a/inner"), None).is_err());
+        assert!(copy_path(path("missing"), path("anywhere"), None).is_err());
+
+        // Overwriting a directory that contains the source is rejected and keeps the source
+        assert!(copy_path(path("tree/a"), path("tree"), Some(true)).is_err());
+        assert_eq!(fs::read_to_string(dir.join("tree/a/b/deep.txt")).unwrap(), "deep");
+
+        // A copy that fails midway leaves the existing destination untouched
+        #[cfg(unix)]
+        {
+            fs::create_dir_all(dir.join("bad")).unwrap();
+            fs::write(dir.join("bad/ok.txt"), "ok").unwrap();
+            let _listener = std::os::unix::net::UnixListener::bind(dir.join("bad/sock")).unwrap();
+            assert!(copy_path(path("bad"), path("tree-copy"), Some(true)).is_err());
+            assert_eq!(fs::read_to_string(dir.join("tree-copy/top.txt")).unwrap(), "top");
+            let leftovers: Vec<_> = fs::read_dir(&dir)
+                .unwrap()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_name().to_string_lossy().starts_with(".tree-copy."))
+                .collect();
+            assert!(leftovers.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_move_by_copy() {
+        let dir = TempDir::new("move");
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        fs::write(dir.join("file.txt"), "hello").unwrap();
+        fs::write(dir.join("src/nested/deep.txt"), "deep").unwrap();
+
+        move_by_copy(&dir.join("file.txt"), &dir.join("moved.txt")).unwrap();
+        assert!(!dir.join("file.txt").exists());
+        assert_eq!(fs::read_to_string(dir.join("moved.txt")).unwrap(), "hello");
+
+        move_by_copy(&dir.join("src"), &dir.join("dest")).unwrap();
+        assert!(!dir.join("src").exists());
+        assert_eq!(fs::read_to_string(dir.join("dest/nested/deep.txt")).unwrap(), "deep");
+
+        // A failed copy leaves nothing behind at the destination
+        assert!(move_by_copy(&dir.join("missing"), &dir.join("nowhere")).is_err());
+        assert!(!dir.join("nowhere").exists());
+    }
+
+    #[tokio::test]
+    async fn test_hash_file() {
+        let dir = TempDir::new("hash");
+        let file = dir.join("hello.txt");
+        fs::write(&file, "hello world\n").unwrap();
+        let path = file.to_string_lossy().to_string();
+
+        assert_eq!(
+            hash_file(path.clone(), None).await.unwrap(),
+            "a948904f2f0f479b8f8197694b30184b0d2ed1c1cd2a1ec0fb85d299a192a447"
+        );
+        assert_eq!(hash_file(path.clone(), Some("MD5".into())).await.unwrap(), "6f5902ac237024bdd0c176cb93063dc4");
+        assert!(hash_file(path, Some("crc32".into())).await.is_err());
+        assert!(hash_file(dir.to_string_lossy().to_string(), None).await.is_err());
+    }
+
+    #[test]
+    fn test_save_file_with_backup() {
+        let dir = TempDir::new("save");
+        let file = dir.join("main.rs");
+        let path = file.to_string_lossy().to_string();
+        fs::write(&file, "old").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o750)).unwrap();
+        }
+
+        save_file(path.clone(), "new".into(), Some(true)).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("main.rs.bak")).unwrap(), "old");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o750);
+        }
+
+        // Without backup the .bak is left alone, and no temp files remain
+        save_file(path, "newer".into(), None).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("main.rs.bak")).unwrap(), "old");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        // Saving through a symlink updates its target and keeps the link
+        #[cfg(unix)]
+        {
+            let link = dir.join("link.rs");
+            std::os::unix::fs::symlink(&file, &link).unwrap();
+            save_file(link.to_string_lossy().to_string(), "via link".into(), None).unwrap();
+            assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+            assert_eq!(fs::read_to_string(&file).unwrap(), "via link");
+        }
+    }
+
+    #[test]
+    fn test_create_file_overwrite() {
+        let dir = TempDir::new("create");
+        let file = dir.join("nested/new.txt");
+        let path = file.to_string_lossy().to_string();
+
+        create_file(path.clone(), Some("first".into()), None).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "first");
+
+        let err = create_file(path.clone(), Some("second".into()), Some(false)).unwrap_err();
+        assert!(err.contains("already exists"), "{}", err);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "first");
+
+        create_file(path, Some("second".into()), Some(true)).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "second");
+
+        assert!(create_file(dir.join("nested").to_string_lossy().to_string(), None, Some(true)).is_err());
+
+        // A dangling symlink is not followed to create its target
+        #[cfg(unix)]
+        {
+            let link = dir.join("link.txt");
+            std::os::unix::fs::symlink(dir.join("target.txt"), &link).unwrap();
+            let err = create_file(link.to_string_lossy().to_string(), Some("x".into()), None).unwrap_err();
+            assert!(err.contains("already exists"), "{}", err);
+            assert!(!dir.join("target.txt").exists());
+        }
+    }
+
+    #[test]
+    fn test_read_skill_info() {
+        let dir = TempDir::new("skill");
+        let skill = dir.join("pdf-tools");
+        fs::create_dir_all(&skill).unwrap();
+        fs::write(
+            skill.join("SKILL.md"),
+            "---\nname: PDF Processing\ndescription: \"Extract text and tables from PDFs\"\nallowed-tools: Read, Grep, Bash(python:*)\n---\n\n# PDF\n",
+        )
+        .unwrap();
+
+        let info = read_skill_info(&skill, "pdf-tools".into(), "user");
+        assert_eq!(info.name, "PDF Processing");
+        assert_eq!(info.description.as_deref(), Some("Extract text and tables from PDFs"));
+        assert_eq!(info.allowed_tools.unwrap(), vec!["Read", "Grep", "Bash(python:*)"]);
+        assert!(info.token_count.is_some());
+
+        // Block scalars and YAML lists
+        let parsed = parse_skill_frontmatter("---\ndescription: >\n  Folded across\n  two lines\nallowed-tools:\n  - Read\n  - 'Write'\n---\n");
+        assert_eq!(parsed.description.as_deref(), Some("Folded across two lines"));
+        assert_eq!(parsed.allowed_tools.unwrap(), vec!["Read", "Write"]);
+        assert_eq!(parse_skill_frontmatter("---\nallowed-tools: [Read, Glob]\n---\n").allowed_tools.unwrap(), vec!["Read", "Glob"]);
+
+        // Commas inside a tool's parentheses or quotes don't split it
+        let parsed = parse_skill_frontmatter("---\nallowed-tools: Read, Bash(git add:*, git commit:*), \"Edit(a, b)\"\n---\n");
+        assert_eq!(parsed.allowed_tools.unwrap(), vec!["Read", "Bash(git add:*, git commit:*)", "Edit(a, b)"]);
+        let parsed = parse_skill_frontmatter("---\nallowed-tools: [Bash(npm run:*, npm test:*), Grep]\n---\n");
+        assert_eq!(parsed.allowed_tools.unwrap(), vec!["Bash(npm run:*, npm test:*)", "Grep"]);
+
+        // No frontmatter: fall back to the directory name
+        fs::write(skill.join("SKILL.md"), "# Just markdown\nname: not frontmatter\n").unwrap();
+        let info = read_skill_info(&skill, "pdf-tools".into(), "user");
+        assert_eq!((info.name.as_str(), info.description, info.allowed_tools), ("pdf-tools", None, None));
+    }
+
+    #[test]
+    fn test_estimate_skill_tokens_is_recursive() {
+        let skill = TempDir::new("skilltok");
+        fs::create_dir_all(skill.join("references/deep")).unwrap();
+        let top = "# Skill\nUse the reference docs.\n";
+        let nested = "Detailed notes about the PDF format and its many quirks.\n";
+        fs::write(skill.join("SKILL.md"), top).unwrap();
+        let top_only = estimate_skill_tokens(&skill).unwrap();
+        assert_eq!(top_only, api_proxy::tokens::count_text_tokens(top) as u64);
+
+        fs::write(skill.join("references/deep/notes.md"), nested).unwrap();
+        fs::write(skill.join("references/logo.png"), b"\x89PNG\x00\x00\x00\rIHDR").unwrap();
+        assert_eq!(
+            estimate_skill_tokens(&skill).unwrap(),
+            top_only + api_proxy::tokens::count_text_tokens(nested) as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_agent_context_counts_tokens() {
+        let project = TempDir::new("agent-context");
+        let text = "Use pnpm, not npm. Run the tests before committing.\n";
+        fs::write(project.join("CLAUDE.md"), text).unwrap();
+
+        let files = read_agent_context(project.to_string_lossy().to_string()).await.unwrap();
+        let file = files.iter().find(|f| f.scope == "project").unwrap();
+        assert_eq!(file.content, text);
+        assert_eq!(file.token_estimate, api_proxy::tokens::count_text_tokens(text) as u64);
+    }
+
+    #[test]
+    fn test_merge_user_and_project_skills() {
+        let dir = TempDir::new("skills");
+        let user_dir = dir.join("home/.claude/skills");
+        let project_dir = dir.join("project/.claude/skills");
+        for skill in [user_dir.join("lint"), user_dir.join("deploy"), project_dir.join("deploy"), project_dir.join("release")] {
+            fs::create_dir_all(&skill).unwrap();
+            fs::write(skill.join("SKILL.md"), "# skill\n").unwrap();
+        }
+        fs::write(project_dir.join("deploy/SKILL.md"), "---\ndescription: Project deploy\n---\n").unwrap();
+
+        let skills = merge_skills(
+            collect_skills(&user_dir, "user").unwrap(),
+            collect_skills(&project_dir, "project").unwrap(),
+        );
+        let summary: Vec<(&str, &str)> = skills.iter().map(|s| (s.name.as_str(), s.scope.as_str())).collect();
+        assert_eq!(summary, vec![("deploy", "project"), ("lint", "user"), ("release", "project")]);
+        assert_eq!(skills[0].description.as_deref(), Some("Project deploy"));
+
+        assert!(collect_skills(&dir.join("missing"), "project").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_add_and_update_mcp_server() {
+        let dir = TempDir::new("mcp");
+        let scope = || Some(McpScope::Project(dir.to_string_lossy().to_string()));
+        // Commands must resolve, so point them at real files
+        let first = dir.join("first").to_string_lossy().to_string();
+        let second = dir.join("second").to_string_lossy().to_string();
+        let third = dir.join("third").to_string_lossy().to_string();
+        for path in [&first, &second, &third] {
+            write_executable(std::path::Path::new(path));
+        }
+        let add = |command: &str, overwrite: Option<bool>| {
+            add_mcp_server("fs".into(), "stdio".into(), Some(command.into()), Some(vec!["--root".into()]), None, None, None, scope(), overwrite)
+        };
+        let server = || load_mcp_config(&scope().unwrap()).unwrap().unwrap()["mcpServers"]["fs"].clone();
+
+        add(&first, None).unwrap();
+        assert!(!dir.join(".mcp.json.bak").exists());
+        let err = add(&second, None).unwrap_err();
+        assert!(err.contains("already exists"), "{}", err);
+        assert_eq!(server()["command"], first.as_str());
+
+        let before = fs::read_to_string(dir.join(".mcp.json")).unwrap();
+        add(&second, Some(true)).unwrap();
+        assert_eq!(server()["command"], second.as_str());
+        assert_eq!(fs::read_to_string(dir.join(".mcp.json.bak")).unwrap(), before);
+
+        // update keeps fields it wasn't given, including manual edits
+        let mut config = load_mcp_config(&scope().unwrap()).unwrap().unwrap();
+        config["mcpServers"]["fs"]["timeout"] = serde_json::json!(30);
+        save_mcp_config(&scope().unwrap(), &config).unwrap();
+        update_mcp_server("fs".into(), Some(third.clone()), None, None, None, None, scope()).unwrap();
+        let updated = server();
+        assert_eq!((updated["command"].as_str(), updated["timeout"].as_u64()), (Some(third.as_str()), Some(30)));
+        assert_eq!(updated["args"], serde_json::json!(["--root"]));
+
+        assert!(update_mcp_server("missing".into(), None, None, None, None, None, scope()).is_err());
+
+        // sse servers are written as sse and read back as sse
+        add_mcp_server("events".into(), "sse".into(), None, None, None, Some("https://example.com/sse".into()), None, scope(), None)
+            .unwrap();
+        let config = load_mcp_config(&scope().unwrap()).unwrap().unwrap();
+        assert_eq!(config["mcpServers"]["events"]["type"], "sse");
+        let servers = parse_mcp_servers(&config, "project");
+        let events = servers.iter().find(|s| s.name == "events").unwrap();
+        assert_eq!(events.transport, "sse");
+    }
+
+    #[test]
+    fn test_validate_mcp_server() {
+        let err = validate_mcp_server("http", None, Some("not a url"), None).unwrap_err();
+        assert!(err.contains("Invalid URL"), "{}", err);
+        assert!(validate_mcp_server("sse", None, Some("ftp://example.com"), None).is_err());
+        assert!(validate_mcp_server("http", None, Some("https://example.com/mcp"), None).is_ok());
+
+        let err = validate_mcp_server("stdio", Some("voltcode-no-such-command"), None, None).unwrap_err();
+        assert!(err.contains("not found"), "{}", err);
+        assert!(validate_mcp_server("stdio", Some("/no/such/dir/server"), None, None).is_err());
+
+        let bin = TempDir::new("mcpbin");
+        write_executable(&bin.join("my-server"));
+        let env = HashMap::from([("PATH".to_string(), bin.to_string_lossy().to_string())]);
+        assert!(validate_mcp_server("stdio", Some("my-server"), None, Some(&env)).is_ok());
+
+        // Files without the executable bit don't count as commands
+        #[cfg(unix)]
+        {
+            fs::write(bin.join("notes.txt"), "").unwrap();
+            assert!(validate_mcp_server("stdio", Some("notes.txt"), None, Some(&env)).is_err());
+            let direct = bin.join("notes.txt").to_string_lossy().to_string();
+            assert!(validate_mcp_server("stdio", Some(&direct), None, None).is_err());
+        }
+
+        let err = validate_mcp_server("websocket", Some("my-server"), None, Some(&env)).unwrap_err();
+        assert!(err.contains("Unsupported MCP transport"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_test_mcp_server_reports_diagnostics() {
+        let result = test_mcp_server("http".into(), None, None, None, Some("::bad".into()), None).await.unwrap();
+        assert!(!result.ok);
+        assert!(result.message.contains("Invalid URL"), "{}", result.message);
+
+        let result = test_mcp_server("stdio".into(), Some("voltcode-no-such-command".into()), None, None, None, None)
+            .await
+            .unwrap();
+        assert!(!result.ok);
+        assert!(result.message.contains("not found"), "{}", result.message);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_probe_mcp_stdio() {
+        let wait = std::time::Duration::from_millis(300);
+        let running = probe_mcp_stdio("sleep", &["30".to_string()], None, wait).await;
+        assert!(running.ok, "{}", running.message);
+        assert!(running.resolved_command.unwrap().ends_with("sleep"));
+
+        let args = vec!["-c".to_string(), "echo boom >&2; exit 3".to_string()];
+        let crashed = probe_mcp_stdio("sh", &args, None, wait).await;
+        assert!(!crashed.ok);
+        assert_eq!((crashed.exit_code, crashed.stderr.as_deref()), (Some(3), Some("boom")));
+    }
+
+    #[test]
+    fn test_expand_mcp_server_vars() {
+        let config = serde_json::json!({
+            "mcpServers": {
+                "github": {
+                    "command": "npx",
+                    "args": ["--token=${FOO}", "$FOO/path", "${MISSING:-fallback}", "cost: $5"],
+                    "env": { "GITHUB_TOKEN": "${FOO}", "OTHER": "${NOT_SET}" },
+                },
+                "remote": {
+                    "url": "https://example.com/mcp",
+                    "headers": { "Authorization": "Bearer ${FOO}" },
+                },
+            }
+        });
+        let lookup = |name: &str| (name == "FOO").then(|| "secret".to_string());
+
+        let mut servers = parse_mcp_servers(&config, "user");
+        let github = servers.iter_mut().find(|s| s.name == "github").unwrap();
+        let unknown = expand_mcp_server_vars(github, &lookup);
+        assert_eq!(
+            github.args.as_ref().unwrap(),
+            &vec!["--token=secret", "secret/path", "fallback", "cost: $5"]
+        );
+        let env = github.env.as_ref().unwrap();
+        assert_eq!(env["GITHUB_TOKEN"], "secret");
+        assert_eq!(env["OTHER"], "${NOT_SET}");
+        assert_eq!(unknown, vec!["NOT_SET"]);
+
+        let remote = servers.iter_mut().find(|s| s.name == "remote").unwrap();
+        assert!(expand_mcp_server_vars(remote, &lookup).is_empty());
+        assert_eq!(remote.headers.as_ref().unwrap()["Authorization"], "Bearer secret");
+
+        // By default the raw strings are kept, so secrets never reach the frontend
+        std::env::set_var("VOLTCODE_TEST_MCP_TOKEN", "from-env");
+        let config = serde_json::json!({ "mcpServers": { "s": { "command": "x", "env": { "T": "${VOLTCODE_TEST_MCP_TOKEN}" } } } });
+        let mut literal = parse_mcp_servers(&config, "user");
+        expand_mcp_servers(&mut literal, None);
+        assert_eq!(literal[0].env.as_ref().unwrap()["T"], "${VOLTCODE_TEST_MCP_TOKEN}");
+
+        // Expansion is opt-in and reads the process environment
+        let mut servers = parse_mcp_servers(&config, "user");
+        expand_mcp_servers(&mut servers, Some(true));
+        assert_eq!(servers[0].env.as_ref().unwrap()["T"], "from-env");
+    }
+
+    #[test]
+    fn test_parse_log_lines() {
+        let text = "a1b2c3d\0HEAD -> main, origin/main\0Ann\0ann@example.com\x002024-05-01T10:00:00+02:00\0Rename a|b to c\n\
+                    9f8e7d6\0\0Bob\0bob@example.com\x002024-04-30T09:00:00Z\0Add util.rs\n\n";
+        let commits = parse_log_lines(text);
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].short_hash, "a1b2c3d");
+        assert_eq!(commits[0].refs, "HEAD -> main, origin/main");
+        assert_eq!(commits[0].message, "Rename a|b to c");
+        assert_eq!((commits[0].author.as_str(), commits[0].email.as_str()), ("Ann", "ann@example.com"));
+        assert_eq!(commits[1].refs, "");
+        assert_eq!(commits[1].date, "2024-04-30T09:00:00Z");
+        assert!(commits.iter().all(|c| c.graph.is_empty()));
+    }
+
+    #[test]
+    fn test_git_log_parses_author_and_date() {
+        let repo = TempRepo::new("log");
+        let project = repo.project.clone();
+        std::fs::write(repo.join("a.txt"), "one\n").unwrap();
+        repo.git(&["add", "a.txt"]);
+        repo.git(&[
+            "-c", "user.name=Grace Hopper", "-c", "user.email=grace@example.com",
+            "commit", "-q", "-m", "Fix | pipe handling", "--date=2024-03-01T12:30:00+01:00",
+        ]);
+
+        let commits = git_log(project.clone(), Some(10)).unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].graph, "* ");
+        assert_eq!(commits[0].author, "Grace Hopper");
+        assert_eq!(commits[0].email, "grace@example.com");
+        assert_eq!(commits[0].date, "2024-03-01T12:30:00+01:00");
+        assert_eq!(commits[0].message, "Fix | pipe handling");
+    }
+
+    #[test]
+    fn test_parse_trailers() {
+        let output = "Change-Id: I1234\nCo-authored-by: A <a@example.com>\nCo-authored-by: B <b@example.com>\n";
+        let trailers = parse_trailers(output);
+        assert_eq!(trailers["Change-Id"], vec!["I1234"]);
+        assert_eq!(trailers["Co-authored-by"], vec!["A <a@example.com>", "B <b@example.com>"]);
+    }
+
+    #[test]
+    fn test_parse_unified_diff_rename() {
+        let diff = "diff --git a/old name.txt b/new name.txt
+similarity index 90%
+rename from old name.txt
+rename to new name.txt
+--- a/old name.txt\t
++++ b/new name.txt\t
+@@ -1 +1 @@
+-a
++b
+";
+        let files = parse_unified_diff(diff);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].status, "renamed");
+        assert_eq!(files[0].path, "new name.txt");
+        assert_eq!(files[0].old_path.as_deref(), Some("old name.txt"));
+    }
 }
@@ -33,6 +33,9 @@ export interface SkillInfo {
   name: string;
   path: string;
   token_count?: number;
+  description?: string;
+  allowed_tools?: string[];
+  scope?: 'user' | 'project';
 }
 
 export type McpTransportType = 'stdio' | 'http';